@@ -0,0 +1,80 @@
+//! Times the transform pipeline on a large generated file, compare a build with and
+//! without the `parallel` feature:
+//!
+//! cargo bench --bench transforms
+//! cargo bench --bench transforms --features parallel
+
+use mtxt::transforms::{TransformDescriptor, apply_transforms};
+use mtxt::{HumanizeDistribution, parse_mtxt};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::time::Instant;
+
+const EVENTS: usize = 500_000;
+const RUNS: u32 = 5;
+
+fn main() {
+    let mut content = String::from("mtxt 1.0\nalias chord C4,E4,G4\n");
+    for idx in 0..EVENTS / 2 {
+        let time = idx as f64 * 0.13;
+        let _ = writeln!(content, "{:.3} note C4 ch={}", time, idx % 4);
+        let _ = writeln!(content, "{:.3} cc volume 0.5 ch={}", time, idx % 4);
+    }
+    let file = parse_mtxt(&content).unwrap();
+
+    let transforms = TransformDescriptor {
+        apply_directives: false,
+        extract_directives: false,
+        flatten: false,
+        infer_duration: false,
+        sort_by_time: false,
+        sort_canonical: false,
+        merge_notes: false,
+        split_notes: false,
+        merge_ties: None,
+        voice_allocate: Vec::new(),
+        quantize_grid: 16,
+        quantize_swing: 0.0,
+        quantize_humanize: 0.0,
+        humanize_distribution: HumanizeDistribution::Uniform,
+        seed: None,
+        quantize_multi: Vec::new(),
+        quantize_targets: Default::default(),
+        quantize_duration: 0,
+        humanize_timing: 0.0,
+        humanize_velocity: 0.0,
+        trim_start: None,
+        trim_end: None,
+        trim_options: Default::default(),
+        transpose_amount: 3,
+        transpose_diatonic: None,
+        transpose_steps: None,
+        transpose_octaves: 1,
+        fold_octaves: None,
+        offset_amount: 2.0,
+        cc_lead: None,
+        rotate: None,
+        compress_tempo: false,
+        tempo_ramp: None,
+        include_channels: HashSet::new(),
+        exclude_channels: HashSet::from([3]),
+        remap_channels: HashMap::new(),
+        include_controllers: HashSet::new(),
+        exclude_controllers: HashSet::new(),
+        strip_meta: None,
+        shuffle_simultaneous: false,
+        group_channels: false,
+        frame_sysex: false,
+    };
+
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        let output = apply_transforms(&file.records, &transforms);
+        assert!(!output.is_empty());
+    }
+    println!(
+        "{} records, transpose + octave + offset + exclude + quantize: {:?} per run",
+        file.records.len(),
+        started.elapsed() / RUNS
+    );
+}
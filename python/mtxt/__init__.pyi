@@ -209,6 +209,16 @@ class MtxtFile:
         """
         ...
 
+    @property
+    def duration_seconds(self) -> Optional[float]:
+        """
+        Get the duration of the file in seconds, following tempo changes.
+
+        Returns:
+            The duration in seconds, or None if no timed events
+        """
+        ...
+
     def __len__(self) -> int:
         """Get the number of records in the file"""
         ...
@@ -0,0 +1,285 @@
+//! ABC notation export of a single monophonic line.
+
+use crate::file::MtxtFile;
+use crate::score::{
+    DEFAULT_TIME_SIGNATURE, MeasureWalker, MeasureWriter, ScoreEvents, TICKS_PER_BEAT,
+};
+use crate::types::beat_time::BeatTime;
+use crate::types::key::{Key, signature_accidental};
+use crate::types::note::Note;
+use crate::types::time_signature::TimeSignature;
+use anyhow::{Result, bail};
+use std::collections::{BTreeMap, HashMap};
+
+const BARS_PER_LINE: usize = 4;
+
+/// Which line `MtxtFile::to_abc_with` exports.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AbcOptions {
+    /// Channel of the line, by default the lowest channel with notes.
+    pub channel: Option<u16>,
+    /// Keep the highest of notes starting together and cut notes at the next start,
+    /// instead of failing on chords and overlapping notes.
+    pub top_note: bool,
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// Number of fifths of a `meta global key` value ("Eb minor" is -6) and its ABC name.
+fn parse_key(value: &str) -> Result<(i8, String)> {
+    let Ok(key) = value.parse::<Key>() else {
+        bail!("Unsupported key for ABC: {}", value);
+    };
+    if !(-7..=7).contains(&key.fifths()) {
+        bail!("Unsupported key for ABC: {}", value);
+    }
+    let suffix = if key.minor { "m" } else { "" };
+    Ok((key.fifths(), format!("{}{}", key.tonic, suffix)))
+}
+
+/// ABC length suffix for `ticks` in units of `unit` ticks: "", "2", "/2", "3/2".
+fn length_suffix(ticks: u64, unit: u64) -> String {
+    let divisor = gcd(ticks, unit);
+    match (ticks / divisor, unit / divisor) {
+        (1, 1) => String::new(),
+        (numerator, 1) => numerator.to_string(),
+        (1, denominator) => format!("/{}", denominator),
+        (numerator, denominator) => format!("{}/{}", numerator, denominator),
+    }
+}
+
+/// Writes the notes and rests of a `MeasureWalker` into bars.
+struct AbcWriter {
+    fifths: i8,
+    unit: u64,
+    bars: Vec<String>,
+    bar: String,
+    // accidentals written earlier in the bar, they hold until the bar line
+    accidentals: HashMap<(char, i32), i8>,
+}
+
+impl AbcWriter {
+    fn note_name(&mut self, note: &Note) -> String {
+        let (letter, accidental, octave) = note.staff_position();
+        let current = self
+            .accidentals
+            .get(&(letter, octave))
+            .copied()
+            .unwrap_or_else(|| signature_accidental(self.fifths, letter));
+
+        let mut name = String::new();
+        if accidental != current {
+            name.push(match accidental {
+                1 => '^',
+                -1 => '_',
+                _ => '=',
+            });
+            self.accidentals.insert((letter, octave), accidental);
+        }
+        // C4-B4 are upper case, each octave above adds ' (from c5 on lower case), below adds ,
+        if octave >= 5 {
+            name.push(letter.to_ascii_lowercase());
+            name.push_str(&"'".repeat((octave - 5) as usize));
+        } else {
+            name.push(letter);
+            name.push_str(&",".repeat((4 - octave).max(0) as usize));
+        }
+        name
+    }
+}
+
+impl MeasureWriter for AbcWriter {
+    fn open_measure(&mut self, number: u32, signature: &TimeSignature, signature_changed: bool) {
+        if number > 1 && signature_changed {
+            self.bar.push_str(&format!("[M:{}] ", signature));
+        }
+        self.accidentals.clear();
+    }
+
+    fn close_measure(&mut self, _cursor: u64, _last: bool) {
+        let bar = std::mem::take(&mut self.bar);
+        self.bars.push(bar.trim_end().to_string());
+    }
+
+    fn write_part(
+        &mut self,
+        _cursor: u64,
+        length: u64,
+        notes: &[Note],
+        _tied_from_previous: bool,
+        tied_to_next: bool,
+    ) {
+        let name = match notes.first() {
+            Some(note) => self.note_name(note),
+            None => "z".to_string(),
+        };
+        self.bar.push_str(&name);
+        self.bar.push_str(&length_suffix(length, self.unit));
+        if tied_to_next {
+            self.bar.push('-');
+        }
+        self.bar.push(' ');
+    }
+}
+
+impl MtxtFile {
+    /// Exports the notes of one channel as an ABC tune with the default `AbcOptions`:
+    /// the lowest channel with notes, failing if it plays more than one note at a time.
+    pub fn to_abc(&self) -> Result<String> {
+        self.to_abc_with(&AbcOptions::default())
+    }
+
+    /// Exports the notes of one channel as an ABC tune (`X:`, `T:`, `M:`, `L:`, `Q:` and
+    /// `K:` header, then bars of notes). The key comes from `meta global key`, the meter
+    /// from the first time signature (4/4 without one). Later time signatures become
+    /// inline `[M:]` fields at the next bar line.
+    ///
+    /// Cents, velocities and everything but notes on the channel are dropped.
+    pub fn to_abc_with(&self, options: &AbcOptions) -> Result<String> {
+        let ScoreEvents {
+            notes,
+            signatures,
+            tempos,
+        } = ScoreEvents::collect(self)?;
+
+        let channel = match options.channel {
+            Some(channel) => channel,
+            None => notes.iter().map(|(ch, ..)| *ch).min().unwrap_or(0),
+        };
+
+        // highest note of each start
+        let mut line: BTreeMap<u64, (u64, Note)> = BTreeMap::new();
+        for (_, start, end, note) in notes.into_iter().filter(|(ch, ..)| *ch == channel) {
+            if let Some((_, other)) = line.get(&start) {
+                if !options.top_note {
+                    bail!(
+                        "Notes {} and {} start together at beat {} on channel {}, \
+                         ABC export needs a single line (use top_note)",
+                        other,
+                        note,
+                        BeatTime::from_beats(start as f64 / TICKS_PER_BEAT as f64),
+                        channel
+                    );
+                }
+                if other.cmp_pitch(&note).is_ge() {
+                    continue;
+                }
+            }
+            line.insert(start, (end, note));
+        }
+
+        let first_signature = signatures
+            .first()
+            .map_or(DEFAULT_TIME_SIGNATURE, |(_, signature)| signature.clone());
+        // unit note length: 1/16 for meters below 3/4, 1/8 otherwise
+        let meter = first_signature.numerator as f64 / first_signature.denominator as f64;
+        let unit_denominator = if meter < 0.75 { 16 } else { 8 };
+        let (fifths, key) = match self.get_global_meta_value("key") {
+            Some(value) => parse_key(value)?,
+            None => (0, "C".to_string()),
+        };
+
+        let writer = AbcWriter {
+            fifths,
+            unit: TICKS_PER_BEAT * 4 / unit_denominator,
+            bars: Vec::new(),
+            bar: String::new(),
+            accidentals: HashMap::new(),
+        };
+        let mut walker = MeasureWalker::new(writer, signatures);
+
+        let starts: Vec<u64> = line.keys().copied().collect();
+        for (idx, (start, (end, note))) in line.into_iter().enumerate() {
+            if let Some(next) = starts.get(idx + 1)
+                && end > *next
+                && !options.top_note
+            {
+                bail!(
+                    "Note {} at beat {} overlaps the next note on channel {}, \
+                     ABC export needs a single line (use top_note)",
+                    note,
+                    BeatTime::from_beats(start as f64 / TICKS_PER_BEAT as f64),
+                    channel
+                );
+            }
+            let end = starts.get(idx + 1).map_or(end, |next| end.min(*next));
+            walker.write_at(start, end, std::slice::from_ref(&note));
+        }
+        let bars = walker.finish().bars;
+
+        let mut abc = String::from("X:1\n");
+        if let Some(title) = self.get_global_meta_value("title") {
+            abc.push_str(&format!("T:{}\n", title));
+        }
+        abc.push_str(&format!("M:{}\n", first_signature));
+        abc.push_str(&format!("L:1/{}\n", unit_denominator));
+        if let Some((_, bpm)) = tempos.first() {
+            abc.push_str(&format!("Q:1/4={}\n", bpm.round()));
+        }
+        abc.push_str(&format!("K:{}\n", key));
+        for (idx, chunk) in bars.chunks(BARS_PER_LINE).enumerate() {
+            let last = (idx + 1) * BARS_PER_LINE >= bars.len();
+            abc.push_str(&chunk.join(" | "));
+            abc.push_str(if last { " |]\n" } else { " |\n" });
+        }
+
+        Ok(abc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_mtxt;
+
+    #[test]
+    fn test_abc_melody() {
+        let file = parse_mtxt(
+            "mtxt 1.0\nmeta global title Tune\nmeta global key G major\n0.0 timesig 3/4\n\
+             0.0 tempo 100\nch=2\n0.0 note G4\n1.0 note A4\n2.0 note B4\n3.0 note C5 dur=2\n\
+             5.0 note F#4\n6.0 note F4 dur=3\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            file.to_abc().unwrap(),
+            "X:1\nT:Tune\nM:3/4\nL:1/8\nQ:1/4=100\nK:G\nG2 A2 B2 | c4 F2 | =F6 |]\n"
+        );
+    }
+
+    #[test]
+    fn test_abc_octaves_and_ties() {
+        let file =
+            parse_mtxt("mtxt 1.0\n0.0 note C3 dur=0.5\n0.5 note Bb5 dur=0.5\n1.0 note E6 dur=4\n")
+                .unwrap();
+
+        assert_eq!(
+            file.to_abc().unwrap(),
+            "X:1\nM:4/4\nL:1/8\nK:C\nC, _b e'6- | e'2 z6 |]\n"
+        );
+    }
+
+    #[test]
+    fn test_abc_polyphony() {
+        let file = parse_mtxt("mtxt 1.0\n0.0 note C4 dur=2\n0.0 note E4\n1.0 note G4\n").unwrap();
+
+        let err = file.to_abc().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Notes C4 and E4 start together at beat 0.0 on channel 0, \
+             ABC export needs a single line (use top_note)"
+        );
+
+        let top_note = AbcOptions {
+            top_note: true,
+            ..AbcOptions::default()
+        };
+        assert!(
+            file.to_abc_with(&top_note)
+                .unwrap()
+                .ends_with("K:C\nE2 G2 z4 |]\n")
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Fluent construction of MTXT files, e.g. for code generation.
+
+use crate::file::MtxtFile;
+use crate::types::beat_time::BeatTime;
+use crate::types::duration::NoteDuration;
+use crate::types::note::{Note, NoteTarget};
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use crate::types::time_signature::TimeSignature;
+use crate::types::version::Version;
+use anyhow::{Result, anyhow, bail};
+
+/// Builds an `MtxtFile` one record at a time, in the order the methods are called.
+/// Times are in beats. Invalid values are reported by `build`, so a chain of calls
+/// doesn't need to be checked step by step.
+///
+/// ```
+/// use mtxt::{MtxtBuilder, TimeSignature};
+///
+/// let file = MtxtBuilder::new()
+///     .header()
+///     .tempo(0.0, 120.0)
+///     .time_signature(0.0, TimeSignature { numerator: 3, denominator: 4 })
+///     .channel(1)
+///     .note(0.0, "C4".parse().unwrap(), 1.0)
+///     .note(1.0, "E4".parse().unwrap(), 0.5)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(
+///     file.to_string(),
+///     "mtxt 1.0\n0.0 tempo 120.0\n0.0 timesig 3/4\nch=1\n0.0 note C4 dur=1.0\n1.0 note E4 dur=0.5\n"
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MtxtBuilder {
+    records: Vec<MtxtRecordLine>,
+    // first invalid value, reported by `build`
+    error: Option<String>,
+}
+
+impl MtxtBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `mtxt 1.0` version line, must come before everything but comments.
+    pub fn header(self) -> Self {
+        self.record(MtxtRecord::Header {
+            version: Version::latest(),
+        })
+    }
+
+    /// `meta global <meta_type> <value>`, e.g. the title.
+    pub fn meta(self, meta_type: &str, value: &str) -> Self {
+        self.record(MtxtRecord::GlobalMeta {
+            meta_type: meta_type.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    /// `ch=` directive, the channel of the following events.
+    pub fn channel(self, channel: u16) -> Self {
+        self.record(MtxtRecord::ChannelDirective { channel })
+    }
+
+    /// `dur=` directive, the duration of the following notes without one.
+    pub fn duration(mut self, beats: f64) -> Self {
+        let duration = self.beat_time(beats);
+        self.record(MtxtRecord::DurationDirective {
+            duration: duration.into(),
+        })
+    }
+
+    /// `vel=` directive (0.0-1.0), the velocity of the following notes without one.
+    pub fn velocity(mut self, velocity: f32) -> Self {
+        self.check_unit("velocity", velocity);
+        self.record(MtxtRecord::VelocityDirective { velocity })
+    }
+
+    pub fn tempo(mut self, time: f64, bpm: f32) -> Self {
+        let time = self.beat_time(time);
+        if !bpm.is_finite() || bpm <= 0.0 {
+            self.fail(format!("{} is not a positive tempo", bpm));
+        }
+        self.record(MtxtRecord::Tempo {
+            time,
+            bpm,
+            transition_curve: None,
+            transition_time: None,
+            transition_interval: None,
+            transition_steps: None,
+        })
+    }
+
+    pub fn time_signature(mut self, time: f64, signature: TimeSignature) -> Self {
+        let time = self.beat_time(time);
+        if signature.numerator == 0 || signature.denominator == 0 {
+            self.fail(format!("Invalid time signature {}", signature));
+        }
+        self.record(MtxtRecord::TimeSignature { time, signature })
+    }
+
+    /// Note with a duration in beats, velocity and channel come from the directives.
+    pub fn note(mut self, time: f64, note: Note, duration: f64) -> Self {
+        let time = self.beat_time(time);
+        let duration = self.beat_time(duration);
+        self.record(MtxtRecord::Note {
+            time,
+            note: NoteTarget::Note(note),
+            duration: Some(NoteDuration::Beats(duration)),
+            velocity: None,
+            off_velocity: None,
+            channel: None,
+        })
+    }
+
+    pub fn note_on(mut self, time: f64, note: Note) -> Self {
+        let time = self.beat_time(time);
+        self.record(MtxtRecord::NoteOn {
+            time,
+            note: NoteTarget::Note(note),
+            velocity: None,
+            channel: None,
+            length: None,
+        })
+    }
+
+    pub fn note_off(mut self, time: f64, note: Note) -> Self {
+        let time = self.beat_time(time);
+        self.record(MtxtRecord::NoteOff {
+            time,
+            note: NoteTarget::Note(note),
+            off_velocity: None,
+            channel: None,
+        })
+    }
+
+    /// Controller change on the current channel, by name (`volume`) or CC number (`20`).
+    pub fn cc(mut self, time: f64, controller: &str, value: f32) -> Self {
+        let time = self.beat_time(time);
+        if controller.is_empty() || controller.contains(char::is_whitespace) {
+            self.fail(format!("Invalid controller name \"{}\"", controller));
+        }
+        if !value.is_finite() {
+            self.fail(format!("Invalid {} value {}", controller, value));
+        }
+        self.record(MtxtRecord::ControlChange {
+            time,
+            note: None,
+            controller: controller.to_string(),
+            value,
+            channel: None,
+            transition_curve: None,
+            transition_time: None,
+            transition_interval: None,
+            transition_steps: None,
+        })
+    }
+
+    /// Full-line `// text` comment.
+    pub fn comment(self, text: &str) -> Self {
+        self.record(MtxtRecord::Comment {
+            text: text.to_string(),
+        })
+    }
+
+    /// Adds any record as is, for everything without a method of its own.
+    pub fn record(mut self, record: MtxtRecord) -> Self {
+        self.records.push(MtxtRecordLine::new(record));
+        self
+    }
+
+    /// Fails on the first invalid value, or if the header is missing or comes after
+    /// other records.
+    pub fn build(self) -> Result<MtxtFile> {
+        if let Some(error) = self.error {
+            return Err(anyhow!(error));
+        }
+
+        let first = self.records.iter().map(|line| &line.record).find(|record| {
+            !matches!(
+                record,
+                MtxtRecord::EmptyLine
+                    | MtxtRecord::Comment { .. }
+                    | MtxtRecord::BlockComment { .. }
+            )
+        });
+        match first {
+            Some(MtxtRecord::Header { .. }) => {}
+            Some(record) => bail!("Missing version declaration before \"{}\"", record),
+            None => bail!("Missing version declaration"),
+        }
+        if let Some(header) = self
+            .records
+            .iter()
+            .filter(|line| matches!(line.record, MtxtRecord::Header { .. }))
+            .nth(1)
+            .map(|line| &line.record)
+        {
+            bail!("Duplicate version declaration \"{}\"", header);
+        }
+
+        Ok(MtxtFile::from_records(self.records))
+    }
+
+    fn fail(&mut self, error: String) {
+        self.error.get_or_insert(error);
+    }
+
+    fn beat_time(&mut self, beats: f64) -> BeatTime {
+        BeatTime::try_from_beats(beats).unwrap_or_else(|error| {
+            self.fail(error.to_string());
+            BeatTime::zero()
+        })
+    }
+
+    fn check_unit(&mut self, name: &str, value: f32) {
+        if !(0.0..=1.0).contains(&value) {
+            self.fail(format!("{} {} is outside 0.0-1.0", name, value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_mtxt;
+
+    #[test]
+    fn test_builder_roundtrip() {
+        let file = MtxtBuilder::new()
+            .comment("generated")
+            .header()
+            .meta("title", "Scale")
+            .duration(0.5)
+            .velocity(0.8)
+            .note_on(0.0, "C4".parse().unwrap())
+            .cc(0.25, "volume", 0.5)
+            .note_off(0.5, "C4".parse().unwrap())
+            .build()
+            .unwrap();
+
+        let content = file.to_string();
+        assert_eq!(
+            content,
+            "// generated\nmtxt 1.0\nmeta global title Scale\ndur=0.5\nvel=0.8\n\
+             0.0 on C4\n0.25 cc volume 0.5\n0.5 off C4\n"
+        );
+        assert_eq!(parse_mtxt(&content).unwrap().records, file.records);
+    }
+
+    #[test]
+    fn test_builder_errors() {
+        let error = |builder: MtxtBuilder| builder.build().unwrap_err().to_string();
+
+        assert_eq!(
+            error(MtxtBuilder::new().tempo(0.0, 120.0).header()),
+            "Missing version declaration before \"tempo 120.0\""
+        );
+        assert_eq!(
+            error(MtxtBuilder::new().header().header()),
+            "Duplicate version declaration \"mtxt 1.0\""
+        );
+        // the first invalid value is reported
+        assert_eq!(
+            error(
+                MtxtBuilder::new()
+                    .header()
+                    .note(-1.0, "C4".parse().unwrap(), 1.0)
+                    .tempo(0.0, 0.0)
+            ),
+            "-1 is not a valid time in beats"
+        );
+        assert_eq!(
+            error(MtxtBuilder::new().header().velocity(1.5)),
+            "velocity 1.5 is outside 0.0-1.0"
+        );
+    }
+}
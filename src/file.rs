@@ -1,53 +1,231 @@
-use crate::process::process_records;
+use crate::process::{DEFAULT_CHANNEL, MIDI_CHANNEL_COUNT, ProcessOptions, process_records_with};
+use crate::transforms::merge::MergePolicy;
+use crate::transforms::{flatten, merge, offset, sort};
 use crate::types::beat_time::BeatTime;
-use crate::types::output_record::MtxtOutputRecord;
-use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use crate::types::duration::NoteDuration;
+use crate::types::note::{Note, NoteTarget, OctaveConvention};
+use crate::types::output_record::{MtxtOutputRecord, TSV_HEADER};
+use crate::types::record::{
+    ALL_CHANNELS, FormatContext, MtxtRecord, MtxtRecordLine, TimeFormat, VelocityScale,
+};
+use crate::types::time_signature::TimeSignature;
 use crate::types::version::Version;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
+use std::mem::{Discriminant, discriminant};
+
+/// Layout options for `MtxtFile::display_with_formatting`. The default matches `Display`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormatOptions {
+    /// Pads timestamps to this width, aligning the event keywords.
+    pub timestamp_width: Option<usize>,
+    /// Column where the inline parameters (`dur=`, `vel=`, ...) of events start.
+    pub directive_column: Option<usize>,
+    /// Column where inline comments start.
+    pub comment_column: Option<usize>,
+    /// Maximum number of decimal places for float values (default 5).
+    pub float_precision: Option<usize>,
+    /// Pads timestamps, event keywords and notes to the widest in the file so the
+    /// columns after them line up. An explicit `timestamp_width` takes precedence.
+    pub align_columns: bool,
+    /// Writes `vel=` and `offvel=` as 0.0-1.0 floats or MIDI style 0-127 integers.
+    pub velocity_scale: VelocityScale,
+    /// Writes times and durations as decimal beats or, where one matches, fractions.
+    pub time_format: TimeFormat,
+    /// Writes event times relative to the previous timed event (`+0.5`). Events earlier
+    /// than the previous one keep their absolute time.
+    pub relative_times: bool,
+    /// Writes note names in this octave numbering, see `OctaveConvention`.
+    pub octave_convention: OctaveConvention,
+}
 
 pub struct MtxtFileFormatter<'a> {
     file: &'a MtxtFile,
-    timestamp_width: Option<usize>,
+    options: FormatOptions,
+    context: FormatContext,
+    // rendered time of each record, `None` for records without one
+    times: Vec<Option<String>>,
+    columns: Option<ColumnWidths>,
+}
+
+/// Renders the time of each record. Relative times are taken from the time the parser
+/// reads back for the previous event, so rounding errors don't add up across deltas.
+fn format_times(
+    records: &[MtxtRecordLine],
+    context: &FormatContext,
+    relative: bool,
+) -> Vec<Option<String>> {
+    let mut parsed_time = BeatTime::zero();
+    records
+        .iter()
+        .map(|line| {
+            let time = line.record.time()?;
+            if relative && time >= parsed_time {
+                let delta = time - parsed_time;
+                let text = context.time_format.format(delta);
+                parsed_time = parsed_time + text.parse().unwrap_or(delta);
+                Some(format!("+{}", text))
+            } else {
+                let text = context.time_format.format(time);
+                parsed_time = text.parse().unwrap_or(time);
+                Some(text)
+            }
+        })
+        .collect()
+}
+
+/// Widths of the leading columns of timed records, measured across the whole file.
+#[derive(Debug, Default)]
+struct ColumnWidths {
+    time: usize,
+    keyword: usize,
+    note: usize,
+}
+
+fn is_note_event(record: &MtxtRecord) -> bool {
+    matches!(
+        record,
+        MtxtRecord::Note { .. } | MtxtRecord::NoteOn { .. } | MtxtRecord::NoteOff { .. }
+    )
+}
+
+impl ColumnWidths {
+    fn measure(
+        records: &[MtxtRecordLine],
+        times: &[Option<String>],
+        context: &FormatContext,
+    ) -> Self {
+        let mut widths = Self::default();
+        for (line, time) in records.iter().zip(times) {
+            let Some(time) = time else {
+                continue;
+            };
+            widths.time = widths.time.max(time.len());
+
+            let rendered = line.record.display_with(context).to_string();
+            let mut tokens = rendered.split(' ').map(|token| token.chars().count());
+            widths.keyword = widths.keyword.max(tokens.next().unwrap_or(0));
+            if is_note_event(&line.record) {
+                widths.note = widths.note.max(tokens.next().unwrap_or(0));
+            }
+        }
+        widths
+    }
+
+    /// Pads the keyword (and the note of note events) of a rendered record. Nothing is
+    /// padded at the end of the line.
+    fn align(&self, record: &MtxtRecord, rendered: &str) -> String {
+        let columns: &[usize] = if is_note_event(record) {
+            &[self.keyword, self.note]
+        } else {
+            &[self.keyword]
+        };
+
+        let mut text = String::new();
+        let mut rest = rendered;
+        for &width in columns {
+            let Some((token, tail)) = rest.split_once(' ') else {
+                break;
+            };
+            let start = text.chars().count();
+            text.push_str(token);
+            pad_to_column(&mut text, Some(start + width + 1));
+            rest = tail;
+        }
+        text.push_str(rest);
+        text
+    }
+}
+
+/// Appends spaces up to `column`, always at least one.
+fn pad_to_column(text: &mut String, column: Option<usize>) {
+    let width = text.chars().count();
+    let padding = column.map_or(1, |column| column.saturating_sub(width).max(1));
+    text.extend(std::iter::repeat_n(' ', padding));
+}
+
+impl MtxtFileFormatter<'_> {
+    fn write_record(&self, text: &mut String, record: &MtxtRecord) {
+        let mut rendered = record.display_with(&self.context).to_string();
+        if let Some(columns) = &self.columns
+            && record.time().is_some()
+        {
+            rendered = columns.align(record, &rendered);
+        }
+
+        let has_parameters = matches!(
+            record,
+            MtxtRecord::Note { .. }
+                | MtxtRecord::NoteOn { .. }
+                | MtxtRecord::NoteOff { .. }
+                | MtxtRecord::ControlChange { .. }
+                | MtxtRecord::Tempo { .. }
+        );
+        // the first space-separated token containing `=` starts the inline parameters
+        let split = if has_parameters && self.options.directive_column.is_some() {
+            rendered
+                .match_indices(' ')
+                .map(|(idx, _)| idx)
+                .find(|&idx| {
+                    rendered[idx + 1..]
+                        .split(' ')
+                        .next()
+                        .is_some_and(|token| token.contains('='))
+                })
+        } else {
+            None
+        };
+
+        match split {
+            Some(idx) => {
+                text.push_str(rendered[..idx].trim_end());
+                pad_to_column(text, self.options.directive_column);
+                text.push_str(&rendered[idx + 1..]);
+            }
+            None => text.push_str(&rendered),
+        }
+    }
 }
 
 impl<'a> fmt::Display for MtxtFileFormatter<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for line in &self.file.records {
+        let timestamp_width = self
+            .options
+            .timestamp_width
+            .or(self.columns.as_ref().map(|columns| columns.time));
+
+        for (line, time) in self.file.records.iter().zip(&self.times) {
             let record = &line.record;
+            let mut text = String::new();
             match record {
                 // File-level records don't have timestamps
                 MtxtRecord::Header { .. } | MtxtRecord::GlobalMeta { .. } => {
-                    write!(f, "{}", record)?;
+                    self.write_record(&mut text, record);
                 }
                 // Formatting-only records
-                MtxtRecord::EmptyLine => {
-                    if let Some(comment) = &line.comment {
-                        write!(f, "// {}", comment)?;
-                    }
+                MtxtRecord::EmptyLine => {}
+                MtxtRecord::Comment { .. } | MtxtRecord::BlockComment { .. } => {
+                    text.push_str(&record.to_string());
                 }
                 // Timed or directive records: print with timestamp
                 _ => {
-                    match record.time() {
-                        Some(time) => {
-                            if let Some(width) = self.timestamp_width {
-                                write!(f, "{:<width$} {}", time, record, width = width)?;
-                            } else {
-                                write!(f, "{} {}", time, record)?;
-                            }
-                        }
-                        None => {
-                            write!(f, "{}", record)?;
+                    if let Some(time) = time {
+                        match timestamp_width {
+                            Some(width) => text.push_str(&format!("{:<width$} ", time)),
+                            None => text.push_str(&format!("{} ", time)),
                         }
-                    };
+                    }
+                    self.write_record(&mut text, record);
                 }
             }
 
-            if record != &MtxtRecord::EmptyLine {
-                if let Some(comment) = &line.comment {
-                    write!(f, " // {}", comment)?;
+            if let Some(comment) = &line.comment {
+                if !text.is_empty() {
+                    pad_to_column(&mut text, self.options.comment_column);
                 }
+                text.push_str(&format!("// {}", comment));
             }
-            writeln!(f)?;
+            writeln!(f, "{}", text)?;
         }
         Ok(())
     }
@@ -111,6 +289,67 @@ impl MtxtFile {
         })
     }
 
+    /// Length in beats of the incomplete first bar, set with `meta global pickup 1.5`.
+    /// This is metadata only, MIDI has no equivalent and the MIDI writer skips it.
+    pub fn pickup(&self) -> Option<BeatTime> {
+        self.get_global_meta_value("pickup")
+            .and_then(|value| value.parse().ok())
+    }
+
+    /// Pitch bend range in semitones (either way) of `pitch` events on MIDI export, set with
+    /// `meta global pitch_bend_range 12`. MIDI export sends it as RPN 0 on every channel.
+    pub fn pitch_bend_range(&self) -> Option<f32> {
+        self.get_global_meta_value("pitch_bend_range")
+            .and_then(|value| value.parse().ok())
+    }
+
+    /// Resolves a bar and a beat offset within that bar to an absolute time.
+    ///
+    /// Bars are numbered from 1, bar 0 is the pickup and only exists when one is set.
+    /// Bar lengths follow the time signature active at the start of each bar (4/4 before the first).
+    /// `bar` events fix where their bar starts, later bars are counted from the last one.
+    pub fn bar_beat_time(&self, bar: u32, beat: BeatTime) -> Option<BeatTime> {
+        let pickup = self.pickup();
+        if bar == 0 {
+            return pickup.map(|_| beat);
+        }
+
+        let mut signatures: Vec<(BeatTime, &TimeSignature)> = self
+            .records
+            .iter()
+            .filter_map(|line| match &line.record {
+                MtxtRecord::TimeSignature { time, signature } => Some((*time, signature)),
+                _ => None,
+            })
+            .collect();
+        signatures.sort_by_key(|(time, _)| *time);
+
+        let anchor = self
+            .records
+            .iter()
+            .filter_map(|line| match &line.record {
+                MtxtRecord::Bar { time, number } if (1..=bar).contains(number) => {
+                    Some((*number, *time))
+                }
+                _ => None,
+            })
+            .max_by_key(|(number, _)| *number);
+
+        let (first_bar, mut bar_start) = anchor.unwrap_or((1, pickup.unwrap_or(BeatTime::zero())));
+        for _ in first_bar..bar {
+            let bar_length = signatures
+                .iter()
+                .rev()
+                .find(|(time, _)| *time <= bar_start)
+                .map_or(BeatTime::from_whole_beats(4), |(_, signature)| {
+                    signature.bar_length()
+                });
+            bar_start = bar_start + bar_length;
+        }
+
+        Some(bar_start + beat)
+    }
+
     pub fn duration(&self) -> Option<BeatTime> {
         self.records
             .iter()
@@ -122,6 +361,186 @@ impl MtxtFile {
             })
     }
 
+    /// Time of the last event in seconds, following tempo changes and transitions.
+    /// Like the MIDI writer, the tempo is 120 bpm before the first tempo event.
+    pub fn duration_seconds(&self) -> Option<f64> {
+        let end_beat = self.duration()?.as_f64();
+
+        let mut micros = 0u64;
+        let mut beat = 0.0f64;
+        let mut bpm = 120.0f64;
+
+        // tempo transitions are resolved into stepped tempo events by the processor
+        for record in self.get_output_records() {
+            if let MtxtOutputRecord::Tempo {
+                time,
+                bpm: next_bpm,
+            } = record
+            {
+                let tempo_beat = beat + (time - micros) as f64 / 1_000_000.0 * bpm / 60.0;
+                if tempo_beat > end_beat {
+                    break;
+                }
+                micros = time;
+                beat = tempo_beat;
+                bpm = next_bpm as f64;
+            }
+        }
+
+        Some(micros as f64 / 1_000_000.0 + (end_beat - beat) * 60.0 / bpm)
+    }
+
+    /// Notes sounding at beat `t`, with their channel, in file order. A note sounds during
+    /// `[start, start + duration)`, an `on` without a matching `off` sounds from its start on.
+    ///
+    /// Aliases are expanded and channels follow `ch=` directives, like in the output.
+    pub fn notes_active_at(&self, t: BeatTime) -> Vec<(Note, u16)> {
+        let records = merge::transform(
+            &sort::transform(&flatten::transform(&self.records)),
+            MergePolicy::Lifo,
+        );
+
+        records
+            .iter()
+            .filter_map(|line| match &line.record {
+                MtxtRecord::Note {
+                    time,
+                    note: NoteTarget::Note(note),
+                    duration: Some(NoteDuration::Beats(duration)),
+                    channel: Some(channel),
+                    ..
+                } if *time <= t && t < *time + *duration => Some((note.clone(), *channel)),
+                MtxtRecord::NoteOn {
+                    time,
+                    note: NoteTarget::Note(note),
+                    channel: Some(channel),
+                    length: Some(NoteDuration::Beats(length)),
+                    ..
+                } if *time <= t && t < *time + *length => Some((note.clone(), *channel)),
+                MtxtRecord::NoteOn {
+                    time,
+                    note: NoteTarget::Note(note),
+                    channel: Some(channel),
+                    length: None,
+                    ..
+                } if *time <= t => Some((note.clone(), *channel)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Lowest and highest note played, with aliases expanded. Notes compare by pitch, see
+    /// `Note`. `None` without notes.
+    pub fn pitch_range(&self) -> Option<(Note, Note)> {
+        let records = flatten::transform(&self.records);
+        let notes = records.iter().filter_map(|line| match &line.record {
+            MtxtRecord::Note {
+                note: NoteTarget::Note(note),
+                ..
+            }
+            | MtxtRecord::NoteOn {
+                note: NoteTarget::Note(note),
+                ..
+            } => Some(note),
+            _ => None,
+        });
+        let low = notes.clone().min_by(|a, b| a.cmp_pitch(b))?;
+        let high = notes.max_by(|a, b| a.cmp_pitch(b))?;
+        Some((low.clone(), high.clone()))
+    }
+
+    /// Timed records in `[start, end)`, in file order, preceded by the directives
+    /// (`ch=`, `vel=`, ...) and alias definitions in effect for them, so the result
+    /// parses into the same events. Directives that are overridden before the first
+    /// event in the window are left out.
+    pub fn events_between(&self, start: BeatTime, end: BeatTime) -> Vec<&MtxtRecordLine> {
+        let mut directives: HashMap<Discriminant<MtxtRecord>, usize> = HashMap::new();
+        let mut aliases: HashMap<&str, usize> = HashMap::new();
+        let mut included = BTreeSet::new();
+
+        for (idx, line) in self.records.iter().enumerate() {
+            match &line.record {
+                MtxtRecord::ChannelDirective { .. }
+                | MtxtRecord::VelocityDirective { .. }
+                | MtxtRecord::OffVelocityDirective { .. }
+                | MtxtRecord::DurationDirective { .. }
+                | MtxtRecord::TransitionCurveDirective { .. }
+                | MtxtRecord::TransitionTimeDirective { .. }
+                | MtxtRecord::TransitionIntervalDirective { .. }
+                | MtxtRecord::TransitionStepsDirective { .. } => {
+                    directives.insert(discriminant(&line.record), idx);
+                }
+                MtxtRecord::AliasDef { value } => {
+                    aliases.insert(&value.name, idx);
+                }
+                record => {
+                    if let Some(time) = record.time()
+                        && start <= time
+                        && time < end
+                    {
+                        included.extend(directives.values());
+                        included.extend(aliases.values());
+                        included.insert(idx);
+                    }
+                }
+            }
+        }
+
+        included.into_iter().map(|idx| &self.records[idx]).collect()
+    }
+
+    /// Channels that notes, `cc` and `voice` events end up on, in ascending order.
+    ///
+    /// Events without `ch=` use the channel of the last `ch=` directive (0 before any),
+    /// including `cc` events: they go to that channel like in the output, even though
+    /// `--include-channels` treats them as belonging to every channel. `ch=*` counts
+    /// as all 16 MIDI channels.
+    pub fn channels_used(&self) -> BTreeSet<u16> {
+        let mut channels = BTreeSet::new();
+        let mut current_channel = DEFAULT_CHANNEL;
+
+        for line in &self.records {
+            let channel = match &line.record {
+                MtxtRecord::ChannelDirective { channel } => {
+                    current_channel = *channel;
+                    continue;
+                }
+                MtxtRecord::Note { channel, .. }
+                | MtxtRecord::NoteOn { channel, .. }
+                | MtxtRecord::NoteOff { channel, .. }
+                | MtxtRecord::ControlChange { channel, .. }
+                | MtxtRecord::Voice { channel, .. } => channel.unwrap_or(current_channel),
+                _ => continue,
+            };
+
+            if channel == ALL_CHANNELS {
+                channels.extend(0..MIDI_CHANNEL_COUNT);
+            } else {
+                channels.insert(channel);
+            }
+        }
+
+        channels
+    }
+
+    /// Removes leading silence by moving the earliest timed event to beat 0.
+    ///
+    /// Keys off all timed events, not just notes: a tempo or meta event at 0 means
+    /// nothing is shifted. Global and directive records are untouched.
+    pub fn shift_to_zero(&mut self) {
+        let first = self
+            .records
+            .iter()
+            .filter_map(|line| line.record.time())
+            .min();
+
+        if let Some(first) = first
+            && first > BeatTime::zero()
+        {
+            offset::transform_by_in_place(&mut self.records, first, true);
+        }
+    }
+
     pub fn add_global_meta(&mut self, meta_type: String, value: String) {
         self.records
             .push(MtxtRecordLine::new(MtxtRecord::GlobalMeta {
@@ -138,27 +557,556 @@ impl MtxtFile {
     }
 
     pub fn get_output_records(&self) -> Vec<MtxtOutputRecord> {
-        let records: Vec<MtxtRecord> = self
-            .records
-            .iter()
-            .map(|line| line.record.clone())
-            .collect();
-        process_records(&records)
+        self.get_output_records_with(&ProcessOptions::default())
+    }
+
+    /// Like `get_output_records`, with other defaults for parameters the file doesn't set.
+    pub fn get_output_records_with(&self, options: &ProcessOptions) -> Vec<MtxtOutputRecord> {
+        process_records_with(self.records.iter().map(|line| &line.record), options)
+    }
+
+    /// Output records as tab-separated values for scripts, a `TSV_HEADER` row followed
+    /// by one `to_tsv_row` line per record.
+    pub fn output_tsv(&self) -> String {
+        let mut tsv = String::from(TSV_HEADER);
+        tsv.push('\n');
+        for record in self.get_output_records() {
+            tsv.push_str(&record.to_tsv_row());
+            tsv.push('\n');
+        }
+        tsv
     }
 
-    pub fn display_with_formatting<'a>(
-        &'a self,
-        timestamp_width: Option<usize>,
-    ) -> MtxtFileFormatter<'a> {
+    /// Renders the file with its events sorted by time (see `sort::transform`),
+    /// leaving `self` as it is.
+    pub fn to_string_sorted(&self) -> String {
+        MtxtFile::from_records(sort::transform(&self.records)).to_string()
+    }
+
+    pub fn display_with_formatting<'a>(&'a self, options: FormatOptions) -> MtxtFileFormatter<'a> {
+        let mut context = FormatContext::default();
+        if let Some(precision) = options.float_precision {
+            context.float_precision = precision;
+        }
+        context.velocity_scale = options.velocity_scale;
+        context.time_format = options.time_format;
+        context.octave_convention = options.octave_convention;
+
+        let times = format_times(&self.records, &context, options.relative_times);
+        let columns = options
+            .align_columns
+            .then(|| ColumnWidths::measure(&self.records, &times, &context));
+
         MtxtFileFormatter {
             file: self,
-            timestamp_width,
+            options,
+            context,
+            times,
+            columns,
         }
     }
 }
 
 impl fmt::Display for MtxtFile {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.display_with_formatting(None))
+        write!(
+            f,
+            "{}",
+            self.display_with_formatting(FormatOptions::default())
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_mtxt;
+    use crate::parser::MtxtParser;
+
+    #[test]
+    fn test_shift_to_zero() {
+        let mut file = parse_mtxt(
+            r#"mtxt 1.0
+ch=1
+2.0 tempo 100
+2.5 note C4
+4.0 note E4
+"#,
+        )
+        .unwrap();
+        file.shift_to_zero();
+
+        assert_eq!(
+            file.to_string(),
+            "mtxt 1.0\nch=1\n0.0 tempo 100.0\n0.5 note C4\n2.0 note E4\n"
+        );
+    }
+
+    #[test]
+    fn test_shift_to_zero_keeps_event_at_zero() {
+        let content = "mtxt 1.0\n0.0 tempo 120.0\n2.0 note C4\n";
+        let mut file = parse_mtxt(content).unwrap();
+        file.shift_to_zero();
+
+        assert_eq!(file.to_string(), content);
+    }
+
+    #[test]
+    fn test_bar_beat_time_with_pickup() {
+        let file = parse_mtxt(
+            "mtxt 1.0\nmeta global pickup 1.5\n0.0 timesig 4/4\n5.5 timesig 3/4\n0.0 note G3\n",
+        )
+        .unwrap();
+        let beat = |s: &str| s.parse::<BeatTime>().unwrap();
+        let bar_beat = |bar, b: &str| file.bar_beat_time(bar, beat(b)).map(|t| t.to_string());
+
+        assert_eq!(file.pickup(), Some(beat("1.5")));
+        assert_eq!(bar_beat(0, "0.5"), Some("0.5".to_string()));
+        assert_eq!(bar_beat(1, "0"), Some("1.5".to_string()));
+        assert_eq!(bar_beat(2, "1"), Some("6.5".to_string()));
+        // 3/4 from bar 2 on
+        assert_eq!(bar_beat(3, "0"), Some("8.5".to_string()));
+
+        let file = parse_mtxt("mtxt 1.0\n").unwrap();
+        assert_eq!(file.bar_beat_time(0, BeatTime::zero()), None);
+        assert_eq!(file.bar_beat_time(2, BeatTime::zero()), Some(beat("4")));
+
+        assert!(parse_mtxt("mtxt 1.0\nmeta global pickup soon\n").is_err());
+    }
+
+    #[test]
+    fn test_bar_markers() {
+        let content = "mtxt 1.0\n0.0 bar 1\n0.0 note C4\n4.0 bar 2\n10.0 bar 5\n";
+        let file = parse_mtxt(content).unwrap();
+        assert_eq!(file.to_string(), content);
+        assert!(matches!(
+            file.records[1].record,
+            MtxtRecord::Bar { number: 1, .. }
+        ));
+
+        // bars are counted on from the last marker at or before them
+        let bar_start = |bar| {
+            file.bar_beat_time(bar, BeatTime::zero())
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(bar_start(2), "4.0");
+        assert_eq!(bar_start(4), "12.0");
+        assert_eq!(bar_start(5), "10.0");
+        assert_eq!(bar_start(6), "14.0");
+
+        // only the marker meta is added to the output, the notes are unchanged
+        let markers: Vec<String> = file
+            .get_output_records()
+            .into_iter()
+            .filter_map(|record| match record {
+                MtxtOutputRecord::GlobalMeta {
+                    meta_type, value, ..
+                } if meta_type == "marker" => Some(value),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(markers, vec!["Bar 1", "Bar 2", "Bar 5"]);
+
+        for invalid in ["1.0 bar", "1.0 bar five", "1.0 bar -1", "1.0 bar 2 3"] {
+            assert!(parse_mtxt(&format!("mtxt 1.0\n{}\n", invalid)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_output_records_with_options() {
+        let file = parse_mtxt("mtxt 1.0\n1.0 note C4\n2.0 note D4 vel=0.8 ch=1\n").unwrap();
+        let options = ProcessOptions {
+            channel: 3,
+            velocity: 0.25,
+            duration: BeatTime::from_beats(0.5),
+            ..ProcessOptions::default()
+        };
+
+        let notes: Vec<String> = file
+            .get_output_records_with(&options)
+            .iter()
+            .map(|record| record.to_string())
+            .filter(|record| record.contains("Note"))
+            .collect();
+        assert_eq!(
+            notes,
+            vec![
+                "[     500] NoteOn C4 vel=0.25 ch=3",
+                "[     750] NoteOff C4 off_vel=0 ch=3",
+                "[    1000] NoteOn D4 vel=0.8 ch=1",
+                "[    1250] NoteOff D4 off_vel=0 ch=1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_relative_times() {
+        let content = "mtxt 1.0\n0.5 note C4\nch=2\n1.0 note E4\n\
+                       meta global title Song\n1.0 cc volume 0.5\n2.33333 note G4\n";
+        let file = parse_mtxt(content).unwrap();
+        assert_eq!(file.to_string(), content);
+
+        let relative = file
+            .display_with_formatting(FormatOptions {
+                relative_times: true,
+                ..FormatOptions::default()
+            })
+            .to_string();
+        assert_eq!(
+            relative,
+            "mtxt 1.0\n+0.5 note C4\nch=2\n+0.5 note E4\n\
+             meta global title Song\n+0.0 cc volume 0.5\n+1.33333 note G4\n"
+        );
+        assert_eq!(parse_mtxt(&relative).unwrap().to_string(), content);
+
+        // thirds don't add up in decimal, each delta makes up for the rounding of the last
+        let thirds: String = (1..=9)
+            .map(|idx| format!("{:.5} note C4\n", idx as f64 / 3.0))
+            .collect();
+        let thirds = parse_mtxt(&format!("mtxt 1.0\n{}", thirds)).unwrap();
+        let relative = thirds
+            .display_with_formatting(FormatOptions {
+                relative_times: true,
+                ..FormatOptions::default()
+            })
+            .to_string();
+        assert_eq!(
+            parse_mtxt(&relative).unwrap().to_string(),
+            thirds.to_string()
+        );
+    }
+
+    #[test]
+    fn test_duration_seconds() {
+        // 4 beats at 120 bpm (2s) + 4 beats at 60 bpm (4s)
+        let file = parse_mtxt("mtxt 1.0\n0.0 tempo 120\n4.0 tempo 60\n8.0 note C4\n").unwrap();
+        assert_eq!(file.duration_seconds(), Some(6.0));
+
+        // 120 bpm before the first tempo event
+        let file = parse_mtxt("mtxt 1.0\n2.0 note C4\n").unwrap();
+        assert_eq!(file.duration_seconds(), Some(1.0));
+
+        assert_eq!(parse_mtxt("mtxt 1.0\n").unwrap().duration_seconds(), None);
+    }
+
+    #[test]
+    fn test_duration_seconds_tempo_transition() {
+        // linear 60 -> 120 bpm over 4 beats: integral of 60 / (60 + 15b) = 4 ln 2 seconds
+        let file =
+            parse_mtxt("mtxt 1.0\n0.0 tempo 60\n4.0 tempo 120 transition_time=4.0\n6.0 note C4\n")
+                .unwrap();
+        let expected = 4.0 * 2.0f64.ln() + 1.0;
+        let seconds = file.duration_seconds().unwrap();
+        assert!((seconds - expected).abs() < 0.01, "{}", seconds);
+    }
+
+    #[test]
+    fn test_float_precision() {
+        let file = parse_mtxt("mtxt 1.0\nvel=0.123456\n1.0 note C4 vel=0.876543\n").unwrap();
+        let with_precision = |float_precision| {
+            file.display_with_formatting(FormatOptions {
+                float_precision,
+                ..FormatOptions::default()
+            })
+            .to_string()
+        };
+
+        assert_eq!(
+            with_precision(Some(2)),
+            "mtxt 1.0\nvel=0.12\n1.0 note C4 vel=0.88\n"
+        );
+        assert_eq!(
+            with_precision(Some(5)),
+            "mtxt 1.0\nvel=0.12346\n1.0 note C4 vel=0.87654\n"
+        );
+        assert_eq!(with_precision(None), file.to_string());
+    }
+
+    #[test]
+    fn test_time_format_fraction() {
+        let content = "mtxt 1.0\ndur=1/3\n1/3 note C4\n1+2/3 note E4 dur=0.5\n2.0 note G4\n";
+        let file = parse_mtxt(content).unwrap();
+
+        let fraction = file
+            .display_with_formatting(FormatOptions {
+                time_format: TimeFormat::Fraction(TimeFormat::DEFAULT_MAX_DENOMINATOR),
+                ..FormatOptions::default()
+            })
+            .to_string();
+        assert_eq!(
+            fraction,
+            "mtxt 1.0\ndur=1/3\n1/3 note C4\n1+2/3 note E4 dur=1/2\n2.0 note G4\n"
+        );
+        assert_eq!(
+            file.to_string(),
+            "mtxt 1.0\ndur=0.33333\n0.33333 note C4\n1.66667 note E4 dur=0.5\n2.0 note G4\n"
+        );
+    }
+
+    #[test]
+    fn test_velocity_scale() {
+        let content = "mtxt 1.0\nvel=100\n1.0 note C4 vel=127 offvel=0\n2.0 on D4 vel=64\n";
+        let file = MtxtParser::new()
+            .with_velocity_scale(VelocityScale::Midi127)
+            .parse(content)
+            .unwrap();
+
+        match &file.records[2].record {
+            MtxtRecord::Note {
+                velocity,
+                off_velocity,
+                ..
+            } => {
+                assert_eq!(*velocity, Some(1.0));
+                assert_eq!(*off_velocity, Some(0.0));
+            }
+            other => panic!("Expected note, got {:?}", other),
+        }
+
+        let midi_127 = file
+            .display_with_formatting(FormatOptions {
+                velocity_scale: VelocityScale::Midi127,
+                ..FormatOptions::default()
+            })
+            .to_string();
+        assert_eq!(midi_127, content);
+        assert_eq!(
+            file.to_string(),
+            "mtxt 1.0\nvel=0.7874\n1.0 note C4 vel=1.0 offvel=0.0\n2.0 on D4 vel=0.50394\n"
+        );
+
+        let parse_127 = |content: &str| {
+            MtxtParser::new()
+                .with_velocity_scale(VelocityScale::Midi127)
+                .parse(content)
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            parse_127("mtxt 1.0\n1.0 note C4 vel=128\n"),
+            "Line #2: Velocity must be 0-127"
+        );
+        assert_eq!(
+            parse_127("mtxt 1.0\n1.0 note C4 vel=0.5\n"),
+            "Line #2: Invalid velocity value, expected 0-127"
+        );
+    }
+
+    #[test]
+    fn test_octave_convention() {
+        let content = "mtxt 1.0\nalias low C2,G2\n1.0 note C3\n2.0 cc C3 pitch 0.5\n";
+        let file = MtxtParser::new()
+            .with_octave_convention(OctaveConvention::Yamaha)
+            .parse(content)
+            .unwrap();
+
+        match &file.records[2].record {
+            MtxtRecord::Note {
+                note: NoteTarget::Note(note),
+                ..
+            } => assert_eq!(note.to_midi_note(), 60),
+            other => panic!("Expected note, got {:?}", other),
+        }
+
+        let yamaha = file
+            .display_with_formatting(FormatOptions {
+                octave_convention: OctaveConvention::Yamaha,
+                ..FormatOptions::default()
+            })
+            .to_string();
+        assert_eq!(yamaha, content);
+        assert_eq!(
+            file.to_string(),
+            "mtxt 1.0\nalias low C3,G3\n1.0 note C4\n2.0 cc C4 pitch 0.5\n"
+        );
+        // other parsers are not affected
+        assert_eq!(parse_mtxt(content).unwrap().to_string(), content);
+    }
+
+    #[test]
+    fn test_events_between() {
+        let file = parse_mtxt(
+            "mtxt 1.0\nalias chord C4,E4\nch=1\nvel=0.5\n0.0 note C4\nch=2\n1.0 note chord\n\
+             vel=0.7\n2.0 note E4 // third\n3.0 note F4\n",
+        )
+        .unwrap();
+
+        let window = file
+            .events_between(BeatTime::from_parts(1, 0.0), BeatTime::from_parts(3, 0.0))
+            .into_iter()
+            .cloned()
+            .collect();
+        assert_eq!(
+            MtxtFile::from_records(window).to_string(),
+            "alias chord C4,E4\nvel=0.5\nch=2\n1.0 note chord\nvel=0.7\n2.0 note E4 // third\n"
+        );
+
+        assert!(
+            file.events_between(BeatTime::from_parts(4, 0.0), BeatTime::from_parts(8, 0.0))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_channels_used() {
+        let file = parse_mtxt(
+            "mtxt 1.0\n0.0 note C4\nch=3\n0.0 voice piano\n1.0 note E4\n\
+             1.0 note G4 ch=9\nch=5\n2.0 cc volume 0.5\n3.0 cc pan 0.0 ch=12\n",
+        )
+        .unwrap();
+        assert_eq!(file.channels_used(), BTreeSet::from([0, 3, 5, 9, 12]));
+
+        let file = parse_mtxt("mtxt 1.0\nch=*\n0.0 note C4\n").unwrap();
+        assert_eq!(file.channels_used(), (0..16).collect());
+
+        assert!(MtxtFile::new().channels_used().is_empty());
+    }
+
+    #[test]
+    fn test_column_alignment() {
+        let content = r#"mtxt 1.0
+// intro
+ch=1 // piano
+0.5 note C4 dur=1.0 vel=0.5 // first
+12.0 cc volume 0.8 transition_time=1.0
+16.0 note Cmaj7 // last
+"#;
+        let file = parse_mtxt(content).unwrap();
+
+        let aligned = file
+            .display_with_formatting(FormatOptions {
+                timestamp_width: Some(5),
+                directive_column: Some(22),
+                comment_column: Some(50),
+                float_precision: None,
+                align_columns: false,
+                velocity_scale: VelocityScale::Unit,
+                time_format: TimeFormat::Decimal,
+                relative_times: false,
+                octave_convention: OctaveConvention::Scientific,
+            })
+            .to_string();
+        assert_eq!(
+            aligned,
+            r#"mtxt 1.0
+// intro
+ch=1                                              // piano
+0.5   note C4         dur=1.0 vel=0.5             // first
+12.0  cc volume 0.8   transition_time=1.0
+16.0  note Cmaj7                                  // last
+"#
+        );
+
+        // default options are byte-identical to Display
+        assert_eq!(
+            file.display_with_formatting(FormatOptions::default())
+                .to_string(),
+            content
+        );
+    }
+
+    #[test]
+    fn test_align_columns() {
+        let content = r#"mtxt 1.0
+ch=1
+0.5 note C4 dur=1.0 vel=0.5 // first
+12.0 note Cmaj7 dur=2.0
+12.25 on F#4
+16.0 cc volume 0.8 // swell
+20.0 note E4
+"#;
+        let file = parse_mtxt(content).unwrap();
+        assert_eq!(file.to_string(), content);
+
+        let aligned = file
+            .display_with_formatting(FormatOptions {
+                align_columns: true,
+                ..FormatOptions::default()
+            })
+            .to_string();
+        assert_eq!(
+            aligned,
+            r#"mtxt 1.0
+ch=1
+0.5   note C4    dur=1.0 vel=0.5 // first
+12.0  note Cmaj7 dur=2.0
+12.25 on   F#4
+16.0  cc   volume 0.8 // swell
+20.0  note E4
+"#
+        );
+    }
+
+    #[test]
+    fn test_notes_active_at() {
+        let file = parse_mtxt(
+            r#"mtxt 1.0
+alias triad C4,E4
+dur=2
+ch=1
+0.0 note triad
+1.0 note G4 dur=0.5 ch=2
+1.0 on A4 ch=3
+1.5 off A4 ch=3
+3.0 on B4
+"#,
+        )
+        .unwrap();
+        let active = |t: &str| {
+            file.notes_active_at(t.parse().unwrap())
+                .iter()
+                .map(|(note, channel)| format!("{} ch={}", note, channel))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            active("1.25"),
+            vec!["C4 ch=1", "E4 ch=1", "G4 ch=2", "A4 ch=3"]
+        );
+        // note ends are exclusive
+        assert_eq!(active("1.5"), vec!["C4 ch=1", "E4 ch=1"]);
+        assert_eq!(active("2.0"), Vec::<String>::new());
+        assert_eq!(active("10.0"), vec!["B4 ch=1"]);
+    }
+
+    #[test]
+    fn test_to_string_sorted() {
+        let content = "mtxt 1.0\n2.0 note E4\n0.0 tempo 120.0\n1.0 note C4\nch=2\n1.0 note G4\n";
+        let file = parse_mtxt(content).unwrap();
+
+        assert_eq!(
+            file.to_string_sorted(),
+            "mtxt 1.0\n0.0 tempo 120.0\n1.0 note C4\n2.0 note E4\nch=2\n1.0 note G4\n"
+        );
+        assert_eq!(
+            file.to_string_sorted(),
+            MtxtFile::from_records(sort::transform(&file.records)).to_string()
+        );
+        // the file itself keeps its order
+        assert_eq!(file.to_string(), content);
+    }
+
+    #[test]
+    fn test_pitch_range() {
+        let content = "mtxt 1.0\nalias wide C2,G4,E6+20\n0.0 note E4\n1.0 note wide\n2.0 on D3\n";
+        let file = parse_mtxt(content).unwrap();
+        let (low, high) = file.pitch_range().unwrap();
+        assert_eq!(low.to_string(), "C2");
+        assert_eq!(high.to_string(), "E6+20");
+
+        let empty = parse_mtxt("mtxt 1.0\n0.0 tempo 120\n").unwrap();
+        assert_eq!(empty.pitch_range(), None);
+    }
+
+    #[test]
+    fn test_output_tsv() {
+        let file = parse_mtxt("mtxt 1.0\n0.5 note C4 dur=1 vel=0.5 ch=1\n").unwrap();
+        let tsv = file.output_tsv();
+        let mut lines = tsv.lines();
+        assert_eq!(lines.next(), Some(TSV_HEADER));
+        assert!(tsv.contains("\n250000\tNoteOn\t1\tC4\t\t0.5\n"), "{}", tsv);
+        assert!(tsv.contains("\n750000\tNoteOff\t1\tC4\t\t"), "{}", tsv);
+        assert_eq!(lines.count(), file.get_output_records().len());
     }
 }
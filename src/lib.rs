@@ -3,6 +3,7 @@
 //! This library provides functionality for working with MTXT (Musical Text) format,
 //! a human-readable text format for representing musical data.
 
+pub mod builder;
 pub mod file;
 pub mod parser;
 pub mod process;
@@ -15,20 +16,46 @@ pub mod util;
 #[cfg(feature = "midi")]
 pub mod midi;
 
+#[cfg(feature = "abc")]
+pub mod abc;
+
+#[cfg(feature = "musicxml")]
+pub mod musicxml;
+
+#[cfg(any(feature = "abc", feature = "musicxml"))]
+mod score;
+
 #[cfg(feature = "python")]
 pub mod python;
 
 // Re-export commonly used types
+pub use builder::MtxtBuilder;
+pub use file::FormatOptions;
 pub use file::MtxtFile;
+pub use parser::parse_line;
 pub use parser::parse_mtxt;
+pub use parser::parse_mtxt_strict;
+pub use process::ProcessOptions;
+pub use process::TuningMode;
 pub use types::beat_time::BeatTime;
+pub use types::duration::NoteDuration;
+pub use types::easing::Easing;
+pub use types::humanize::HumanizeDistribution;
+pub use types::interval::Interval;
+pub use types::key::Key;
 pub use types::note::Note;
 pub use types::note::NoteTarget;
+pub use types::note::OctaveConvention;
 pub use types::output_record::MtxtOutputRecord;
 pub use types::pitch::PitchClass;
+pub use types::record::FormatContext;
 pub use types::record::MtxtRecord;
 pub use types::record::MtxtRecordLine;
+pub use types::record::TimeFormat;
+pub use types::record::VelocityScale;
+pub use types::scale::Scale;
 pub use types::time_signature::TimeSignature;
+pub use types::version::Compat;
 pub use types::version::Version;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
@@ -1,6 +1,23 @@
 use anyhow::{Context, Result};
 use clap::{Arg, Command};
-use std::path::Path;
+use mtxt::FormatOptions;
+use mtxt::HumanizeDistribution;
+use mtxt::Interval;
+use mtxt::Note;
+use mtxt::OctaveConvention;
+use mtxt::PitchClass;
+use mtxt::Scale;
+use mtxt::TimeFormat;
+use mtxt::TuningMode;
+use mtxt::VelocityScale;
+use mtxt::transforms::TransformDescriptor;
+use mtxt::transforms::cc_lead::LeadPolicy;
+use mtxt::transforms::quantize::QuantizeTargets;
+use mtxt::transforms::tempo_ramp::TempoRamp;
+use mtxt::transforms::trim::TrimOptions;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[cfg(feature = "midi")]
 use mtxt::midi;
@@ -11,6 +28,16 @@ enum FileFormat {
     Mtxt,
 }
 
+struct ConvertOptions {
+    transforms: TransformDescriptor,
+    indent: bool,
+    format: FormatOptions,
+    verbose: bool,
+    channel_wrap: bool,
+    tuning_mode: TuningMode,
+    no_running_status: bool,
+}
+
 fn detect_file_format(file_path: &str) -> Result<FileFormat> {
     let path = Path::new(file_path);
     let extension = path
@@ -18,6 +45,10 @@ fn detect_file_format(file_path: &str) -> Result<FileFormat> {
         .and_then(|ext| ext.to_str())
         .ok_or_else(|| anyhow::anyhow!("File has no extension: {}", file_path))?;
 
+    format_from_extension(extension)
+}
+
+fn format_from_extension(extension: &str) -> Result<FileFormat> {
     match extension.to_lowercase().as_str() {
         "mid" => Ok(FileFormat::Midi),
         "midi" => Ok(FileFormat::Midi),
@@ -30,24 +61,126 @@ fn detect_file_format(file_path: &str) -> Result<FileFormat> {
     }
 }
 
+fn parse_fold_range(value: &str, convention: OctaveConvention) -> Result<(Note, u32)> {
+    let (low, span) = value.split_once(':').unwrap_or((value, "1"));
+    let low = Note::parse_with(low, convention)?;
+    let span: u32 = span
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid octave span: {}", span))?;
+    if span == 0 {
+        anyhow::bail!("Octave span must be at least 1");
+    }
+    Ok((low, span))
+}
+
+/// `--transpose-diatonic` takes an interval or scale steps in a given key.
+#[derive(Debug, Clone, Copy)]
+enum DiatonicTranspose {
+    Interval(Interval),
+    Steps(PitchClass, Scale, i32),
+}
+
+// INTERVAL or ROOT:SCALE:STEPS
+fn parse_diatonic_transpose(value: &str) -> Result<DiatonicTranspose> {
+    let parts: Vec<&str> = value.split(':').collect();
+    match parts.as_slice() {
+        [interval] => Ok(DiatonicTranspose::Interval(interval.parse()?)),
+        [root, scale, steps] => {
+            let root: PitchClass = root.parse()?;
+            let scale: Scale = scale.parse()?;
+            let steps: i32 = steps
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid scale steps: {}", steps))?;
+            Ok(DiatonicTranspose::Steps(root, scale, steps))
+        }
+        _ => anyhow::bail!("Expected INTERVAL or ROOT:SCALE:STEPS, got {}", value),
+    }
+}
+
+// FROM:TO
+fn parse_channel_remap(value: &str) -> Result<(u16, u16)> {
+    let (from, to) = value
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Expected FROM:TO, got {}", value))?;
+    let channel = |c: &str| {
+        c.parse::<u16>()
+            .map_err(|_| anyhow::anyhow!("Invalid channel: {}", c))
+    };
+    Ok((channel(from)?, channel(to)?))
+}
+
+// START:END:FROM_BPM:TO_BPM[:CURVE], `slowing` selects between --rit and --accel
+fn parse_tempo_ramp(value: &str, slowing: bool) -> Result<TempoRamp> {
+    let parts: Vec<&str> = value.split(':').collect();
+    if !(4..=5).contains(&parts.len()) {
+        anyhow::bail!("Expected START:END:FROM_BPM:TO_BPM[:CURVE], got {}", value);
+    }
+
+    let start: mtxt::BeatTime = parts[0].parse()?;
+    let end: mtxt::BeatTime = parts[1].parse()?;
+    let from_bpm: f32 = parts[2]
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid tempo: {}", parts[2]))?;
+    let to_bpm: f32 = parts[3]
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid tempo: {}", parts[3]))?;
+    let curve: mtxt::Easing = match parts.get(4) {
+        Some(curve) => curve.parse()?,
+        None => mtxt::Easing::default(),
+    };
+
+    if end <= start {
+        anyhow::bail!("End {} must be after start {}", end, start);
+    }
+    if slowing && to_bpm >= from_bpm {
+        anyhow::bail!("Ritardando must slow down ({} -> {})", from_bpm, to_bpm);
+    }
+    if !slowing && to_bpm <= from_bpm {
+        anyhow::bail!("Accelerando must speed up ({} -> {})", from_bpm, to_bpm);
+    }
+
+    Ok(TempoRamp {
+        start,
+        end,
+        from_bpm,
+        to_bpm,
+        curve,
+    })
+}
+
+// AMOUNT:LOOP_LEN in beats
+fn parse_rotate(value: &str) -> Result<(mtxt::BeatTime, mtxt::BeatTime)> {
+    let Some((amount, loop_len)) = value.split_once(':') else {
+        anyhow::bail!("Expected AMOUNT:LOOP_LEN, got {}", value);
+    };
+
+    let amount: mtxt::BeatTime = amount.parse()?;
+    let loop_len: mtxt::BeatTime = loop_len.parse()?;
+    if loop_len == mtxt::BeatTime::zero() {
+        anyhow::bail!("Loop length must be greater than 0");
+    }
+
+    Ok((amount, loop_len))
+}
+
 fn main() -> Result<()> {
     println!("MTXT Converter v{}", env!("CARGO_PKG_VERSION"));
-    println!("");
+    println!();
 
     let matches = Command::new("mtxt")
         .version(env!("CARGO_PKG_VERSION"))
         .about("MTXT converter")
         .arg(
             Arg::new("input")
-                .help("Input file (.mid or .mtxt)")
+                .help("Input file (.mid or .mtxt) or directory")
                 .required(true)
                 .value_name("INPUT_FILE")
                 .index(1),
         )
         .arg(
             Arg::new("output")
-                .help("Output file (.mid or .mtxt)")
-                .required(true)
+                .help("Output file (.mid or .mtxt) or directory")
+                .required_unless_present("print-output")
                 .value_name("OUTPUT_FILE")
                 .index(2),
         )
@@ -66,6 +199,28 @@ fn main() -> Result<()> {
                 .value_name("SEMITONES")
                 .value_parser(clap::value_parser!(i32)),
         )
+        .arg(
+            Arg::new("transpose-diatonic")
+                .help("Transpose by an interval, spelled for the key in meta global key (e.g. m3, -P5), or by scale steps in a key (e.g. C:major:2, D:minor:-1)")
+                .long("transpose-diatonic")
+                .allow_hyphen_values(true)
+                .value_name("INTERVAL|ROOT:SCALE:STEPS")
+                .value_parser(parse_diatonic_transpose),
+        )
+        .arg(
+            Arg::new("transpose-octaves")
+                .help("Transpose by whole octaves (e.g. +1, -2)")
+                .long("transpose-octaves")
+                .allow_hyphen_values(true)
+                .value_name("OCTAVES")
+                .value_parser(clap::value_parser!(i32)),
+        )
+        .arg(
+            Arg::new("fold-octaves")
+                .help("Fold all notes into a range starting at a note, spanning octaves (e.g. C4, C3:2)")
+                .long("fold-octaves")
+                .value_name("LOW[:OCTAVES]"),
+        )
         .arg(
             Arg::new("offset")
                 .help("Offset all events by beats (e.g. 1.5, -0.5)")
@@ -74,6 +229,48 @@ fn main() -> Result<()> {
                 .value_name("BEATS")
                 .value_parser(clap::value_parser!(f32)),
         )
+        .arg(
+            Arg::new("cc-lead")
+                .help("Move all controller changes earlier by beats, clamped at 0 (e.g. 0.1)")
+                .long("cc-lead")
+                .value_name("BEATS")
+                .value_parser(|v: &str| v.parse::<mtxt::BeatTime>()),
+        )
+        .arg(
+            Arg::new("cc-lead-drop")
+                .help("Drop controller changes --cc-lead moves before 0 instead of clamping")
+                .long("cc-lead-drop")
+                .requires("cc-lead")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("rotate")
+                .help("Rotate a loop by beats, wrapping events around (e.g. 1:4)")
+                .long("rotate")
+                .value_name("AMOUNT:LOOP_LEN")
+                .value_parser(parse_rotate),
+        )
+        .arg(
+            Arg::new("compress-tempo")
+                .help("Collapse runs of tempo events forming a linear ramp into one transition")
+                .long("compress-tempo")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("rit")
+                .help("Slow down between two beats (e.g. 16:20:120:90, optional :CURVE)")
+                .long("rit")
+                .value_name("START:END:FROM:TO")
+                .conflicts_with("accel")
+                .value_parser(|v: &str| parse_tempo_ramp(v, true)),
+        )
+        .arg(
+            Arg::new("accel")
+                .help("Speed up between two beats (e.g. 0:8:90:120, optional :CURVE)")
+                .long("accel")
+                .value_name("START:END:FROM:TO")
+                .value_parser(|v: &str| parse_tempo_ramp(v, false)),
+        )
         .arg(
             Arg::new("include-channels")
                 .help("Include only specific channels (comma-separated, e.g. 1,2,10)")
@@ -90,18 +287,74 @@ fn main() -> Result<()> {
                 .value_delimiter(',')
                 .value_parser(clap::value_parser!(u16)),
         )
+        .arg(
+            Arg::new("remap-channel")
+                .help("Move a channel to another, after channel filtering (e.g. 5:2, repeatable)")
+                .long("remap-channel")
+                .value_name("FROM:TO")
+                .action(clap::ArgAction::Append)
+                .value_delimiter(',')
+                .value_parser(parse_channel_remap),
+        )
+        .arg(
+            Arg::new("include-cc")
+                .help("Keep only these controllers in cc events (comma-separated, e.g. volume,pan)")
+                .long("include-cc")
+                .value_name("CONTROLLERS")
+                .value_delimiter(','),
+        )
+        .arg(
+            Arg::new("exclude-cc")
+                .help("Drop cc events of these controllers (comma-separated, e.g. pitch,aftertouch)")
+                .long("exclude-cc")
+                .value_name("CONTROLLERS")
+                .value_delimiter(','),
+        )
+        .arg(
+            Arg::new("strip-meta")
+                .help("Remove meta events of these types (comma-separated, e.g. lyric,marker), or every text meta if no types are given")
+                .long("strip-meta")
+                .value_name("TYPES")
+                .num_args(0..=1)
+                .value_delimiter(','),
+        )
         .arg(
             Arg::new("apply-directives")
                 .help("Apply directives to events")
                 .long("apply-directives")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("infer-duration")
+                .help("Make notes without a duration last until the next note on their channel")
+                .long("infer-duration")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("flatten")
+                .help("Resolve aliases, directives and defaults into fully explicit events")
+                .long("flatten")
+                .conflicts_with("extract-directives")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("sort")
                 .help("Sort events by time (respecting directives)")
                 .long("sort")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("sort-canonical")
+                .help("Sort events by time, then simultaneous events by kind, channel and pitch")
+                .long("sort-canonical")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("shuffle-simultaneous")
+                .help("Randomly reorder notes that share the same time (see --seed)")
+                .long("shuffle-simultaneous")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("extract-directives")
                 .help("Extract common inline parameters into global directives")
@@ -120,6 +373,60 @@ fn main() -> Result<()> {
                 .long("merge-notes")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("split-notes")
+                .help("Split note shorthand events into explicit note on / off pairs")
+                .long("split-notes")
+                .conflicts_with("merge-notes")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("merge-ties")
+                .help("Join back-to-back notes of the same pitch into one longer note")
+                .long("merge-ties")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tie-gap")
+                .help("Largest gap in beats between notes joined by --merge-ties (default 0)")
+                .long("tie-gap")
+                .value_name("BEATS")
+                .requires("merge-ties")
+                .value_parser(|v: &str| v.parse::<mtxt::BeatTime>()),
+        )
+        .arg(
+            Arg::new("voice-allocate")
+                .help("Spread overlapping notes across a pool of channels (comma-separated, e.g. 1,2,3,4). Voice, cc and tuning events are copied to every pool channel")
+                .long("voice-allocate")
+                .value_name("CHANNELS")
+                .value_delimiter(',')
+                .value_parser(clap::value_parser!(u16)),
+        )
+        .arg(
+            Arg::new("frame-sysex")
+                .help("Add missing F0/F7 framing bytes to SysEx events")
+                .long("frame-sysex")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("channel-wrap")
+                .help("Map channels above 15 to ch % 16 when writing MIDI")
+                .long("channel-wrap")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("tuning-mode")
+                .help("How tuning events are written to MIDI: bake (bend the tuned notes, default) or mts (MIDI Tuning Standard SysEx)")
+                .long("tuning-mode")
+                .value_name("MODE")
+                .value_parser(|v: &str| v.parse::<TuningMode>()),
+        )
+        .arg(
+            Arg::new("no-running-status")
+                .help("Write the status byte of every MIDI message, for devices without running status support")
+                .long("no-running-status")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("quantize")
                 .help("Quantize grid (e.g. 4 for quarter notes, 16 for 16th notes)")
@@ -128,6 +435,27 @@ fn main() -> Result<()> {
                 .value_name("GRID")
                 .value_parser(clap::value_parser!(u32)),
         )
+        .arg(
+            Arg::new("quantize-multi")
+                .help("Quantize to the nearest line of several grids in subdivisions per beat (comma-separated, e.g. 4,3 for 16ths and 8th-triplets)")
+                .long("quantize-multi")
+                .value_name("GRIDS")
+                .value_delimiter(',')
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("quantize-duration")
+                .help("Round note lengths to a grid of subdivisions per beat, at least one step (e.g. 4)")
+                .long("quantize-duration")
+                .value_name("GRID")
+                .value_parser(clap::value_parser!(u32)),
+        )
+        .arg(
+            Arg::new("quantize-notes-only")
+                .help("Only quantize notes, leaving controllers, tempo and meta events in place")
+                .long("quantize-notes-only")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("swing")
                 .help("Swing amount (0.0 to 1.0)")
@@ -137,34 +465,267 @@ fn main() -> Result<()> {
         )
         .arg(
             Arg::new("humanize")
-                .help("Humanize amount (0.0 to 1.0)")
+                .help("Humanize amount around the --quantize grid (0.0 to 1.0)")
                 .long("humanize")
                 .value_name("AMOUNT")
                 .value_parser(clap::value_parser!(f32)),
         )
+        .arg(
+            Arg::new("humanize-timing")
+                .help("Move notes randomly by up to this many beats, without a grid (e.g. 0.02)")
+                .long("humanize-timing")
+                .value_name("BEATS")
+                .value_parser(clap::value_parser!(f32)),
+        )
+        .arg(
+            Arg::new("humanize-velocity")
+                .help("Change note velocities randomly by up to this amount (0.0 to 1.0)")
+                .long("humanize-velocity")
+                .value_name("AMOUNT")
+                .value_parser(clap::value_parser!(f32)),
+        )
+        .arg(
+            Arg::new("trim-start")
+                .help("Drop events before this beat and move it to 0 (e.g. 8)")
+                .long("trim-start")
+                .value_name("BEATS")
+                .value_parser(|v: &str| v.parse::<mtxt::BeatTime>()),
+        )
+        .arg(
+            Arg::new("trim-end")
+                .help("Drop events from this beat on and cut notes off there (e.g. 16)")
+                .long("trim-end")
+                .alias("trim")
+                .value_name("BEATS")
+                .value_parser(|v: &str| v.parse::<mtxt::BeatTime>()),
+        )
+        .arg(
+            Arg::new("keep-origin")
+                .help("Keep event times with --trim-start instead of moving the start to 0")
+                .long("keep-origin")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("keep-sounding")
+                .help("Keep notes still sounding at --trim-start, shortened to begin there")
+                .long("keep-sounding")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("humanize-dist")
+                .help("Humanize distribution: uniform (default) or gaussian")
+                .long("humanize-dist")
+                .value_name("DISTRIBUTION")
+                .value_parser(|v: &str| v.parse::<HumanizeDistribution>()),
+        )
+        .arg(
+            Arg::new("seed")
+                .help("Seed for humanize and --shuffle-simultaneous, the same seed gives the same output")
+                .long("seed")
+                .value_name("SEED")
+                .value_parser(clap::value_parser!(u64)),
+        )
         .arg(
             Arg::new("indent")
                 .help("Enable timestamp padding")
                 .long("indent")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("pretty")
+                .help("Align timestamps, event keywords and notes in columns")
+                .long("pretty")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("directive-column")
+                .help("Align inline parameters (dur=, vel=, ...) of events at this column")
+                .long("directive-column")
+                .value_name("COLUMN")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("comment-column")
+                .help("Align inline comments at this column")
+                .long("comment-column")
+                .value_name("COLUMN")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("float-precision")
+                .help("Maximum decimal places for float values in MTXT output (default 5)")
+                .long("float-precision")
+                .value_name("DIGITS")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("time-format")
+                .help("Write times as decimal (default) or fraction (1/3, 2+2/3) where one matches")
+                .long("time-format")
+                .value_name("FORMAT")
+                .value_parser(|v: &str| v.parse::<TimeFormat>()),
+        )
+        .arg(
+            Arg::new("relative-times")
+                .help("Write event times relative to the previous event (+0.5)")
+                .long("relative-times")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("print-output")
+                .help("Print the processed output events instead of writing a file")
+                .long("print-output")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("to")
+                .help("Target extension when converting a directory (e.g. mid, mtxt)")
+                .long("to")
+                .value_name("EXTENSION"),
+        )
+        .arg(
+            Arg::new("recursive")
+                .help("Convert files in subdirectories too")
+                .long("recursive")
+                .short('r')
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("octave-convention")
+                .help("Octave numbering of note names: scientific (C4 = middle C, default) or yamaha (C3 = middle C)")
+                .long("octave-convention")
+                .value_name("CONVENTION")
+                .value_parser(|v: &str| v.parse::<OctaveConvention>()),
+        )
+        .arg(
+            Arg::new("jobs")
+                .help("Number of files converted in parallel")
+                .long("jobs")
+                .short('j')
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize)),
+        )
         .get_matches();
 
+    let octave_convention = matches
+        .get_one::<OctaveConvention>("octave-convention")
+        .copied()
+        .unwrap_or_default();
     let input_file = matches.get_one::<String>("input").unwrap();
-    let output_file = matches.get_one::<String>("output").unwrap();
+    let output_file = matches.get_one::<String>("output");
     let verbose = matches.get_flag("verbose");
+    let channel_wrap = matches.get_flag("channel-wrap");
+    let tuning_mode = matches
+        .get_one::<TuningMode>("tuning-mode")
+        .copied()
+        .unwrap_or_default();
+    let no_running_status = matches.get_flag("no-running-status");
     let apply_directives = matches.get_flag("apply-directives");
+    let flatten = matches.get_flag("flatten");
+    let infer_duration = matches.get_flag("infer-duration");
     let sort_by_time = matches.get_flag("sort");
+    let sort_canonical = matches.get_flag("sort-canonical");
     let merge_notes = matches.get_flag("merge-notes");
+    let split_notes = matches.get_flag("split-notes");
     let extract_directives = matches.get_flag("extract-directives");
+    let shuffle_simultaneous = matches.get_flag("shuffle-simultaneous");
     let group_channels = matches.get_flag("group-channels");
+    let frame_sysex = matches.get_flag("frame-sysex");
 
     let transpose_amount = matches.get_one::<i32>("transpose").copied().unwrap_or(0);
+    let diatonic = matches.get_one::<DiatonicTranspose>("transpose-diatonic");
+    let (transpose_diatonic, transpose_steps) = match diatonic.copied() {
+        Some(DiatonicTranspose::Interval(interval)) => (Some(interval), None),
+        Some(DiatonicTranspose::Steps(root, scale, steps)) => (None, Some((root, scale, steps))),
+        None => (None, None),
+    };
+    let transpose_octaves = matches
+        .get_one::<i32>("transpose-octaves")
+        .copied()
+        .unwrap_or(0);
+    let fold_octaves = matches
+        .get_one::<String>("fold-octaves")
+        .map(|value| parse_fold_range(value, octave_convention))
+        .transpose()?;
     let offset_amount = matches.get_one::<f32>("offset").copied().unwrap_or(0.0);
+    let cc_lead = matches.get_one::<mtxt::BeatTime>("cc-lead").map(|lead| {
+        let policy = if matches.get_flag("cc-lead-drop") {
+            LeadPolicy::Drop
+        } else {
+            LeadPolicy::Clamp
+        };
+        (*lead, policy)
+    });
+    let rotate = matches
+        .get_one::<(mtxt::BeatTime, mtxt::BeatTime)>("rotate")
+        .copied();
+    let compress_tempo = matches.get_flag("compress-tempo");
+    let tempo_ramp = matches
+        .get_one::<TempoRamp>("rit")
+        .or(matches.get_one::<TempoRamp>("accel"))
+        .cloned();
+    let merge_ties = matches.get_flag("merge-ties").then(|| {
+        matches
+            .get_one::<mtxt::BeatTime>("tie-gap")
+            .copied()
+            .unwrap_or(mtxt::BeatTime::zero())
+    });
+    let voice_allocate: Vec<u16> = matches
+        .get_many::<u16>("voice-allocate")
+        .unwrap_or_default()
+        .copied()
+        .collect();
     let quantize_grid = matches.get_one::<u32>("quantize").copied().unwrap_or(0);
     let quantize_swing = matches.get_one::<f32>("swing").copied().unwrap_or(0.0);
     let quantize_humanize = matches.get_one::<f32>("humanize").copied().unwrap_or(0.0);
+    let humanize_distribution = matches
+        .get_one::<HumanizeDistribution>("humanize-dist")
+        .copied()
+        .unwrap_or_default();
+    let seed = matches.get_one::<u64>("seed").copied();
+    let quantize_multi: Vec<u32> = matches
+        .get_many::<u32>("quantize-multi")
+        .unwrap_or_default()
+        .copied()
+        .collect();
+    let humanize_timing = matches
+        .get_one::<f32>("humanize-timing")
+        .copied()
+        .unwrap_or(0.0);
+    let humanize_velocity = matches
+        .get_one::<f32>("humanize-velocity")
+        .copied()
+        .unwrap_or(0.0);
+    let trim_start = matches.get_one::<mtxt::BeatTime>("trim-start").copied();
+    let trim_end = matches.get_one::<mtxt::BeatTime>("trim-end").copied();
+    let trim_options = TrimOptions {
+        keep_origin: matches.get_flag("keep-origin"),
+        keep_sounding: matches.get_flag("keep-sounding"),
+    };
+    let quantize_duration = matches
+        .get_one::<u32>("quantize-duration")
+        .copied()
+        .unwrap_or(0);
+    let quantize_targets = if matches.get_flag("quantize-notes-only") {
+        QuantizeTargets::NOTES
+    } else {
+        QuantizeTargets::ALL
+    };
     let indent = matches.get_flag("indent");
+    let format = FormatOptions {
+        timestamp_width: None,
+        directive_column: matches.get_one::<usize>("directive-column").copied(),
+        comment_column: matches.get_one::<usize>("comment-column").copied(),
+        float_precision: matches.get_one::<usize>("float-precision").copied(),
+        align_columns: matches.get_flag("pretty"),
+        velocity_scale: VelocityScale::Unit,
+        time_format: matches
+            .get_one::<TimeFormat>("time-format")
+            .copied()
+            .unwrap_or_default(),
+        relative_times: matches.get_flag("relative-times"),
+        octave_convention,
+    };
 
     let include_channels: std::collections::HashSet<u16> = matches
         .get_many::<u16>("include-channels")
@@ -178,39 +739,145 @@ fn main() -> Result<()> {
         .copied()
         .collect();
 
-    let transforms = mtxt::transforms::TransformDescriptor {
+    let mut remap_channels = std::collections::HashMap::new();
+    for (from, to) in matches
+        .get_many::<(u16, u16)>("remap-channel")
+        .unwrap_or_default()
+    {
+        if remap_channels.insert(*from, *to).is_some() {
+            anyhow::bail!("Channel {} is remapped more than once", from);
+        }
+    }
+
+    let include_controllers: std::collections::HashSet<String> = matches
+        .get_many::<String>("include-cc")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    let exclude_controllers: std::collections::HashSet<String> = matches
+        .get_many::<String>("exclude-cc")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    let strip_meta: Option<std::collections::HashSet<String>> = matches
+        .get_many::<String>("strip-meta")
+        .map(|kinds| kinds.cloned().collect());
+
+    let transforms = TransformDescriptor {
         apply_directives,
         extract_directives,
+        flatten,
+        infer_duration,
         sort_by_time,
+        sort_canonical,
         merge_notes,
+        split_notes,
+        merge_ties,
+        voice_allocate,
         quantize_grid,
         quantize_swing,
         quantize_humanize,
+        humanize_distribution,
+        seed,
+        quantize_multi,
+        quantize_targets,
+        quantize_duration,
+        humanize_timing,
+        humanize_velocity,
+        trim_start,
+        trim_end,
+        trim_options,
         transpose_amount,
+        transpose_diatonic,
+        transpose_steps,
+        transpose_octaves,
+        fold_octaves,
         offset_amount,
+        cc_lead,
+        rotate,
+        compress_tempo,
+        tempo_ramp,
         include_channels,
         exclude_channels,
+        remap_channels,
+        include_controllers,
+        exclude_controllers,
+        strip_meta,
+        shuffle_simultaneous,
         group_channels,
+        frame_sysex,
     };
 
+    let options = ConvertOptions {
+        transforms,
+        indent,
+        format,
+        verbose,
+        channel_wrap,
+        tuning_mode,
+        no_running_status,
+    };
+
+    if Path::new(input_file).is_dir() {
+        let output_file = output_file
+            .ok_or_else(|| anyhow::anyhow!("OUTPUT_FILE is required when INPUT is a directory"))?;
+        let target = matches
+            .get_one::<String>("to")
+            .ok_or_else(|| anyhow::anyhow!("--to is required when INPUT is a directory"))?;
+        let recursive = matches.get_flag("recursive");
+        let num_jobs = matches.get_one::<usize>("jobs").copied().unwrap_or(1);
+        return convert_directory(
+            Path::new(input_file),
+            Path::new(output_file),
+            target,
+            recursive,
+            num_jobs,
+            &options,
+        );
+    }
+
+    if matches.get_flag("print-output") {
+        return print_output_records(input_file, &options);
+    }
+
+    let output_file = output_file
+        .ok_or_else(|| anyhow::anyhow!("OUTPUT_FILE is required unless --print-output is set"))?;
+
+    convert_file(input_file, output_file, &options)
+}
+
+// Prints the fully resolved output event stream instead of writing a file
+fn print_output_records(input_file: &str, options: &ConvertOptions) -> Result<()> {
+    let mtxt_file = read_file(input_file, options)?;
+
+    if options.verbose {
+        print!("{}", mtxt_file);
+        println!();
+    }
+
+    for record in mtxt_file.get_output_records() {
+        println!("{}", record);
+    }
+
+    Ok(())
+}
+
+// Reads the input file and applies the requested transforms
+fn read_file(input_file: &str, options: &ConvertOptions) -> Result<mtxt::MtxtFile> {
     let input_format = detect_file_format(input_file)
         .with_context(|| format!("Failed to detect input file format: {}", input_file))?;
 
-    let output_format = detect_file_format(output_file)
-        .with_context(|| format!("Failed to detect output file format: {}", output_file))?;
-
-    if verbose {
-        println!(
-            "Input format: {:?}, Output format: {:?}",
-            input_format, output_format
-        );
+    if options.verbose {
+        println!("Input format: {:?}", input_format);
     }
 
     let mut mtxt_file = match input_format {
         FileFormat::Midi => {
             #[cfg(feature = "midi")]
             {
-                if verbose {
+                if options.verbose {
                     println!("Reading MIDI file: {}", input_file);
                 }
                 let midi_bytes = std::fs::read(input_file)
@@ -223,30 +890,63 @@ fn main() -> Result<()> {
             }
         }
         FileFormat::Mtxt => {
-            if verbose {
+            if options.verbose {
                 println!("Reading MTXT file: {}", input_file);
             }
             let content = std::fs::read_to_string(input_file)
                 .with_context(|| format!("Failed to read input file: {}", input_file))?;
-            mtxt::parse_mtxt(&content)
-                .with_context(|| format!("Failed to parse MTXT file: {}", input_file))?
+            // note names are read in the convention they are written in
+            let mut parser = mtxt::parser::MtxtParser::new()
+                .with_octave_convention(options.format.octave_convention);
+            let mtxt_file = parser
+                .parse(&content)
+                .with_context(|| format!("Failed to parse MTXT file: {}", input_file))?;
+            for warning in parser.warnings() {
+                eprintln!("Warning: {}", warning);
+            }
+            mtxt_file
         }
     };
 
-    if verbose {
+    if options.verbose {
         println!("Applying transforms...");
     }
-    mtxt_file.records = mtxt::transforms::apply_transforms(&mtxt_file.records, &transforms);
+    let records = std::mem::take(&mut mtxt_file.records);
+    mtxt_file.records =
+        mtxt::transforms::apply_transforms_owned_checked(records, &options.transforms)?;
+
+    Ok(mtxt_file)
+}
+
+fn convert_file(input_file: &str, output_file: &str, options: &ConvertOptions) -> Result<()> {
+    let output_format = detect_file_format(output_file)
+        .with_context(|| format!("Failed to detect output file format: {}", output_file))?;
+
+    if options.verbose {
+        println!("Output format: {:?}", output_format);
+    }
+
+    let mtxt_file = read_file(input_file, options)?;
 
     match output_format {
         FileFormat::Midi => {
             #[cfg(feature = "midi")]
             {
-                if verbose {
+                if options.verbose {
                     println!("Writing MIDI file: {}", output_file);
                 }
-                let midi_bytes = midi::convert_mtxt_to_midi(&mtxt_file)
-                    .context("Failed to convert MTXT to MIDI")?;
+                let export_options = midi::MidiExportOptions {
+                    channel_wrap: options.channel_wrap,
+                    tuning_mode: options.tuning_mode,
+                    no_running_status: options.no_running_status,
+                    ..midi::MidiExportOptions::default()
+                };
+                let midi_bytes = midi::convert_mtxt_to_midi_bytes_with(
+                    &mtxt_file,
+                    options.verbose,
+                    &export_options,
+                )
+                .context("Failed to convert MTXT to MIDI")?;
                 std::fs::write(output_file, midi_bytes)
                     .with_context(|| format!("Failed to write MIDI file: {}", output_file))?;
             }
@@ -256,15 +956,14 @@ fn main() -> Result<()> {
             }
         }
         FileFormat::Mtxt => {
-            if verbose {
+            if options.verbose {
                 println!("Writing MTXT file: {}", output_file);
             }
-            let timestamp_width = if indent {
-                Some(mtxt_file.calculate_auto_timestamp_width())
-            } else {
-                None
-            };
-            let output_content = format!("{}", mtxt_file.display_with_formatting(timestamp_width));
+            let mut format = options.format.clone();
+            if options.indent {
+                format.timestamp_width = Some(mtxt_file.calculate_auto_timestamp_width());
+            }
+            let output_content = format!("{}", mtxt_file.display_with_formatting(format));
             std::fs::write(output_file, output_content)
                 .with_context(|| format!("Failed to write output file: {}", output_file))?;
         }
@@ -272,3 +971,128 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+// Collects (input, output) pairs for every file in `input_dir` that is not already in the target format
+fn collect_batch_jobs(
+    input_dir: &Path,
+    output_dir: &Path,
+    target_format: &FileFormat,
+    target_extension: &str,
+    recursive: bool,
+    jobs: &mut Vec<(PathBuf, PathBuf)>,
+) -> Result<()> {
+    let mut entries = std::fs::read_dir(input_dir)
+        .with_context(|| format!("Failed to read directory: {}", input_dir.display()))?
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.path());
+
+    for entry in entries {
+        let path = entry.path();
+        let output_path = output_dir.join(entry.file_name());
+
+        if path.is_dir() {
+            if recursive {
+                collect_batch_jobs(
+                    &path,
+                    &output_path,
+                    target_format,
+                    target_extension,
+                    recursive,
+                    jobs,
+                )?;
+            }
+            continue;
+        }
+
+        let format = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| format_from_extension(ext).ok());
+
+        if let Some(format) = format
+            && &format != target_format
+        {
+            jobs.push((path, output_path.with_extension(target_extension)));
+        }
+    }
+
+    Ok(())
+}
+
+fn convert_directory(
+    input_dir: &Path,
+    output_dir: &Path,
+    target_extension: &str,
+    recursive: bool,
+    num_jobs: usize,
+    options: &ConvertOptions,
+) -> Result<()> {
+    let target_format = format_from_extension(target_extension)?;
+
+    let mut jobs = Vec::new();
+    collect_batch_jobs(
+        input_dir,
+        output_dir,
+        &target_format,
+        target_extension,
+        recursive,
+        &mut jobs,
+    )?;
+
+    if options.verbose {
+        println!("Found {} files to convert", jobs.len());
+    }
+
+    let next_job = AtomicUsize::new(0);
+    let failures: Mutex<Vec<(PathBuf, anyhow::Error)>> = Mutex::new(Vec::new());
+
+    std::thread::scope(|scope| {
+        for _ in 0..num_jobs.clamp(1, jobs.len().max(1)) {
+            scope.spawn(|| {
+                while let Some((input, output)) = jobs.get(next_job.fetch_add(1, Ordering::Relaxed))
+                {
+                    let result = output
+                        .parent()
+                        .map_or(Ok(()), std::fs::create_dir_all)
+                        .with_context(|| {
+                            format!("Failed to create directory for {}", output.display())
+                        })
+                        .and_then(|_| {
+                            convert_file(
+                                &input.to_string_lossy(),
+                                &output.to_string_lossy(),
+                                options,
+                            )
+                        });
+
+                    if let Err(e) = result {
+                        failures.lock().unwrap().push((input.clone(), e));
+                    }
+                }
+            });
+        }
+    });
+
+    let mut failures = failures.into_inner().unwrap();
+    failures.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (path, e) in &failures {
+        println!("Failed: {}: {:#}", path.display(), e);
+    }
+
+    println!(
+        "Converted {} files, {} failed",
+        jobs.len() - failures.len(),
+        failures.len()
+    );
+
+    if !failures.is_empty() {
+        anyhow::bail!(
+            "{} of {} files failed to convert",
+            failures.len(),
+            jobs.len()
+        );
+    }
+
+    Ok(())
+}
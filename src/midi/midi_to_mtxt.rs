@@ -1,8 +1,10 @@
 use crate::file::MtxtFile;
 use crate::midi::drums;
+use crate::transforms::merge::MergePolicy;
 use crate::transforms::{extract, merge};
 use crate::types::beat_time::BeatTime;
 use crate::types::note::NoteTarget;
+use crate::types::pitch::PitchClass;
 use crate::types::record::{MtxtRecord, MtxtRecordLine, VoiceList};
 use crate::types::time_signature::TimeSignature;
 use crate::types::version::Version;
@@ -10,22 +12,44 @@ use anyhow::{Result, bail};
 use midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
 
 use super::escape::escape_string;
-use super::shared::{midi_cc_to_name, midi_key_signature_to_string, midi_key_to_note};
+use super::shared::{
+    ControllerMessage, DEFAULT_PITCH_BEND_RANGE, controller_name, midi_cc_to_name,
+    midi_key_signature_to_string, midi_key_to_note, midi_smpte_to_string, pitch_bend_to_semitones,
+};
 
 use super::drums::DRUMS;
 use super::instruments::INSTRUMENTS;
 use crate::types::record::AliasDefinition;
-use std::rc::Rc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
 
 #[derive(Debug)]
 struct MidiSingleTrackEvent {
     tick: BeatTime,
+    track: usize,
     record: MtxtRecordLine,
 }
 
 pub fn convert_midi_to_mtxt(midi_bytes: &[u8]) -> Result<MtxtFile> {
     let smf = Smf::parse(midi_bytes)?;
-    convert_smf_to_mtxt(&smf)
+    convert_smf_to_mtxt(&smf, false, false)
+}
+
+/// Like `convert_midi_to_mtxt`, but keeps the events of each MIDI track together
+/// instead of merging them into one timeline. Each track starts with a
+/// `// --- track N ---` comment (N counts from 0). Global meta events still come first.
+pub fn convert_midi_to_mtxt_preserve_tracks(midi_bytes: &[u8]) -> Result<MtxtFile> {
+    let smf = Smf::parse(midi_bytes)?;
+    convert_smf_to_mtxt(&smf, true, false)
+}
+
+/// Like `convert_midi_to_mtxt`, but a channel whose pitch bend stays at one value
+/// within a semitone, set before its first note, gets `tuning` records for every
+/// pitch class instead of its `cc pitch` events. This recovers files that detune a
+/// channel with pitch bend for microtonal music.
+pub fn convert_midi_to_mtxt_detect_tuning(midi_bytes: &[u8]) -> Result<MtxtFile> {
+    let smf = Smf::parse(midi_bytes)?;
+    convert_smf_to_mtxt(&smf, false, true)
 }
 
 // It merges all events from all MIDI tracks into a single list of events
@@ -45,7 +69,12 @@ fn get_midi_single_track_events(smf: &Smf) -> Result<Vec<MidiSingleTrackEvent>>
         Timing::Timecode(_, _) => bail!("Timecode timing is not yet supported"),
     };
 
-    for (_track_idx, track) in smf.tracks.iter().enumerate() {
+    // pitch bend range of each channel set with RPN 0, and the channels whose data entry
+    // LSB (CC 38) still sets its cents
+    let mut pitch_bend_ranges: HashMap<u16, f32> = HashMap::new();
+    let mut pitch_bend_rpn: HashSet<u16> = HashSet::new();
+
+    for (track_idx, track) in smf.tracks.iter().enumerate() {
         let mut current_raw_ticks = 0u64;
 
         // Heuristic: associate track with a channel (Type 1 MIDI)
@@ -68,13 +97,44 @@ fn get_midi_single_track_events(smf: &Smf) -> Result<Vec<MidiSingleTrackEvent>>
 
             match &event.kind {
                 TrackEventKind::Midi { channel, message } => {
-                    let record = convert_midi_message_to_record(
+                    let channel = channel.as_int() as u16;
+                    if let MidiMessage::Controller { controller, value } = message {
+                        let value = value.as_int() as f32;
+                        match controller.as_int() {
+                            6 if take_pitch_bend_rpn(&mut all_events, track_idx, channel) => {
+                                pitch_bend_ranges.insert(channel, value);
+                                pitch_bend_rpn.insert(channel);
+                                continue;
+                            }
+                            38 if pitch_bend_rpn.contains(&channel) => {
+                                if let Some(range) = pitch_bend_ranges.get_mut(&channel) {
+                                    *range = range.trunc() + value / 100.0;
+                                }
+                                continue;
+                            }
+                            100 | 101 => {
+                                pitch_bend_rpn.remove(&channel);
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    let pitch_bend_range = pitch_bend_ranges
+                        .get(&channel)
+                        .copied()
+                        .unwrap_or(DEFAULT_PITCH_BEND_RANGE);
+                    let mut record = convert_midi_message_to_record(
                         message,
-                        channel.as_int() as u16,
+                        channel,
                         beat_time,
+                        pitch_bend_range,
                     )?;
+                    if let MtxtRecord::Voice { channel, bank, .. } = &mut record {
+                        *bank = take_bank_select(&mut all_events, track_idx, *channel);
+                    }
                     all_events.push(MidiSingleTrackEvent {
                         tick: beat_time,
+                        track: track_idx,
                         record: MtxtRecordLine::new(record),
                     });
                 }
@@ -82,11 +142,12 @@ fn get_midi_single_track_events(smf: &Smf) -> Result<Vec<MidiSingleTrackEvent>>
                     if let Some(record) = convert_meta_message(
                         meta_msg,
                         beat_time,
-                        _track_idx == 0,
+                        track_idx == 0,
                         guessed_track_channel,
                     )? {
                         all_events.push(MidiSingleTrackEvent {
                             tick: beat_time,
+                            track: track_idx,
                             record: MtxtRecordLine::new(record),
                         });
                     }
@@ -94,9 +155,10 @@ fn get_midi_single_track_events(smf: &Smf) -> Result<Vec<MidiSingleTrackEvent>>
                 TrackEventKind::SysEx(data) => {
                     all_events.push(MidiSingleTrackEvent {
                         tick: beat_time,
+                        track: track_idx,
                         record: MtxtRecordLine::new(MtxtRecord::SysEx {
                             time: beat_time,
-                            data: data.to_vec(),
+                            data: sysex_from_midi(data),
                         }),
                     });
                 }
@@ -106,21 +168,114 @@ fn get_midi_single_track_events(smf: &Smf) -> Result<Vec<MidiSingleTrackEvent>>
 
                     all_events.push(MidiSingleTrackEvent {
                         tick: beat_time,
-                        record: MtxtRecordLine::with_comment(
-                            MtxtRecord::EmptyLine,
-                            format!("Escape sequence: {}", formatted.trim()),
-                        ),
+                        track: track_idx,
+                        record: MtxtRecordLine::new(MtxtRecord::Comment {
+                            text: format!("Escape sequence: {}", formatted.trim()),
+                        }),
                     });
                 }
             }
         }
     }
 
+    // the file has one range, wide enough for the bends of every channel
+    if let Some(range) = pitch_bend_ranges
+        .into_values()
+        .filter(|range| *range > 0.0)
+        .reduce(f32::max)
+    {
+        all_events.push(MidiSingleTrackEvent {
+            tick: BeatTime::zero(),
+            track: 0,
+            record: MtxtRecordLine::new(MtxtRecord::GlobalMeta {
+                meta_type: "pitch_bend_range".to_string(),
+                value: range.to_string(),
+            }),
+        });
+    }
+
     all_events.sort_by_key(|event| event.tick);
     Ok(all_events)
 }
 
-fn convert_smf_to_mtxt(smf: &Smf) -> Result<MtxtFile> {
+/// Removes the RPN 0 (pitch bend sensitivity) selection, CC 101 and 100 set to 0, sent on
+/// `channel` right before a data entry, and returns whether it was there.
+fn take_pitch_bend_rpn(events: &mut Vec<MidiSingleTrackEvent>, track: usize, channel: u16) -> bool {
+    // the RPN number part (CC 101 or 100) an event sets to 0
+    let selects = |event: &MidiSingleTrackEvent| match &event.record.record {
+        MtxtRecord::ControlChange {
+            controller,
+            value: 0.0,
+            channel: Some(cc_channel),
+            note: None,
+            ..
+        } if event.track == track && *cc_channel == channel => controller.parse::<u8>().ok(),
+        _ => None,
+    };
+
+    let Some(start) = events.len().checked_sub(2) else {
+        return false;
+    };
+    let selected = matches!(
+        (selects(&events[start]), selects(&events[start + 1])),
+        (Some(101), Some(100)) | (Some(100), Some(101))
+    );
+    if selected {
+        events.truncate(start);
+    }
+    selected
+}
+
+/// Removes the bank select CCs (0 and 32) sent on `channel` right before a program change
+/// and returns the 14-bit bank they select.
+fn take_bank_select(
+    events: &mut Vec<MidiSingleTrackEvent>,
+    track: usize,
+    channel: Option<u16>,
+) -> Option<u16> {
+    let mut msb = None;
+    let mut lsb = None;
+
+    while let Some(event) = events.last()
+        && event.track == track
+        && let MtxtRecord::ControlChange {
+            controller,
+            value,
+            channel: cc_channel,
+            note: None,
+            ..
+        } = &event.record.record
+        && *cc_channel == channel
+    {
+        let value = Some((value * 127.0).round() as u16);
+        match controller.as_str() {
+            "0" if msb.is_none() => msb = value,
+            "32" if lsb.is_none() => lsb = value,
+            _ => break,
+        }
+        events.pop();
+    }
+
+    if msb.is_none() && lsb.is_none() {
+        return None;
+    }
+    Some(msb.unwrap_or(0) << 7 | lsb.unwrap_or(0))
+}
+
+// midly omits the leading F0; complete messages get it back so they read as framed SysEx.
+// Split packets without a trailing F7 are kept as-is.
+fn sysex_from_midi(data: &[u8]) -> Vec<u8> {
+    if data.last() == Some(&0xF7) {
+        let mut framed = Vec::with_capacity(data.len() + 1);
+        framed.push(0xF0);
+        framed.extend_from_slice(data);
+        framed
+    } else {
+        data.to_vec()
+    }
+}
+
+fn convert_smf_to_mtxt(smf: &Smf, preserve_tracks: bool, detect_tuning: bool) -> Result<MtxtFile> {
     let mut mtxt_file = MtxtFile::new();
     mtxt_file
         .records
@@ -128,7 +283,7 @@ fn convert_smf_to_mtxt(smf: &Smf) -> Result<MtxtFile> {
             version: Version { major: 1, minor: 0 },
         }));
 
-    let all_events = get_midi_single_track_events(smf)?;
+    let mut all_events = get_midi_single_track_events(smf)?;
 
     // Collect used drum aliases
     let mut used_drum_aliases = std::collections::HashSet::new();
@@ -149,26 +304,24 @@ fn convert_smf_to_mtxt(smf: &Smf) -> Result<MtxtFile> {
     }
 
     for drum in DRUMS.iter() {
-        if used_drum_aliases.contains(drum.slug) {
-            if let Ok(note) = midi_key_to_note(drum.number.into()) {
-                mtxt_file
-                    .records
-                    .push(MtxtRecordLine::new(MtxtRecord::AliasDef {
-                        value: Rc::new(AliasDefinition {
-                            name: drum.slug.to_string(),
-                            notes: vec![note],
-                        }),
-                    }));
-            }
+        if used_drum_aliases.contains(drum.slug)
+            && let Ok(note) = midi_key_to_note(drum.number)
+        {
+            mtxt_file
+                .records
+                .push(MtxtRecordLine::new(MtxtRecord::AliasDef {
+                    value: Arc::new(AliasDefinition {
+                        name: drum.slug.to_string(),
+                        notes: vec![note],
+                    }),
+                }));
         }
     }
-    let mut final_events: Vec<MtxtRecordLine> =
-        all_events.into_iter().map(|event| event.record).collect();
 
     // Sort final events to ensure None/GlobalMeta come first
-    final_events.sort_by(|a_line, b_line| {
-        let a = &a_line.record;
-        let b = &b_line.record;
+    all_events.sort_by(|a_event, b_event| {
+        let a = &a_event.record.record;
+        let b = &b_event.record.record;
 
         // Helper to get sort key: (order_group, time)
         // order_group: 0=GlobalMeta, 1=Meta(None), 2=Other
@@ -186,6 +339,7 @@ fn convert_smf_to_mtxt(smf: &Smf) -> Result<MtxtFile> {
                 | MtxtRecord::Voice { time, .. }
                 | MtxtRecord::Tempo { time, .. }
                 | MtxtRecord::TimeSignature { time, .. }
+                | MtxtRecord::Bar { time, .. }
                 | MtxtRecord::SysEx { time, .. } => (2, *time),
                 _ => (2, BeatTime::zero()),
             }
@@ -198,11 +352,35 @@ fn convert_smf_to_mtxt(smf: &Smf) -> Result<MtxtFile> {
             return group_a.cmp(&group_b);
         }
 
+        // timed events stay grouped by track
+        if preserve_tracks && group_a == 2 && a_event.track != b_event.track {
+            return a_event.track.cmp(&b_event.track);
+        }
+
         time_a.cmp(&time_b)
     });
 
+    let mut final_events: Vec<MtxtRecordLine> = Vec::with_capacity(all_events.len());
+    let mut current_track = None;
+    for event in all_events {
+        if preserve_tracks
+            && event.record.record.time().is_some()
+            && current_track != Some(event.track)
+        {
+            current_track = Some(event.track);
+            final_events.push(MtxtRecordLine::new(MtxtRecord::Comment {
+                text: format!("--- track {} ---", event.track),
+            }));
+        }
+        final_events.push(event.record);
+    }
+
+    if detect_tuning {
+        final_events = fold_constant_pitch_bends(final_events);
+    }
+
     final_events = extract::transform(&final_events);
-    final_events = merge::transform(&final_events);
+    final_events = merge::transform(&final_events, MergePolicy::Lifo);
 
     for line in final_events {
         mtxt_file.records.push(line);
@@ -211,10 +389,86 @@ fn convert_smf_to_mtxt(smf: &Smf) -> Result<MtxtFile> {
     Ok(mtxt_file)
 }
 
+// Replaces the pitch bends of each channel detuned by one steady bend with tuning
+// records, see `convert_midi_to_mtxt_detect_tuning`. Events still carry their channel.
+fn fold_constant_pitch_bends(events: Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine> {
+    let pitch = controller_name(ControllerMessage::PitchBend);
+    // per channel: index, time and value of each pitch bend
+    let mut bends: BTreeMap<u16, Vec<(usize, BeatTime, f32)>> = BTreeMap::new();
+    let mut first_note: HashMap<u16, BeatTime> = HashMap::new();
+    for (index, line) in events.iter().enumerate() {
+        match &line.record {
+            MtxtRecord::ControlChange {
+                time,
+                note: None,
+                controller,
+                value,
+                channel: Some(channel),
+                ..
+            } if *controller == pitch => {
+                bends
+                    .entry(*channel)
+                    .or_default()
+                    .push((index, *time, *value));
+            }
+            MtxtRecord::NoteOn {
+                time,
+                channel: Some(channel),
+                ..
+            } => {
+                first_note.entry(*channel).or_insert(*time);
+            }
+            _ => {}
+        }
+    }
+
+    // first bend index -> tuning records replacing it, and the bends to drop
+    let mut tunings: HashMap<usize, Vec<MtxtRecordLine>> = HashMap::new();
+    let mut dropped = HashSet::new();
+    for (channel, bends) in bends {
+        // events are in time order, the first bend is the earliest
+        let (first_index, start, value) = bends[0];
+        let cents = value * 100.0;
+        if cents == 0.0
+            || cents.abs() > 100.0
+            || bends.iter().any(|(_, _, other)| *other != value)
+            || first_note.get(&channel).is_some_and(|note| *note < start)
+        {
+            continue;
+        }
+
+        let records = PitchClass::all()
+            .map(|pitch_class| {
+                MtxtRecordLine::new(MtxtRecord::Tuning {
+                    time: start,
+                    target: pitch_class.to_string(),
+                    cents,
+                    channel: Some(channel),
+                })
+            })
+            .collect();
+        tunings.insert(first_index, records);
+        dropped.extend(bends.iter().map(|(index, _, _)| *index));
+    }
+
+    let mut folded = Vec::with_capacity(events.len());
+    for (index, line) in events.into_iter().enumerate() {
+        if let Some(records) = tunings.remove(&index) {
+            folded.extend(records);
+        }
+        if !dropped.contains(&index) {
+            folded.push(line);
+        }
+    }
+    folded
+}
+
+/// `pitch_bend_range` is the range in semitones of a pitch bend on `channel`.
 fn convert_midi_message_to_record(
     msg: &MidiMessage,
     channel: u16,
     beat_time: BeatTime,
+    pitch_bend_range: f32,
 ) -> Result<MtxtRecord> {
     match msg {
         MidiMessage::NoteOn { key, vel } => {
@@ -239,12 +493,13 @@ fn convert_midi_message_to_record(
             }
 
             let velocity = int_vel as f32 / 127.0;
-            return Ok(MtxtRecord::NoteOn {
+            Ok(MtxtRecord::NoteOn {
                 time: beat_time,
                 note: note_target,
                 velocity: Some(velocity),
                 channel: Some(channel),
-            });
+                length: None,
+            })
         }
         MidiMessage::NoteOff { key, vel } => {
             let note_target = if channel == 9 {
@@ -259,18 +514,18 @@ fn convert_midi_message_to_record(
 
             let off_velocity = vel.as_int() as f32 / 127.0;
 
-            return Ok(MtxtRecord::NoteOff {
+            Ok(MtxtRecord::NoteOff {
                 time: beat_time,
                 note: note_target,
                 off_velocity: Some(off_velocity),
                 channel: Some(channel),
-            });
+            })
         }
         MidiMessage::Controller { controller, value } => {
             let controller_name = midi_cc_to_name(controller.as_int());
             let mtxt_value = value.as_int() as f32 / 127.0;
 
-            return Ok(MtxtRecord::ControlChange {
+            Ok(MtxtRecord::ControlChange {
                 time: beat_time,
                 note: None,
                 controller: controller_name,
@@ -279,7 +534,8 @@ fn convert_midi_message_to_record(
                 transition_curve: None,
                 transition_time: None,
                 transition_interval: None,
-            });
+                transition_steps: None,
+            })
         }
         MidiMessage::ProgramChange { program } => {
             let prog_num = program.as_int();
@@ -292,40 +548,55 @@ fn convert_midi_message_to_record(
                 voice_names.push(prog_num.to_string());
             }
 
-            return Ok(MtxtRecord::Voice {
+            Ok(MtxtRecord::Voice {
                 time: beat_time,
                 voices: VoiceList {
                     voices: voice_names,
                 },
                 channel: Some(channel),
-            });
+                bank: None,
+            })
         }
         MidiMessage::PitchBend { bend } => {
-            let bend_value = (bend.as_int() as f32 - 8192.0) / 8192.0 * 12.0;
+            let bend_value = pitch_bend_to_semitones(bend.0.as_int(), pitch_bend_range);
 
-            return Ok(MtxtRecord::ControlChange {
+            Ok(MtxtRecord::ControlChange {
                 time: beat_time,
                 note: None,
-                controller: "pitch".to_string(),
+                controller: controller_name(ControllerMessage::PitchBend),
                 value: bend_value,
                 channel: Some(channel),
                 transition_curve: None,
                 transition_time: None,
                 transition_interval: None,
-            });
+                transition_steps: None,
+            })
         }
-        MidiMessage::Aftertouch { key: _, vel } | MidiMessage::ChannelAftertouch { vel } => {
+        // polyphonic key pressure is a per-note aftertouch, channel pressure has no note
+        MidiMessage::Aftertouch { key, vel } => Ok(MtxtRecord::ControlChange {
+            time: beat_time,
+            note: Some(NoteTarget::Note(midi_key_to_note(key.as_int())?)),
+            controller: controller_name(ControllerMessage::Aftertouch),
+            value: vel.as_int() as f32 / 127.0,
+            channel: Some(channel),
+            transition_curve: None,
+            transition_time: None,
+            transition_interval: None,
+            transition_steps: None,
+        }),
+        MidiMessage::ChannelAftertouch { vel } => {
             let value = vel.as_int() as f32 / 127.0;
-            return Ok(MtxtRecord::ControlChange {
+            Ok(MtxtRecord::ControlChange {
                 time: beat_time,
                 note: None,
-                controller: "aftertouch".to_string(),
+                controller: controller_name(ControllerMessage::Aftertouch),
                 value,
                 channel: Some(channel),
                 transition_curve: None,
                 transition_time: None,
                 transition_interval: None,
-            });
+                transition_steps: None,
+            })
         }
     }
 }
@@ -346,6 +617,7 @@ fn convert_meta_message(
                 transition_curve: None,
                 transition_time: None,
                 transition_interval: None,
+                transition_steps: None,
             }))
         }
         MetaMessage::TimeSignature(num, den, _clocks, _bb) => {
@@ -423,6 +695,16 @@ fn convert_meta_message(
             }))
         }
         MetaMessage::Marker(text) => {
+            // markers written for `bar` events read back as bars
+            if let Some(number) = String::from_utf8_lossy(text)
+                .strip_prefix("Bar ")
+                .and_then(|number| number.parse().ok())
+            {
+                return Ok(Some(MtxtRecord::Bar {
+                    time: beat_time,
+                    number,
+                }));
+            }
             let value = escape_string(&String::from_utf8_lossy(text));
             Ok(Some(MtxtRecord::Meta {
                 time: Some(beat_time),
@@ -478,21 +760,10 @@ fn convert_meta_message(
             meta_type: "midiport".to_string(),
             value: port.as_int().to_string(),
         })),
-        MetaMessage::SmpteOffset(smpte) => {
-            // HH:MM:SS:FF (Hours:Minutes:Seconds:Frames)
-            let value = format!(
-                "{:02}:{:02}:{:02}:{:02}",
-                smpte.hour(),
-                smpte.minute(),
-                smpte.second(),
-                smpte.frame()
-            );
-
-            Ok(Some(MtxtRecord::GlobalMeta {
-                meta_type: "smpte".to_string(),
-                value,
-            }))
-        }
+        MetaMessage::SmpteOffset(smpte) => Ok(Some(MtxtRecord::GlobalMeta {
+            meta_type: "smpte".to_string(),
+            value: midi_smpte_to_string(smpte),
+        })),
         MetaMessage::KeySignature(sharps_flats, minor) => {
             let value = midi_key_signature_to_string(*sharps_flats, *minor);
 
@@ -543,3 +814,118 @@ fn convert_meta_message(
         MetaMessage::EndOfTrack => Ok(None),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::num::{u4, u7, u14, u15, u24, u28};
+    use midly::{Header, TrackEvent};
+
+    fn two_track_midi() -> Vec<u8> {
+        let event = |delta: u32, kind| TrackEvent {
+            delta: u28::new(delta),
+            kind,
+        };
+        let note_on = |channel: u8, key: u8, vel: u8| TrackEventKind::Midi {
+            channel: u4::new(channel),
+            message: MidiMessage::NoteOn {
+                key: u7::new(key),
+                vel: u7::new(vel),
+            },
+        };
+        let end = TrackEventKind::Meta(MetaMessage::EndOfTrack);
+
+        let smf = Smf {
+            header: Header::new(Format::Parallel, Timing::Metrical(u15::new(480))),
+            tracks: vec![
+                vec![
+                    event(
+                        0,
+                        TrackEventKind::Meta(MetaMessage::Tempo(u24::new(500_000))),
+                    ),
+                    event(0, note_on(0, 60, 100)),
+                    event(480, note_on(0, 60, 0)),
+                    event(0, end),
+                ],
+                vec![
+                    event(0, note_on(1, 64, 100)),
+                    event(480, note_on(1, 64, 0)),
+                    event(0, end),
+                ],
+            ],
+        };
+
+        let mut bytes = Vec::new();
+        smf.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_preserve_tracks() {
+        let bytes = two_track_midi();
+
+        let output = convert_midi_to_mtxt_preserve_tracks(&bytes)
+            .unwrap()
+            .to_string();
+        let track_0 = output.find("// --- track 0 ---").unwrap();
+        let track_1 = output.find("// --- track 1 ---").unwrap();
+        let e4 = output.find("note E4").unwrap();
+        assert!(track_0 < output.find("tempo 120").unwrap());
+        assert!(output.find("note C4").unwrap() < track_1);
+        assert!(track_1 < e4);
+
+        let merged = convert_midi_to_mtxt(&bytes).unwrap().to_string();
+        assert!(!merged.contains("--- track"));
+    }
+
+    #[test]
+    fn test_detect_tuning() {
+        let event = |delta: u32, channel: u8, message| TrackEvent {
+            delta: u28::new(delta),
+            kind: TrackEventKind::Midi {
+                channel: u4::new(channel),
+                message,
+            },
+        };
+        let bend = |value: u16| MidiMessage::PitchBend {
+            bend: midly::PitchBend(u14::new(value)),
+        };
+        let note = |key: u8, vel: u8| MidiMessage::NoteOn {
+            key: u7::new(key),
+            vel: u7::new(vel),
+        };
+        // channel 0 steady at +50 cents, channel 1 bends the note
+        let smf = Smf {
+            header: Header::new(Format::SingleTrack, Timing::Metrical(u15::new(480))),
+            tracks: vec![vec![
+                event(0, 0, bend(0x2800)),
+                event(0, 1, bend(0x2000)),
+                event(0, 0, note(60, 100)),
+                event(0, 1, note(64, 100)),
+                event(240, 0, bend(0x2800)),
+                event(0, 1, bend(0x3000)),
+                event(240, 0, note(60, 0)),
+                event(0, 1, note(64, 0)),
+                TrackEvent {
+                    delta: u28::new(0),
+                    kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+                },
+            ]],
+        };
+        let mut bytes = Vec::new();
+        smf.write(&mut bytes).unwrap();
+
+        let output = convert_midi_to_mtxt_detect_tuning(&bytes)
+            .unwrap()
+            .to_string();
+        assert!(output.contains("0.0 tuning ch=0 C +50.0\n"), "{}", output);
+        assert!(output.contains("0.0 tuning ch=0 B +50.0\n"));
+        assert_eq!(output.matches("tuning").count(), 12);
+        assert_eq!(output.matches("cc pitch").count(), 2);
+        assert!(!output.contains("tuning ch=1"));
+
+        let plain = convert_midi_to_mtxt(&bytes).unwrap().to_string();
+        assert!(!plain.contains("tuning"));
+        assert_eq!(plain.matches("cc pitch").count(), 4);
+    }
+}
@@ -5,7 +5,13 @@ mod midi_to_mtxt;
 mod mtxt_to_midi;
 pub mod shared;
 
-pub use midi_to_mtxt::convert_midi_to_mtxt;
-pub use mtxt_to_midi::{convert_mtxt_to_midi, convert_mtxt_to_midi_bytes};
+pub use midi_to_mtxt::{
+    convert_midi_to_mtxt, convert_midi_to_mtxt_detect_tuning, convert_midi_to_mtxt_preserve_tracks,
+};
+pub use mtxt_to_midi::{
+    MidiExportOptions, convert_mtxt_to_midi, convert_mtxt_to_midi_bytes,
+    convert_mtxt_to_midi_bytes_with, convert_mtxt_to_midi_channel_wrap,
+    convert_mtxt_to_midi_multitrack, convert_mtxt_to_midi_with,
+};
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
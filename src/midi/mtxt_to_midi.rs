@@ -1,45 +1,126 @@
 use crate::file::MtxtFile;
+use crate::process::{MIDI_CHANNEL_COUNT, ProcessOptions, TuningMode};
 use crate::types::output_record::MtxtOutputRecord;
 use crate::types::record::VoiceList;
 use anyhow::{Result, bail};
 use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use std::collections::{BTreeSet, HashMap};
 
 use super::escape::unescape_string;
 use super::instruments::INSTRUMENTS;
 use super::shared::{
-    MidiControllerEvent, controller_name_to_midi, note_to_midi_number, time_signature_to_midi,
+    ControllerMessage, DEFAULT_PITCH_BEND_RANGE, MidiControllerEvent, controller_message,
+    controller_name, controller_name_to_midi, note_to_midi_number, reset_target_to_midi,
+    string_to_midi_key_signature, string_to_midi_smpte, time_signature_to_midi,
 };
 
+/// Options of `convert_mtxt_to_midi_with`, the default matches `convert_mtxt_to_midi`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MidiExportOptions {
+    /// Map channels above 15 to `ch % 16` instead of rejecting them.
+    pub channel_wrap: bool,
+    /// Write a format 1 file, see `convert_mtxt_to_midi_multitrack`.
+    pub multitrack: bool,
+    /// `Mts` sends `tuning` events as MIDI Tuning Standard SysEx instead of bending
+    /// the tuned notes. MTS only addresses channels 0-15.
+    pub tuning_mode: TuningMode,
+    /// Write the status byte of every channel message. By default a message with the
+    /// same status as the one before it leaves it out (running status), which some
+    /// hardware doesn't read.
+    pub no_running_status: bool,
+}
+
 pub fn convert_mtxt_to_midi(mtxt_file: &MtxtFile) -> Result<Vec<u8>> {
-    let mut output_records = mtxt_file.get_output_records();
-    let smf = convert_output_records_to_midi(&mut output_records)?;
+    convert_mtxt_to_midi_with(mtxt_file, &MidiExportOptions::default())
+}
 
-    let mut buffer = Vec::new();
-    smf.write(&mut buffer)
-        .map_err(|e| anyhow::anyhow!("{}", e))?;
+/// Like `convert_mtxt_to_midi`, but channels above 15 are mapped to `ch % 16`
+/// instead of being rejected.
+pub fn convert_mtxt_to_midi_channel_wrap(mtxt_file: &MtxtFile) -> Result<Vec<u8>> {
+    let options = MidiExportOptions {
+        channel_wrap: true,
+        ..MidiExportOptions::default()
+    };
+    convert_mtxt_to_midi_with(mtxt_file, &options)
+}
 
-    Ok(buffer)
+/// Writes a format 1 MIDI file: a conductor track with tempo, time signature and other
+/// events without a channel, then one track per channel in channel order.
+/// `meta global title` names the conductor track and a channel's `name` meta names its track.
+pub fn convert_mtxt_to_midi_multitrack(mtxt_file: &MtxtFile) -> Result<Vec<u8>> {
+    let options = MidiExportOptions {
+        multitrack: true,
+        ..MidiExportOptions::default()
+    };
+    convert_mtxt_to_midi_with(mtxt_file, &options)
+}
+
+pub fn convert_mtxt_to_midi_with(
+    mtxt_file: &MtxtFile,
+    options: &MidiExportOptions,
+) -> Result<Vec<u8>> {
+    convert_mtxt_to_midi_bytes_with(mtxt_file, false, options)
 }
 
 pub fn convert_mtxt_to_midi_bytes(mtxt_file: &MtxtFile, verbose: bool) -> Result<Vec<u8>> {
+    convert_mtxt_to_midi_bytes_with(mtxt_file, verbose, &MidiExportOptions::default())
+}
+
+/// Like `convert_mtxt_to_midi_with`, printing progress and warnings when `verbose`.
+pub fn convert_mtxt_to_midi_bytes_with(
+    mtxt_file: &MtxtFile,
+    verbose: bool,
+    options: &MidiExportOptions,
+) -> Result<Vec<u8>> {
     if verbose {
         println!("Converting to MIDI...");
     }
 
-    let mut output_records = mtxt_file.get_output_records();
+    let pitch_bend_range = mtxt_file.pitch_bend_range();
+    let mut output_records = mtxt_file.get_output_records_with(&ProcessOptions {
+        tuning_mode: options.tuning_mode,
+        pitch_bend_range: pitch_bend_range.unwrap_or(DEFAULT_PITCH_BEND_RANGE),
+        ..ProcessOptions::default()
+    });
 
     if verbose {
         println!("Processing {} output records", output_records.len());
+
+        for record in &output_records {
+            if let MtxtOutputRecord::Reset { target, .. } = record
+                && reset_target_to_midi(target).is_none()
+            {
+                println!(
+                    "Warning: skipping \"reset {}\", it has no MIDI equivalent",
+                    target
+                );
+            }
+        }
     }
 
-    let smf = convert_output_records_to_midi(&mut output_records)?;
+    prepare_channels(&mut output_records, options.channel_wrap)?;
+    if let Some(range) = pitch_bend_range {
+        insert_pitch_bend_range(&mut output_records, range);
+    }
+    let mut output_records = insert_cents_bends(output_records);
+    let raw_metas = decode_raw_metas(&output_records)?;
+    let smf = convert_output_records_to_midi(
+        &mut output_records,
+        &raw_metas,
+        options.multitrack,
+        pitch_bend_range.unwrap_or(DEFAULT_PITCH_BEND_RANGE),
+    )?;
 
     if verbose {
         println!("Writing MIDI to bytes...");
     }
 
+    // midly always writes with running status
     let mut buffer = Vec::new();
     smf.write(&mut buffer).map_err(|e| anyhow::anyhow!("Failed to write MIDI: {}", e))?;
+    if options.no_running_status {
+        buffer = write_full_status(&buffer)?;
+    }
 
     if verbose {
         println!("Conversion completed successfully! ({} bytes)", buffer.len());
@@ -48,6 +129,190 @@ pub fn convert_mtxt_to_midi_bytes(mtxt_file: &MtxtFile, verbose: bool) -> Result
     Ok(buffer)
 }
 
+/// Copies a MIDI file, putting back the status bytes running status left out of its
+/// tracks.
+fn write_full_status(smf: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(smf.len() * 3 / 2);
+    let mut pos = 0;
+    while pos < smf.len() {
+        let header = smf
+            .get(pos..pos + 8)
+            .ok_or_else(|| anyhow::anyhow!("Truncated MIDI chunk"))?;
+        let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let data = smf
+            .get(pos + 8..pos + 8 + len)
+            .ok_or_else(|| anyhow::anyhow!("Truncated MIDI chunk"))?;
+        let data = if &header[..4] == b"MTrk" {
+            &track_with_full_status(data)?
+        } else {
+            data
+        };
+        out.extend(&header[..4]);
+        out.extend((data.len() as u32).to_be_bytes());
+        out.extend(data);
+        pos += 8 + len;
+    }
+    Ok(out)
+}
+
+fn track_with_full_status(track: &[u8]) -> Result<Vec<u8>> {
+    let truncated = || anyhow::anyhow!("Truncated MIDI track");
+    let mut out = Vec::with_capacity(track.len() * 3 / 2);
+    let mut running_status = None;
+    let mut pos = 0;
+    while pos < track.len() {
+        // delta time
+        let delta_start = pos;
+        read_varlen(track, &mut pos).ok_or_else(truncated)?;
+        out.extend(&track[delta_start..pos]);
+
+        let byte = *track.get(pos).ok_or_else(truncated)?;
+        let status = if byte < 0x80 {
+            running_status.ok_or_else(|| anyhow::anyhow!("MIDI data byte without a status"))?
+        } else {
+            pos += 1;
+            byte
+        };
+        out.push(status);
+
+        let data_start = pos;
+        match status {
+            0x80..=0xEF => {
+                running_status = Some(status);
+                // program change and channel aftertouch have one data byte
+                pos += match status & 0xF0 {
+                    0xC0 | 0xD0 => 1,
+                    _ => 2,
+                };
+            }
+            // meta: type, then length and data like SysEx
+            0xFF => {
+                running_status = None;
+                pos += 1;
+                let len = read_varlen(track, &mut pos).ok_or_else(truncated)?;
+                pos += len;
+            }
+            0xF0 | 0xF7 => {
+                running_status = None;
+                let len = read_varlen(track, &mut pos).ok_or_else(truncated)?;
+                pos += len;
+            }
+            _ => bail!("Unexpected MIDI status byte {:#04X}", status),
+        }
+        out.extend(track.get(data_start..pos).ok_or_else(truncated)?);
+    }
+    Ok(out)
+}
+
+// variable-length quantity at `pos`, moving `pos` past it
+fn read_varlen(data: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut value = 0usize;
+    loop {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value = (value << 7) | (byte & 0x7F) as usize;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+/// Wraps channels into MIDI range, or checks them all before writing anything so the
+/// error lists every channel MIDI cannot represent.
+fn prepare_channels(records: &mut [MtxtOutputRecord], channel_wrap: bool) -> Result<()> {
+    if channel_wrap {
+        for channel in records.iter_mut().filter_map(|record| record.channel_mut()) {
+            *channel %= MIDI_CHANNEL_COUNT;
+        }
+        return Ok(());
+    }
+
+    let mut out_of_range: Vec<u16> = records
+        .iter()
+        .filter_map(|record| record.channel())
+        .filter(|channel| *channel >= MIDI_CHANNEL_COUNT)
+        .collect();
+    out_of_range.sort_unstable();
+    out_of_range.dedup();
+
+    if !out_of_range.is_empty() {
+        let channels: Vec<String> = out_of_range.iter().map(|ch| ch.to_string()).collect();
+        bail!(
+            "Channels out of range for MIDI (0-15): {}. Enable channel wrap to map them to ch % 16",
+            channels.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Sends the pitch bend range as RPN 0 (pitch bend sensitivity) at the start of every
+/// channel in use: CC 101 and 100 select it, then CC 6 sets the semitones and CC 38 the
+/// cents.
+fn insert_pitch_bend_range(records: &mut Vec<MtxtOutputRecord>, range: f32) {
+    let channels: BTreeSet<u16> = records
+        .iter()
+        .filter_map(|record| record.channel())
+        .collect();
+    let cents = (range * 100.0).round() as u32;
+    let data = [
+        ("101", 0),
+        ("100", 0),
+        ("6", (cents / 100).min(127)),
+        ("38", cents % 100),
+    ];
+    let rpn = channels.into_iter().flat_map(|channel| {
+        data.map(|(controller, value)| MtxtOutputRecord::ControlChange {
+            time: 0,
+            note: None,
+            controller: controller.to_string(),
+            value: value as f32 / 127.0,
+            channel,
+        })
+    });
+    records.splice(0..0, rpn);
+}
+
+/// MIDI notes have no cents, a note with cents is bent into tune by a pitch bend on its
+/// channel just before it. The bend is reset before the next note without cents, unless
+/// a `pitch` event took over the channel's bend in between. Notes playing at the same
+/// time on a channel share its bend.
+fn insert_cents_bends(records: Vec<MtxtOutputRecord>) -> Vec<MtxtOutputRecord> {
+    // cents the bend of each channel was last set to for a note
+    let mut bends: HashMap<u16, f32> = HashMap::new();
+    let mut new_records = Vec::with_capacity(records.len());
+
+    for record in records {
+        match &record {
+            MtxtOutputRecord::NoteOn {
+                time,
+                note,
+                channel,
+                ..
+            } if bends.get(channel).copied().unwrap_or(0.0) != note.cents => {
+                new_records.push(MtxtOutputRecord::ControlChange {
+                    time: *time,
+                    note: None,
+                    controller: controller_name(ControllerMessage::PitchBend),
+                    value: note.cents / 100.0,
+                    channel: *channel,
+                });
+                bends.insert(*channel, note.cents);
+            }
+            MtxtOutputRecord::ControlChange {
+                controller,
+                channel,
+                ..
+            } if controller_message(controller) == Some(ControllerMessage::PitchBend) => {
+                bends.remove(channel);
+            }
+            _ => {}
+        }
+        new_records.push(record);
+    }
+
+    new_records
+}
+
 fn voice_to_program_change(voice: &VoiceList) -> u8 {
     for voice in voice.voices.iter().rev() {
         let voice_lower = voice.to_lowercase();
@@ -65,10 +330,49 @@ fn voice_to_program_change(voice: &VoiceList) -> u8 {
     0
 }
 
-fn record_to_track_event(
-    record: &mut MtxtOutputRecord,
+/// Meta type byte of `sequencerspecific` and `unknown_XX` metas. MIDI import writes
+/// their data as hex, export restores the original bytes instead of writing text.
+fn raw_meta_type(meta_type: &str) -> Option<u8> {
+    match meta_type {
+        "sequencerspecific" => Some(0x7F),
+        _ => u8::from_str_radix(meta_type.strip_prefix("unknown_")?, 16).ok(),
+    }
+}
+
+/// Decodes the hex data of raw metas up front, the MIDI events borrow it.
+/// Entries line up with `records`, `None` for everything else.
+fn decode_raw_metas(records: &[MtxtOutputRecord]) -> Result<Vec<Option<Vec<u8>>>> {
+    records
+        .iter()
+        .map(|record| match record {
+            MtxtOutputRecord::GlobalMeta {
+                meta_type, value, ..
+            }
+            | MtxtOutputRecord::ChannelMeta {
+                meta_type, value, ..
+            } if raw_meta_type(meta_type).is_some() => {
+                let invalid =
+                    || anyhow::anyhow!("Invalid hex data in {} meta: {}", meta_type, value);
+                if value.len() % 2 != 0 || !value.is_ascii() {
+                    return Err(invalid());
+                }
+                (0..value.len())
+                    .step_by(2)
+                    .map(|idx| u8::from_str_radix(&value[idx..idx + 2], 16).map_err(|_| invalid()))
+                    .collect::<Result<Vec<u8>>>()
+                    .map(Some)
+            }
+            _ => Ok(None),
+        })
+        .collect()
+}
+
+fn record_to_track_event<'a>(
+    record: &'a mut MtxtOutputRecord,
+    raw_meta: Option<&'a [u8]>,
     delta_tick: u32,
-) -> Result<Option<TrackEvent<'_>>> {
+    pitch_bend_range: f32,
+) -> Result<Option<TrackEvent<'a>>> {
     match record {
         MtxtOutputRecord::NoteOn {
             note,
@@ -119,6 +423,7 @@ fn record_to_track_event(
             }))
         }
         MtxtOutputRecord::ControlChange {
+            note,
             controller,
             value,
             channel,
@@ -129,7 +434,7 @@ fn record_to_track_event(
             }
             let ch = *channel as u8;
 
-            match controller_name_to_midi(controller, *value)? {
+            match controller_name_to_midi(controller, *value, pitch_bend_range)? {
                 MidiControllerEvent::CC { number, value } => Ok(Some(TrackEvent {
                     delta: midly::num::u28::new(delta_tick),
                     kind: TrackEventKind::Midi {
@@ -149,15 +454,24 @@ fn record_to_track_event(
                         },
                     },
                 })),
-                MidiControllerEvent::Aftertouch { value } => Ok(Some(TrackEvent {
-                    delta: midly::num::u28::new(delta_tick),
-                    kind: TrackEventKind::Midi {
-                        channel: midly::num::u4::new(ch),
-                        message: MidiMessage::ChannelAftertouch {
+                MidiControllerEvent::Aftertouch { value } => {
+                    let message = match note {
+                        Some(note) => MidiMessage::Aftertouch {
+                            key: midly::num::u7::new(note_to_midi_number(note)?),
                             vel: midly::num::u7::new(value),
                         },
-                    },
-                })),
+                        None => MidiMessage::ChannelAftertouch {
+                            vel: midly::num::u7::new(value),
+                        },
+                    };
+                    Ok(Some(TrackEvent {
+                        delta: midly::num::u28::new(delta_tick),
+                        kind: TrackEventKind::Midi {
+                            channel: midly::num::u4::new(ch),
+                            message,
+                        },
+                    }))
+                }
             }
         }
         MtxtOutputRecord::Voice {
@@ -208,10 +522,34 @@ fn record_to_track_event(
                 )),
             }))
         }
-        MtxtOutputRecord::Reset { .. } => {
-            // Reset events don't have a direct MIDI equivalent
-            // Could send All Notes Off (CC 123) or All Sound Off (CC 120)
-            // For now, just skip it
+        MtxtOutputRecord::Reset {
+            target, channel, ..
+        } => {
+            let Some(controller) = reset_target_to_midi(target) else {
+                return Ok(None);
+            };
+
+            if *channel > 15 {
+                bail!("Channel {} out of range for MIDI", *channel);
+            }
+            let ch = *channel as u8;
+
+            Ok(Some(TrackEvent {
+                delta: midly::num::u28::new(delta_tick),
+                kind: TrackEventKind::Midi {
+                    channel: midly::num::u4::new(ch),
+                    message: MidiMessage::Controller {
+                        controller: midly::num::u7::new(controller),
+                        value: midly::num::u7::new(0),
+                    },
+                },
+            }))
+        }
+        // the pickup only affects bar numbering, MIDI has no equivalent, and the bend
+        // range went out as RPN 0 on every channel
+        MtxtOutputRecord::GlobalMeta { meta_type, .. }
+            if matches!(meta_type.as_str(), "pickup" | "pitch_bend_range") =>
+        {
             Ok(None)
         }
         MtxtOutputRecord::GlobalMeta {
@@ -220,7 +558,40 @@ fn record_to_track_event(
         | MtxtOutputRecord::ChannelMeta {
             meta_type, value, ..
         } => {
-            *value = unescape_string(value);
+            if let Some(type_byte) = raw_meta_type(meta_type)
+                && let Some(data) = raw_meta
+            {
+                let kind = match type_byte {
+                    0x7F => MetaMessage::SequencerSpecific(data),
+                    _ => MetaMessage::Unknown(type_byte, data),
+                };
+                return Ok(Some(TrackEvent {
+                    delta: midly::num::u28::new(delta_tick),
+                    kind: TrackEventKind::Meta(kind),
+                }));
+            }
+
+            if meta_type == "smpte" {
+                let smpte = string_to_midi_smpte(value)?;
+                return Ok(Some(TrackEvent {
+                    delta: midly::num::u28::new(delta_tick),
+                    kind: TrackEventKind::Meta(MetaMessage::SmpteOffset(smpte)),
+                }));
+            }
+
+            // values that don't parse as a key are kept as text
+            if matches!(meta_type.as_str(), "key" | "keysignature")
+                && let Some((sharps_flats, minor)) = string_to_midi_key_signature(value)
+            {
+                return Ok(Some(TrackEvent {
+                    delta: midly::num::u28::new(delta_tick),
+                    kind: TrackEventKind::Meta(MetaMessage::KeySignature(sharps_flats, minor)),
+                }));
+            }
+
+            if value.contains('\\') {
+                *value = unescape_string(value);
+            }
             let meta_bytes = value.as_bytes();
             let kind = match meta_type.as_str() {
                 "copyright" => MetaMessage::Copyright(meta_bytes),
@@ -240,66 +611,733 @@ fn record_to_track_event(
             }))
         }
         MtxtOutputRecord::Beat { .. } => Ok(None),
-        MtxtOutputRecord::SysEx { data, .. } => Ok(Some(TrackEvent {
-            delta: midly::num::u28::new(delta_tick),
-            kind: TrackEventKind::SysEx(data),
-        })),
+        MtxtOutputRecord::SysEx { data, .. } => {
+            // midly writes the F0 status byte itself, the data must only carry the rest
+            if data.first() == Some(&0xF0) {
+                data.remove(0);
+            }
+
+            Ok(Some(TrackEvent {
+                delta: midly::num::u28::new(delta_tick),
+                kind: TrackEventKind::SysEx(data),
+            }))
+        }
+    }
+}
+
+/// Events of one output track, `last_tick` is the absolute tick of the last written event.
+#[derive(Default)]
+struct TrackWriter<'a> {
+    events: Vec<TrackEvent<'a>>,
+    last_tick: u64,
+}
+
+impl<'a> TrackWriter<'a> {
+    fn write(
+        &mut self,
+        tick: u64,
+        record: &'a mut MtxtOutputRecord,
+        raw_meta: Option<&'a [u8]>,
+        pitch_bend_range: f32,
+    ) -> Result<()> {
+        let max_delta = midly::num::u28::max_value().as_int() as u64;
+        let mut delta_tick = tick - self.last_tick;
+
+        while delta_tick > max_delta {
+            self.events.push(TrackEvent {
+                delta: midly::num::u28::max_value(),
+                kind: TrackEventKind::Meta(MetaMessage::Text(b"long delta")),
+            });
+            delta_tick -= max_delta;
+            self.last_tick += max_delta;
+        }
+
+        // records without a MIDI event leave their delta to the next event
+        if let Some(event) =
+            record_to_track_event(record, raw_meta, delta_tick as u32, pitch_bend_range)?
+        {
+            self.events.push(event);
+            self.last_tick = tick;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<TrackEvent<'a>> {
+        self.events.push(TrackEvent {
+            delta: midly::num::u28::new(0),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        self.events
     }
 }
 
-fn convert_output_records_to_midi(records: &mut [MtxtOutputRecord]) -> Result<Smf<'_>> {
+fn convert_output_records_to_midi<'a>(
+    records: &'a mut [MtxtOutputRecord],
+    raw_metas: &'a [Option<Vec<u8>>],
+    multitrack: bool,
+    pitch_bend_range: f32,
+) -> Result<Smf<'a>> {
     let ppqn = 480;
     let timing = Timing::Metrical(midly::num::u15::new(ppqn));
 
-    let mut track_events = Vec::new();
+    // track 0 holds everything in single track mode, and the conductor track otherwise
+    let channels: BTreeSet<u16> = if multitrack {
+        records
+            .iter()
+            .filter_map(|record| record.channel())
+            .collect()
+    } else {
+        BTreeSet::new()
+    };
+    let mut tracks: Vec<TrackWriter> = (0..=channels.len())
+        .map(|_| TrackWriter::default())
+        .collect();
 
     let mut current_bpm = 120.0;
 
     let mut last_micros = 0u64;
-    let mut accumulated_delta_ticks = 0u64;
+    let mut current_tick = 0u64;
 
-    for record in records.iter_mut() {
+    for (record, raw_meta) in records.iter_mut().zip(raw_metas) {
         let time_micros = record.time();
         let delta_micros = time_micros.saturating_sub(last_micros);
         last_micros = time_micros;
 
         let micros_per_beat = 60_000_000.0 / current_bpm;
         let delta_beats = delta_micros as f64 / micros_per_beat;
-        let mut delta_tick = accumulated_delta_ticks + ((delta_beats * ppqn as f64).round() as u64);
-
-        while delta_tick > midly::num::u28::max_value().as_int() as u64 {
-            track_events.push(TrackEvent {
-                delta: midly::num::u28::max_value(),
-                kind: TrackEventKind::Meta(MetaMessage::Text(b"long delta")),
-            });
-            delta_tick -= midly::num::u28::max_value().as_int() as u64;
-        }
+        current_tick += (delta_beats * ppqn as f64).round() as u64;
 
         if let MtxtOutputRecord::Tempo { bpm, .. } = record {
             current_bpm = *bpm as f64;
         }
 
-        let track_event = record_to_track_event(record, delta_tick as u32)?;
-
-        if let Some(event) = track_event {
-            track_events.push(event);
-            accumulated_delta_ticks = 0;
-        } else {
-            // did not manage to consume deltas -> accumulate
-            accumulated_delta_ticks = delta_tick;
-        }
+        let track_idx = record
+            .channel()
+            .and_then(|channel| channels.iter().position(|c| *c == channel))
+            .map_or(0, |idx| idx + 1);
+        tracks[track_idx].write(current_tick, record, raw_meta.as_deref(), pitch_bend_range)?;
     }
 
-    track_events.push(TrackEvent {
-        delta: midly::num::u28::new(0),
-        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
-    });
-
     Ok(Smf {
         header: midly::Header {
-            format: midly::Format::SingleTrack,
+            format: if multitrack {
+                midly::Format::Parallel
+            } else {
+                midly::Format::SingleTrack
+            },
             timing,
         },
-        tracks: vec![track_events],
+        tracks: tracks.into_iter().map(TrackWriter::finish).collect(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::convert_midi_to_mtxt;
+    use crate::parse_mtxt;
+    use midly::Fps;
+
+    #[test]
+    fn test_framed_sysex_roundtrip() {
+        let file = parse_mtxt("mtxt 1.0\n1.0 sysex f0 43 10 4c 00 00 7e 00 f7\n").unwrap();
+        let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let sysex: Vec<_> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::SysEx(data) => Some(data.to_vec()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            sysex,
+            vec![vec![0x43, 0x10, 0x4c, 0x00, 0x00, 0x7e, 0x00, 0xf7]]
+        );
+
+        let imported = convert_midi_to_mtxt(&midi_bytes).unwrap();
+        assert!(
+            imported
+                .to_string()
+                .contains("1.0 sysex f0 43 10 4c 00 00 7e 00 f7")
+        );
+    }
+
+    #[test]
+    fn test_bank_select_roundtrip() {
+        let file = parse_mtxt("mtxt 1.0\n1.0 voice ch=1 bank=129 piano\n").unwrap();
+        let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let messages: Vec<_> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Midi { message, .. } => Some(message),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                MidiMessage::Controller {
+                    controller: 0.into(),
+                    value: 1.into()
+                },
+                MidiMessage::Controller {
+                    controller: 32.into(),
+                    value: 1.into()
+                },
+                MidiMessage::ProgramChange { program: 0.into() },
+            ]
+        );
+
+        let imported = convert_midi_to_mtxt(&midi_bytes).unwrap().to_string();
+        assert!(
+            imported.contains("1.0 voice ch=1 bank=129 "),
+            "{}",
+            imported
+        );
+        assert!(!imported.contains(" cc "), "{}", imported);
+    }
+
+    #[test]
+    fn test_aftertouch_roundtrip() {
+        let file =
+            parse_mtxt("mtxt 1.0\n1.0 cc aftertouch 1.0 ch=2\n2.0 cc C4 aftertouch 1.0 ch=2\n")
+                .unwrap();
+        let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let messages: Vec<_> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Midi { message, .. } => Some(message),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                MidiMessage::ChannelAftertouch { vel: 127.into() },
+                MidiMessage::Aftertouch {
+                    key: 60.into(),
+                    vel: 127.into()
+                },
+            ]
+        );
+
+        let imported = convert_midi_to_mtxt(&midi_bytes).unwrap().to_string();
+        assert!(imported.contains("1.0 cc aftertouch 1.0 ch=2"));
+        assert!(imported.contains("2.0 cc C4 aftertouch 1.0 ch=2"));
+    }
+
+    #[test]
+    fn test_reset_targets() {
+        let file = parse_mtxt(
+            "mtxt 1.0\nch=3\n1.0 reset notes\n2.0 reset tuning\nch=5\n3.0 reset all\n4.0 reset controllers\n\
+             5.0 reset ch=9\n",
+        )
+        .unwrap();
+        let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let messages: Vec<_> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Midi { channel, message } => Some((channel.as_int(), message)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (
+                    3,
+                    MidiMessage::Controller {
+                        controller: 123.into(),
+                        value: 0.into()
+                    }
+                ),
+                (
+                    5,
+                    MidiMessage::Controller {
+                        controller: 120.into(),
+                        value: 0.into()
+                    }
+                ),
+                (
+                    5,
+                    MidiMessage::Controller {
+                        controller: 121.into(),
+                        value: 0.into()
+                    }
+                ),
+                // a channel target resets the controllers and notes of that channel
+                (
+                    9,
+                    MidiMessage::Controller {
+                        controller: 121.into(),
+                        value: 0.into()
+                    }
+                ),
+                (
+                    9,
+                    MidiMessage::Controller {
+                        controller: 123.into(),
+                        value: 0.into()
+                    }
+                ),
+            ]
+        );
+
+        let output: Vec<String> = file
+            .get_output_records()
+            .iter()
+            .filter(|record| record.channel() == Some(9))
+            .map(|record| record.to_string())
+            .collect();
+        assert_eq!(
+            output,
+            vec![
+                "[    2500] Reset controllers ch=9",
+                "[    2500] Reset notes ch=9"
+            ]
+        );
+        assert!(parse_mtxt("mtxt 1.0\n1.0 reset ch=x\n").is_err());
+        assert!(parse_mtxt("mtxt 1.0\n1.0 reset ch=*\n").is_ok());
+    }
+
+    #[test]
+    fn test_out_of_range_channels() {
+        let file =
+            parse_mtxt("mtxt 1.0\n1.0 note C4 ch=20\n2.0 cc volume 1.0 ch=16\n3.0 note E4 ch=20\n")
+                .unwrap();
+
+        let err = convert_mtxt_to_midi(&file).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Channels out of range for MIDI (0-15): 16, 20. Enable channel wrap to map them to ch % 16"
+        );
+
+        let midi_bytes = convert_mtxt_to_midi_channel_wrap(&file).unwrap();
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let channels: Vec<u8> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Midi { channel, .. } => Some(channel.as_int()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(channels, vec![4, 4, 0, 4, 4]);
+    }
+
+    #[test]
+    fn test_raw_meta_roundtrip() {
+        let metas = [
+            MetaMessage::Unknown(0x60, &[0x01, 0x02, 0xFF]),
+            MetaMessage::SequencerSpecific(&[0x00, 0x20, 0x29, 0x7F]),
+        ];
+        let mut track: Vec<TrackEvent> = metas
+            .iter()
+            .map(|meta| TrackEvent {
+                delta: 0.into(),
+                kind: TrackEventKind::Meta(*meta),
+            })
+            .collect();
+        track.push(TrackEvent {
+            delta: 0.into(),
+            kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+        });
+        let smf = Smf {
+            header: midly::Header::new(midly::Format::SingleTrack, Timing::Metrical(480.into())),
+            tracks: vec![track],
+        };
+        let mut midi_bytes = Vec::new();
+        smf.write(&mut midi_bytes).unwrap();
+
+        let imported = convert_midi_to_mtxt(&midi_bytes).unwrap();
+        let text = imported.to_string();
+        assert!(text.contains("0.0 meta unknown_60 0102FF"), "{}", text);
+        assert!(
+            text.contains("0.0 meta sequencerspecific 0020297F"),
+            "{}",
+            text
+        );
+
+        let exported = convert_mtxt_to_midi(&parse_mtxt(&text).unwrap()).unwrap();
+        let smf = Smf::parse(&exported).unwrap();
+        let exported_metas: Vec<_> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Meta(MetaMessage::EndOfTrack) => None,
+                TrackEventKind::Meta(meta) => Some(meta),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(exported_metas, metas);
+
+        let err = convert_mtxt_to_midi(&parse_mtxt("mtxt 1.0\n1.0 meta unknown_60 0G\n").unwrap())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid hex data in unknown_60 meta: 0G");
+    }
+
+    #[test]
+    fn test_key_signature_roundtrip() {
+        let file = parse_mtxt(
+            "mtxt 1.0\nmeta global key A major\n4.0 meta keysignature Eb minor\n\
+             8.0 meta keysignature Bb major\n12.0 meta keysignature C# minor\n",
+        )
+        .unwrap();
+        let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let keys: Vec<_> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Meta(MetaMessage::KeySignature(sharps_flats, minor)) => {
+                    Some((sharps_flats, minor))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(keys, vec![(3, false), (-6, true), (-2, false), (4, true)]);
+
+        let imported = convert_midi_to_mtxt(&midi_bytes).unwrap().to_string();
+        for expected in [
+            "meta global key A major",
+            "4.0 meta keysignature Eb minor",
+            "8.0 meta keysignature Bb major",
+            "12.0 meta keysignature C# minor",
+        ] {
+            assert!(imported.contains(expected), "{}", imported);
+        }
+    }
+
+    #[test]
+    fn test_smpte_roundtrip() {
+        let file = parse_mtxt("mtxt 1.0\nmeta global smpte 01:02:03:29\n1.0 note C4\n").unwrap();
+        let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let smpte = smf.tracks[0]
+            .iter()
+            .find_map(|event| match event.kind {
+                TrackEventKind::Meta(MetaMessage::SmpteOffset(smpte)) => Some(smpte),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(
+            (smpte.hour(), smpte.minute(), smpte.second(), smpte.frame()),
+            (1, 2, 3, 29)
+        );
+
+        let imported = convert_midi_to_mtxt(&midi_bytes).unwrap().to_string();
+        assert!(
+            imported.contains("meta global smpte 01:02:03:29 30"),
+            "{}",
+            imported
+        );
+
+        // the frame rate is written back, so it survives a second export
+        for (value, fps) in [
+            ("00:00:01:23 24", Fps::Fps24),
+            ("00:00:01:24 25", Fps::Fps25),
+            ("00:00:01:28 29.97", Fps::Fps29),
+            ("00:00:01:29 30", Fps::Fps30),
+        ] {
+            let content = format!("mtxt 1.0\nmeta global smpte {}\n1.0 note C4\n", value);
+            let midi_bytes = convert_mtxt_to_midi(&parse_mtxt(&content).unwrap()).unwrap();
+            let imported = convert_midi_to_mtxt(&midi_bytes).unwrap();
+            assert_eq!(imported.get_global_meta_value("smpte"), Some(value));
+
+            let midi_bytes = convert_mtxt_to_midi(&imported).unwrap();
+            let smf = Smf::parse(&midi_bytes).unwrap();
+            let smpte = smf.tracks[0]
+                .iter()
+                .find_map(|event| match event.kind {
+                    TrackEventKind::Meta(MetaMessage::SmpteOffset(smpte)) => Some(smpte),
+                    _ => None,
+                })
+                .unwrap();
+            assert_eq!(smpte.fps(), fps);
+        }
+
+        let err = convert_mtxt_to_midi(
+            &parse_mtxt("mtxt 1.0\nmeta global smpte 00:00:00:29 29.97\n").unwrap(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "SMPTE offset out of range: 00:00:00:29 29.97 (hours 0-23, minutes and seconds 0-59, frames 0-28)"
+        );
+    }
+
+    #[test]
+    fn test_default_velocity() {
+        let file = parse_mtxt("mtxt 1.0\n0.0 note C4\n1.0 note D4 vel=1.0\n").unwrap();
+        let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let velocities: Vec<u8> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Midi {
+                    message: MidiMessage::NoteOn { vel, .. },
+                    ..
+                } => Some(vel.as_int()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(velocities, vec![63, 127]);
+    }
+
+    fn pitch_bends(midi_bytes: &[u8]) -> Vec<(u8, u16)> {
+        let smf = Smf::parse(midi_bytes).unwrap();
+        smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::PitchBend { bend },
+                } => Some((channel.as_int(), bend.0.as_int())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pitch_bend_roundtrip() {
+        let content =
+            "mtxt 1.0\n0.0 cc pitch 0.5 ch=1\n1.0 cc pitch -2.0 ch=1\n2.0 cc pitch 0.0 ch=1\n";
+        let midi_bytes = convert_mtxt_to_midi(&parse_mtxt(content).unwrap()).unwrap();
+        // +-2 semitones by default
+        assert_eq!(
+            pitch_bends(&midi_bytes),
+            vec![(1, 10240), (1, 0), (1, 8192)]
+        );
+
+        let imported = convert_midi_to_mtxt(&midi_bytes).unwrap().to_string();
+        for expected in ["cc pitch 0.5", "cc pitch -2.0", "cc pitch 0.0"] {
+            assert!(imported.contains(expected), "{}", imported);
+        }
+
+        let file =
+            parse_mtxt("mtxt 1.0\nmeta global pitch_bend_range 12\n0.0 cc pitch 6\n").unwrap();
+        assert_eq!(file.pitch_bend_range(), Some(12.0));
+        assert_eq!(
+            pitch_bends(&convert_mtxt_to_midi(&file).unwrap()),
+            vec![(0, 12288)]
+        );
+        assert!(parse_mtxt("mtxt 1.0\nmeta global pitch_bend_range 0\n").is_err());
+    }
+
+    #[test]
+    fn test_pitch_bend_range_rpn_roundtrip() {
+        let file = parse_mtxt(
+            "mtxt 1.0\nmeta global pitch_bend_range 12.5\n0.0 cc pitch 6.25 ch=1\n1.0 note C4 ch=3\n",
+        )
+        .unwrap();
+        let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let controllers: Vec<(u8, u8, u8)> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::Controller { controller, value },
+                } => Some((channel.as_int(), controller.as_int(), value.as_int())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            controllers,
+            vec![
+                (1, 101, 0),
+                (1, 100, 0),
+                (1, 6, 12),
+                (1, 38, 50),
+                (3, 101, 0),
+                (3, 100, 0),
+                (3, 6, 12),
+                (3, 38, 50),
+            ]
+        );
+        // half of the 12.5 semitone range
+        assert_eq!(pitch_bends(&midi_bytes), vec![(1, 12288)]);
+
+        let imported = convert_midi_to_mtxt(&midi_bytes).unwrap();
+        assert_eq!(imported.pitch_bend_range(), Some(12.5));
+        let imported = imported.to_string();
+        assert!(imported.contains("cc pitch 6.25"), "{}", imported);
+        assert!(!imported.contains("cc 101"), "{}", imported);
+        let reexported = convert_mtxt_to_midi(&parse_mtxt(&imported).unwrap()).unwrap();
+        assert_eq!(pitch_bends(&reexported), vec![(1, 12288)]);
+    }
+
+    #[test]
+    fn test_cents_note_bend() {
+        let file = parse_mtxt(
+            "mtxt 1.0\n0.0 note C4+50 dur=1 ch=2\n1.0 note D4 dur=1 ch=2\n2.0 note E4 dur=1 ch=2\n",
+        )
+        .unwrap();
+        let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+
+        // +50 cents is a quarter of the +-2 semitone range, reset to no bend once for D4
+        assert_eq!(pitch_bends(&midi_bytes), vec![(2, 10240), (2, 8192)]);
+
+        // the bend comes right before the note on
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let messages: Vec<&MidiMessage> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match &event.kind {
+                TrackEventKind::Midi { message, .. } => Some(message),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(messages[0], MidiMessage::PitchBend { .. }));
+        assert!(matches!(messages[1], MidiMessage::NoteOn { key, .. } if key.as_int() == 60));
+    }
+
+    #[test]
+    fn test_no_running_status() {
+        let file = parse_mtxt(
+            "mtxt 1.0\nch=1\n0.0 note C4\n0.0 note E4\n1.0 voice piano\n1.0 note G4 dur=1\n",
+        )
+        .unwrap();
+        let running = convert_mtxt_to_midi(&file).unwrap();
+        let options = MidiExportOptions {
+            no_running_status: true,
+            ..MidiExportOptions::default()
+        };
+        let full = convert_mtxt_to_midi_with(&file, &options).unwrap();
+
+        // the same events, two notes starting together share a status byte by default
+        assert_eq!(
+            Smf::parse(&running).unwrap().tracks,
+            Smf::parse(&full).unwrap().tracks
+        );
+        assert!(full.len() > running.len());
+        // nothing is left to put back
+        assert_eq!(write_full_status(&full).unwrap(), full);
+    }
+
+    #[test]
+    fn test_mts_tuning_sysex() {
+        let file =
+            parse_mtxt("mtxt 1.0\n0.0 tuning E -50\n1.0 tuning ch=2 C +25\n1.0 note E4 ch=2\n")
+                .unwrap();
+        let options = MidiExportOptions {
+            tuning_mode: TuningMode::Mts,
+            ..MidiExportOptions::default()
+        };
+        let midi_bytes = convert_mtxt_to_midi_with(&file, &options).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let sysex: Vec<Vec<u8>> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::SysEx(data) => Some(data.to_vec()),
+                _ => None,
+            })
+            .collect();
+
+        // real-time scale/octave tuning, 2-byte form: channel mask, then C to B where
+        // 40 00 is 0 cents and each step is 100/8192 cents
+        let message = |mask: [u8; 3], c: [u8; 2], e: [u8; 2]| {
+            let mut data = vec![0x7F, 0x7F, 0x08, 0x09];
+            data.extend(mask);
+            for semitone in 0..12 {
+                data.extend(match semitone {
+                    0 => c,
+                    4 => e,
+                    _ => [0x40, 0x00],
+                });
+            }
+            data.push(0xF7);
+            data
+        };
+        assert_eq!(
+            sysex,
+            vec![
+                // -50 cents on every channel
+                message([0x03, 0x7F, 0x7F], [0x40, 0x00], [0x20, 0x00]),
+                // channel 2 also gets C +25 and keeps E -50
+                message([0x00, 0x00, 0x04], [0x50, 0x00], [0x20, 0x00]),
+            ]
+        );
+
+        // the note itself is not bent
+        assert!(pitch_bends(&midi_bytes).is_empty());
+        let baked = convert_mtxt_to_midi(&file).unwrap();
+        assert_eq!(pitch_bends(&baked), vec![(2, 6144)]);
+    }
+
+    #[test]
+    fn test_bar_marker_roundtrip() {
+        let file =
+            parse_mtxt("mtxt 1.0\n0.0 bar 1\n0.0 note C4\n4.0 bar 2\n4.0 meta marker Bar none\n")
+                .unwrap();
+        let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        let markers: Vec<&[u8]> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Meta(MetaMessage::Marker(text)) => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(markers, vec![b"Bar 1".as_slice(), b"Bar 2", b"Bar none"]);
+
+        let imported = convert_midi_to_mtxt(&midi_bytes).unwrap().to_string();
+        for expected in ["0.0 bar 1", "4.0 bar 2", "4.0 meta marker Bar none"] {
+            assert!(imported.contains(expected), "{}", imported);
+        }
+    }
+
+    #[test]
+    fn test_multitrack_track_names() {
+        let file = parse_mtxt(
+            "mtxt 1.0\nmeta global title Song\n0.0 meta ch=0 name Piano\n0.0 meta ch=1 name Bass\n\
+             0.0 note C4 ch=0\n1.0 note C2 ch=1\n",
+        )
+        .unwrap();
+        let midi_bytes = convert_mtxt_to_midi_multitrack(&file).unwrap();
+
+        let smf = Smf::parse(&midi_bytes).unwrap();
+        assert_eq!(smf.header.format, midly::Format::Parallel);
+        let track_names: Vec<Vec<&[u8]>> = smf
+            .tracks
+            .iter()
+            .map(|track| {
+                track
+                    .iter()
+                    .filter_map(|event| match event.kind {
+                        TrackEventKind::Meta(MetaMessage::TrackName(name)) => Some(name),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            track_names,
+            vec![
+                vec![b"Song".as_slice()],
+                vec![b"Piano".as_slice()],
+                vec![b"Bass".as_slice()]
+            ]
+        );
+
+        let imported = convert_midi_to_mtxt(&midi_bytes).unwrap().to_string();
+        for expected in [
+            "meta global title Song",
+            "meta ch=0 name Piano",
+            "meta ch=1 name Bass",
+        ] {
+            assert!(imported.contains(expected), "{}", imported);
+        }
+    }
+}
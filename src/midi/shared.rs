@@ -1,49 +1,114 @@
+pub use crate::process::{
+    DEFAULT_PITCH_BEND_RANGE, pitch_bend_to_semitones, semitones_to_pitch_bend,
+};
+use crate::types::key::Key;
 use crate::types::note::Note;
 use crate::types::pitch::PitchClass;
 use anyhow::{Result, anyhow};
+use midly::{Fps, SmpteTime};
+use std::collections::HashMap;
+use std::sync::OnceLock;
 
-const MIDI_CC_MAPPINGS: &[(u8, &str)] = &[
-    (1, "vibrato"),
-    (2, "breath"),
-    (4, "foot"),
-    (5, "portamento"),
-    (7, "volume"),
-    (8, "balance"),
-    (10, "pan"),
-    (11, "expression"),
-    (64, "sustain"),
-    (65, "portamento_switch"),
-    (66, "sostenuto"),
-    (67, "soft"),
-    (68, "legato"),
-    (70, "sound_variation"),
-    (71, "timbre"),
-    (73, "attack"),
-    (74, "cutoff"),
-    (75, "decay"),
-    (76, "vibrato_rate"),
-    (77, "vibrato_depth"),
-    (78, "vibrato_delay"),
-    (91, "reverb"),
-    (92, "tremolo"),
-    (93, "chorus"),
-    (94, "detune"),
-    (95, "phaser"),
+/// The MIDI message a controller name is sent as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControllerMessage {
+    /// Control change, values 0.0-1.0.
+    Cc(u8),
+    /// Control change centered on 64, values -1.0-1.0.
+    BipolarCc(u8),
+    /// Pitch bend, values in semitones within the pitch bend range.
+    PitchBend,
+    /// Channel pressure, values 0.0-1.0. A `cc` with a note target
+    /// (`cc C4 aftertouch 0.5`) is polyphonic key pressure instead.
+    Aftertouch,
+}
+
+/// Recognized controller names. Any other CC can be written by number (`cc 20 0.5`).
+/// Import uses the first name listed for a message, later ones are aliases.
+pub const CONTROLLER_MESSAGES: &[(&str, ControllerMessage)] = &[
+    ("pitch", ControllerMessage::PitchBend),
+    ("aftertouch", ControllerMessage::Aftertouch),
+    ("vibrato", ControllerMessage::Cc(1)),
+    ("breath", ControllerMessage::Cc(2)),
+    ("foot", ControllerMessage::Cc(4)),
+    ("portamento", ControllerMessage::Cc(5)),
+    ("volume", ControllerMessage::Cc(7)),
+    ("balance", ControllerMessage::BipolarCc(8)),
+    ("pan", ControllerMessage::BipolarCc(10)),
+    ("expression", ControllerMessage::Cc(11)),
+    ("sustain", ControllerMessage::Cc(64)),
+    ("portamento_switch", ControllerMessage::Cc(65)),
+    ("sostenuto", ControllerMessage::Cc(66)),
+    ("soft", ControllerMessage::Cc(67)),
+    ("legato", ControllerMessage::Cc(68)),
+    ("sound_variation", ControllerMessage::Cc(70)),
+    ("timbre", ControllerMessage::Cc(71)),
+    ("resonance", ControllerMessage::Cc(71)),
+    ("attack", ControllerMessage::Cc(73)),
+    ("cutoff", ControllerMessage::Cc(74)),
+    ("brightness", ControllerMessage::Cc(74)),
+    ("decay", ControllerMessage::Cc(75)),
+    ("vibrato_rate", ControllerMessage::Cc(76)),
+    ("vibrato_depth", ControllerMessage::Cc(77)),
+    ("vibrato_delay", ControllerMessage::Cc(78)),
+    ("reverb", ControllerMessage::Cc(91)),
+    ("tremolo", ControllerMessage::Cc(92)),
+    ("chorus", ControllerMessage::Cc(93)),
+    ("detune", ControllerMessage::Cc(94)),
+    ("phaser", ControllerMessage::Cc(95)),
 ];
 
-pub fn midi_cc_to_name(cc: u8) -> String {
-    MIDI_CC_MAPPINGS
+/// Name used on import for a MIDI message, the inverse of `controller_message`.
+/// CCs without a name use their number.
+pub fn controller_name(message: ControllerMessage) -> String {
+    let cc = controller_cc_number(message);
+
+    CONTROLLER_MESSAGES
         .iter()
-        .find(|(num, _)| *num == cc)
-        .map(|(_, name)| name.to_string())
-        .unwrap_or_else(|| cc.to_string())
+        .find(|(_, m)| match cc {
+            Some(_) => controller_cc_number(*m) == cc,
+            None => *m == message,
+        })
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_else(|| cc.map(|cc| cc.to_string()).unwrap_or_default())
 }
 
-fn midi_cc_name_to_number(name: &str) -> Option<u8> {
-    MIDI_CC_MAPPINGS
-        .iter()
-        .find(|(_, n)| *n == name)
-        .map(|(num, _)| *num)
+pub fn midi_cc_to_name(cc: u8) -> String {
+    controller_name(ControllerMessage::Cc(cc))
+}
+
+fn controller_cc_number(message: ControllerMessage) -> Option<u8> {
+    match message {
+        ControllerMessage::Cc(cc) | ControllerMessage::BipolarCc(cc) => Some(cc),
+        _ => None,
+    }
+}
+
+/// MIDI message for a controller name or CC number. Export looks up every `cc` event,
+/// the names are hashed once instead of searched each time.
+pub fn controller_message(name: &str) -> Option<ControllerMessage> {
+    static MESSAGES: OnceLock<HashMap<&str, ControllerMessage>> = OnceLock::new();
+    let messages = MESSAGES.get_or_init(|| CONTROLLER_MESSAGES.iter().copied().collect());
+
+    if let Some(message) = messages.get(name) {
+        return Some(*message);
+    }
+
+    match name.parse::<u8>() {
+        Ok(num) if num <= 127 => Some(ControllerMessage::Cc(num)),
+        _ => None,
+    }
+}
+
+/// Channel mode controller a `reset` target is sent as, `None` for targets
+/// without a MIDI equivalent (e.g. `reset tuning`).
+pub fn reset_target_to_midi(target: &str) -> Option<u8> {
+    match target {
+        "all" => Some(120),         // All Sound Off
+        "controllers" => Some(121), // Reset All Controllers
+        "notes" => Some(123),       // All Notes Off
+        _ => None,
+    }
 }
 
 pub fn note_to_midi_number(note: &Note) -> Result<u8> {
@@ -99,64 +164,31 @@ pub enum MidiControllerEvent {
     Aftertouch { value: u8 },
 }
 
-pub fn controller_name_to_midi(name: &str, value: f32) -> Result<MidiControllerEvent> {
-    match name {
-        "pitch" => {
-            // Clamp to reasonable range
-            let clamped = value.clamp(-12.0, 12.0);
-            // Map from -12..12 to 0..16383
-            // 0 (no bend) = 8192
-            let bend_val = ((clamped + 12.0) / 24.0 * 16383.0) as u16;
-            Ok(MidiControllerEvent::PitchBend { value: bend_val })
-        }
-
-        "aftertouch" => {
-            let val = (value.clamp(0.0, 1.0) * 127.0) as u8;
-            Ok(MidiControllerEvent::Aftertouch { value: val })
-        }
+/// `pitch_bend_range` is the bend in semitones of a full pitch bend, used for `pitch`.
+pub fn controller_name_to_midi(
+    name: &str,
+    value: f32,
+    pitch_bend_range: f32,
+) -> Result<MidiControllerEvent> {
+    let message =
+        controller_message(name).ok_or_else(|| anyhow!("Unknown controller name: {}", name))?;
 
-        // Special aliases that need different handling
-        "balance" => Ok(MidiControllerEvent::CC {
-            number: midi_cc_name_to_number("balance").unwrap(),
-            value: ((value.clamp(-1.0, 1.0) + 1.0) / 2.0 * 127.0) as u8,
-        }),
-        "pan" => Ok(MidiControllerEvent::CC {
-            number: midi_cc_name_to_number("pan").unwrap(),
-            value: ((value.clamp(-1.0, 1.0) + 1.0) / 2.0 * 127.0) as u8,
-        }),
-
-        // Aliases for standard CC names
-        "resonance" => Ok(MidiControllerEvent::CC {
-            number: midi_cc_name_to_number("timbre").unwrap(),
+    Ok(match message {
+        ControllerMessage::Cc(number) => MidiControllerEvent::CC {
+            number,
             value: (value.clamp(0.0, 1.0) * 127.0) as u8,
-        }),
-        "brightness" => Ok(MidiControllerEvent::CC {
-            number: midi_cc_name_to_number("cutoff").unwrap(),
+        },
+        ControllerMessage::BipolarCc(number) => MidiControllerEvent::CC {
+            number,
+            value: ((value.clamp(-1.0, 1.0) + 1.0) / 2.0 * 127.0) as u8,
+        },
+        ControllerMessage::PitchBend => MidiControllerEvent::PitchBend {
+            value: semitones_to_pitch_bend(value, pitch_bend_range),
+        },
+        ControllerMessage::Aftertouch => MidiControllerEvent::Aftertouch {
             value: (value.clamp(0.0, 1.0) * 127.0) as u8,
-        }),
-
-        // Try standard CC names from centralized mapping
-        _ => {
-            // Try to find a standard CC name
-            if let Some(cc_num) = midi_cc_name_to_number(name) {
-                return Ok(MidiControllerEvent::CC {
-                    number: cc_num,
-                    value: (value.clamp(0.0, 1.0) * 127.0) as u8,
-                });
-            }
-
-            // Try parsing as a numeric CC number
-            if let Ok(num) = name.parse::<u8>()
-                && num <= 127 {
-                    return Ok(MidiControllerEvent::CC {
-                        number: num,
-                        value: (value.clamp(0.0, 1.0) * 127.0) as u8,
-                    });
-                }
-
-            Err(anyhow!("Unknown controller name: {}", name))
-        }
-    }
+        },
+    })
 }
 
 pub fn time_signature_to_midi(sig: &crate::types::time_signature::TimeSignature) -> (u8, u8) {
@@ -208,3 +240,159 @@ pub fn midi_key_signature_to_string(sharps_flats: i8, minor: bool) -> String {
 
     format!("{} {}", key_name, if minor { "minor" } else { "major" })
 }
+
+/// Inverse of `midi_key_signature_to_string`: "Eb minor" -> (-6, true). Keys are read
+/// like `Key` reads them, those past 7 sharps or flats have no MIDI key signature.
+pub fn string_to_midi_key_signature(value: &str) -> Option<(i8, bool)> {
+    // out of range values are written as a number
+    if let Some((number, mode)) = value.trim().split_once(' ')
+        && let Ok(sharps_flats) = number.parse::<i8>()
+    {
+        let minor = match mode.trim() {
+            "major" => false,
+            "minor" => true,
+            _ => return None,
+        };
+        return Some((sharps_flats, minor));
+    }
+
+    let key: Key = value.parse().ok()?;
+    let sharps_flats = key.fifths();
+    (-7..=7)
+        .contains(&sharps_flats)
+        .then_some((sharps_flats, key.minor))
+}
+
+/// Writes a MIDI SMPTE offset as a `meta global smpte` value, `HH:MM:SS:FF` and the frame
+/// rate, read back by `string_to_midi_smpte`.
+pub fn midi_smpte_to_string(smpte: &SmpteTime) -> String {
+    let fps = match smpte.fps() {
+        Fps::Fps24 => "24",
+        Fps::Fps25 => "25",
+        Fps::Fps29 => "29.97",
+        Fps::Fps30 => "30",
+    };
+    format!(
+        "{:02}:{:02}:{:02}:{:02} {}",
+        smpte.hour(),
+        smpte.minute(),
+        smpte.second(),
+        smpte.frame(),
+        fps
+    )
+}
+
+/// Parses a `meta global smpte` value: `HH:MM:SS:FF`, optionally followed by the frame rate
+/// (`24`, `25`, `29.97` or `30`, default 30). Frames must be below the frame rate, midly
+/// counts 29.97 fps as 29 frames.
+pub fn string_to_midi_smpte(value: &str) -> Result<SmpteTime> {
+    let mut parts = value.split_whitespace();
+    let time = parts.next().unwrap_or_default();
+    let fps = match parts.next() {
+        None | Some("30") => Fps::Fps30,
+        Some("24") => Fps::Fps24,
+        Some("25") => Fps::Fps25,
+        Some("29.97") | Some("29") => Fps::Fps29,
+        Some(other) => {
+            return Err(anyhow!(
+                "Invalid SMPTE frame rate: {}. Expected 24, 25, 29.97 or 30",
+                other
+            ));
+        }
+    };
+    if parts.next().is_some() {
+        return Err(anyhow!("Invalid SMPTE offset: {}", value));
+    }
+
+    let fields = time
+        .split(':')
+        .map(|field| field.parse::<u8>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| anyhow!("Invalid SMPTE offset: {}. Expected HH:MM:SS:FF", value))?;
+    let [hour, minute, second, frame] = fields[..] else {
+        return Err(anyhow!(
+            "Invalid SMPTE offset: {}. Expected HH:MM:SS:FF",
+            value
+        ));
+    };
+
+    SmpteTime::new(hour, minute, second, frame, 0, fps).ok_or_else(|| {
+        anyhow!(
+            "SMPTE offset out of range: {} (hours 0-23, minutes and seconds 0-59, frames 0-{})",
+            value,
+            fps.as_int() - 1
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_signature_round_trip() {
+        for minor in [false, true] {
+            for sharps_flats in -7..=7 {
+                let value = midi_key_signature_to_string(sharps_flats, minor);
+                assert_eq!(
+                    string_to_midi_key_signature(&value),
+                    Some((sharps_flats, minor))
+                );
+            }
+        }
+        assert_eq!(string_to_midi_key_signature("9 major"), Some((9, false)));
+        assert_eq!(string_to_midi_key_signature("H major"), None);
+        assert_eq!(string_to_midi_key_signature("C dorian"), None);
+        assert_eq!(string_to_midi_key_signature("Db"), Some((-5, false)));
+        assert_eq!(string_to_midi_key_signature("D# major"), None);
+    }
+
+    #[test]
+    fn test_controller_names_round_trip() {
+        for (name, message) in CONTROLLER_MESSAGES {
+            assert_eq!(controller_message(name), Some(*message));
+
+            // aliases import under the first name for their message
+            let imported = controller_name(*message);
+            assert_eq!(controller_message(&imported), Some(*message), "{}", name);
+        }
+
+        assert_eq!(controller_name(ControllerMessage::Aftertouch), "aftertouch");
+        assert_eq!(controller_name(ControllerMessage::PitchBend), "pitch");
+        assert_eq!(controller_name(ControllerMessage::Cc(74)), "cutoff");
+        assert_eq!(controller_name(ControllerMessage::Cc(10)), "pan");
+        assert_eq!(controller_name(ControllerMessage::Cc(20)), "20");
+
+        assert_eq!(
+            controller_message("brightness"),
+            Some(ControllerMessage::Cc(74))
+        );
+        assert_eq!(controller_message("20"), Some(ControllerMessage::Cc(20)));
+        assert_eq!(controller_message("128"), None);
+        assert_eq!(controller_message("unknown"), None);
+    }
+
+    #[test]
+    fn test_string_to_midi_smpte() {
+        let smpte = string_to_midi_smpte("01:02:03:29").unwrap();
+        assert_eq!(
+            (smpte.hour(), smpte.minute(), smpte.second(), smpte.frame()),
+            (1, 2, 3, 29)
+        );
+        assert_eq!(smpte.fps(), Fps::Fps30);
+        assert_eq!(
+            string_to_midi_smpte("00:00:00:00 25").unwrap().fps(),
+            Fps::Fps25
+        );
+        assert_eq!(
+            string_to_midi_smpte("00:00:00:28 29.97").unwrap().fps(),
+            Fps::Fps29
+        );
+
+        assert!(string_to_midi_smpte("00:00:00:29 29.97").is_err());
+        assert!(string_to_midi_smpte("00:00:00:24 24").is_err());
+        assert!(string_to_midi_smpte("24:00:00:00").is_err());
+        assert!(string_to_midi_smpte("00:00:00:00 60").is_err());
+        assert!(string_to_midi_smpte("00:00:00").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! MusicXML export. Only notes, tempo and time signatures are written, everything else
+//! (controllers, voices, meta events) has no place in the score and is dropped.
+
+use crate::file::MtxtFile;
+use crate::score::{MeasureWalker, MeasureWriter, ScoreEvents, TICKS_PER_BEAT};
+use crate::types::note::Note;
+use crate::types::time_signature::TimeSignature;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// MusicXML step, alter (cents become a fractional alter) and octave.
+fn pitch_parts(note: &Note) -> (char, f32, i32) {
+    let (step, accidental, octave) = note.staff_position();
+    (step, accidental as f32 + note.cents / 100.0, octave)
+}
+
+/// Writes the notes and rests of a `MeasureWalker` into measures, with tempo changes
+/// as directions where they fall.
+struct ScoreWriter {
+    out: String,
+    tempos: Vec<(u64, f32)>,
+}
+
+impl ScoreWriter {
+    fn write_tempos(&mut self, cursor: u64) {
+        while let Some(&(time, bpm)) = self.tempos.first()
+            && time <= cursor
+        {
+            self.tempos.remove(0);
+            let _ = writeln!(
+                self.out,
+                "      <direction placement=\"above\"><direction-type><metronome>\
+                 <beat-unit>quarter</beat-unit><per-minute>{}</per-minute></metronome>\
+                 </direction-type><sound tempo=\"{}\"/></direction>",
+                bpm, bpm
+            );
+        }
+    }
+}
+
+impl MeasureWriter for ScoreWriter {
+    fn open_measure(&mut self, number: u32, signature: &TimeSignature, signature_changed: bool) {
+        let first = number == 1;
+        let _ = writeln!(self.out, "    <measure number=\"{}\">", number);
+        if signature_changed {
+            self.out.push_str("      <attributes>\n");
+            if first {
+                let _ = writeln!(
+                    self.out,
+                    "        <divisions>{}</divisions>",
+                    TICKS_PER_BEAT
+                );
+            }
+            let _ = writeln!(
+                self.out,
+                "        <time><beats>{}</beats><beat-type>{}</beat-type></time>",
+                signature.numerator, signature.denominator
+            );
+            if first {
+                self.out
+                    .push_str("        <clef><sign>G</sign><line>2</line></clef>\n");
+            }
+            self.out.push_str("      </attributes>\n");
+        }
+    }
+
+    fn close_measure(&mut self, cursor: u64, last: bool) {
+        if last {
+            self.write_tempos(cursor);
+        }
+        self.out.push_str("    </measure>\n");
+    }
+
+    fn write_part(
+        &mut self,
+        cursor: u64,
+        length: u64,
+        notes: &[Note],
+        tied_from_previous: bool,
+        tied_to_next: bool,
+    ) {
+        self.write_tempos(cursor);
+
+        if notes.is_empty() {
+            let _ = writeln!(
+                self.out,
+                "      <note><rest/><duration>{}</duration></note>",
+                length
+            );
+        }
+        for (idx, note) in notes.iter().enumerate() {
+            let (step, alter, octave) = pitch_parts(note);
+            self.out.push_str("      <note>");
+            if idx > 0 {
+                self.out.push_str("<chord/>");
+            }
+            let _ = write!(self.out, "<pitch><step>{}</step>", step);
+            if alter != 0.0 {
+                let _ = write!(self.out, "<alter>{}</alter>", alter);
+            }
+            let _ = write!(
+                self.out,
+                "<octave>{}</octave></pitch><duration>{}</duration>",
+                octave, length
+            );
+            if tied_from_previous {
+                self.out.push_str("<tie type=\"stop\"/>");
+            }
+            if tied_to_next {
+                self.out.push_str("<tie type=\"start\"/>");
+            }
+            self.out.push_str("</note>\n");
+        }
+    }
+
+    // rests end at the next tempo change so it lands in the right place
+    fn rest_break(&self, cursor: u64) -> Option<u64> {
+        self.tempos
+            .iter()
+            .map(|(time, _)| *time)
+            .find(|time| *time > cursor)
+    }
+}
+
+impl MtxtFile {
+    /// Exports a single-part MusicXML (partwise, 4.0) score with the notes, tempo changes
+    /// and time signatures of the file. Aliases are expanded and all channels are merged.
+    ///
+    /// Notes starting together form a chord that lasts until the next note starts, longer
+    /// notes are cut there. Notes crossing a bar line are tied, and time signatures
+    /// take effect at the next bar line.
+    pub fn to_musicxml(&self) -> Result<String> {
+        let ScoreEvents {
+            notes,
+            signatures,
+            tempos,
+        } = ScoreEvents::collect(self)?;
+
+        let mut chords: BTreeMap<u64, (u64, Vec<Note>)> = BTreeMap::new();
+        for (_, start, end, note) in notes {
+            let (chord_end, notes) = chords.entry(start).or_default();
+            *chord_end = (*chord_end).max(end);
+            if !notes.contains(&note) {
+                notes.push(note);
+            }
+        }
+
+        let writer = ScoreWriter {
+            out: String::new(),
+            tempos,
+        };
+        let mut walker = MeasureWalker::new(writer, signatures);
+        let starts: Vec<u64> = chords.keys().copied().collect();
+        for (idx, (start, (end, mut notes))) in chords.into_iter().enumerate() {
+            let end = starts.get(idx + 1).map_or(end, |next| end.min(*next));
+            notes.sort_by(Note::cmp_pitch);
+            walker.write_at(start, end, &notes);
+        }
+        let measures = walker.finish().out;
+
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str(
+            "<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \
+             \"http://www.musicxml.org/dtds/partwise.dtd\">\n",
+        );
+        xml.push_str("<score-partwise version=\"4.0\">\n");
+        if let Some(title) = self.get_global_meta_value("title") {
+            let _ = writeln!(
+                xml,
+                "  <work><work-title>{}</work-title></work>",
+                escape_xml(title)
+            );
+        }
+        xml.push_str("  <part-list>\n");
+        xml.push_str("    <score-part id=\"P1\"><part-name>Music</part-name></score-part>\n");
+        xml.push_str("  </part-list>\n");
+        xml.push_str("  <part id=\"P1\">\n");
+        xml.push_str(&measures);
+        xml.push_str("  </part>\n");
+        xml.push_str("</score-partwise>\n");
+
+        Ok(xml)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_mtxt;
+
+    /// Checks that every opened tag is closed in order.
+    fn assert_well_formed(xml: &str) {
+        let mut open: Vec<&str> = Vec::new();
+        for tag in xml.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').unwrap()];
+            if tag.starts_with('?') || tag.starts_with('!') || tag.ends_with('/') {
+                continue;
+            }
+            if let Some(name) = tag.strip_prefix('/') {
+                assert_eq!(open.pop(), Some(name), "{}", xml);
+            } else {
+                open.push(tag.split_whitespace().next().unwrap());
+            }
+        }
+        assert!(open.is_empty(), "{}", xml);
+    }
+
+    #[test]
+    fn test_musicxml_notes() {
+        let file = parse_mtxt(
+            "mtxt 1.0\nmeta global title C & E\n0.0 tempo 100\n0.0 timesig 3/4\n\
+             0.0 note C4 dur=1.0\n1.0 note E4 dur=3.0\n",
+        )
+        .unwrap();
+        let xml = file.to_musicxml().unwrap();
+        assert_well_formed(&xml);
+
+        for expected in [
+            "<work-title>C &amp; E</work-title>",
+            "<divisions>480</divisions>",
+            "<beats>3</beats><beat-type>4</beat-type>",
+            "<sound tempo=\"100\"/>",
+        ] {
+            assert!(xml.contains(expected), "{}", xml);
+        }
+
+        let pitches: Vec<&str> = xml
+            .match_indices("<pitch>")
+            .map(|(idx, _)| &xml[idx..idx + xml[idx..].find("</pitch>").unwrap()])
+            .collect();
+        assert_eq!(
+            pitches,
+            vec![
+                "<pitch><step>C</step><octave>4</octave>",
+                "<pitch><step>E</step><octave>4</octave>",
+                "<pitch><step>E</step><octave>4</octave>",
+            ]
+        );
+
+        // E4 crosses the bar line into measure 2 and is tied, the rest of the bar is filled
+        for expected in [
+            "<measure number=\"2\">",
+            "<duration>960</duration><tie type=\"start\"/>",
+            "<duration>480</duration><tie type=\"stop\"/>",
+            "<note><rest/><duration>960</duration></note>",
+        ] {
+            assert!(xml.contains(expected), "{}", xml);
+        }
+    }
+
+    #[test]
+    fn test_musicxml_accidentals() {
+        let file = parse_mtxt("mtxt 1.0\n0.0 note Bb3 dur=4.0\n0.0 note F#4+50 dur=4.0\n").unwrap();
+        let xml = file.to_musicxml().unwrap();
+        assert_well_formed(&xml);
+
+        assert!(
+            xml.contains("<step>B</step><alter>-1</alter><octave>3</octave>"),
+            "{}",
+            xml
+        );
+        assert!(
+            xml.contains("<chord/><pitch><step>F</step><alter>1.5</alter><octave>4</octave>"),
+            "{}",
+            xml
+        );
+    }
+}
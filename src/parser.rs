@@ -1,15 +1,44 @@
 use crate::file::MtxtFile;
-use crate::record_parser::parse_mtxt_line;
-use crate::types::record::MtxtRecord;
+use crate::record_parser::{UnknownEventType, parse_mtxt_line, parse_mtxt_line_records_with};
+use crate::types::beat_time::BeatTime;
+use crate::types::note::OctaveConvention;
+use crate::types::record::{AliasTable, MtxtRecord, MtxtRecordLine, VelocityScale};
+use crate::types::version::Compat;
 use anyhow::{Result, bail};
 
-pub struct MtxtParser {}
+pub struct MtxtParser {
+    // time of the last timed event, used to resolve relative "+" times
+    last_time: BeatTime,
+    // open block comment: line number it started on, text so far and nesting depth
+    block_comment: Option<(usize, String, usize)>,
+    // reject conflicting alias redefinitions instead of using the last one
+    strict: bool,
+    // scale of `vel=` and `offvel=` values in the source
+    velocity_scale: VelocityScale,
+    // octave numbering of note names in the source
+    octave_convention: OctaveConvention,
+    // lines skipped while parsing the last file
+    warnings: Vec<String>,
+}
 
 pub fn parse_mtxt(content: &str) -> Result<MtxtFile> {
     let mut parser = MtxtParser::new();
     parser.parse(content)
 }
 
+/// Like `parse_mtxt`, but fails when an alias is redefined with different notes.
+pub fn parse_mtxt_strict(content: &str) -> Result<MtxtFile> {
+    let mut parser = MtxtParser::strict();
+    parser.parse(content)
+}
+
+/// Parses a single line without the rest of the file, e.g. to check it while it is
+/// being edited. Relative `+` times are taken from 0 and block comments are not
+/// recognized, `MtxtParser` keeps that context across lines.
+pub fn parse_line(line: &str) -> Result<MtxtRecordLine> {
+    parse_mtxt_line(line)
+}
+
 impl Default for MtxtParser {
     fn default() -> Self {
         Self::new()
@@ -18,27 +47,139 @@ impl Default for MtxtParser {
 
 impl MtxtParser {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            last_time: BeatTime::zero(),
+            block_comment: None,
+            strict: false,
+            velocity_scale: VelocityScale::Unit,
+            octave_convention: OctaveConvention::Scientific,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn strict() -> Self {
+        Self {
+            strict: true,
+            ..Self::new()
+        }
+    }
+
+    /// Reads `vel=` and `offvel=` values in `velocity_scale`, e.g. `vel=100` as 100/127
+    /// with `VelocityScale::Midi127`.
+    pub fn with_velocity_scale(self, velocity_scale: VelocityScale) -> Self {
+        Self {
+            velocity_scale,
+            ..self
+        }
+    }
+
+    /// Reads note names in `octave_convention`, e.g. `C3` as middle C with
+    /// `OctaveConvention::Yamaha`.
+    pub fn with_octave_convention(self, octave_convention: OctaveConvention) -> Self {
+        Self {
+            octave_convention,
+            ..self
+        }
+    }
+
+    /// Lines of the last parsed file that were skipped, e.g. unknown event types in a
+    /// file of a newer minor version.
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
     }
 
     pub fn parse(&mut self, content: &str) -> Result<MtxtFile> {
         let mut mtxt_file = MtxtFile::new();
 
         let mut has_mtxt_header = false;
+        let mut compat = Compat::Supported;
+        let mut aliases = AliasTable::default();
+        self.last_time = BeatTime::zero();
+        self.block_comment = None;
+        self.warnings.clear();
 
         for (line_idx, line) in content.lines().enumerate() {
-            let parsed = parse_mtxt_line(line);
+            // block comments only open at the start of a line, so `/*` inside
+            // meta values or after a timestamp is left to the line parser
+            let trimmed = line.trim_start();
+            if self.block_comment.is_none() && trimmed.starts_with("/*") {
+                self.block_comment = Some((line_idx + 1, String::new(), 0));
+            }
+
+            if let Some((start_line, mut text, mut depth)) = self.block_comment.take() {
+                let line = if text.is_empty() { trimmed } else { line };
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+
+                match find_block_comment_end(line, &mut depth) {
+                    Some(end) => {
+                        if !line[end..].trim().is_empty() {
+                            bail!(
+                                "Line #{}: Unexpected content after block comment",
+                                line_idx + 1
+                            );
+                        }
+                        text.push_str(&line[..end]);
+                        // drop the outer markers
+                        let text = text[2..text.len() - 2].to_string();
+                        mtxt_file
+                            .records
+                            .push(MtxtRecordLine::new(MtxtRecord::BlockComment { text }));
+                    }
+                    None => {
+                        text.push_str(line);
+                        self.block_comment = Some((start_line, text, depth));
+                    }
+                }
+                continue;
+            }
+
+            let parsed = parse_mtxt_line_records_with(
+                line,
+                self.last_time,
+                self.velocity_scale,
+                self.octave_convention,
+            );
             match parsed {
-                Ok(record_line) => {
-                    if matches!(record_line.record, MtxtRecord::Header { version: _ }) {
-                        has_mtxt_header = true;
+                Ok(record_lines) => {
+                    for record_line in record_lines {
+                        if let MtxtRecord::Header { version } = &record_line.record {
+                            has_mtxt_header = true;
+                            compat = version.compatibility();
+                        }
+                        if let Some(time) = record_line.record.time() {
+                            self.last_time = time;
+                        }
+                        if self.strict
+                            && let MtxtRecord::AliasDef { value } = &record_line.record
+                            && let Some(previous) = aliases.define(value)
+                        {
+                            bail!(
+                                "Line #{}: Alias \"{}\" redefined with different notes (was {})",
+                                line_idx + 1,
+                                value.name,
+                                MtxtRecord::AliasDef { value: previous }
+                            );
+                        }
+                        mtxt_file.records.push(record_line);
                     }
-                    mtxt_file.records.push(record_line);
+                }
+                Err(e)
+                    if compat == Compat::PartiallySupported
+                        && e.downcast_ref::<UnknownEventType>().is_some() =>
+                {
+                    self.warnings
+                        .push(format!("Line #{}: {}, skipped", line_idx + 1, e));
                 }
                 Err(e) => bail!("Line #{}: {}", line_idx + 1, e),
             }
         }
 
+        if let Some((start_line, _, _)) = &self.block_comment {
+            bail!("Line #{}: Unterminated block comment", start_line);
+        }
+
         if !has_mtxt_header {
             bail!("Missing version declaration");
         }
@@ -46,3 +187,370 @@ impl MtxtParser {
         Ok(mtxt_file)
     }
 }
+
+/// Scans `text` for `/*` and `*/` markers, tracking the nesting depth.
+/// Returns the index just past the `*/` that closes the outermost block.
+fn find_block_comment_end(text: &str, depth: &mut usize) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut idx = 0;
+
+    while idx + 1 < bytes.len() {
+        match (bytes[idx], bytes[idx + 1]) {
+            (b'/', b'*') => {
+                *depth += 1;
+                idx += 2;
+            }
+            (b'*', b'/') => {
+                *depth -= 1;
+                idx += 2;
+                if *depth == 0 {
+                    return Some(idx);
+                }
+            }
+            _ => idx += 1,
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FormatOptions;
+    use crate::MtxtOutputRecord;
+
+    #[test]
+    fn test_newer_minor_version() {
+        let content = "mtxt 1.1\n1.0 note C4\n2.0 glide C4 D4\n3.0 note E4\n";
+        let mut parser = MtxtParser::new();
+        let file = parser.parse(content).unwrap();
+
+        assert_eq!(file.to_string(), "mtxt 1.1\n1.0 note C4\n3.0 note E4\n");
+        assert_eq!(
+            parser.warnings(),
+            ["Line #3: Unknown event type: glide, skipped"]
+        );
+
+        // the current version still rejects unknown events
+        let err = parse_mtxt(&content.replace("1.1", "1.0")).unwrap_err();
+        assert_eq!(err.to_string(), "Line #3: Unknown event type: glide");
+        assert!(parse_mtxt("mtxt 2.0\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_line() {
+        let line = parse_line("  2.0 note C4 vel=0.5 // accent").unwrap();
+        assert_eq!(line.record.time(), Some(BeatTime::from_beats(2.0)));
+        assert_eq!(line.comment.as_deref(), Some("accent"));
+        assert_eq!(
+            parse_line("+1 note C4").unwrap().record.time(),
+            Some(BeatTime::from_beats(1.0))
+        );
+
+        let err = parse_line("1.0 glide C4").unwrap_err();
+        assert!(err.downcast_ref::<UnknownEventType>().is_some());
+        assert!(parse_line("1.0 note C4 vel=2").is_err());
+    }
+
+    #[test]
+    fn test_relative_times() {
+        let file = parse_mtxt(
+            r#"
+mtxt 1.0
++0.5 note C4
++0.5 note E4
+ch=2
++1 note G4
+4.0 note C5
++0.25 note E5
+"#,
+        )
+        .unwrap();
+
+        let times: Vec<String> = file
+            .records
+            .iter()
+            .filter_map(|line| line.record.time())
+            .map(|time| time.to_string())
+            .collect();
+        assert_eq!(times, vec!["0.5", "1.0", "2.0", "4.0", "4.25"]);
+    }
+
+    #[test]
+    fn test_comment_marker_in_meta_values() {
+        let file = parse_mtxt(
+            r#"mtxt 1.0
+meta global url https://example.com/a // homepage
+1.0 meta text "Hello // world" // greeting
+2.0 meta text a \// b
+3.0 meta text "unterminated // comment
+"#,
+        )
+        .unwrap();
+
+        let values: Vec<(String, Option<String>)> = file
+            .records
+            .iter()
+            .filter_map(|line| match &line.record {
+                MtxtRecord::GlobalMeta { value, .. } | MtxtRecord::Meta { value, .. } => {
+                    Some((value.clone(), line.comment.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                (
+                    "https://example.com/a".to_string(),
+                    Some("homepage".to_string())
+                ),
+                ("Hello // world".to_string(), Some("greeting".to_string())),
+                ("a \\// b".to_string(), None),
+                ("\"unterminated".to_string(), Some("comment".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_meta_values_round_trip() {
+        let content = r#"mtxt 1.0
+meta global url https://example.com/a
+1.0 meta text "Hello // world"
+2.0 meta text "say \"hi\" // there"
+3.0 meta text "\"quoted\""
+4.0 meta text plain "quotes" inside
+"#;
+        let file = parse_mtxt(content).unwrap();
+        assert_eq!(file.to_string(), content);
+
+        let reparsed = parse_mtxt(&file.to_string()).unwrap();
+        assert_eq!(reparsed.records, file.records);
+
+        // quotes around a plain value are optional
+        let file = parse_mtxt("mtxt 1.0\n1.0 meta text \"quoted\"\n").unwrap();
+        assert_eq!(file.to_string(), "mtxt 1.0\n1.0 meta text quoted\n");
+    }
+
+    #[test]
+    fn test_timed_meta_round_trip() {
+        let content = "mtxt 1.0\n4.0 meta marker intro\nmeta text untimed\n";
+        let file = parse_mtxt(content).unwrap();
+        assert_eq!(file.records[1].record.time(), Some("4.0".parse().unwrap()));
+        assert_eq!(file.to_string(), content);
+        assert_eq!(parse_mtxt(&file.to_string()).unwrap().records, file.records);
+
+        // on its own, and with relative times
+        let line = &file.records[1];
+        assert_eq!(line.to_string(), "4.0 meta marker intro");
+        assert_eq!(&parse_line(&line.to_string()).unwrap(), line);
+        let relative = file
+            .display_with_formatting(FormatOptions {
+                relative_times: true,
+                ..FormatOptions::default()
+            })
+            .to_string();
+        assert_eq!(parse_mtxt(&relative).unwrap().records, file.records);
+    }
+
+    #[test]
+    fn test_block_comments() {
+        let content = r#"/* header notes
+   spanning /* nested */ lines
+*/
+mtxt 1.0
+/* single line */
+1.0 meta text a /* b
+2.0 note C4 // see /* here
+"#;
+        let file = parse_mtxt(content).unwrap();
+
+        let texts: Vec<&str> = file
+            .records
+            .iter()
+            .filter_map(|line| match &line.record {
+                MtxtRecord::BlockComment { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            texts,
+            vec![
+                " header notes\n   spanning /* nested */ lines\n",
+                " single line "
+            ]
+        );
+        assert!(matches!(
+            &file.records[3].record,
+            MtxtRecord::Meta { value, .. } if value == "a /* b"
+        ));
+        assert_eq!(file.to_string(), content);
+    }
+
+    #[test]
+    fn test_unterminated_block_comment() {
+        let err =
+            parse_mtxt("mtxt 1.0\n1.0 note C4\n/* open\n/* nested */\n2.0 note E4\n").unwrap_err();
+        assert_eq!(err.to_string(), "Line #3: Unterminated block comment");
+
+        let err = parse_mtxt("mtxt 1.0\n/* closed */ 1.0 note C4\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Line #2: Unexpected content after block comment"
+        );
+    }
+
+    #[test]
+    fn test_full_line_comments() {
+        let content = "mtxt 1.0\n// intro\n\n1.0 note C4 // melody\n  //   indented\n";
+        let file = parse_mtxt(content).unwrap();
+
+        assert_eq!(
+            file.records[1],
+            MtxtRecordLine::new(MtxtRecord::Comment {
+                text: "intro".to_string()
+            })
+        );
+        assert_eq!(file.records[2], MtxtRecordLine::new(MtxtRecord::EmptyLine));
+        assert_eq!(
+            file.to_string(),
+            "mtxt 1.0\n// intro\n\n1.0 note C4 // melody\n// indented\n"
+        );
+    }
+
+    #[test]
+    fn test_all_channels() {
+        let content = "mtxt 1.0\n1.0 note C4 ch=*\nch=*\n2.0 cc volume 0.5\n";
+        let file = parse_mtxt(content).unwrap();
+        assert_eq!(file.to_string(), content);
+
+        let note_channels: Vec<u16> = file
+            .get_output_records()
+            .iter()
+            .filter_map(|record| match record {
+                MtxtOutputRecord::NoteOn { channel, .. } => Some(*channel),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(note_channels, (0..16).collect::<Vec<u16>>());
+
+        let cc_count = file
+            .get_output_records()
+            .iter()
+            .filter(|record| matches!(record, MtxtOutputRecord::ControlChange { .. }))
+            .count();
+        assert_eq!(cc_count, 16);
+
+        let err = parse_mtxt("mtxt 1.0\n1.0 note C4 ch=65535\n").unwrap_err();
+        assert!(err.to_string().contains("Invalid channel number"));
+    }
+
+    #[test]
+    fn test_cc_channel_list() {
+        let file = parse_mtxt(
+            "mtxt 1.0\n1.0 cc ch=1,2,3 volume 0.8 // swell\n2.0 cc C4 pitch 0.5 ch=4,5\n",
+        )
+        .unwrap();
+
+        let channels: Vec<(Option<u16>, bool)> = file
+            .records
+            .iter()
+            .filter_map(|line| match &line.record {
+                MtxtRecord::ControlChange { channel, note, .. } => Some((*channel, note.is_some())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            channels,
+            vec![
+                (Some(1), false),
+                (Some(2), false),
+                (Some(3), false),
+                (Some(4), true),
+                (Some(5), true),
+            ]
+        );
+        assert_eq!(
+            file.to_string(),
+            "mtxt 1.0\n1.0 cc volume 0.8 ch=1 // swell\n1.0 cc volume 0.8 ch=2\n\
+             1.0 cc volume 0.8 ch=3\n2.0 cc C4 pitch 0.5 ch=4\n2.0 cc C4 pitch 0.5 ch=5\n"
+        );
+
+        let err = parse_mtxt("mtxt 1.0\n1.0 note C4 ch=1,2\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Line #2: Channel lists (\"ch=1,2\") are only supported on cc events"
+        );
+        let err = parse_mtxt("mtxt 1.0\nch=1,2\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Line #2: The ch= directive takes a single channel"
+        );
+    }
+
+    #[test]
+    fn test_unsupported_inline_directives() {
+        let error = |line: &str| {
+            parse_mtxt(&format!("mtxt 1.0\n{}\n", line))
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(
+            error("1.0 tempo 120 vel=0.5"),
+            "Line #2: Unsupported directive \"vel=0.5\", tempo events accept transition_curve, transition_time, transition_interval, transition_steps"
+        );
+        assert_eq!(
+            error("1.0 note C4 foo=1"),
+            "Line #2: Unsupported directive \"foo=1\", note events accept dur, vel, offvel, ch"
+        );
+        assert_eq!(
+            error("1.0 meta dur=1 text hello"),
+            "Line #2: Unsupported directive \"dur=1\", meta events accept ch"
+        );
+        assert_eq!(
+            error("1.0 sysex 7e ch=1"),
+            "Line #2: Unsupported directive \"ch=1\", sysex events take no directives"
+        );
+        assert_eq!(
+            error("1.0 voice bank=16384 piano"),
+            "Line #2: Bank must be 0-16383"
+        );
+        assert_eq!(
+            error("1.0 on C4 vel=2"),
+            "Line #2: Velocity must be 0.0-1.0"
+        );
+
+        assert!(parse_mtxt("mtxt 1.0\n1.0 meta ch=2 text a=b\n1.0 voice ch=1 piano\n").is_ok());
+    }
+
+    #[test]
+    fn test_strict_alias_redefinition() {
+        let conflicting = "mtxt 1.0\nalias x C4,E4\n1.0 note x\nalias x C4,G4\n";
+        assert!(parse_mtxt(conflicting).is_ok());
+        assert_eq!(
+            parse_mtxt_strict(conflicting).unwrap_err().to_string(),
+            "Line #4: Alias \"x\" redefined with different notes (was alias x C4,E4)"
+        );
+
+        let identical = "mtxt 1.0\nalias x C4,E4\n1.0 note x\nalias x C4, E4\n";
+        assert!(parse_mtxt_strict(identical).is_ok());
+    }
+
+    #[test]
+    fn test_alias_of_alias_rejected() {
+        let error = |content: &str| parse_mtxt(content).unwrap_err().to_string();
+
+        // self and mutual references fail while parsing, before anything resolves them
+        assert_eq!(
+            error("mtxt 1.0\nalias x x\n1.0 note x\n"),
+            "Line #2: Invalid note \"x\" in alias \"x\", aliases can only contain notes \
+             (Invalid pitch class: x)"
+        );
+        assert!(
+            error("mtxt 1.0\nalias x C4,y\nalias y C4,x\n1.0 note x\n")
+                .starts_with("Line #2: Invalid note \"y\" in alias \"x\"")
+        );
+    }
+}
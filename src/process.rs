@@ -1,38 +1,149 @@
 use crate::BeatTime;
+use crate::Easing;
 use crate::MtxtRecord;
 use crate::Note;
 use crate::NoteTarget;
 use crate::transitions::TransitionProcessor;
+use crate::types::duration::NoteDuration;
 // use crate::transitions::expand_transitions;
 use crate::types::output_record::MtxtOutputRecord;
 use crate::types::pitch::PitchClass;
-use crate::types::record::AliasDefinition;
-use std::collections::HashMap;
-use std::rc::Rc;
+use crate::types::record::{ALL_CHANNELS, AliasTable};
+use anyhow::{Result, anyhow, bail};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+
+/// Values used for parameters that are neither set inline nor by a directive.
+pub const DEFAULT_DURATION: BeatTime = BeatTime::from_whole_beats(1);
+pub const DEFAULT_CHANNEL: u16 = 0;
+pub const DEFAULT_VELOCITY: f32 = 0.5;
+pub const DEFAULT_OFF_VELOCITY: f32 = 0.0;
+pub const DEFAULT_TRANSITION_INTERVAL: f32 = 0.01;
+/// Tempo before the first tempo event, used to turn `dur=250ms` into beats.
+pub const DEFAULT_BPM: f32 = 120.0;
+
+/// Pitch bend range in semitones (either way) when `meta global pitch_bend_range` is not
+/// set, the General MIDI default.
+pub const DEFAULT_PITCH_BEND_RANGE: f32 = 2.0;
+
+/// 14-bit pitch bend value of a bend in semitones, 8192 is no bend. Bends beyond `range`
+/// are clamped.
+pub fn semitones_to_pitch_bend(semitones: f32, range: f32) -> u16 {
+    (8192.0 + semitones / range * 8192.0)
+        .round()
+        .clamp(0.0, 16383.0) as u16
+}
+
+/// Bend in semitones of a 14-bit pitch bend value, the inverse of `semitones_to_pitch_bend`.
+pub fn pitch_bend_to_semitones(bend: u16, range: f32) -> f32 {
+    (bend as f32 - 8192.0) / 8192.0 * range
+}
+
+/// Number of MIDI channels, a `ch=*` event is sent to all of them. MTXT channels go up
+/// to 65535.
+pub(crate) const MIDI_CHANNEL_COUNT: u16 = 16;
 
 struct ProcessState {
-    duration: BeatTime,
+    duration: NoteDuration,
+    // bpm of the last tempo event, for clock time durations
+    bpm: f32,
     channel: u16,
     velocity: f32,
     off_velocity: f32,
-    transition_curve: f32,
+    transition_curve: Easing,
+    transition_time: BeatTime,
     transition_interval: f32,
-    aliases: HashMap<String, Rc<AliasDefinition>>,
+    transition_steps: u32,
+    aliases: AliasTable,
     tuning: HashMap<PitchClass, f32>,
+    // `tuning ch=N`, takes precedence over `tuning` on its channel
+    channel_tuning: HashMap<u16, HashMap<PitchClass, f32>>,
+    tuning_mode: TuningMode,
+}
+
+/// How `tuning` events reach the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TuningMode {
+    /// Added to the cents of the notes they tune.
+    #[default]
+    Bake,
+    /// Sent as MIDI Tuning Standard scale/octave tuning SysEx, notes keep their own cents.
+    Mts,
+}
+
+impl FromStr for TuningMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "bake" => Ok(TuningMode::Bake),
+            "mts" => Ok(TuningMode::Mts),
+            _ => Err(anyhow!("Invalid tuning mode: {}", s)),
+        }
+    }
+}
+
+/// Starting values of `process_records_with` for parameters that are neither set inline
+/// nor by a directive. The default uses the `DEFAULT_*` constants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessOptions {
+    pub channel: u16,
+    pub velocity: f32,
+    pub off_velocity: f32,
+    pub duration: BeatTime,
+    /// Milliseconds between the steps of smooth transitions.
+    pub transition_interval: f32,
+    pub tuning_mode: TuningMode,
+    /// Pitch bend range in semitones of `pitch` values, `pitch` ramps leave out the
+    /// points that would send the same pitch bend as the one before.
+    pub pitch_bend_range: f32,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            channel: DEFAULT_CHANNEL,
+            velocity: DEFAULT_VELOCITY,
+            off_velocity: DEFAULT_OFF_VELOCITY,
+            duration: DEFAULT_DURATION,
+            transition_interval: DEFAULT_TRANSITION_INTERVAL,
+            tuning_mode: TuningMode::default(),
+            pitch_bend_range: DEFAULT_PITCH_BEND_RANGE,
+        }
+    }
 }
 
 impl ProcessState {
-    fn new() -> Self {
+    fn new(options: &ProcessOptions) -> Self {
         Self {
-            duration: BeatTime::from_parts(1, 0.0),
-            channel: 0,
-            velocity: 64.0,
-            off_velocity: 0.0,
-            transition_curve: 0.0,
-            transition_interval: 0.01,
-            aliases: HashMap::new(),
+            duration: options.duration.into(),
+            bpm: DEFAULT_BPM,
+            channel: options.channel,
+            velocity: options.velocity,
+            off_velocity: options.off_velocity,
+            transition_curve: Easing::default(),
+            transition_time: BeatTime::zero(),
+            transition_interval: options.transition_interval,
+            transition_steps: 0,
+            aliases: AliasTable::default(),
             tuning: HashMap::new(),
+            channel_tuning: HashMap::new(),
+            tuning_mode: options.tuning_mode,
+        }
+    }
+
+    /// Tuning offset in cents of `pitch_class` on `channel`, 0 unless tuning is baked.
+    fn tuning_cents(&self, channel: u16, pitch_class: &PitchClass) -> f32 {
+        if self.tuning_mode != TuningMode::Bake {
+            return 0.0;
         }
+        self.channel_tuning
+            .get(&channel)
+            .and_then(|tuning| tuning.get(pitch_class))
+            .or_else(|| self.tuning.get(pitch_class))
+            .copied()
+            .unwrap_or(0.0)
     }
 }
 
@@ -41,19 +152,32 @@ pub struct IntermediateRecord {
     pub start_beat_time: BeatTime, // start_beat_time = end_beat_time - transition_time
     pub end_beat_time: BeatTime,
     pub record: MtxtOutputRecord,
-    pub transition_curve: f32,
+    pub transition_curve: Easing,
     pub transition_time: BeatTime,
     pub transition_interval: f32,
+    pub transition_steps: u32, // 0 = smooth ramp
 }
 
 pub fn process_records(records: &[MtxtRecord]) -> Vec<MtxtOutputRecord> {
-    let intermediate_output = create_intermediate_records(records);
-    let mut transition_processor = TransitionProcessor::new(&intermediate_output);
+    process_records_with(records, &ProcessOptions::default())
+}
+
+/// Like `process_records`, starting from `options` instead of the default values.
+pub fn process_records_with<'a>(
+    records: impl IntoIterator<Item = &'a MtxtRecord>,
+    options: &ProcessOptions,
+) -> Vec<MtxtOutputRecord> {
+    let intermediate_output = create_intermediate_records(records, options);
+    let mut transition_processor =
+        TransitionProcessor::new(intermediate_output, options.pitch_bend_range);
     transition_processor.process_all()
 }
 
-fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord> {
-    let mut state = ProcessState::new();
+fn create_intermediate_records<'a>(
+    records: impl IntoIterator<Item = &'a MtxtRecord>,
+    options: &ProcessOptions,
+) -> Vec<IntermediateRecord> {
+    let mut state = ProcessState::new(options);
     let mut intermediate_output = Vec::new();
 
     for record in records {
@@ -63,11 +187,13 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
             MtxtRecord::VelocityDirective { velocity } => state.velocity = *velocity,
             MtxtRecord::OffVelocityDirective { off_velocity } => state.off_velocity = *off_velocity,
             MtxtRecord::TransitionCurveDirective { curve } => state.transition_curve = *curve,
+            MtxtRecord::TransitionTimeDirective { duration } => state.transition_time = *duration,
             MtxtRecord::TransitionIntervalDirective { interval } => {
                 state.transition_interval = *interval
             }
+            MtxtRecord::TransitionStepsDirective { steps } => state.transition_steps = *steps,
             MtxtRecord::AliasDef { value } => {
-                state.aliases.insert(value.name.clone(), value.clone());
+                state.aliases.define(value);
             }
 
             // Events
@@ -79,16 +205,16 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                 off_velocity,
                 channel,
             } => {
-                let dur = duration.unwrap_or(state.duration);
+                let dur = duration
+                    .unwrap_or(state.duration)
+                    .to_beats(state.bpm as f64);
                 let vel = velocity.unwrap_or(state.velocity);
                 let off_vel = off_velocity.unwrap_or(state.off_velocity);
                 let ch = channel.unwrap_or(state.channel);
 
                 let notes = resolve_note_target(note, &state.aliases);
-                for mut n in notes {
-                    if let Some(cents) = state.tuning.get(&n.pitch_class) {
-                        n.cents += cents;
-                    }
+                for mut n in notes.iter().cloned() {
+                    n.cents += state.tuning_cents(ch, &n.pitch_class);
                     intermediate_output.push(IntermediateRecord {
                         start_beat_time: *time,
                         end_beat_time: *time,
@@ -98,9 +224,10 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                             velocity: vel,
                             channel: ch,
                         },
-                        transition_curve: 0.0,
+                        transition_curve: Easing::default(),
                         transition_time: BeatTime::zero(),
                         transition_interval: 0.0,
+                        transition_steps: 0,
                     });
 
                     intermediate_output.push(IntermediateRecord {
@@ -112,9 +239,10 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                             off_velocity: off_vel,
                             channel: ch,
                         },
-                        transition_curve: 0.0,
+                        transition_curve: Easing::default(),
                         transition_time: BeatTime::zero(),
                         transition_interval: 0.0,
+                        transition_steps: 0,
                     });
                 }
             }
@@ -124,27 +252,45 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                 note,
                 velocity,
                 channel,
+                length,
             } => {
                 let vel = velocity.unwrap_or(state.velocity);
                 let ch = channel.unwrap_or(state.channel);
+                let off_time = length.map(|length| *time + length.to_beats(state.bpm as f64));
                 let notes = resolve_note_target(note, &state.aliases);
-                for mut n in notes {
-                    if let Some(cents) = state.tuning.get(&n.pitch_class) {
-                        n.cents += cents;
-                    }
+                for mut n in notes.iter().cloned() {
+                    n.cents += state.tuning_cents(ch, &n.pitch_class);
                     intermediate_output.push(IntermediateRecord {
                         start_beat_time: *time,
                         end_beat_time: *time,
                         record: MtxtOutputRecord::NoteOn {
                             time: 0,
-                            note: n,
+                            note: n.clone(),
                             velocity: vel,
                             channel: ch,
                         },
-                        transition_curve: 0.0,
+                        transition_curve: Easing::default(),
                         transition_time: BeatTime::zero(),
                         transition_interval: 0.0,
+                        transition_steps: 0,
                     });
+
+                    if let Some(off_time) = off_time {
+                        intermediate_output.push(IntermediateRecord {
+                            start_beat_time: off_time,
+                            end_beat_time: off_time,
+                            record: MtxtOutputRecord::NoteOff {
+                                time: 0,
+                                note: n,
+                                off_velocity: state.off_velocity,
+                                channel: ch,
+                            },
+                            transition_curve: Easing::default(),
+                            transition_time: BeatTime::zero(),
+                            transition_interval: 0.0,
+                            transition_steps: 0,
+                        });
+                    }
                 }
             }
 
@@ -157,10 +303,8 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                 let off_vel = off_velocity.unwrap_or(state.off_velocity);
                 let ch = channel.unwrap_or(state.channel);
                 let notes = resolve_note_target(note, &state.aliases);
-                for mut n in notes {
-                    if let Some(cents) = state.tuning.get(&n.pitch_class) {
-                        n.cents += cents;
-                    }
+                for mut n in notes.iter().cloned() {
+                    n.cents += state.tuning_cents(ch, &n.pitch_class);
                     intermediate_output.push(IntermediateRecord {
                         start_beat_time: *time,
                         end_beat_time: *time,
@@ -170,9 +314,10 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                             off_velocity: off_vel,
                             channel: ch,
                         },
-                        transition_curve: 0.0,
+                        transition_curve: Easing::default(),
                         transition_time: BeatTime::zero(),
                         transition_interval: 0.0,
+                        transition_steps: 0,
                     });
                 }
             }
@@ -186,15 +331,17 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                 transition_curve,
                 transition_time,
                 transition_interval,
+                transition_steps,
             } => {
                 let ch = channel.unwrap_or(state.channel);
                 let t_curve = transition_curve.unwrap_or(state.transition_curve);
-                let t_time = transition_time.unwrap_or(BeatTime::zero());
+                let t_time = transition_time.unwrap_or(state.transition_time);
                 let t_interval = transition_interval.unwrap_or(state.transition_interval);
+                let t_steps = transition_steps.unwrap_or(state.transition_steps);
 
                 if let Some(target) = note {
                     let notes = resolve_note_target(target, &state.aliases);
-                    for n in notes {
+                    for n in notes.iter().cloned() {
                         intermediate_output.push(IntermediateRecord {
                             start_beat_time: *time - t_time,
                             end_beat_time: *time,
@@ -208,6 +355,7 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                             transition_curve: t_curve,
                             transition_time: t_time,
                             transition_interval: t_interval,
+                            transition_steps: t_steps,
                         });
                     }
                 } else {
@@ -224,6 +372,7 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                         transition_curve: t_curve,
                         transition_time: t_time,
                         transition_interval: t_interval,
+                        transition_steps: t_steps,
                     });
                 }
             }
@@ -232,8 +381,31 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                 time,
                 voices,
                 channel,
+                bank,
             } => {
                 let ch = channel.unwrap_or(state.channel);
+
+                // bank select goes out as CC 0 (MSB) and CC 32 (LSB) before the program change
+                if let Some(bank) = bank {
+                    for (controller, value) in [("0", bank >> 7), ("32", bank & 0x7F)] {
+                        intermediate_output.push(IntermediateRecord {
+                            start_beat_time: *time,
+                            end_beat_time: *time,
+                            record: MtxtOutputRecord::ControlChange {
+                                time: 0,
+                                note: None,
+                                controller: controller.to_string(),
+                                value: value as f32 / 127.0,
+                                channel: ch,
+                            },
+                            transition_curve: Easing::default(),
+                            transition_time: BeatTime::zero(),
+                            transition_interval: 0.0,
+                            transition_steps: 0,
+                        });
+                    }
+                }
+
                 intermediate_output.push(IntermediateRecord {
                     start_beat_time: *time,
                     end_beat_time: *time,
@@ -242,9 +414,10 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                         voices: voices.clone(),
                         channel: ch,
                     },
-                    transition_curve: 0.0,
+                    transition_curve: Easing::default(),
                     transition_time: BeatTime::zero(),
                     transition_interval: 0.0,
+                    transition_steps: 0,
                 });
             }
 
@@ -254,10 +427,13 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                 transition_curve,
                 transition_time,
                 transition_interval,
+                transition_steps,
             } => {
+                state.bpm = *bpm;
                 let t_curve = transition_curve.unwrap_or(state.transition_curve);
-                let t_time = transition_time.unwrap_or(BeatTime::zero());
+                let t_time = transition_time.unwrap_or(state.transition_time);
                 let t_interval = transition_interval.unwrap_or(state.transition_interval);
+                let t_steps = transition_steps.unwrap_or(state.transition_steps);
 
                 intermediate_output.push(IntermediateRecord {
                     start_beat_time: *time - t_time,
@@ -266,6 +442,7 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                     transition_curve: t_curve,
                     transition_time: t_time,
                     transition_interval: t_interval,
+                    transition_steps: t_steps,
                 });
             }
 
@@ -277,34 +454,87 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                         time: 0,
                         signature: signature.clone(),
                     },
-                    transition_curve: 0.0,
+                    transition_curve: Easing::default(),
                     transition_time: BeatTime::zero(),
                     transition_interval: 0.0,
+                    transition_steps: 0,
+                });
+            }
+
+            // bars are markers only, they don't move or end anything
+            MtxtRecord::Bar { time, number } => {
+                intermediate_output.push(IntermediateRecord {
+                    start_beat_time: *time,
+                    end_beat_time: *time,
+                    record: MtxtOutputRecord::GlobalMeta {
+                        time: 0,
+                        meta_type: "marker".to_string(),
+                        value: format!("Bar {}", number),
+                    },
+                    transition_curve: Easing::default(),
+                    transition_time: BeatTime::zero(),
+                    transition_interval: 0.0,
+                    transition_steps: 0,
                 });
             }
 
             MtxtRecord::Tuning {
-                time: _,
+                time,
                 target,
                 cents,
+                channel,
             } => {
                 if let Ok(pitch_class) = target.parse::<PitchClass>() {
-                    state.tuning.insert(pitch_class, *cents);
+                    let channel = channel.filter(|ch| *ch != ALL_CHANNELS);
+                    let tuning = match channel {
+                        Some(ch) => state.channel_tuning.entry(ch).or_default(),
+                        None => &mut state.tuning,
+                    };
+                    tuning.insert(pitch_class, *cents);
+
+                    if state.tuning_mode == TuningMode::Mts {
+                        for data in mts_tuning_messages(&state, channel) {
+                            intermediate_output.push(IntermediateRecord {
+                                start_beat_time: *time,
+                                end_beat_time: *time,
+                                record: MtxtOutputRecord::SysEx { time: 0, data },
+                                transition_curve: Easing::default(),
+                                transition_time: BeatTime::zero(),
+                                transition_interval: 0.0,
+                                transition_steps: 0,
+                            });
+                        }
+                    }
                 }
             }
 
             MtxtRecord::Reset { time, target } => {
-                intermediate_output.push(IntermediateRecord {
-                    start_beat_time: *time,
-                    end_beat_time: *time,
-                    record: MtxtOutputRecord::Reset {
-                        time: 0,
-                        target: target.clone(),
-                    },
-                    transition_curve: 0.0,
-                    transition_time: BeatTime::zero(),
-                    transition_interval: 0.0,
-                });
+                // `reset ch=9` resets the controllers and stops the notes of that channel
+                let resets = match target.strip_prefix("ch=") {
+                    Some(channel) => {
+                        let channel = match channel {
+                            "*" => ALL_CHANNELS,
+                            _ => channel.parse().unwrap_or(state.channel),
+                        };
+                        vec![("controllers", channel), ("notes", channel)]
+                    }
+                    None => vec![(target.as_str(), state.channel)],
+                };
+                for (target, channel) in resets {
+                    intermediate_output.push(IntermediateRecord {
+                        start_beat_time: *time,
+                        end_beat_time: *time,
+                        record: MtxtOutputRecord::Reset {
+                            time: 0,
+                            target: target.to_string(),
+                            channel,
+                        },
+                        transition_curve: Easing::default(),
+                        transition_time: BeatTime::zero(),
+                        transition_interval: 0.0,
+                        transition_steps: 0,
+                    });
+                }
             }
 
             MtxtRecord::Meta {
@@ -324,9 +554,10 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                         meta_type: meta_type.clone(),
                         value: value.clone(),
                     },
-                    transition_curve: 0.0,
+                    transition_curve: Easing::default(),
                     transition_time: BeatTime::zero(),
                     transition_interval: 0.0,
+                    transition_steps: 0,
                 });
             }
 
@@ -339,9 +570,10 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                         meta_type: meta_type.clone(),
                         value: value.clone(),
                     },
-                    transition_curve: 0.0,
+                    transition_curve: Easing::default(),
                     transition_time: BeatTime::zero(),
                     transition_interval: 0.0,
+                    transition_steps: 0,
                 });
             }
 
@@ -353,33 +585,332 @@ fn create_intermediate_records(records: &[MtxtRecord]) -> Vec<IntermediateRecord
                         time: 0,
                         data: data.clone(),
                     },
-                    transition_curve: 0.0,
+                    transition_curve: Easing::default(),
                     transition_time: BeatTime::zero(),
                     transition_interval: 0.0,
+                    transition_steps: 0,
                 });
             }
 
-            MtxtRecord::Header { version: _ } | MtxtRecord::EmptyLine => {}
+            MtxtRecord::Header { version: _ }
+            | MtxtRecord::EmptyLine
+            | MtxtRecord::Comment { .. }
+            | MtxtRecord::BlockComment { .. } => {}
         }
     }
 
-    intermediate_output.sort_by(|a, b| a.end_beat_time.cmp(&b.end_beat_time));
+    let mut intermediate_output = expand_all_channels(intermediate_output);
+    intermediate_output.sort_by_key(|a| a.end_beat_time);
     intermediate_output
 }
 
-fn resolve_note_target(
-    target: &NoteTarget,
-    aliases: &HashMap<String, Rc<AliasDefinition>>,
-) -> Vec<Note> {
+/// MTS scale/octave tuning (real-time, 2-byte form) of the MIDI channels a tuning
+/// event on `channel` (`None` for all) changed, one message per distinct tuning.
+/// Each message carries the whole octave of the channels it addresses.
+fn mts_tuning_messages(state: &ProcessState, channel: Option<u16>) -> Vec<Vec<u8>> {
+    let channels = match channel {
+        Some(ch) if ch >= MIDI_CHANNEL_COUNT => 0..0,
+        Some(ch) => ch..ch + 1,
+        None => 0..MIDI_CHANNEL_COUNT,
+    };
+
+    // 14-bit value of each semitone of the octave -> channel bit mask
+    let mut messages: BTreeMap<[u16; 12], u16> = BTreeMap::new();
+    for ch in channels {
+        let mut values = [MTS_CENTER; 12];
+        let channel_tuning = state.channel_tuning.get(&ch);
+        // the channel's own tuning goes last so it takes precedence
+        for tuning in std::iter::once(&state.tuning).chain(channel_tuning) {
+            for (pitch_class, cents) in tuning {
+                values[pitch_class.to_semitone() as usize] = mts_value(*cents);
+            }
+        }
+        *messages.entry(values).or_default() |= 1 << ch;
+    }
+
+    messages
+        .into_iter()
+        .map(|(values, mask)| {
+            let mut data = vec![
+                0xF0,
+                0x7F, // universal real-time
+                0x7F, // all devices
+                0x08, // MIDI tuning standard
+                0x09, // scale/octave tuning, 2-byte form
+                ((mask >> 14) & 0x03) as u8,
+                ((mask >> 7) & 0x7F) as u8,
+                (mask & 0x7F) as u8,
+            ];
+            for value in values {
+                data.push((value >> 7) as u8);
+                data.push((value & 0x7F) as u8);
+            }
+            data.push(0xF7);
+            data
+        })
+        .collect()
+}
+
+/// 14-bit MTS value of 0 cents, 0 is -100 and 16383 is +100 cents.
+const MTS_CENTER: u16 = 0x2000;
+
+fn mts_value(cents: f32) -> u16 {
+    let value = MTS_CENTER as f32 + (cents * MTS_CENTER as f32 / 100.0).round();
+    value.clamp(0.0, 0x3FFF as f32) as u16
+}
+
+/// Sends each `ch=*` event out once on every MIDI channel.
+fn expand_all_channels(records: Vec<IntermediateRecord>) -> Vec<IntermediateRecord> {
+    let mut expanded = Vec::with_capacity(records.len());
+    for mut record in records {
+        if !matches!(record.record.channel_mut(), Some(ch) if *ch == ALL_CHANNELS) {
+            expanded.push(record);
+            continue;
+        }
+        for channel in 0..MIDI_CHANNEL_COUNT {
+            let mut copy = record.clone();
+            if let Some(ch) = copy.record.channel_mut() {
+                *ch = channel;
+            }
+            expanded.push(copy);
+        }
+    }
+    expanded
+}
+
+/// Longest chain of nested aliases `resolve_alias` follows.
+const MAX_ALIAS_DEPTH: usize = 32;
+
+/// Notes of the alias `name`, with the aliases among its members expanded in place.
+/// `members` looks up the members of an alias. Resolution is iterative and fails on an
+/// undefined alias, on an alias that refers back to itself, and on nesting deeper than
+/// `MAX_ALIAS_DEPTH`, instead of overflowing the stack.
+pub(crate) fn resolve_alias(
+    name: &str,
+    members: impl Fn(&str) -> Option<Vec<NoteTarget>>,
+) -> Result<Vec<Note>> {
+    let lookup = |name: &str| {
+        members(name)
+            .map(Vec::into_iter)
+            .ok_or_else(|| anyhow!("Undefined alias \"{}\"", name))
+    };
+
+    let mut notes = Vec::new();
+    // aliases being expanded, outermost first, with their members not expanded yet
+    let mut stack = vec![(name.to_string(), lookup(name)?)];
+    let mut visited: HashSet<String> = HashSet::from([name.to_string()]);
+
+    while let Some((_, pending)) = stack.last_mut() {
+        match pending.next() {
+            Some(NoteTarget::Note(note)) => notes.push(note),
+            Some(NoteTarget::Alias(def)) => notes.extend(def.notes.iter().cloned()),
+            Some(NoteTarget::AliasKey(nested)) => {
+                if visited.contains(&nested) {
+                    let chain: Vec<&str> = stack.iter().map(|(name, _)| name.as_str()).collect();
+                    bail!(
+                        "Alias \"{}\" refers back to itself: {} -> {}",
+                        nested,
+                        chain.join(" -> "),
+                        nested
+                    );
+                }
+                if stack.len() >= MAX_ALIAS_DEPTH {
+                    bail!(
+                        "Alias \"{}\" nests aliases more than {} deep",
+                        name,
+                        MAX_ALIAS_DEPTH
+                    );
+                }
+                let pending = lookup(&nested)?;
+                visited.insert(nested.clone());
+                stack.push((nested, pending));
+            }
+            None => {
+                let (done, _) = stack.pop().unwrap();
+                visited.remove(&done);
+            }
+        }
+    }
+    Ok(notes)
+}
+
+/// Notes of a target. An alias name is resolved through `aliases` with `resolve_alias`;
+/// one that doesn't resolve plays no notes.
+pub(crate) fn resolve_note_target<'a>(
+    target: &'a NoteTarget,
+    aliases: &'a AliasTable,
+) -> Cow<'a, [Note]> {
     match target {
-        NoteTarget::Note(note) => vec![note.clone()],
+        NoteTarget::Note(note) => Cow::Borrowed(std::slice::from_ref(note)),
         NoteTarget::AliasKey(name) => {
-            if let Some(def) = aliases.get(name) {
-                def.notes.clone()
-            } else {
-                vec![]
-            }
+            let members = |name: &str| {
+                let def = aliases.get(name)?;
+                Some(def.notes.iter().cloned().map(NoteTarget::Note).collect())
+            };
+            Cow::Owned(resolve_alias(name, members).unwrap_or_default())
         }
-        NoteTarget::Alias(def) => def.notes.clone(),
+        NoteTarget::Alias(def) => Cow::Borrowed(&def.notes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_mtxt;
+
+    #[test]
+    fn test_directives_override_options() {
+        let file = parse_mtxt("mtxt 1.0\n0.0 note C4\nvel=0.75\nch=2\n1.0 note D4\n").unwrap();
+        let options = ProcessOptions {
+            channel: 5,
+            velocity: 0.25,
+            ..ProcessOptions::default()
+        };
+
+        let note_ons: Vec<(f32, u16)> = process_records_with(file.get_records(), &options)
+            .into_iter()
+            .filter_map(|record| match record {
+                MtxtOutputRecord::NoteOn {
+                    velocity, channel, ..
+                } => Some((velocity, channel)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(note_ons, vec![(0.25, 5), (0.75, 2)]);
+    }
+
+    #[test]
+    fn test_clock_time_durations() {
+        // 500ms is one beat at 120 bpm and half a beat at 60 bpm
+        let file = parse_mtxt(
+            "mtxt 1.0\n0.0 tempo 120\n0.0 note C4 dur=500ms\n1.0 note D4 dur=1\n\
+             2.0 tempo 60\ndur=0.5s\n2.0 note E4\n",
+        )
+        .unwrap();
+        assert_eq!(file.records[2].record.to_string(), "note C4 dur=500ms");
+
+        let note_offs: Vec<String> =
+            process_records_with(file.get_records(), &ProcessOptions::default())
+                .iter()
+                .map(|record| record.to_string())
+                .filter(|record| record.contains("NoteOff"))
+                .collect();
+        assert_eq!(
+            note_offs,
+            vec![
+                "[     500] NoteOff C4 off_vel=0 ch=0",
+                "[    1000] NoteOff D4 off_vel=0 ch=0",
+                "[    1500] NoteOff E4 off_vel=0 ch=0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_channel_tuning() {
+        let file = parse_mtxt(
+            "mtxt 1.0\n0.0 tuning C +5\n0.0 tuning E -10\n0.0 tuning ch=2 C +10\n\
+             0.0 tuning ch=3 E -20\n1.0 note C4 ch=1\n1.0 note C4 ch=2\n1.0 note E4 ch=2\n\
+             1.0 note E4 ch=3\n",
+        )
+        .unwrap();
+        assert_eq!(file.records[3].record.to_string(), "tuning ch=2 C +10.0");
+
+        let note_ons: Vec<(u16, String)> =
+            process_records_with(file.get_records(), &ProcessOptions::default())
+                .into_iter()
+                .filter_map(|record| match record {
+                    MtxtOutputRecord::NoteOn { note, channel, .. } => {
+                        Some((channel, note.to_string()))
+                    }
+                    _ => None,
+                })
+                .collect();
+        // the global tuning applies to pitch classes a channel doesn't tune itself
+        assert_eq!(
+            note_ons,
+            vec![
+                (1, "C4+5".to_string()),
+                (2, "C4+10".to_string()),
+                (2, "E4-10".to_string()),
+                (3, "E4-20".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_note_on_length() {
+        let file = parse_mtxt(
+            "mtxt 1.0\n0.0 tempo 120\noffvel=0.25\n0.0 on C4 len=0.5 vel=0.8\n\
+             1.0 on D4 len=250ms\n2.0 on E4\n3.0 off E4\n",
+        )
+        .unwrap();
+        assert_eq!(file.records[3].to_string(), "0.0 on C4 len=0.5 vel=0.8");
+        assert!(parse_mtxt("mtxt 1.0\nlen=1\n").is_err());
+
+        let output: Vec<String> =
+            process_records_with(file.get_records(), &ProcessOptions::default())
+                .iter()
+                .map(|record| record.to_string())
+                .filter(|record| record.contains("Note"))
+                .collect();
+        assert_eq!(
+            output,
+            vec![
+                "[       0] NoteOn C4 vel=0.8 ch=0",
+                "[     250] NoteOff C4 off_vel=0.25 ch=0",
+                "[     500] NoteOn D4 vel=0.5 ch=0",
+                "[     750] NoteOff D4 off_vel=0.25 ch=0",
+                "[    1000] NoteOn E4 vel=0.5 ch=0",
+                "[    1500] NoteOff E4 off_vel=0.25 ch=0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_alias_cycles() {
+        let target = |s: &str| s.parse::<NoteTarget>().unwrap();
+        let aliases: HashMap<&str, Vec<NoteTarget>> = HashMap::from([
+            ("chord", vec![target("C4"), target("triad")]),
+            ("triad", vec![target("E4"), target("G4")]),
+            ("x", vec![target("C4"), target("y")]),
+            ("y", vec![target("x")]),
+            ("self", vec![target("self")]),
+        ]);
+        let resolve = |name: &str| {
+            resolve_alias(name, |name: &str| aliases.get(name).cloned())
+                .map_err(|error| error.to_string())
+        };
+
+        assert_eq!(
+            resolve("chord").unwrap(),
+            ["C4", "E4", "G4"].map(|note| note.parse::<Note>().unwrap())
+        );
+        assert_eq!(
+            resolve("x").unwrap_err(),
+            "Alias \"x\" refers back to itself: x -> y -> x"
+        );
+        assert_eq!(
+            resolve("self").unwrap_err(),
+            "Alias \"self\" refers back to itself: self -> self"
+        );
+        assert_eq!(resolve("none").unwrap_err(), "Undefined alias \"none\"");
+
+        // a long chain without a cycle stops at the depth cap
+        let chain: HashMap<String, Vec<NoteTarget>> = (0..100)
+            .map(|i| {
+                (
+                    format!("step{}", i),
+                    vec![target(&format!("step{}", i + 1))],
+                )
+            })
+            .collect();
+        let error = resolve_alias("step0", |name: &str| chain.get(name).cloned()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Alias \"step0\" nests aliases more than {} deep",
+                MAX_ALIAS_DEPTH
+            )
+        );
     }
 }
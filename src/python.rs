@@ -126,6 +126,11 @@ impl PyMtxtFile {
         self.inner.duration().map(|bt| bt.as_f64())
     }
 
+    #[getter]
+    fn duration_seconds(&self) -> Option<f64> {
+        self.inner.duration_seconds()
+    }
+
     fn __len__(&self) -> usize {
         self.inner.get_records().len()
     }
@@ -1,30 +1,48 @@
-use crate::types::record::MtxtRecordLine;
+use crate::types::duration::NoteDuration;
+use crate::types::note::OctaveConvention;
 use crate::types::record::VoiceList;
+use crate::types::record::{ALL_CHANNELS, ChannelLabel, MtxtRecordLine, VelocityScale};
 use crate::{
-    BeatTime, MtxtRecord, Note, NoteTarget, TimeSignature, Version, types::record::AliasDefinition,
+    BeatTime, Easing, MtxtRecord, Note, NoteTarget, TimeSignature, Version,
+    types::record::AliasDefinition,
 };
 use anyhow::{Result, bail};
 use std::fmt;
-use std::rc::Rc;
+use std::sync::Arc;
+
+/// Largest bank number, sent as a 14-bit value over CC 0 (MSB) and CC 32 (LSB).
+pub const MAX_BANK: u16 = 0x3FFF;
 
 #[derive(Debug)]
 enum ParsedDirective {
     Channel { channel: u16 },
+    // `ch=1,2,3`, only cc events accept a list
+    Channels { channels: Vec<u16> },
     Velocity { velocity: f32 },
     OffVelocity { off_velocity: f32 },
-    Duration { duration: BeatTime },
-    TransitionCurve { curve: f32 },
+    Duration { duration: NoteDuration },
+    Length { length: NoteDuration },
+    TransitionCurve { curve: Easing },
     TransitionTime { duration: BeatTime },
     TransitionInterval { interval: f32 },
+    TransitionSteps { steps: u32 },
+    Bank { bank: u16 },
 }
 
 impl fmt::Display for ParsedDirective {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ParsedDirective::Channel { channel } => write!(f, "ch={}", channel),
+            ParsedDirective::Channel { channel } => {
+                write!(f, "ch={}", ChannelLabel(*channel))
+            }
+            ParsedDirective::Channels { channels } => {
+                let channels: Vec<String> = channels.iter().map(|ch| ch.to_string()).collect();
+                write!(f, "ch={}", channels.join(","))
+            }
             ParsedDirective::Velocity { velocity } => write!(f, "vel={}", velocity),
             ParsedDirective::OffVelocity { off_velocity } => write!(f, "offvel={}", off_velocity),
             ParsedDirective::Duration { duration } => write!(f, "dur={}", duration),
+            ParsedDirective::Length { length } => write!(f, "len={}", length),
             ParsedDirective::TransitionCurve { curve } => write!(f, "transition_curve={}", curve),
             ParsedDirective::TransitionTime { duration } => {
                 write!(f, "transition_time={}", duration)
@@ -32,46 +50,61 @@ impl fmt::Display for ParsedDirective {
             ParsedDirective::TransitionInterval { interval } => {
                 write!(f, "transition_interval={}", interval)
             }
+            ParsedDirective::TransitionSteps { steps } => write!(f, "transition_steps={}", steps),
+            ParsedDirective::Bank { bank } => write!(f, "bank={}", bank),
         }
     }
 }
 
-fn try_parse_directive(part: &str) -> Result<Option<ParsedDirective>> {
+fn try_parse_directive(
+    part: &str,
+    velocity_scale: VelocityScale,
+) -> Result<Option<ParsedDirective>> {
     let splitted = part.split_once("=");
     if let Some((key, value)) = splitted {
         match key {
             "ch" => {
-                let channel: u16 = value
-                    .parse()
-                    .map_err(|_| anyhow::anyhow!("Invalid channel number"))?;
+                if value == "*" {
+                    return Ok(Some(ParsedDirective::Channel {
+                        channel: ALL_CHANNELS,
+                    }));
+                }
+                let parse_channel = |value: &str| {
+                    value
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|channel| *channel != ALL_CHANNELS)
+                        .ok_or_else(|| anyhow::anyhow!("Invalid channel number"))
+                };
+                if value.contains(',') {
+                    let channels = value.split(',').map(parse_channel).collect::<Result<_>>()?;
+                    return Ok(Some(ParsedDirective::Channels { channels }));
+                }
+                let channel = parse_channel(value)?;
                 Ok(Some(ParsedDirective::Channel { channel }))
             }
             "vel" => {
-                let velocity: f32 = value
-                    .parse()
-                    .map_err(|_| anyhow::anyhow!("Invalid velocity value"))?;
-                if !(0.0..=1.0).contains(&velocity) {
-                    bail!("Velocity must be 0.0-1.0");
-                }
+                let velocity = parse_velocity(value, velocity_scale, "Velocity")?;
                 Ok(Some(ParsedDirective::Velocity { velocity }))
             }
             "offvel" => {
-                let off_velocity: f32 = value
-                    .parse()
-                    .map_err(|_| anyhow::anyhow!("Invalid off velocity value"))?;
-                if !(0.0..=1.0).contains(&off_velocity) {
-                    bail!("Off velocity must be 0.0-1.0");
-                }
+                let off_velocity = parse_velocity(value, velocity_scale, "Off velocity")?;
                 Ok(Some(ParsedDirective::OffVelocity { off_velocity }))
             }
             "dur" => {
-                let duration: BeatTime = value
+                let duration: NoteDuration = value
                     .parse()
                     .map_err(|_| anyhow::anyhow!("Invalid duration value"))?;
                 Ok(Some(ParsedDirective::Duration { duration }))
             }
+            "len" => {
+                let length: NoteDuration = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid length value"))?;
+                Ok(Some(ParsedDirective::Length { length }))
+            }
             "transition_curve" => {
-                let curve: f32 = value
+                let curve: Easing = value
                     .parse()
                     .map_err(|_| anyhow::anyhow!("Invalid transition_curve value"))?;
                 Ok(Some(ParsedDirective::TransitionCurve { curve }))
@@ -91,6 +124,21 @@ fn try_parse_directive(part: &str) -> Result<Option<ParsedDirective>> {
                 }
                 Ok(Some(ParsedDirective::TransitionInterval { interval }))
             }
+            "transition_steps" => {
+                let steps: u32 = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid transition_steps value"))?;
+                Ok(Some(ParsedDirective::TransitionSteps { steps }))
+            }
+            "bank" => {
+                let bank: u16 = value
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Invalid bank number"))?;
+                if bank > MAX_BANK {
+                    bail!("Bank must be 0-{}", MAX_BANK);
+                }
+                Ok(Some(ParsedDirective::Bank { bank }))
+            }
             _ => bail!("Invalid directive"),
         }
     } else {
@@ -98,8 +146,170 @@ fn try_parse_directive(part: &str) -> Result<Option<ParsedDirective>> {
     }
 }
 
-fn try_parse_global_directive(part: &str) -> Result<Option<MtxtRecord>> {
-    let parsed = try_parse_directive(part)?;
+/// Parses a `vel=` / `offvel=` value written in `velocity_scale` into 0.0-1.0.
+fn parse_velocity(value: &str, velocity_scale: VelocityScale, label: &str) -> Result<f32> {
+    match velocity_scale {
+        VelocityScale::Unit => {
+            let velocity: f32 = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid {} value", label.to_lowercase()))?;
+            if !(0.0..=1.0).contains(&velocity) {
+                bail!("{} must be 0.0-1.0", label);
+            }
+            Ok(velocity)
+        }
+        VelocityScale::Midi127 => {
+            let velocity: u32 = value.parse().map_err(|_| {
+                anyhow::anyhow!("Invalid {} value, expected 0-127", label.to_lowercase())
+            })?;
+            if velocity > 127 {
+                bail!("{} must be 0-127", label);
+            }
+            Ok(velocity as f32 / 127.0)
+        }
+    }
+}
+
+// Inline directives accepted by each event type. Anything else is rejected
+// with an error listing these, rather than silently ignored.
+const NOTE_DIRECTIVES: &[&str] = &["dur", "vel", "offvel", "ch"];
+const NOTE_ON_DIRECTIVES: &[&str] = &["len", "vel", "ch"];
+const NOTE_OFF_DIRECTIVES: &[&str] = &["offvel", "ch"];
+const CC_DIRECTIVES: &[&str] = &[
+    "ch",
+    "transition_curve",
+    "transition_time",
+    "transition_interval",
+    "transition_steps",
+];
+const TEMPO_DIRECTIVES: &[&str] = &[
+    "transition_curve",
+    "transition_time",
+    "transition_interval",
+    "transition_steps",
+];
+// voice, meta and tuning events take their directives before the arguments
+const VOICE_DIRECTIVES: &[&str] = &["ch", "bank"];
+const META_DIRECTIVES: &[&str] = &["ch"];
+const TUNING_DIRECTIVES: &[&str] = &["ch"];
+// timesig and sysex. `reset ch=9` is a target, not a directive
+const NO_DIRECTIVES: &[&str] = &[];
+
+/// Parses `part` as an inline directive of `event`, which accepts the `allowed` keys.
+/// Returns `None` when the part is not a directive at all.
+fn parse_event_directive(
+    event: &str,
+    part: &str,
+    allowed: &[&str],
+    velocity_scale: VelocityScale,
+) -> Result<Option<ParsedDirective>> {
+    let Some((key, _)) = part.split_once('=') else {
+        return Ok(None);
+    };
+
+    if !allowed.contains(&key) {
+        if allowed.is_empty() {
+            bail!(
+                "Unsupported directive \"{}\", {} events take no directives",
+                part,
+                event
+            );
+        }
+        bail!(
+            "Unsupported directive \"{}\", {} events accept {}",
+            part,
+            event,
+            allowed.join(", ")
+        );
+    }
+
+    let directive = try_parse_directive(part, velocity_scale)?;
+    if let Some(ParsedDirective::Channels { .. }) = directive
+        && event != "cc"
+    {
+        bail!(
+            "Channel lists (\"{}\") are only supported on cc events",
+            part
+        );
+    }
+    Ok(directive)
+}
+
+/// For events without directives, gives a clearer error than their argument parsing would.
+fn reject_directives(event: &str, parts: &[&str]) -> Result<()> {
+    for part in parts {
+        parse_event_directive(event, part, NO_DIRECTIVES, VelocityScale::Unit)?;
+    }
+    Ok(())
+}
+
+/// Inline directives of a single event, keys the event doesn't accept stay `None`.
+#[derive(Default)]
+struct InlineDirectives {
+    channel: Option<u16>,
+    // set instead of `channel` by a `ch=1,2,3` list
+    channels: Vec<u16>,
+    velocity: Option<f32>,
+    off_velocity: Option<f32>,
+    duration: Option<NoteDuration>,
+    length: Option<NoteDuration>,
+    transition_curve: Option<Easing>,
+    transition_time: Option<BeatTime>,
+    transition_interval: Option<f32>,
+    transition_steps: Option<u32>,
+    bank: Option<u16>,
+}
+
+/// Parses the trailing `key=value` parameters of an event.
+fn parse_inline_directives(
+    event: &str,
+    parts: &[&str],
+    allowed: &[&str],
+    velocity_scale: VelocityScale,
+) -> Result<InlineDirectives> {
+    let mut directives = InlineDirectives::default();
+
+    for part in parts {
+        match parse_event_directive(event, part, allowed, velocity_scale)? {
+            Some(ParsedDirective::Channel { channel }) => {
+                directives.channel = Some(channel);
+                directives.channels.clear();
+            }
+            Some(ParsedDirective::Channels { channels }) => {
+                directives.channel = None;
+                directives.channels = channels;
+            }
+            Some(ParsedDirective::Velocity { velocity }) => directives.velocity = Some(velocity),
+            Some(ParsedDirective::OffVelocity { off_velocity }) => {
+                directives.off_velocity = Some(off_velocity)
+            }
+            Some(ParsedDirective::Duration { duration }) => directives.duration = Some(duration),
+            Some(ParsedDirective::Length { length }) => directives.length = Some(length),
+            Some(ParsedDirective::TransitionCurve { curve }) => {
+                directives.transition_curve = Some(curve)
+            }
+            Some(ParsedDirective::TransitionTime { duration }) => {
+                directives.transition_time = Some(duration)
+            }
+            Some(ParsedDirective::TransitionInterval { interval }) => {
+                directives.transition_interval = Some(interval)
+            }
+            Some(ParsedDirective::TransitionSteps { steps }) => {
+                directives.transition_steps = Some(steps)
+            }
+            Some(ParsedDirective::Bank { bank }) => directives.bank = Some(bank),
+            None => bail!("Unexpected \"{}\" in {} event", part, event),
+        }
+    }
+
+    Ok(directives)
+}
+
+fn try_parse_global_directive(
+    part: &str,
+    velocity_scale: VelocityScale,
+) -> Result<Option<MtxtRecord>> {
+    let parsed = try_parse_directive(part, velocity_scale)?;
     if let Some(parsed) = parsed {
         match parsed {
             ParsedDirective::Channel { channel } => {
@@ -120,10 +330,20 @@ fn try_parse_global_directive(part: &str) -> Result<Option<MtxtRecord>> {
             ParsedDirective::TransitionInterval { interval } => {
                 Ok(Some(MtxtRecord::TransitionIntervalDirective { interval }))
             }
-            ParsedDirective::TransitionTime {
-                duration: _duration,
-            } => {
-                bail!("transition_time= is not supported here");
+            ParsedDirective::TransitionSteps { steps } => {
+                Ok(Some(MtxtRecord::TransitionStepsDirective { steps }))
+            }
+            ParsedDirective::TransitionTime { duration } => {
+                Ok(Some(MtxtRecord::TransitionTimeDirective { duration }))
+            }
+            ParsedDirective::Bank { bank: _bank } => {
+                bail!("bank= is not supported here");
+            }
+            ParsedDirective::Length { .. } => {
+                bail!("len= is only supported on on events");
+            }
+            ParsedDirective::Channels { .. } => {
+                bail!("The ch= directive takes a single channel");
             }
         }
     } else {
@@ -131,129 +351,93 @@ fn try_parse_global_directive(part: &str) -> Result<Option<MtxtRecord>> {
     }
 }
 
-fn parse_note_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
+fn parse_note_event(
+    time: BeatTime,
+    parts: &[&str],
+    velocity_scale: VelocityScale,
+    octave_convention: OctaveConvention,
+) -> Result<MtxtRecord> {
     if parts.is_empty() {
         bail!("Note event requires note name");
     }
 
-    let note: NoteTarget = parts[0]
-        .parse()
+    let note = NoteTarget::parse_with(parts[0], octave_convention)
         .map_err(|_| anyhow::anyhow!("Invalid note"))?;
 
-    let mut duration = None;
-    let mut velocity = None;
-    let mut off_velocity = None;
-    let mut channel = None;
-
-    for part in &parts[1..] {
-        let directive = try_parse_directive(part);
-        match directive {
-            Ok(d) => match d {
-                Some(ParsedDirective::Duration { duration: d }) => {
-                    duration = Some(d);
-                }
-                Some(ParsedDirective::Velocity { velocity: v }) => {
-                    velocity = Some(v);
-                }
-                Some(ParsedDirective::OffVelocity { off_velocity: v }) => {
-                    off_velocity = Some(v);
-                }
-                Some(ParsedDirective::Channel { channel: c }) => {
-                    channel = Some(c);
-                }
-                _ => bail!("Unsupported directive \"{}\"", part),
-            },
-            Err(e) => bail!("{}", e),
-        }
-    }
+    let directives = parse_inline_directives("note", &parts[1..], NOTE_DIRECTIVES, velocity_scale)?;
 
     Ok(MtxtRecord::Note {
         time,
         note,
-        duration,
-        velocity,
-        off_velocity,
-        channel,
+        duration: directives.duration,
+        velocity: directives.velocity,
+        off_velocity: directives.off_velocity,
+        channel: directives.channel,
     })
 }
 
-fn parse_note_on_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
+fn parse_note_on_event(
+    time: BeatTime,
+    parts: &[&str],
+    velocity_scale: VelocityScale,
+    octave_convention: OctaveConvention,
+) -> Result<MtxtRecord> {
     if parts.is_empty() {
         bail!("Note on event requires note name");
     }
 
-    let note: NoteTarget = parts[0]
-        .parse()
+    let note = NoteTarget::parse_with(parts[0], octave_convention)
         .map_err(|_| anyhow::anyhow!("Invalid note"))?;
 
-    let mut velocity = None;
-    let mut channel = None;
-
-    for part in &parts[1..] {
-        let directive = try_parse_directive(part);
-        match directive {
-            Ok(d) => match d {
-                Some(ParsedDirective::Velocity { velocity: v }) => {
-                    velocity = Some(v);
-                }
-                Some(ParsedDirective::Channel { channel: c }) => {
-                    channel = Some(c);
-                }
-                _ => bail!("Unsupported directive \"{}\"", part),
-            },
-            Err(e) => bail!("{}", e),
-        }
-    }
+    let directives =
+        parse_inline_directives("note on", &parts[1..], NOTE_ON_DIRECTIVES, velocity_scale)?;
 
     Ok(MtxtRecord::NoteOn {
         time,
         note,
-        velocity,
-        channel,
+        velocity: directives.velocity,
+        channel: directives.channel,
+        length: directives.length,
     })
 }
 
-fn parse_note_off_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
+fn parse_note_off_event(
+    time: BeatTime,
+    parts: &[&str],
+    velocity_scale: VelocityScale,
+    octave_convention: OctaveConvention,
+) -> Result<MtxtRecord> {
     if parts.is_empty() {
         bail!("Note off event requires note name");
     }
 
-    let note: NoteTarget = parts[0]
-        .parse()
+    let note = NoteTarget::parse_with(parts[0], octave_convention)
         .map_err(|_| anyhow::anyhow!("Invalid note"))?;
 
-    let mut velocity = None;
-    let mut channel = None;
-
-    for part in &parts[1..] {
-        let directive = try_parse_directive(part);
-        match directive {
-            Ok(d) => match d {
-                Some(ParsedDirective::OffVelocity { off_velocity: v }) => {
-                    velocity = Some(v);
-                }
-                Some(ParsedDirective::Channel { channel: c }) => {
-                    channel = Some(c);
-                }
-                _ => bail!("Unsupported directive \"{}\"", part),
-            },
-            Err(e) => bail!("{}", e),
-        }
-    }
+    let directives =
+        parse_inline_directives("note off", &parts[1..], NOTE_OFF_DIRECTIVES, velocity_scale)?;
 
     Ok(MtxtRecord::NoteOff {
         time,
         note,
-        off_velocity: velocity,
-        channel,
+        off_velocity: directives.off_velocity,
+        channel: directives.channel,
     })
 }
 
-fn parse_control_change_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
+/// A `ch=1,2,3` list expands into one record per channel.
+fn parse_control_change_event(
+    time: BeatTime,
+    parts: &[&str],
+    octave_convention: OctaveConvention,
+) -> Result<Vec<MtxtRecord>> {
+    // directives may also come first, like for voice events (`cc ch=1,2 volume 0.8`)
+    let leading = parts.iter().take_while(|part| part.contains('=')).count();
+    let (leading_directives, parts) = parts.split_at(leading);
+
     let (note, controller, value, idx) = if parts.len() >= 3 && parts[2].parse::<f32>().is_ok() {
         // Case: cc <note> <controller> <value>
-        let note: NoteTarget = parts[0]
-            .parse()
+        let note = NoteTarget::parse_with(parts[0], octave_convention)
             .map_err(|_| anyhow::anyhow!("Invalid note"))?;
         let controller = parts[1].to_string();
         let value: f32 = parts[2].parse().unwrap();
@@ -267,63 +451,50 @@ fn parse_control_change_event(time: BeatTime, parts: &[&str]) -> Result<MtxtReco
         bail!("CC event requires controller and value (float)");
     };
 
-    let mut channel = None;
-    let mut transition_curve = None;
-    let mut transition_time = None;
-    let mut transition_interval = None;
-
-    for part in &parts[idx..] {
-        let directive = try_parse_directive(part);
-        match directive {
-            Ok(d) => match d {
-                Some(ParsedDirective::Channel { channel: c }) => {
-                    channel = Some(c);
-                }
-                Some(ParsedDirective::TransitionCurve { curve: c }) => {
-                    transition_curve = Some(c);
-                }
-                Some(ParsedDirective::TransitionTime { duration: d }) => {
-                    transition_time = Some(d);
-                }
-                Some(ParsedDirective::TransitionInterval { interval: i }) => {
-                    transition_interval = Some(i);
-                }
-                _ => bail!("Unsupported directive \"{}\"", part),
-            },
-            Err(e) => bail!("{}", e),
-        }
-    }
+    let directives = parse_inline_directives(
+        "cc",
+        &[leading_directives, &parts[idx..]].concat(),
+        CC_DIRECTIVES,
+        VelocityScale::Unit,
+    )?;
+    let channels = if directives.channels.is_empty() {
+        vec![directives.channel]
+    } else {
+        directives.channels.iter().copied().map(Some).collect()
+    };
 
-    Ok(MtxtRecord::ControlChange {
-        time,
-        note,
-        controller: controller.to_string(),
-        value,
-        channel,
-        transition_curve,
-        transition_time,
-        transition_interval,
-    })
+    Ok(channels
+        .into_iter()
+        .map(|channel| MtxtRecord::ControlChange {
+            time,
+            note: note.clone(),
+            controller: controller.clone(),
+            value,
+            channel,
+            transition_curve: directives.transition_curve,
+            transition_time: directives.transition_time,
+            transition_interval: directives.transition_interval,
+            transition_steps: directives.transition_steps,
+        })
+        .collect())
 }
 
 fn parse_voice_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
     let mut channel: Option<u16> = None;
+    let mut bank: Option<u16> = None;
     let mut idx = 0;
 
-    // Parse optional channel parameter first
-    if let Some(part) = parts.get(idx) {
-        let directive = try_parse_directive(part);
+    // Parse optional channel and bank parameters first
+    while let Some(part) = parts.get(idx)
+        && let Some(directive) =
+            parse_event_directive("voice", part, VOICE_DIRECTIVES, VelocityScale::Unit)?
+    {
         match directive {
-            Ok(d) => match d {
-                Some(ParsedDirective::Channel { channel: ch }) => {
-                    channel = Some(ch);
-                    idx += 1;
-                }
-                None => {}
-                _ => bail!("Unsupported directive \"{}\"", part),
-            },
-            Err(e) => bail!("{}", e),
+            ParsedDirective::Channel { channel: ch } => channel = Some(ch),
+            ParsedDirective::Bank { bank: b } => bank = Some(b),
+            _ => unreachable!("voice events only accept ch= and bank="),
         }
+        idx += 1;
     }
 
     let rest = &parts[idx..];
@@ -337,10 +508,24 @@ fn parse_voice_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
         time,
         voices,
         channel,
+        bank,
     })
 }
 
 fn parse_tuning_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
+    let mut channel = None;
+    let mut parts = parts;
+
+    // Optional channel directive first
+    if let Some(part) = parts.first()
+        && let Some(ParsedDirective::Channel { channel: ch }) =
+            parse_event_directive("tuning", part, TUNING_DIRECTIVES, VelocityScale::Unit)?
+    {
+        channel = Some(ch);
+        parts = &parts[1..];
+    }
+
+    reject_directives("tuning", parts)?;
     if parts.len() != 2 {
         bail!("Tuning event requires target and cents");
     }
@@ -354,6 +539,7 @@ fn parse_tuning_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
         time,
         target,
         cents,
+        channel,
     })
 }
 
@@ -363,6 +549,15 @@ fn parse_reset_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
     }
 
     let target = parts[0].to_string();
+    // a channel target must name a channel, `reset ch=*` resets all of them
+    if target.starts_with("ch=")
+        && !matches!(
+            try_parse_directive(&target, VelocityScale::Unit)?,
+            Some(ParsedDirective::Channel { .. })
+        )
+    {
+        bail!("Invalid reset channel: {}", target);
+    }
 
     Ok(MtxtRecord::Reset { time, target })
 }
@@ -376,45 +571,21 @@ fn parse_tempo_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
         .parse()
         .map_err(|_| anyhow::anyhow!("Invalid BPM value"))?;
 
-    let mut transition_curve = None;
-    let mut transition_time = None;
-    let mut transition_interval = None;
-
-    for part in &parts[1..] {
-        let directive = try_parse_directive(part);
-        match directive {
-            Ok(d) => {
-                if let Some(d) = d {
-                    match d {
-                        ParsedDirective::TransitionCurve { curve } => {
-                            transition_curve = Some(curve)
-                        }
-                        ParsedDirective::TransitionTime { duration } => {
-                            transition_time = Some(duration)
-                        }
-                        ParsedDirective::TransitionInterval { interval } => {
-                            transition_interval = Some(interval)
-                        }
-                        _ => bail!("Unsupported directive \"{}\"", part),
-                    }
-                } else {
-                    bail!("Invalid tempo command");
-                }
-            }
-            Err(e) => bail!("{}", e),
-        }
-    }
+    let directives =
+        parse_inline_directives("tempo", &parts[1..], TEMPO_DIRECTIVES, VelocityScale::Unit)?;
 
     Ok(MtxtRecord::Tempo {
         time,
         bpm,
-        transition_curve,
-        transition_time,
-        transition_interval,
+        transition_curve: directives.transition_curve,
+        transition_time: directives.transition_time,
+        transition_interval: directives.transition_interval,
+        transition_steps: directives.transition_steps,
     })
 }
 
 fn parse_time_signature_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
+    reject_directives("timesig", parts)?;
     if parts.len() != 1 {
         bail!("Time signature event requires signature");
     }
@@ -424,6 +595,19 @@ fn parse_time_signature_event(time: BeatTime, parts: &[&str]) -> Result<MtxtReco
     Ok(MtxtRecord::TimeSignature { time, signature })
 }
 
+fn parse_bar_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
+    reject_directives("bar", parts)?;
+    if parts.len() != 1 {
+        bail!("Bar event requires a bar number");
+    }
+
+    let number = parts[0]
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid bar number: {}", parts[0]))?;
+
+    Ok(MtxtRecord::Bar { time, number })
+}
+
 fn parse_meta_event(time: Option<BeatTime>, parts: &[&str]) -> Result<MtxtRecord> {
     if parts.is_empty() {
         bail!("Meta event requires type and value");
@@ -434,7 +618,17 @@ fn parse_meta_event(time: Option<BeatTime>, parts: &[&str]) -> Result<MtxtRecord
             bail!("Global meta event requires type and value");
         }
         let meta_type = parts[1].to_string();
-        let value = parts[2..].join(" ");
+        let value = unquote_meta_value(parts[2..].join(" "));
+        if meta_type == "pickup" && value.parse::<BeatTime>().is_err() {
+            bail!("Invalid pickup length \"{}\"", value);
+        }
+        if meta_type == "pitch_bend_range"
+            && !value
+                .parse::<f32>()
+                .is_ok_and(|range| range > 0.0 && range.is_finite())
+        {
+            bail!("Invalid pitch bend range \"{}\"", value);
+        }
         return Ok(MtxtRecord::GlobalMeta { meta_type, value });
     }
 
@@ -442,7 +636,9 @@ fn parse_meta_event(time: Option<BeatTime>, parts: &[&str]) -> Result<MtxtRecord
     let mut index = 0;
 
     // Check for channel directive
-    if let Ok(Some(ParsedDirective::Channel { channel: ch })) = try_parse_directive(parts[index]) {
+    if let Some(ParsedDirective::Channel { channel: ch }) =
+        parse_event_directive("meta", parts[index], META_DIRECTIVES, VelocityScale::Unit)?
+    {
         channel = Some(ch);
         index += 1;
     }
@@ -452,7 +648,7 @@ fn parse_meta_event(time: Option<BeatTime>, parts: &[&str]) -> Result<MtxtRecord
     }
 
     let meta_type = parts[index].to_string();
-    let value = parts[index + 1..].join(" ");
+    let value = unquote_meta_value(parts[index + 1..].join(" "));
 
     Ok(MtxtRecord::Meta {
         time,
@@ -462,7 +658,18 @@ fn parse_meta_event(time: Option<BeatTime>, parts: &[&str]) -> Result<MtxtRecord
     })
 }
 
+// SysEx data may be written with or without the F0 start byte,
+// but a message that starts with F0 has to be terminated with F7
+fn validate_sysex_framing(data: &[u8]) -> Result<()> {
+    if data.first() == Some(&0xF0) && (data.len() < 2 || data.last() != Some(&0xF7)) {
+        bail!("SysEx starting with f0 must end with f7");
+    }
+
+    Ok(())
+}
+
 fn parse_sysex_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
+    reject_directives("sysex", parts)?;
     let mut data = Vec::new();
 
     for part in parts {
@@ -471,15 +678,35 @@ fn parse_sysex_event(time: BeatTime, parts: &[&str]) -> Result<MtxtRecord> {
         data.push(byte);
     }
 
+    validate_sysex_framing(&data)?;
+
     Ok(MtxtRecord::SysEx { time, data })
 }
 
-fn try_parse_time_event(parts: &[&str]) -> Result<Option<MtxtRecord>> {
+/// Event keyword the parser doesn't know, a newer minor version of the format may use it.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("Unknown event type: {0}")]
+pub struct UnknownEventType(pub String);
+
+// Relative times ("+0.5") are resolved against the time of the previous timed event
+fn parse_event_time(part: &str, previous_time: BeatTime) -> Result<BeatTime> {
+    match part.strip_prefix('+') {
+        Some(relative) => Ok(previous_time + relative.parse::<BeatTime>()?),
+        None => part.parse(),
+    }
+}
+
+fn try_parse_time_event(
+    parts: &[&str],
+    previous_time: BeatTime,
+    velocity_scale: VelocityScale,
+    octave_convention: OctaveConvention,
+) -> Result<Option<Vec<MtxtRecord>>> {
     if parts.len() < 2 {
         return Ok(None);
     }
 
-    let time: Result<BeatTime> = parts[0].parse();
+    let time: Result<BeatTime> = parse_event_time(parts[0], previous_time);
 
     if time.is_err() {
         return Ok(None);
@@ -488,50 +715,135 @@ fn try_parse_time_event(parts: &[&str]) -> Result<Option<MtxtRecord>> {
     let time = time.unwrap();
 
     let res = match parts[1] {
-        "note" => parse_note_event(time, &parts[2..]),
-        "on" => parse_note_on_event(time, &parts[2..]),
-        "off" => parse_note_off_event(time, &parts[2..]),
-        "cc" => parse_control_change_event(time, &parts[2..]),
+        "note" => parse_note_event(time, &parts[2..], velocity_scale, octave_convention),
+        "on" => parse_note_on_event(time, &parts[2..], velocity_scale, octave_convention),
+        "off" => parse_note_off_event(time, &parts[2..], velocity_scale, octave_convention),
+        "cc" => return parse_control_change_event(time, &parts[2..], octave_convention).map(Some),
         "voice" => parse_voice_event(time, &parts[2..]),
         "tempo" => parse_tempo_event(time, &parts[2..]),
         "timesig" => parse_time_signature_event(time, &parts[2..]),
+        "bar" => parse_bar_event(time, &parts[2..]),
         "tuning" => parse_tuning_event(time, &parts[2..]),
         "reset" => parse_reset_event(time, &parts[2..]),
         "meta" => parse_meta_event(Some(time), &parts[2..]),
         "sysex" => parse_sysex_event(time, &parts[2..]),
-        _ => bail!("Unknown event type: {}", parts[1]),
+        _ => return Err(UnknownEventType(parts[1].to_string()).into()),
     }?;
 
-    Ok(Some(res))
+    Ok(Some(vec![res]))
 }
 
-// Detect inline comment if present (ignoring :// for URLs)
-fn find_inline_comment_index(line: &str) -> Option<usize> {
-    let mut search_start = 0;
-    while let Some(idx) = line[search_start..].find("//") {
-        let abs_idx = search_start + idx;
-        if abs_idx == 0 || !line[..abs_idx].ends_with(':') {
-            return Some(abs_idx);
+/// Finds the start of an inline comment. `//` is not a comment when it is part of a URL
+/// (`://`), inside a double-quoted string or escaped with a backslash.
+/// With an unterminated quote the quotes are ignored.
+pub(crate) fn find_inline_comment_index(line: &str) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut in_quotes = false;
+    let mut first_unquoted = None;
+    let mut idx = 0;
+
+    while idx < bytes.len() {
+        match bytes[idx] {
+            // skip the escaped character
+            b'\\' => idx += 1,
+            b'"' => in_quotes = !in_quotes,
+            b'/' if bytes.get(idx + 1) == Some(&b'/') => {
+                if idx == 0 || bytes[idx - 1] != b':' {
+                    if !in_quotes {
+                        return Some(idx);
+                    }
+                    first_unquoted.get_or_insert(idx);
+                }
+                idx += 1;
+            }
+            _ => {}
+        }
+        idx += 1;
+    }
+
+    if in_quotes { first_unquoted } else { None }
+}
+
+/// Meta values may be wrapped in double quotes to protect `//`. The quotes are removed and
+/// `\"` is unescaped, other escapes stay as written like in unquoted values.
+fn unquote_meta_value(value: String) -> String {
+    let Some(inner) = value
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+    else {
+        return value;
+    };
+
+    let mut output = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return value, // the quoted string ends before the value does
+            '\\' => match chars.next() {
+                Some('"') => output.push('"'),
+                Some(next) => {
+                    output.push('\\');
+                    output.push(next);
+                }
+                // the closing quote was escaped
+                None => return value,
+            },
+            _ => output.push(c),
         }
-        search_start = abs_idx + 2;
     }
-    None
+    output
 }
 
 pub fn parse_mtxt_line(line: &str) -> Result<MtxtRecordLine, anyhow::Error> {
+    parse_mtxt_line_after(line, BeatTime::zero())
+}
+
+/// Parses a line, resolving a relative `+` event time against `previous_time`.
+/// Fails for lines that expand into several records, see `parse_mtxt_line_records_after`.
+pub fn parse_mtxt_line_after(
+    line: &str,
+    previous_time: BeatTime,
+) -> Result<MtxtRecordLine, anyhow::Error> {
+    let mut records = parse_mtxt_line_records_after(line, previous_time)?;
+    if records.len() != 1 {
+        bail!("Line expands to {} records", records.len());
+    }
+    Ok(records.remove(0))
+}
+
+/// Parses a line into its records. A cc event with a channel list (`ch=1,2,3`) expands
+/// into one record per channel, an inline comment stays with the first one.
+pub fn parse_mtxt_line_records_after(
+    line: &str,
+    previous_time: BeatTime,
+) -> Result<Vec<MtxtRecordLine>, anyhow::Error> {
+    parse_mtxt_line_records_with(
+        line,
+        previous_time,
+        VelocityScale::Unit,
+        OctaveConvention::Scientific,
+    )
+}
+
+/// Like `parse_mtxt_line_records_after`, reading `vel=` and `offvel=` in `velocity_scale`
+/// and note names in `octave_convention`.
+pub fn parse_mtxt_line_records_with(
+    line: &str,
+    previous_time: BeatTime,
+    velocity_scale: VelocityScale,
+    octave_convention: OctaveConvention,
+) -> Result<Vec<MtxtRecordLine>, anyhow::Error> {
     let line = line.trim();
 
     if line.is_empty() {
-        return Ok(MtxtRecordLine::new(MtxtRecord::EmptyLine));
+        return Ok(vec![MtxtRecordLine::new(MtxtRecord::EmptyLine)]);
     }
 
     // Full-line comments (line starts with //)
-    if line.starts_with("//") {
-        let comment_text = line[2..].trim().to_string();
-        return Ok(MtxtRecordLine::with_comment(
-            MtxtRecord::EmptyLine,
-            comment_text,
-        ));
+    if let Some(comment_text) = line.strip_prefix("//") {
+        return Ok(vec![MtxtRecordLine::new(MtxtRecord::Comment {
+            text: comment_text.trim().to_string(),
+        })]);
     }
 
     // Inline comments
@@ -545,10 +857,10 @@ pub fn parse_mtxt_line(line: &str) -> Result<MtxtRecordLine, anyhow::Error> {
 
     let parts: Vec<&str> = line.split_ascii_whitespace().collect();
     if parts.is_empty() {
-        return Ok(MtxtRecordLine::new(MtxtRecord::EmptyLine));
+        return Ok(vec![MtxtRecordLine::new(MtxtRecord::EmptyLine)]);
     }
 
-    let record = match parts[0] {
+    let records = match parts[0] {
         "mtxt" => {
             if parts.len() != 2 {
                 bail!(
@@ -558,45 +870,51 @@ pub fn parse_mtxt_line(line: &str) -> Result<MtxtRecordLine, anyhow::Error> {
             }
             let version: Version = parts[1].parse().map_err(|e| anyhow::anyhow!("{}", e))?;
             version.fail_if_not_supported()?;
-            MtxtRecord::Header { version }
+            vec![MtxtRecord::Header { version }]
         }
 
-        "meta" => parse_meta_event(None, &parts[1..])?,
+        "meta" => vec![parse_meta_event(None, &parts[1..])?],
 
         "alias" => {
             if parts.len() < 3 {
                 bail!("alias requires name and at least one note");
             }
             let name = parts[1].to_string();
-            if name.parse::<Note>().is_ok() {
+            if Note::parse_with(&name, octave_convention).is_ok() {
                 bail!("Cannot redefine note \"{}\" as alias", name);
             }
             let mut notes = Vec::new();
             let merged_notes = parts[2..].join(" ");
             for note_str in merged_notes.split(',') {
-                let note: Note = note_str
-                    .trim()
-                    .parse()
-                    .map_err(|e| anyhow::anyhow!("{}", e))?;
+                let note_str = note_str.trim();
+                let note = Note::parse_with(note_str, octave_convention).map_err(|e| {
+                    anyhow::anyhow!(
+                        "Invalid note \"{}\" in alias \"{}\", aliases can only contain notes ({})",
+                        note_str,
+                        name,
+                        e
+                    )
+                })?;
                 notes.push(note);
             }
-            let alias_def = Rc::new(AliasDefinition {
+            let alias_def = Arc::new(AliasDefinition {
                 name: name.clone(),
                 notes,
             });
-            MtxtRecord::AliasDef { value: alias_def }
+            vec![MtxtRecord::AliasDef { value: alias_def }]
         }
         _ => {
-            let parsed_directive = try_parse_global_directive(parts[0])?;
+            let parsed_directive = try_parse_global_directive(parts[0], velocity_scale)?;
             if let Some(record) = parsed_directive {
                 if parts.len() > 1 {
                     bail!("Cannot parse global directive {}", parts.join(" "));
                 }
-                record
+                vec![record]
             } else {
-                let parsed_time_event = try_parse_time_event(&parts)?;
-                if let Some(record) = parsed_time_event {
-                    record
+                let parsed_time_event =
+                    try_parse_time_event(&parts, previous_time, velocity_scale, octave_convention)?;
+                if let Some(records) = parsed_time_event {
+                    records
                 } else {
                     bail!("Cannot parse \"{}\"", parts.join(" "));
                 }
@@ -604,8 +922,12 @@ pub fn parse_mtxt_line(line: &str) -> Result<MtxtRecordLine, anyhow::Error> {
         }
     };
 
-    Ok(match inline_comment {
-        Some(comment) => MtxtRecordLine::with_comment(record, comment),
-        None => MtxtRecordLine::new(record),
-    })
+    let mut comment = inline_comment;
+    Ok(records
+        .into_iter()
+        .map(|record| match comment.take() {
+            Some(comment) => MtxtRecordLine::with_comment(record, comment),
+            None => MtxtRecordLine::new(record),
+        })
+        .collect())
 }
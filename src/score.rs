@@ -0,0 +1,194 @@
+//! Measure layout shared by the score exports (ABC, MusicXML): collecting the notes of a
+//! file in ticks and walking them through measures.
+
+use crate::file::MtxtFile;
+use crate::transforms::merge::MergePolicy;
+use crate::transforms::{flatten, merge, sort};
+use crate::types::beat_time::BeatTime;
+use crate::types::duration::NoteDuration;
+use crate::types::note::{Note, NoteTarget};
+use crate::types::record::MtxtRecord;
+use crate::types::time_signature::TimeSignature;
+use anyhow::{Result, bail};
+
+/// Ticks per quarter note, fine enough for sixteenth triplets.
+pub(crate) const TICKS_PER_BEAT: u64 = 480;
+
+pub(crate) const DEFAULT_TIME_SIGNATURE: TimeSignature = TimeSignature {
+    numerator: 4,
+    denominator: 4,
+};
+
+pub(crate) fn to_ticks(time: BeatTime) -> u64 {
+    (time.as_beats() * TICKS_PER_BEAT as f64).round() as u64
+}
+
+/// Notes, tempo changes and time signatures of a file in ticks. Aliases are expanded and
+/// overlapping notes of the same pitch merged, everything else is dropped.
+pub(crate) struct ScoreEvents {
+    /// Channel, start, end and pitch of each note, by start.
+    pub notes: Vec<(u16, u64, u64, Note)>,
+    pub signatures: Vec<(u64, TimeSignature)>,
+    pub tempos: Vec<(u64, f32)>,
+}
+
+impl ScoreEvents {
+    pub fn collect(file: &MtxtFile) -> Result<Self> {
+        let records = merge::transform(
+            &sort::transform(&flatten::transform(&file.records)),
+            MergePolicy::Lifo,
+        );
+
+        let mut events = Self {
+            notes: Vec::new(),
+            signatures: Vec::new(),
+            tempos: Vec::new(),
+        };
+        for line in &records {
+            match &line.record {
+                MtxtRecord::Note {
+                    time,
+                    note: NoteTarget::Note(note),
+                    duration: Some(NoteDuration::Beats(duration)),
+                    channel: Some(channel),
+                    ..
+                } => {
+                    let start = to_ticks(*time);
+                    let end = to_ticks(*time + *duration);
+                    if end > start {
+                        events.notes.push((*channel, start, end, note.clone()));
+                    }
+                }
+                MtxtRecord::Tempo { time, bpm, .. } => {
+                    events.tempos.push((to_ticks(*time), *bpm));
+                }
+                MtxtRecord::TimeSignature { time, signature } => {
+                    if signature.numerator == 0 || signature.denominator == 0 {
+                        bail!("Invalid time signature {}", signature);
+                    }
+                    events.signatures.push((to_ticks(*time), signature.clone()));
+                }
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// Output of a `MeasureWalker`.
+pub(crate) trait MeasureWriter {
+    /// Starts measure `number` (from 1). `signature_changed` is set for the first measure
+    /// and when the time signature differs from the one before.
+    fn open_measure(&mut self, number: u32, signature: &TimeSignature, signature_changed: bool);
+
+    /// Ends the current measure at tick `cursor`, `last` for the final one.
+    fn close_measure(&mut self, cursor: u64, last: bool);
+
+    /// Writes `length` ticks of `notes` from tick `cursor`, a rest if `notes` is empty.
+    /// The notes are tied to the part before the last bar line and to the one after the
+    /// next when they cross them.
+    fn write_part(
+        &mut self,
+        cursor: u64,
+        length: u64,
+        notes: &[Note],
+        tied_from_previous: bool,
+        tied_to_next: bool,
+    );
+
+    /// Tick after `cursor` where a rest is split, e.g. at a tempo change.
+    fn rest_break(&self, _cursor: u64) -> Option<u64> {
+        None
+    }
+}
+
+/// Lays notes and rests out in measures, splitting and tying them at bar lines. Time
+/// signatures take effect at the first bar line at or after them.
+pub(crate) struct MeasureWalker<W> {
+    writer: W,
+    signatures: Vec<(u64, TimeSignature)>,
+    number: u32,
+    start: u64,
+    length: u64,
+    signature: Option<TimeSignature>,
+    cursor: u64,
+}
+
+impl<W: MeasureWriter> MeasureWalker<W> {
+    pub fn new(writer: W, signatures: Vec<(u64, TimeSignature)>) -> Self {
+        let mut walker = Self {
+            writer,
+            signatures,
+            number: 0,
+            start: 0,
+            length: 0,
+            signature: None,
+            cursor: 0,
+        };
+        walker.open_measure();
+        walker
+    }
+
+    fn end(&self) -> u64 {
+        self.start + self.length
+    }
+
+    fn open_measure(&mut self) {
+        let signature = self
+            .signatures
+            .iter()
+            .rev()
+            .find(|(time, _)| *time <= self.cursor)
+            .map_or(DEFAULT_TIME_SIGNATURE, |(_, signature)| signature.clone());
+
+        let changed = self.signature.as_ref() != Some(&signature);
+        self.number += 1;
+        self.start = self.cursor;
+        self.length = to_ticks(signature.bar_length()).max(1);
+        self.writer.open_measure(self.number, &signature, changed);
+        self.signature = Some(signature);
+    }
+
+    /// Writes a rest up to `start`, then `notes` (a rest if empty) until `end`.
+    pub fn write_at(&mut self, start: u64, end: u64, notes: &[Note]) {
+        if start > self.cursor {
+            self.write(start - self.cursor, &[]);
+        }
+        self.write(end - start, notes);
+    }
+
+    fn write(&mut self, mut length: u64, notes: &[Note]) {
+        let mut tied_from_previous = false;
+
+        while length > 0 {
+            if self.cursor >= self.end() {
+                self.writer.close_measure(self.cursor, false);
+                self.open_measure();
+            }
+
+            let mut part = length.min(self.end() - self.cursor);
+            if notes.is_empty()
+                && let Some(time) = self.writer.rest_break(self.cursor)
+            {
+                part = part.min(time - self.cursor);
+            }
+            let tied_to_next = part < length && !notes.is_empty();
+            self.writer
+                .write_part(self.cursor, part, notes, tied_from_previous, tied_to_next);
+
+            self.cursor += part;
+            length -= part;
+            tied_from_previous = true;
+        }
+    }
+
+    /// Completes the last measure with a rest, or gives an empty score a full measure
+    /// rest, and closes it.
+    pub fn finish(mut self) -> W {
+        if self.cursor > self.start || self.cursor == 0 {
+            self.write(self.end() - self.cursor, &[]);
+        }
+        self.writer.close_measure(self.cursor, true);
+        self.writer
+    }
+}
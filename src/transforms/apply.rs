@@ -1,13 +1,19 @@
 use crate::BeatTime;
-use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use crate::Easing;
+use crate::process::resolve_note_target;
+use crate::types::duration::NoteDuration;
+use crate::types::note::NoteTarget;
+use crate::types::record::{AliasTable, MtxtRecord, MtxtRecordLine};
 
 struct State {
     channel: Option<u16>,
     velocity: Option<f32>,
     off_velocity: Option<f32>,
-    duration: Option<BeatTime>,
-    transition_curve: Option<f32>,
+    duration: Option<NoteDuration>,
+    transition_curve: Option<Easing>,
+    transition_time: Option<BeatTime>,
     transition_interval: Option<f32>,
+    transition_steps: Option<u32>,
 }
 
 impl State {
@@ -18,18 +24,34 @@ impl State {
             off_velocity: None,
             duration: None,
             transition_curve: None,
+            transition_time: None,
             transition_interval: None,
+            transition_steps: None,
         }
     }
 }
 
 pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
+    transform_with(records, false)
+}
+
+/// Like `transform`, with `expand_aliases` also replacing each aliased note with one
+/// line per note of the alias and dropping the alias definitions, see `expand_targets`.
+pub fn transform_with(records: &[MtxtRecordLine], expand_aliases: bool) -> Vec<MtxtRecordLine> {
+    let records = transform_owned(records.to_vec());
+    if expand_aliases {
+        expand_alias_targets(records)
+    } else {
+        records
+    }
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(mut records: Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine> {
     let mut state = State::new();
-    let mut new_records = Vec::with_capacity(records.len());
 
-    for line in records {
-        let record = &line.record;
-        match record {
+    records.retain_mut(|line| {
+        match &mut line.record {
             MtxtRecord::ChannelDirective { channel } => {
                 state.channel = Some(*channel);
             }
@@ -45,122 +67,142 @@ pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
             MtxtRecord::TransitionCurveDirective { curve } => {
                 state.transition_curve = Some(*curve);
             }
+            MtxtRecord::TransitionTimeDirective { duration } => {
+                state.transition_time = Some(*duration);
+            }
             MtxtRecord::TransitionIntervalDirective { interval } => {
                 state.transition_interval = Some(*interval);
             }
+            MtxtRecord::TransitionStepsDirective { steps } => {
+                state.transition_steps = Some(*steps);
+            }
 
             MtxtRecord::Note {
-                time,
-                note,
                 duration,
                 velocity,
                 off_velocity,
                 channel,
+                ..
             } => {
-                new_records.push(MtxtRecordLine {
-                    record: MtxtRecord::Note {
-                        time: *time,
-                        note: note.clone(),
-                        duration: duration.or(state.duration),
-                        velocity: velocity.or(state.velocity),
-                        off_velocity: off_velocity.or(state.off_velocity),
-                        channel: channel.or(state.channel),
-                    },
-                    comment: line.comment.clone(),
-                });
+                *duration = duration.or(state.duration);
+                *velocity = velocity.or(state.velocity);
+                *off_velocity = off_velocity.or(state.off_velocity);
+                *channel = channel.or(state.channel);
+                return true;
             }
             MtxtRecord::NoteOn {
-                time,
-                note,
-                velocity,
-                channel,
+                velocity, channel, ..
             } => {
-                new_records.push(MtxtRecordLine {
-                    record: MtxtRecord::NoteOn {
-                        time: *time,
-                        note: note.clone(),
-                        velocity: velocity.or(state.velocity),
-                        channel: channel.or(state.channel),
-                    },
-                    comment: line.comment.clone(),
-                });
+                *velocity = velocity.or(state.velocity);
+                *channel = channel.or(state.channel);
+                return true;
             }
             MtxtRecord::NoteOff {
-                time,
-                note,
                 off_velocity,
                 channel,
+                ..
             } => {
-                new_records.push(MtxtRecordLine {
-                    record: MtxtRecord::NoteOff {
-                        time: *time,
-                        note: note.clone(),
-                        off_velocity: off_velocity.or(state.off_velocity),
-                        channel: channel.or(state.channel),
-                    },
-                    comment: line.comment.clone(),
-                });
+                *off_velocity = off_velocity.or(state.off_velocity);
+                *channel = channel.or(state.channel);
+                return true;
             }
             MtxtRecord::ControlChange {
-                time,
-                note,
-                controller,
-                value,
-                channel,
                 transition_curve,
                 transition_time,
                 transition_interval,
-            } => {
-                new_records.push(MtxtRecordLine {
-                    record: MtxtRecord::ControlChange {
-                        time: *time,
-                        note: note.clone(),
-                        controller: controller.clone(),
-                        value: *value,
-                        channel: *channel,
-                        transition_curve: transition_curve.or(state.transition_curve),
-                        transition_time: *transition_time,
-                        transition_interval: transition_interval.or(state.transition_interval),
-                    },
-                    comment: line.comment.clone(),
-                });
-            }
-            MtxtRecord::Voice {
-                time,
-                voices,
-                channel,
-            } => {
-                new_records.push(MtxtRecordLine {
-                    record: MtxtRecord::Voice {
-                        time: *time,
-                        voices: voices.clone(),
-                        channel: channel.or(state.channel),
-                    },
-                    comment: line.comment.clone(),
-                });
-            }
-            MtxtRecord::Tempo {
-                time,
-                bpm,
+                transition_steps,
+                ..
+            }
+            | MtxtRecord::Tempo {
                 transition_curve,
                 transition_time,
                 transition_interval,
+                transition_steps,
+                ..
             } => {
-                new_records.push(MtxtRecordLine {
-                    record: MtxtRecord::Tempo {
-                        time: *time,
-                        bpm: *bpm,
-                        transition_curve: transition_curve.or(state.transition_curve),
-                        transition_time: *transition_time,
-                        transition_interval: transition_interval.or(state.transition_interval),
-                    },
-                    comment: line.comment.clone(),
-                });
-            }
-            _ => {
-                new_records.push(line.clone());
+                *transition_curve = transition_curve.or(state.transition_curve);
+                *transition_time = transition_time.or(state.transition_time);
+                *transition_interval = transition_interval.or(state.transition_interval);
+                *transition_steps = transition_steps.or(state.transition_steps);
+                return true;
+            }
+            MtxtRecord::Voice { channel, .. } => {
+                *channel = channel.or(state.channel);
+                return true;
             }
+            _ => return true,
         }
+        // directives are applied to the events and dropped
+        false
+    });
+
+    records
+}
+
+fn note_target_mut(record: &mut MtxtRecord) -> Option<&mut NoteTarget> {
+    match record {
+        MtxtRecord::Note { note, .. }
+        | MtxtRecord::NoteOn { note, .. }
+        | MtxtRecord::NoteOff { note, .. }
+        | MtxtRecord::ControlChange {
+            note: Some(note), ..
+        } => Some(note),
+        _ => None,
+    }
+}
+
+/// One line per note `target` resolves to, made by `make_record`, with the comment of
+/// `line` on the first one. An alias name without a definition before the event gives a
+/// single line keeping the name as written, so no event is lost (it plays no notes on
+/// export). Shared by `flatten` and alias expansion here.
+pub(crate) fn expand_targets(
+    line: &MtxtRecordLine,
+    target: &NoteTarget,
+    aliases: &AliasTable,
+    make_record: impl Fn(NoteTarget) -> MtxtRecord,
+) -> Vec<MtxtRecordLine> {
+    let notes = resolve_note_target(target, aliases);
+    if notes.is_empty() {
+        return vec![MtxtRecordLine {
+            record: make_record(target.clone()),
+            comment: line.comment.clone(),
+        }];
+    }
+
+    notes
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(i, note)| MtxtRecordLine {
+            record: make_record(NoteTarget::Note(note)),
+            comment: if i == 0 { line.comment.clone() } else { None },
+        })
+        .collect()
+}
+
+fn expand_alias_targets(records: Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine> {
+    let mut aliases = AliasTable::default();
+    let mut new_records = Vec::with_capacity(records.len());
+
+    for mut line in records {
+        if let MtxtRecord::AliasDef { value } = &line.record {
+            aliases.define(value);
+            continue;
+        }
+        let target = match note_target_mut(&mut line.record) {
+            Some(NoteTarget::Note(_)) | None => {
+                new_records.push(line);
+                continue;
+            }
+            Some(target) => target.clone(),
+        };
+        new_records.extend(expand_targets(&line, &target, &aliases, |note| {
+            let mut record = line.record.clone();
+            if let Some(target) = note_target_mut(&mut record) {
+                *target = note;
+            }
+            record
+        }));
     }
 
     new_records
@@ -193,6 +235,48 @@ mtxt 1.0
 3.0 note G4 dur=1 vel=0.5 ch=1
 4.0 note C5 dur=1 vel=0.8 ch=2
 5.0 cc volume 1 transition_curve=0.5
+"#;
+
+        assert_eq_records(input, transform, expected);
+    }
+
+    #[test]
+    fn test_apply_expand_aliases() {
+        let input = r#"
+mtxt 1.0
+alias chord C4,E4,G4
+ch=2
+vel=0.7
+1.0 note chord dur=2 // intro
+2.0 note D4
+3.0 note pad
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 note C4 dur=2 vel=0.7 ch=2 // intro
+1.0 note E4 dur=2 vel=0.7 ch=2
+1.0 note G4 dur=2 vel=0.7 ch=2
+2.0 note D4 vel=0.7 ch=2
+3.0 note pad vel=0.7 ch=2
+"#;
+
+        assert_eq_records(input, |r| transform_with(r, true), expected);
+    }
+
+    #[test]
+    fn test_apply_transition_time() {
+        let input = r#"
+mtxt 1.0
+transition_time=2
+1.0 tempo 90
+4.0 cc volume 1.0
+6.0 cc volume 0.5 transition_time=1
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 tempo 90 transition_time=2
+4.0 cc volume 1.0 transition_time=2
+6.0 cc volume 0.5 transition_time=1
 "#;
 
         assert_eq_records(input, transform, expected);
@@ -0,0 +1,89 @@
+use crate::BeatTime;
+use crate::transforms::for_each_record;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+
+/// What happens to a controller change that would move before time 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LeadPolicy {
+    /// Moved to time 0.
+    #[default]
+    Clamp,
+    /// Removed.
+    Drop,
+}
+
+/// Moves every `cc` event `lead` beats earlier so it takes effect before the notes it
+/// modulates. Notes, meta and all other events stay put, events moved before time 0
+/// are clamped to 0.
+pub fn transform(records: &[MtxtRecordLine], lead: BeatTime) -> Vec<MtxtRecordLine> {
+    transform_with(records, lead, LeadPolicy::Clamp)
+}
+
+/// Same as `transform`, with `policy` deciding what happens to events moved before 0.
+pub fn transform_with(
+    records: &[MtxtRecordLine],
+    lead: BeatTime,
+    policy: LeadPolicy,
+) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_in_place(&mut records, lead, policy);
+    records
+}
+
+/// Same as `transform_with`, moving the records in place.
+pub(crate) fn transform_in_place(
+    records: &mut Vec<MtxtRecordLine>,
+    lead: BeatTime,
+    policy: LeadPolicy,
+) {
+    if lead == BeatTime::zero() {
+        return;
+    }
+
+    if policy == LeadPolicy::Drop {
+        records.retain(|line| match &line.record {
+            MtxtRecord::ControlChange { time, .. } => *time >= lead,
+            _ => true,
+        });
+    }
+
+    for_each_record(records, |line| {
+        if let MtxtRecord::ControlChange { time, .. } = &mut line.record {
+            // subtraction saturates at 0
+            *time = *time - lead;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_mtxt;
+
+    // BeatTime is fixed point, so compare the times as written
+    fn times(records: &[MtxtRecordLine]) -> Vec<String> {
+        records
+            .iter()
+            .filter_map(|line| line.record.time())
+            .map(|time| time.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_cc_lead() {
+        let file = parse_mtxt(
+            "mtxt 1.0\nch=1\n0.05 cc volume 0.5\n2.0 cc volume 0.8\n2.0 note C4\n\
+             2.0 tempo 100\n",
+        )
+        .unwrap();
+        let lead: BeatTime = "0.1".parse().unwrap();
+
+        let records = transform(&file.records, lead);
+        assert_eq!(records.len(), file.records.len());
+        assert_eq!(times(&records), vec!["0.0", "1.9", "2.0", "2.0"]);
+
+        let records = transform_with(&file.records, lead, LeadPolicy::Drop);
+        assert_eq!(records.len(), file.records.len() - 1);
+        assert_eq!(times(&records), vec!["1.9", "2.0", "2.0"]);
+    }
+}
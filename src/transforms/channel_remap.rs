@@ -0,0 +1,94 @@
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use std::collections::HashMap;
+
+/// Moves events from one channel to another by `map` (from -> to), on explicit `ch=`
+/// parameters and on `ch=` directives, so notes inheriting their channel move with
+/// the directive. Each channel is mapped once, `{1: 2, 2: 1}` swaps two channels.
+/// Channels not in the map and `ch=*` stay as they are.
+pub fn transform(records: &[MtxtRecordLine], map: &HashMap<u16, u16>) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_in_place(&mut records, map);
+    records
+}
+
+/// Same as `transform`, changing the records in place.
+pub(crate) fn transform_in_place(records: &mut [MtxtRecordLine], map: &HashMap<u16, u16>) {
+    if map.is_empty() {
+        return;
+    }
+
+    for line in records {
+        let channel = match &mut line.record {
+            MtxtRecord::ChannelDirective { channel } => channel,
+            MtxtRecord::Note {
+                channel: Some(channel),
+                ..
+            }
+            | MtxtRecord::NoteOn {
+                channel: Some(channel),
+                ..
+            }
+            | MtxtRecord::NoteOff {
+                channel: Some(channel),
+                ..
+            }
+            | MtxtRecord::ControlChange {
+                channel: Some(channel),
+                ..
+            }
+            | MtxtRecord::Voice {
+                channel: Some(channel),
+                ..
+            }
+            | MtxtRecord::Meta {
+                channel: Some(channel),
+                ..
+            }
+            | MtxtRecord::Tuning {
+                channel: Some(channel),
+                ..
+            } => channel,
+            _ => continue,
+        };
+        if let Some(to) = map.get(channel) {
+            *channel = *to;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_channel_remap() {
+        let input = r#"
+mtxt 1.0
+ch=5
+0.0 voice piano
+1.0 note C4
+1.0 note E4 ch=1
+2.0 cc volume 0.5
+0.0 meta ch=5 name Lead
+ch=1
+3.0 note G4 ch=5
+3.0 note A4
+4.0 cc volume 1 ch=*
+"#;
+        let expected = r#"
+mtxt 1.0
+ch=2
+0.0 voice piano
+1.0 note C4
+1.0 note E4 ch=1
+2.0 cc volume 0.5
+0.0 meta ch=2 name Lead
+ch=1
+3.0 note G4 ch=2
+3.0 note A4
+4.0 cc volume 1 ch=*
+"#;
+        assert_eq_records(input, |r| transform(r, &HashMap::from([(5, 2)])), expected);
+    }
+}
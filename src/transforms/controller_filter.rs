@@ -0,0 +1,86 @@
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use std::collections::HashSet;
+
+/// Keeps only `cc` events whose controller is in `controllers`. Controllers are matched
+/// by name as written (`volume` does not match `cc 7`). Other records are untouched.
+pub fn include(records: &[MtxtRecordLine], controllers: &HashSet<String>) -> Vec<MtxtRecordLine> {
+    include_owned(records.to_vec(), controllers)
+}
+
+/// Same as `include`, taking the records by value so nothing is copied.
+pub fn include_owned(
+    records: Vec<MtxtRecordLine>,
+    controllers: &HashSet<String>,
+) -> Vec<MtxtRecordLine> {
+    filter(records, |controller| controllers.contains(controller))
+}
+
+/// Drops `cc` events whose controller is in `controllers`, e.g. all `pitch` bends.
+pub fn exclude(records: &[MtxtRecordLine], controllers: &HashSet<String>) -> Vec<MtxtRecordLine> {
+    exclude_owned(records.to_vec(), controllers)
+}
+
+/// Same as `exclude`, taking the records by value so nothing is copied.
+pub fn exclude_owned(
+    records: Vec<MtxtRecordLine>,
+    controllers: &HashSet<String>,
+) -> Vec<MtxtRecordLine> {
+    filter(records, |controller| !controllers.contains(controller))
+}
+
+fn filter(mut records: Vec<MtxtRecordLine>, keep: impl Fn(&str) -> bool) -> Vec<MtxtRecordLine> {
+    records.retain(|line| match &line.record {
+        MtxtRecord::ControlChange { controller, .. } => keep(controller),
+        _ => true,
+    });
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    const INPUT: &str = r#"
+mtxt 1.0
+1.0 note C4
+1.0 cc pitch 0.5
+1.5 cc volume 0.8
+2.0 cc C4 pitch -0.5 ch=2
+2.0 meta text pitch
+3.0 cc aftertouch 0.3
+"#;
+
+    #[test]
+    fn test_exclude_controllers() {
+        let expected = r#"
+mtxt 1.0
+1.0 note C4
+1.5 cc volume 0.8
+2.0 meta text pitch
+3.0 cc aftertouch 0.3
+"#;
+
+        assert_eq_records(
+            INPUT,
+            |r| exclude(r, &HashSet::from(["pitch".to_string()])),
+            expected,
+        );
+    }
+
+    #[test]
+    fn test_include_controllers() {
+        let expected = r#"
+mtxt 1.0
+1.0 note C4
+1.5 cc volume 0.8
+2.0 meta text pitch
+"#;
+
+        assert_eq_records(
+            INPUT,
+            |r| include(r, &HashSet::from(["volume".to_string()])),
+            expected,
+        );
+    }
+}
@@ -1,40 +1,48 @@
-use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use crate::types::record::{ALL_CHANNELS, MtxtRecord, MtxtRecordLine};
 use std::collections::HashSet;
 
 pub fn transform(records: &[MtxtRecordLine], channels: &HashSet<u16>) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_in_place(&mut records, channels);
+    records
+}
+
+/// Same as `transform`, removing the records instead of copying the ones that are kept.
+pub(crate) fn transform_in_place(records: &mut Vec<MtxtRecordLine>, channels: &HashSet<u16>) {
     if channels.is_empty() {
-        return records.to_vec();
+        return;
     }
 
+    // `ch=*` still reaches the channels that are not excluded, so events on it are kept
+    let keep = |channel: &u16| *channel == ALL_CHANNELS || !channels.contains(channel);
     let mut current_channel: Option<u16> = None;
 
-    records
-        .iter()
-        .filter(|line| match &line.record {
-            MtxtRecord::Note { channel, .. }
-            | MtxtRecord::NoteOn { channel, .. }
-            | MtxtRecord::NoteOff { channel, .. }
-            | MtxtRecord::Voice { channel, .. } => {
-                if let Some(channel) = channel {
-                    !channels.contains(channel)
-                } else if let Some(curr) = current_channel {
-                    !channels.contains(&curr)
-                } else {
-                    false
-                }
+    records.retain(|line| match &line.record {
+        MtxtRecord::Note { channel, .. }
+        | MtxtRecord::NoteOn { channel, .. }
+        | MtxtRecord::NoteOff { channel, .. }
+        | MtxtRecord::Voice { channel, .. } => {
+            if let Some(channel) = channel {
+                keep(channel)
+            } else if let Some(curr) = current_channel {
+                keep(&curr)
+            } else {
+                false
             }
-            MtxtRecord::ControlChange { channel, .. } => {
-                // if channel is None, affects all channels
-                channel.is_none_or(|ch| !channels.contains(&ch))
-            }
-            MtxtRecord::ChannelDirective { channel } => {
-                current_channel = Some(*channel);
-                !channels.contains(channel)
-            }
-            _ => true,
-        })
-        .cloned()
-        .collect()
+        }
+        MtxtRecord::ControlChange { channel, .. } => {
+            // if channel is None, affects all channels
+            channel.as_ref().is_none_or(keep)
+        }
+        // metas without their own channel are on the channel of the `ch=` directive
+        // before them, and stay with the song without one
+        MtxtRecord::Meta { channel, .. } => channel.or(current_channel).as_ref().is_none_or(keep),
+        MtxtRecord::ChannelDirective { channel } => {
+            current_channel = Some(*channel);
+            keep(channel)
+        }
+        _ => true,
+    });
 }
 
 #[cfg(test)]
@@ -76,4 +84,56 @@ ch=5
 
         assert_eq_records(input, exclude_channels_1_2, expected);
     }
+
+    #[test]
+    fn test_exclude_all_channels() {
+        let input = r#"
+mtxt 1.0
+ch=1
+1.0 note C4 ch=*
+2.0 cc volume 1
+3.0 cc volume 0.5 ch=*
+4.0 note D4
+ch=*
+5.0 note E4
+6.0 note G4 ch=1
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 note C4 ch=*
+2.0 cc volume 1
+3.0 cc volume 0.5 ch=*
+ch=*
+5.0 note E4
+"#;
+
+        assert_eq_records(input, exclude_channels_1_2, expected);
+    }
+
+    #[test]
+    fn test_exclude_channel_meta() {
+        let input = r#"
+mtxt 1.0
+meta global title Song
+ch=1
+0.0 meta ch=1 name Piano
+0.0 meta ch=2 name foo
+0.0 meta ch=* text all
+0.0 tempo 120
+1.0 meta marker verse
+ch=2
+2.0 meta name foo
+"#;
+        let expected = r#"
+mtxt 1.0
+meta global title Song
+ch=1
+0.0 meta ch=1 name Piano
+0.0 meta ch=* text all
+0.0 tempo 120
+1.0 meta marker verse
+"#;
+
+        assert_eq_records(input, |r| transform(r, &HashSet::from([2])), expected);
+    }
 }
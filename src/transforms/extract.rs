@@ -1,14 +1,20 @@
 use crate::transforms::apply;
+use crate::types::beat_time::BeatTime;
 use crate::types::record::{MtxtRecord, MtxtRecordLine};
 
+/// Moves a record out, leaving an empty line that is never read again.
+fn take(line: &mut MtxtRecordLine) -> MtxtRecordLine {
+    std::mem::replace(line, MtxtRecordLine::new(MtxtRecord::EmptyLine))
+}
+
 /// Extracts common inline parameters into global directives.
 fn extract_property<T: PartialEq + Clone + Copy + std::fmt::Debug>(
-    records: Vec<MtxtRecordLine>,
+    mut records: Vec<MtxtRecordLine>,
     get_fn: impl Fn(&MtxtRecord) -> Option<T>,
     create_directive_fn: impl Fn(T) -> MtxtRecord,
     remove_fn: impl Fn(&mut MtxtRecord),
 ) -> Vec<MtxtRecordLine> {
-    let mut result = Vec::new();
+    let mut result = Vec::with_capacity(records.len());
     let mut current_global_value: Option<T> = None;
     let mut i = 0;
     while i < records.len() {
@@ -22,7 +28,7 @@ fn extract_property<T: PartialEq + Clone + Copy + std::fmt::Debug>(
                 && val == global
             {
                 // Matches global, just remove inline property
-                let mut new_line = line.clone();
+                let mut new_line = take(&mut records[i]);
                 remove_fn(&mut new_line.record);
                 result.push(new_line);
                 i += 1;
@@ -56,8 +62,8 @@ fn extract_property<T: PartialEq + Clone + Copy + std::fmt::Debug>(
                 current_global_value = Some(val);
 
                 // Process the block from i to j
-                for k in i..j {
-                    let mut r_line = records[k].clone();
+                for (k, r_line) in records.iter_mut().enumerate().take(j).skip(i) {
+                    let mut r_line = take(r_line);
                     if run_indices.contains(&k) {
                         remove_fn(&mut r_line.record);
                     }
@@ -66,22 +72,60 @@ fn extract_property<T: PartialEq + Clone + Copy + std::fmt::Debug>(
                 i = j;
             } else {
                 // Not enough for a run, just push the current record
-                result.push(line.clone());
+                result.push(take(&mut records[i]));
                 i += 1;
             }
         } else {
             // No explicit property, just push
-            result.push(line.clone());
+            result.push(take(&mut records[i]));
             i += 1;
         }
     }
     result
 }
 
+/// The transition time of a `cc` or `tempo`, where none means an instant change.
+fn transition_time(record: &mut MtxtRecord) -> Option<&mut Option<BeatTime>> {
+    match record {
+        MtxtRecord::ControlChange {
+            transition_time, ..
+        }
+        | MtxtRecord::Tempo {
+            transition_time, ..
+        } => Some(transition_time),
+        _ => None,
+    }
+}
+
+/// Removes `transition_time=` directives and inline transition times that repeat the one
+/// in effect. Instant changes were written as `transition_time=0` before the extraction,
+/// so the ones after a `transition_time=` directive keep it and don't inherit its ramp.
+fn remove_redundant_transition_times(records: &mut Vec<MtxtRecordLine>) {
+    let mut current = BeatTime::zero();
+    records.retain_mut(|line| {
+        if let MtxtRecord::TransitionTimeDirective { duration } = line.record {
+            let redundant = duration == current;
+            current = duration;
+            return !redundant;
+        }
+        if let Some(time) = transition_time(&mut line.record)
+            && *time == Some(current)
+        {
+            *time = None;
+        }
+        true
+    });
+}
+
 pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec())
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(records: Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine> {
     // Step 1: Apply all directives to make everything inline
     // This removes all existing directives and propagates their values to the events
-    let mut current = apply::transform(records);
+    let mut current = apply::transform_owned(records);
 
     // Step 2: Extract properties one by one
     current = extract_property(
@@ -171,6 +215,37 @@ pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
         },
     );
 
+    // instant changes are marked so a `transition_time=` run doesn't absorb them
+    for line in current.iter_mut() {
+        if let Some(time @ None) = transition_time(&mut line.record) {
+            *time = Some(BeatTime::zero());
+        }
+    }
+
+    current = extract_property(
+        current,
+        |r| match r {
+            MtxtRecord::ControlChange {
+                transition_time, ..
+            }
+            | MtxtRecord::Tempo {
+                transition_time, ..
+            } => *transition_time,
+            _ => None,
+        },
+        |v| MtxtRecord::TransitionTimeDirective { duration: v },
+        |r| match r {
+            MtxtRecord::ControlChange {
+                transition_time, ..
+            }
+            | MtxtRecord::Tempo {
+                transition_time, ..
+            } => *transition_time = None,
+            _ => {}
+        },
+    );
+    remove_redundant_transition_times(&mut current);
+
     current = extract_property(
         current,
         |r| match r {
@@ -198,6 +273,29 @@ pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
         },
     );
 
+    current = extract_property(
+        current,
+        |r| match r {
+            MtxtRecord::ControlChange {
+                transition_steps, ..
+            }
+            | MtxtRecord::Tempo {
+                transition_steps, ..
+            } => *transition_steps,
+            _ => None,
+        },
+        |v| MtxtRecord::TransitionStepsDirective { steps: v },
+        |r| match r {
+            MtxtRecord::ControlChange {
+                transition_steps, ..
+            }
+            | MtxtRecord::Tempo {
+                transition_steps, ..
+            } => *transition_steps = None,
+            _ => {}
+        },
+    );
+
     current
 }
 
@@ -306,4 +404,37 @@ ch=1
 "#;
         assert_eq_records(input, transform, expected);
     }
+
+    #[test]
+    fn test_extract_transition_time_keeps_instant_changes() {
+        let input = r#"
+mtxt 1.0
+0.0 cc volume 0
+1.0 cc volume 1 transition_time=1
+2.0 cc volume 0.5 transition_time=1
+3.0 tempo 100 transition_time=1
+4.0 cc volume 0.2
+5.0 cc volume 0.8 transition_time=1
+6.0 cc volume 0.4
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 cc volume 0
+transition_time=1
+1.0 cc volume 1
+2.0 cc volume 0.5
+3.0 tempo 100
+4.0 cc volume 0.2 transition_time=0
+5.0 cc volume 0.8
+6.0 cc volume 0.4 transition_time=0
+"#;
+        assert_eq_records(input, transform, expected);
+
+        let original = crate::parse_mtxt(input).unwrap();
+        let extracted = crate::file::MtxtFile::from_records(transform(&original.records));
+        assert_eq!(
+            extracted.get_output_records(),
+            original.get_output_records()
+        );
+    }
 }
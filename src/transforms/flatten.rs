@@ -0,0 +1,181 @@
+use crate::process::{
+    DEFAULT_BPM, DEFAULT_CHANNEL, DEFAULT_DURATION, DEFAULT_OFF_VELOCITY, DEFAULT_VELOCITY,
+};
+use crate::transforms::apply::{self, expand_targets};
+use crate::types::record::{AliasTable, MtxtRecord, MtxtRecordLine};
+
+/// `apply` leaves control change channels alone, resolve them from `ch=` here.
+fn apply_cc_channels(records: &mut [MtxtRecordLine]) {
+    let mut current_channel = None;
+    for line in records {
+        match &mut line.record {
+            MtxtRecord::ChannelDirective { channel } => current_channel = Some(*channel),
+            MtxtRecord::ControlChange { channel, .. } => {
+                *channel = channel.or(current_channel);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Resolves directives, aliases and defaults so every event is fully explicit.
+/// The output contains no directive or alias lines, aliased notes become one line per note
+/// (see `apply::expand_targets`).
+pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec())
+}
+
+/// Same as `transform`, taking the records by value so only aliased notes are copied.
+pub fn transform_owned(mut records: Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine> {
+    let mut aliases = AliasTable::default();
+    let mut new_records = Vec::with_capacity(records.len());
+    let mut bpm = DEFAULT_BPM;
+
+    apply_cc_channels(&mut records);
+    for line in apply::transform_owned(records) {
+        match &line.record {
+            MtxtRecord::AliasDef { value } => {
+                aliases.define(value);
+            }
+            MtxtRecord::Tempo { bpm: tempo, .. } => {
+                bpm = *tempo;
+                new_records.push(line);
+            }
+            MtxtRecord::Note {
+                time,
+                note,
+                duration,
+                velocity,
+                off_velocity,
+                channel,
+            } => {
+                // clock time durations become beats at the current tempo
+                let duration =
+                    duration.map_or(DEFAULT_DURATION, |duration| duration.to_beats(bpm as f64));
+                new_records.extend(expand_targets(&line, note, &aliases, |note| {
+                    MtxtRecord::Note {
+                        time: *time,
+                        note,
+                        duration: Some(duration.into()),
+                        velocity: Some(velocity.unwrap_or(DEFAULT_VELOCITY)),
+                        off_velocity: Some(off_velocity.unwrap_or(DEFAULT_OFF_VELOCITY)),
+                        channel: Some(channel.unwrap_or(DEFAULT_CHANNEL)),
+                    }
+                }));
+            }
+            MtxtRecord::NoteOn {
+                time,
+                note,
+                velocity,
+                channel,
+                length,
+            } => {
+                new_records.extend(expand_targets(&line, note, &aliases, |note| {
+                    MtxtRecord::NoteOn {
+                        time: *time,
+                        note,
+                        velocity: Some(velocity.unwrap_or(DEFAULT_VELOCITY)),
+                        channel: Some(channel.unwrap_or(DEFAULT_CHANNEL)),
+                        length: *length,
+                    }
+                }));
+            }
+            MtxtRecord::NoteOff {
+                time,
+                note,
+                off_velocity,
+                channel,
+            } => {
+                new_records.extend(expand_targets(&line, note, &aliases, |note| {
+                    MtxtRecord::NoteOff {
+                        time: *time,
+                        note,
+                        off_velocity: Some(off_velocity.unwrap_or(DEFAULT_OFF_VELOCITY)),
+                        channel: Some(channel.unwrap_or(DEFAULT_CHANNEL)),
+                    }
+                }));
+            }
+            MtxtRecord::ControlChange {
+                time,
+                note,
+                controller,
+                value,
+                channel,
+                transition_curve,
+                transition_time,
+                transition_interval,
+                transition_steps,
+            } => {
+                let make_record = |note| MtxtRecord::ControlChange {
+                    time: *time,
+                    note,
+                    controller: controller.clone(),
+                    value: *value,
+                    channel: Some(channel.unwrap_or(DEFAULT_CHANNEL)),
+                    transition_curve: *transition_curve,
+                    transition_time: *transition_time,
+                    transition_interval: *transition_interval,
+                    transition_steps: *transition_steps,
+                };
+                match note {
+                    Some(target) => {
+                        new_records.extend(expand_targets(&line, target, &aliases, |note| {
+                            make_record(Some(note))
+                        }));
+                    }
+                    None => new_records.push(MtxtRecordLine {
+                        record: make_record(None),
+                        comment: line.comment.clone(),
+                    }),
+                }
+            }
+            MtxtRecord::Voice {
+                time,
+                voices,
+                channel,
+                bank,
+            } => {
+                new_records.push(MtxtRecordLine {
+                    record: MtxtRecord::Voice {
+                        time: *time,
+                        voices: voices.clone(),
+                        channel: Some(channel.unwrap_or(DEFAULT_CHANNEL)),
+                        bank: *bank,
+                    },
+                    comment: line.comment.clone(),
+                });
+            }
+            _ => new_records.push(line),
+        }
+    }
+
+    new_records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_flatten_alias_chord() {
+        let input = r#"
+mtxt 1.0
+alias Cmaj C4,E4,G4
+ch=2
+vel=0.7
+1.0 note Cmaj // chord
+2.0 cc volume 0.5
+3.0 note pad
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 note C4 dur=1 vel=0.7 offvel=0 ch=2 // chord
+1.0 note E4 dur=1 vel=0.7 offvel=0 ch=2
+1.0 note G4 dur=1 vel=0.7 offvel=0 ch=2
+2.0 cc volume 0.5 ch=2
+3.0 note pad dur=1 vel=0.7 offvel=0 ch=2
+"#;
+        assert_eq_records(input, transform, expected);
+    }
+}
@@ -15,8 +15,13 @@ fn get_channel(record: &MtxtRecord) -> Option<u16> {
 }
 
 pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec())
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(records: Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine> {
     // 1. Apply directives to flatten state
-    let mut current_records = apply::transform(records);
+    let mut current_records = apply::transform_owned(records);
 
     // 2. Sort by Channel then Time
     current_records.sort_by(|a, b| {
@@ -39,7 +44,7 @@ pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
     });
 
     // 3. Extract directives to re-group
-    extract::transform(&current_records)
+    extract::transform_owned(current_records)
 }
 
 #[cfg(test)]
@@ -0,0 +1,170 @@
+use crate::process::{DEFAULT_CHANNEL, DEFAULT_VELOCITY};
+use crate::transforms::merge::{NoteKey, get_key};
+use crate::types::beat_time::BeatTime;
+use crate::types::humanize::HumanizeDistribution;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::collections::HashMap;
+
+/// Moves notes by up to `timing` beats either way and changes their velocity by up to
+/// `velocity` (on the 0.0-1.0 scale), without snapping to a grid like quantize's
+/// humanize does. An `off` moves with its `on`, so the note keeps its length. Notes
+/// without `vel=` start from the `vel=` directive before them. The same `seed` always
+/// gives the same result.
+pub fn transform(
+    records: &[MtxtRecordLine],
+    timing: f32,
+    velocity: f32,
+    seed: Option<u64>,
+) -> Vec<MtxtRecordLine> {
+    transform_with(
+        records,
+        timing,
+        velocity,
+        HumanizeDistribution::Uniform,
+        seed,
+    )
+}
+
+/// Same as `transform`, drawing the offsets from `distribution`.
+pub fn transform_with(
+    records: &[MtxtRecordLine],
+    timing: f32,
+    velocity: f32,
+    distribution: HumanizeDistribution,
+    seed: Option<u64>,
+) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_in_place(&mut records, timing, velocity, distribution, seed);
+    records
+}
+
+/// Same as `transform_with`, changing the records in place. Offsets are drawn in
+/// record order.
+pub(crate) fn transform_in_place(
+    records: &mut [MtxtRecordLine],
+    timing: f32,
+    velocity: f32,
+    distribution: HumanizeDistribution,
+    seed: Option<u64>,
+) {
+    if timing <= 0.0 && velocity <= 0.0 {
+        return;
+    }
+
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut current_velocity = DEFAULT_VELOCITY;
+    let mut current_channel = DEFAULT_CHANNEL;
+    // timing offsets of the note ons still waiting for their note off, by channel and note
+    let mut held: HashMap<(u16, NoteKey), Vec<f64>> = HashMap::new();
+
+    for line in records.iter_mut() {
+        let (time, note_velocity, offset) = match &mut line.record {
+            MtxtRecord::VelocityDirective { velocity } => {
+                current_velocity = *velocity;
+                continue;
+            }
+            MtxtRecord::ChannelDirective { channel } => {
+                current_channel = *channel;
+                continue;
+            }
+            MtxtRecord::Note { time, velocity, .. } => (time, Some(velocity), None),
+            MtxtRecord::NoteOn {
+                time,
+                velocity,
+                note,
+                channel,
+                length,
+            } => {
+                let offset = (timing > 0.0).then(|| distribution.sample(&mut rng, timing as f64));
+                if let Some(offset) = offset
+                    && length.is_none()
+                {
+                    let key = (channel.unwrap_or(current_channel), get_key(note));
+                    held.entry(key).or_default().push(offset);
+                }
+                (time, Some(velocity), offset)
+            }
+            MtxtRecord::NoteOff {
+                time,
+                note,
+                channel,
+                ..
+            } => {
+                let key = (channel.unwrap_or(current_channel), get_key(note));
+                let offset = held.get_mut(&key).and_then(|offsets| offsets.pop());
+                (time, None, offset)
+            }
+            _ => continue,
+        };
+
+        if timing > 0.0 {
+            let offset = offset.unwrap_or_else(|| distribution.sample(&mut rng, timing as f64));
+            *time = BeatTime::from_beats((time.as_beats() + offset).max(0.0));
+        }
+        if velocity > 0.0
+            && let Some(note_velocity) = note_velocity
+        {
+            let offset = distribution.sample(&mut rng, velocity as f64) as f32;
+            let base = note_velocity.unwrap_or(current_velocity);
+            *note_velocity = Some((base + offset).clamp(0.0, 1.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_mtxt;
+
+    #[test]
+    fn test_humanize_timing_and_velocity() {
+        let input = "mtxt 1.0\nvel=0.6\n1.0 note C4\n2.0 note E4 vel=0.8\n3.0 on G4\n4.0 off G4\n";
+        let records = parse_mtxt(input).unwrap().records;
+
+        let output = transform(&records, 0.05, 0.1, Some(3));
+        assert_eq!(output, transform(&records, 0.05, 0.1, Some(3)));
+
+        let mut timing_changed = false;
+        let mut velocity_changed = false;
+        for (before, after) in records.iter().zip(&output) {
+            if let (Some(before), Some(after)) = (before.record.time(), after.record.time()) {
+                assert!((after.as_beats() - before.as_beats()).abs() <= 0.05 + 1e-6);
+                timing_changed |= after != before;
+            }
+            let velocity = |record: &MtxtRecord| match record {
+                MtxtRecord::Note { velocity, .. } | MtxtRecord::NoteOn { velocity, .. } => {
+                    *velocity
+                }
+                _ => None,
+            };
+            if let Some(after) = velocity(&after.record) {
+                let base = velocity(&before.record).unwrap_or(0.6);
+                assert!((after - base).abs() <= 0.1 + 1e-6);
+                velocity_changed |= after != base;
+            }
+        }
+        assert!(timing_changed && velocity_changed);
+
+        // an off moves with its on, so it can't come first
+        let records = parse_mtxt("mtxt 1.0\n1.0 on C4\n1.01 off C4\n")
+            .unwrap()
+            .records;
+        for seed in 1..=20 {
+            let output = transform(&records, 0.05, 0.0, Some(seed));
+            let on = output[1].record.time().unwrap().as_beats();
+            let off = output[2].record.time().unwrap().as_beats();
+            assert!((off - on - 0.01).abs() < 1e-6, "seed {}", seed);
+        }
+
+        // velocity only leaves the timing alone
+        let output = transform(&records, 0.0, 0.1, Some(3));
+        for (before, after) in records.iter().zip(&output) {
+            assert_eq!(before.record.time(), after.record.time());
+        }
+    }
+}
@@ -1,40 +1,48 @@
-use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use crate::types::record::{ALL_CHANNELS, MtxtRecord, MtxtRecordLine};
 use std::collections::HashSet;
 
 pub fn transform(records: &[MtxtRecordLine], channels: &HashSet<u16>) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_in_place(&mut records, channels);
+    records
+}
+
+/// Same as `transform`, removing the records instead of copying the ones that are kept.
+pub(crate) fn transform_in_place(records: &mut Vec<MtxtRecordLine>, channels: &HashSet<u16>) {
     if channels.is_empty() {
-        return records.to_vec();
+        return;
     }
 
+    // `ch=*` matches every channel, so events on it are kept
+    let keep = |channel: &u16| *channel == ALL_CHANNELS || channels.contains(channel);
     let mut current_channel: Option<u16> = None;
 
-    records
-        .iter()
-        .filter(|line| match &line.record {
-            MtxtRecord::Note { channel, .. }
-            | MtxtRecord::NoteOn { channel, .. }
-            | MtxtRecord::NoteOff { channel, .. }
-            | MtxtRecord::Voice { channel, .. } => {
-                if let Some(channel) = channel {
-                    channels.contains(channel)
-                } else if let Some(curr) = current_channel {
-                    channels.contains(&curr)
-                } else {
-                    true
-                }
+    records.retain(|line| match &line.record {
+        MtxtRecord::Note { channel, .. }
+        | MtxtRecord::NoteOn { channel, .. }
+        | MtxtRecord::NoteOff { channel, .. }
+        | MtxtRecord::Voice { channel, .. } => {
+            if let Some(channel) = channel {
+                keep(channel)
+            } else if let Some(curr) = current_channel {
+                keep(&curr)
+            } else {
+                true
             }
-            MtxtRecord::ControlChange { channel, .. } => {
-                // if channel is None, affects all channels
-                channel.is_none_or(|ch| channels.contains(&ch))
-            }
-            MtxtRecord::ChannelDirective { channel } => {
-                current_channel = Some(*channel);
-                channels.contains(channel)
-            }
-            _ => true,
-        })
-        .cloned()
-        .collect()
+        }
+        MtxtRecord::ControlChange { channel, .. } => {
+            // if channel is None, affects all channels
+            channel.as_ref().is_none_or(keep)
+        }
+        // metas without their own channel are on the channel of the `ch=` directive
+        // before them, and stay with the song without one
+        MtxtRecord::Meta { channel, .. } => channel.or(current_channel).as_ref().is_none_or(keep),
+        MtxtRecord::ChannelDirective { channel } => {
+            current_channel = Some(*channel);
+            keep(channel)
+        }
+        _ => true,
+    });
 }
 
 #[cfg(test)]
@@ -72,6 +80,56 @@ mtxt 1.0
 ch=5
 5.0 note A5 dur=1
 7.0 cc G4 volume 0.5
+"#;
+
+        assert_eq_records(input, include_channels_3_5, expected);
+    }
+
+    #[test]
+    fn test_include_all_channels() {
+        let input = r#"
+mtxt 1.0
+ch=1
+1.0 note C4 ch=*
+2.0 cc volume 1
+3.0 cc volume 0.5 ch=*
+4.0 note D4
+ch=*
+5.0 note E4
+6.0 note G4 ch=1
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 note C4 ch=*
+2.0 cc volume 1
+3.0 cc volume 0.5 ch=*
+ch=*
+5.0 note E4
+"#;
+
+        assert_eq_records(input, include_channels_3_5, expected);
+    }
+
+    #[test]
+    fn test_include_channel_meta() {
+        let input = r#"
+mtxt 1.0
+0.0 meta ch=3 name Trombone
+0.0 meta ch=2 name foo
+0.0 tempo 120
+1.0 meta marker verse
+ch=2
+2.0 meta name foo
+ch=5
+3.0 meta name Bass
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 meta ch=3 name Trombone
+0.0 tempo 120
+1.0 meta marker verse
+ch=5
+3.0 meta name Bass
 "#;
 
         assert_eq_records(input, include_channels_3_5, expected);
@@ -0,0 +1,101 @@
+use crate::BeatTime;
+use crate::process::DEFAULT_CHANNEL;
+use crate::types::duration::NoteDuration;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Bound;
+
+/// Gives notes without a duration one lasting until the next note on the same
+/// channel, like a step sequencer. Notes starting together all last until the next
+/// start, and the last note of a channel keeps the default duration. Durations set
+/// with `dur=` on the note or by a `dur=` directive are kept.
+pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_in_place(&mut records);
+    records
+}
+
+/// Same as `transform`, changing the records in place.
+pub(crate) fn transform_in_place(records: &mut [MtxtRecordLine]) {
+    // start times of the notes on each channel
+    let mut starts: HashMap<u16, BTreeSet<BeatTime>> = HashMap::new();
+    let mut current_channel = DEFAULT_CHANNEL;
+    for line in records.iter() {
+        match &line.record {
+            MtxtRecord::ChannelDirective { channel } => current_channel = *channel,
+            MtxtRecord::Note { time, channel, .. } | MtxtRecord::NoteOn { time, channel, .. } => {
+                starts
+                    .entry(channel.unwrap_or(current_channel))
+                    .or_default()
+                    .insert(*time);
+            }
+            _ => {}
+        }
+    }
+
+    let mut current_channel = DEFAULT_CHANNEL;
+    let mut directive_duration = false;
+    for line in records.iter_mut() {
+        match &mut line.record {
+            MtxtRecord::ChannelDirective { channel } => current_channel = *channel,
+            MtxtRecord::DurationDirective { .. } => directive_duration = true,
+            MtxtRecord::Note {
+                time,
+                duration: duration @ None,
+                channel,
+                ..
+            } if !directive_duration => {
+                let next = starts
+                    .get(&channel.unwrap_or(current_channel))
+                    .and_then(|starts| {
+                        starts
+                            .range((Bound::Excluded(*time), Bound::Unbounded))
+                            .next()
+                    });
+                if let Some(next) = next {
+                    *duration = Some(NoteDuration::Beats(*next - *time));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_infer_duration() {
+        let input = r#"
+mtxt 1.0
+ch=1
+0.0 note C4
+0.0 note E4
+0.5 note G4 ch=2
+1.5 note D4
+2.0 note F4 dur=0.25
+3.0 note G4 ch=2
+4.0 note A4
+dur=2
+5.0 note B4
+6.0 note C5
+"#;
+        let expected = r#"
+mtxt 1.0
+ch=1
+0.0 note C4 dur=1.5
+0.0 note E4 dur=1.5
+0.5 note G4 ch=2 dur=2.5
+1.5 note D4 dur=0.5
+2.0 note F4 dur=0.25
+3.0 note G4 ch=2
+4.0 note A4 dur=1
+dur=2
+5.0 note B4
+6.0 note C5
+"#;
+        assert_eq_records(input, transform, expected);
+    }
+}
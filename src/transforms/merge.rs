@@ -1,28 +1,44 @@
 use crate::types::note::NoteTarget;
 use crate::types::record::{MtxtRecord, MtxtRecordLine};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+
+/// Which pending NoteOn a NoteOff closes when the same note is on more than once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// The first NoteOn still pending.
+    Fifo,
+    /// The last NoteOn still pending.
+    #[default]
+    Lifo,
+}
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
-enum NoteKey {
+pub(crate) enum NoteKey {
     Note(i32, u32), // total semitone, cents as u32 bits
     Alias(String),
 }
 
-fn get_key(target: &NoteTarget) -> NoteKey {
+pub(crate) fn get_key(target: &NoteTarget) -> NoteKey {
     match target {
-        NoteTarget::Note(n) => {
-            let semitone = (n.octave as i32 + 1) * 12 + n.pitch_class.to_semitone() as i32;
-            NoteKey::Note(semitone, n.cents.to_bits())
-        }
+        NoteTarget::Note(n) => NoteKey::Note(n.semitone(), n.cents.to_bits()),
         NoteTarget::AliasKey(s) => NoteKey::Alias(s.clone()),
         NoteTarget::Alias(def) => NoteKey::Alias(def.name.clone()),
     }
 }
 
-pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
-    let mut new_records = Vec::new();
-    // Key: (effective_channel, note_key) -> index in new_records
-    let mut pending: HashMap<(u16, NoteKey), usize> = HashMap::new();
+/// Merges matching NoteOn/NoteOff pairs into notes with a duration.
+///
+/// The merged note keeps the NoteOn's comment. A comment on the NoteOff is joined
+/// after it with `; `, or kept on its own when the NoteOn has none.
+pub fn transform(records: &[MtxtRecordLine], policy: MergePolicy) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec(), policy)
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(records: Vec<MtxtRecordLine>, policy: MergePolicy) -> Vec<MtxtRecordLine> {
+    let mut new_records = Vec::with_capacity(records.len());
+    // Key: (effective_channel, note_key) -> indices in new_records of the pending NoteOns
+    let mut pending: HashMap<(u16, NoteKey), VecDeque<usize>> = HashMap::new();
     let mut current_channel: u16 = 0;
 
     for line in records {
@@ -34,22 +50,22 @@ pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
         }
 
         match record {
+            // a note on with `len=` has its own note off
             MtxtRecord::NoteOn {
                 time: _,
                 note,
                 velocity: _,
                 channel,
+                length: None,
             } => {
                 let eff_ch = channel.unwrap_or(current_channel);
                 let key = get_key(note);
 
-                // If we already have a pending for this key, we leave it as NoteOn
-                // and start a new one. This handles polyphony/retrigger if allowed,
-                // or just error recovery.
-                // Better strategy: overwrite pending with new index.
+                // Stacked NoteOns of the same key wait in order, the policy picks
+                // which one the next NoteOff closes.
                 let idx = new_records.len();
-                pending.insert((eff_ch, key), idx);
-                new_records.push(line.clone());
+                pending.entry((eff_ch, key)).or_default().push_back(idx);
+                new_records.push(line);
             }
             MtxtRecord::NoteOff {
                 time: off_time,
@@ -60,40 +76,45 @@ pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
                 let eff_ch = channel.unwrap_or(current_channel);
                 let key = get_key(note);
 
-                if let Some(idx) = pending.remove(&(eff_ch, key)) {
-                    if let Some(MtxtRecordLine {
-                        record:
-                            MtxtRecord::NoteOn {
-                                time: on_time,
-                                note: _,
-                                velocity,
-                                channel: on_channel,
-                            },
-                        comment: on_comment,
-                    }) = new_records.get(idx).cloned()
+                let matched = pending
+                    .get_mut(&(eff_ch, key))
+                    .and_then(|stack| match policy {
+                        MergePolicy::Fifo => stack.pop_front(),
+                        MergePolicy::Lifo => stack.pop_back(),
+                    });
+
+                if let Some(idx) = matched {
+                    let on_line = &mut new_records[idx];
+                    if let MtxtRecord::NoteOn {
+                        time: on_time,
+                        note: _,
+                        velocity,
+                        channel: on_channel,
+                        length: _,
+                    } = on_line.record
                     {
                         let duration = *off_time - on_time;
                         // Create merged Note
-                        let new_note = MtxtRecord::Note {
+                        on_line.record = MtxtRecord::Note {
                             time: on_time,
                             note: note.clone(),
-                            duration: Some(duration),
+                            duration: Some(duration.into()),
                             velocity,
                             off_velocity: *off_velocity,
                             channel: on_channel,
                         };
-                        new_records[idx] = MtxtRecordLine {
-                            record: new_note,
-                            comment: on_comment,
+                        on_line.comment = match (on_line.comment.take(), line.comment) {
+                            (Some(on), Some(off)) => Some(format!("{}; {}", on, off)),
+                            (on, off) => on.or(off),
                         };
                     }
                 } else {
                     // Unmatched NoteOff
-                    new_records.push(line.clone());
+                    new_records.push(line);
                 }
             }
             _ => {
-                new_records.push(line.clone());
+                new_records.push(line);
             }
         }
     }
@@ -119,7 +140,7 @@ mtxt 1.0
 ch=1
 1.0 note C4 dur=1.0 vel=0.5 offvel=0.8
 "#;
-        assert_eq_records(input, transform, expected);
+        assert_eq_records(input, |r| transform(r, MergePolicy::Lifo), expected);
     }
 
     #[test]
@@ -138,7 +159,7 @@ ch=1
 1.0 note C4 dur=1.0
 1.5 note E4 dur=2.0
 "#;
-        assert_eq_records(input, transform, expected);
+        assert_eq_records(input, |r| transform(r, MergePolicy::Lifo), expected);
     }
 
     #[test]
@@ -161,7 +182,101 @@ ch=2
 1.0 note C4 dur=1.0
 ch=1
 "#;
-        assert_eq_records(input, transform, expected);
+        assert_eq_records(input, |r| transform(r, MergePolicy::Lifo), expected);
+    }
+
+    #[test]
+    fn test_merge_explicit_and_directive_channels() {
+        // an inline ch= only applies to its line, offs pair by effective channel
+        let input = r#"
+mtxt 1.0
+ch=1
+1.0 on C4 ch=2
+1.5 on C4
+2.0 off C4
+ch=2
+3.0 off C4
+4.0 on E4
+ch=1
+5.0 off E4 ch=2
+"#;
+        let expected = r#"
+mtxt 1.0
+ch=1
+1.0 note C4 dur=2.0 ch=2
+1.5 note C4 dur=0.5
+ch=2
+4.0 note E4 dur=1.0
+ch=1
+"#;
+        assert_eq_records(input, |r| transform(r, MergePolicy::Fifo), expected);
+    }
+
+    #[test]
+    fn test_merge_stacked_fifo() {
+        let input = r#"
+mtxt 1.0
+1.0 on C4
+2.0 on C4
+3.0 off C4
+5.0 off C4
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 note C4 dur=2.0
+2.0 note C4 dur=3.0
+"#;
+        assert_eq_records(input, |r| transform(r, MergePolicy::Fifo), expected);
+    }
+
+    #[test]
+    fn test_merge_stacked_lifo() {
+        let input = r#"
+mtxt 1.0
+1.0 on C4
+2.0 on C4
+3.0 off C4
+5.0 off C4
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 note C4 dur=4.0
+2.0 note C4 dur=1.0
+"#;
+        assert_eq_records(input, |r| transform(r, MergePolicy::Lifo), expected);
+    }
+
+    #[test]
+    fn test_merge_keeps_both_comments() {
+        let input = r#"
+mtxt 1.0
+1.0 on C4 // start
+2.0 off C4 // end
+3.0 on E4
+4.0 off E4 // release
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 note C4 dur=1.0 // start; end
+3.0 note E4 dur=1.0 // release
+"#;
+        assert_eq_records(input, |r| transform(r, MergePolicy::Lifo), expected);
+    }
+
+    #[test]
+    fn test_merge_skips_note_on_with_length() {
+        let input = r#"
+mtxt 1.0
+1.0 on C4 len=0.5
+2.0 on C4
+3.0 off C4
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 on C4 len=0.5
+2.0 note C4 dur=1.0
+"#;
+        assert_eq_records(input, |r| transform(r, MergePolicy::Fifo), expected);
     }
 
     #[test]
@@ -174,7 +289,7 @@ mtxt 1.0
 mtxt 1.0
 1.0 on C4
 "#;
-        assert_eq_records(input, transform, expected);
+        assert_eq_records(input, |r| transform(r, MergePolicy::Lifo), expected);
     }
 
     #[test]
@@ -187,6 +302,6 @@ mtxt 1.0
 mtxt 1.0
 1.0 off C4
 "#;
-        assert_eq_records(input, transform, expected);
+        assert_eq_records(input, |r| transform(r, MergePolicy::Lifo), expected);
     }
 }
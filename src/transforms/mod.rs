@@ -1,84 +1,890 @@
 pub mod apply;
+pub mod cc_lead;
+pub mod channel_remap;
+pub mod controller_filter;
 pub mod exclude;
 pub mod extract;
+pub mod flatten;
 pub mod group;
+pub mod humanize;
 pub mod include;
+pub mod infer_duration;
 pub mod merge;
+pub mod octave;
 pub mod offset;
 pub mod quantize;
+pub mod rotate;
+pub mod shuffle_simultaneous;
 pub mod sort;
+pub mod split;
+pub mod strip_meta;
+pub mod sysex;
+pub mod tempo_compress;
+pub mod tempo_ramp;
+pub mod tie;
 pub mod transpose;
+pub mod transpose_diatonic;
+pub mod trim;
+pub mod voice_allocate;
 
+use crate::types::beat_time::BeatTime;
+use crate::types::humanize::HumanizeDistribution;
+use crate::types::interval::Interval;
+use crate::types::note::Note;
+use crate::types::pitch::PitchClass;
 use crate::types::record::MtxtRecordLine;
-use std::collections::HashSet;
+use crate::types::scale::Scale;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// Largest quantize grid accepted by `apply_transforms_checked` (subdivisions per beat).
+pub const MAX_QUANTIZE_GRID: u32 = 1024;
+
+/// Runs `f` on every record, spread over all cores with the `parallel` feature. Only for
+/// changes that depend on nothing but the record itself, so the output is the same either way.
+pub(crate) fn for_each_record(
+    records: &mut [MtxtRecordLine],
+    f: impl Fn(&mut MtxtRecordLine) + Send + Sync,
+) {
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        // small chunks cost more to hand out than to process
+        records.par_iter_mut().with_min_len(1024).for_each(f);
+    }
+    #[cfg(not(feature = "parallel"))]
+    records.iter_mut().for_each(f);
+}
 
 pub struct TransformDescriptor {
     pub apply_directives: bool,
     pub extract_directives: bool,
+    pub flatten: bool,
+    /// Notes without a duration last until the next note on their channel, see
+    /// `infer_duration`.
+    pub infer_duration: bool,
     pub sort_by_time: bool,
+    pub sort_canonical: bool,
     pub merge_notes: bool,
+    pub split_notes: bool,
+    pub merge_ties: Option<BeatTime>,
+    pub voice_allocate: Vec<u16>,
     pub quantize_grid: u32,
     pub quantize_swing: f32,
+    /// Random offset around the quantize grid line (0.0-1.0 of a quarter grid step),
+    /// only applied together with `quantize_grid`.
     pub quantize_humanize: f32,
+    /// Distribution of both the quantize humanize and `humanize_timing`/`humanize_velocity`.
+    pub humanize_distribution: HumanizeDistribution,
+    /// Seed of every random stage: humanize, quantize humanize and shuffle.
+    pub seed: Option<u64>,
+    pub quantize_multi: Vec<u32>,
+    pub quantize_targets: quantize::QuantizeTargets,
+    pub quantize_duration: u32,
+    /// Largest random note offset in beats, without a grid, see `humanize`.
+    pub humanize_timing: f32,
+    /// Largest random velocity change (0.0-1.0), see `humanize`.
+    pub humanize_velocity: f32,
+    /// Keep only the events from `trim_start` up to `trim_end`, see `trim`.
+    pub trim_start: Option<BeatTime>,
+    pub trim_end: Option<BeatTime>,
+    pub trim_options: trim::TrimOptions,
     pub transpose_amount: i32,
+    pub transpose_diatonic: Option<Interval>,
+    pub transpose_steps: Option<(PitchClass, Scale, i32)>,
+    pub transpose_octaves: i32,
+    pub fold_octaves: Option<(Note, u32)>,
     pub offset_amount: f32,
+    pub cc_lead: Option<(BeatTime, cc_lead::LeadPolicy)>,
+    pub rotate: Option<(BeatTime, BeatTime)>,
+    pub compress_tempo: bool,
+    pub tempo_ramp: Option<tempo_ramp::TempoRamp>,
     pub include_channels: HashSet<u16>,
     pub exclude_channels: HashSet<u16>,
+    /// Channel renumbering (from -> to), applied after the channel filters.
+    pub remap_channels: HashMap<u16, u16>,
+    pub include_controllers: HashSet<String>,
+    pub exclude_controllers: HashSet<String>,
+    pub strip_meta: Option<HashSet<String>>,
+    pub shuffle_simultaneous: bool,
     pub group_channels: bool,
+    pub frame_sysex: bool,
+}
+
+/// Invalid transform parameter, naming the stage and parameter that rejected it.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{stage} stage: invalid {parameter}: {message}")]
+pub struct TransformError {
+    pub stage: &'static str,
+    pub parameter: &'static str,
+    pub message: String,
 }
 
+impl TransformError {
+    fn new(stage: &'static str, parameter: &'static str, message: String) -> Self {
+        Self {
+            stage,
+            parameter,
+            message,
+        }
+    }
+}
+
+/// Record counts and elapsed time of a single executed transform stage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageReport {
+    pub stage: &'static str,
+    pub records_in: usize,
+    pub records_out: usize,
+    pub elapsed: Duration,
+}
+
+impl TransformDescriptor {
+    pub fn validate(&self) -> Result<(), TransformError> {
+        if self.quantize_grid > MAX_QUANTIZE_GRID {
+            return Err(TransformError::new(
+                "quantize",
+                "grid",
+                format!(
+                    "{} exceeds the maximum of {}",
+                    self.quantize_grid, MAX_QUANTIZE_GRID
+                ),
+            ));
+        }
+
+        if self.quantize_duration > MAX_QUANTIZE_GRID {
+            return Err(TransformError::new(
+                "quantize_duration",
+                "grid",
+                format!(
+                    "{} exceeds the maximum of {}",
+                    self.quantize_duration, MAX_QUANTIZE_GRID
+                ),
+            ));
+        }
+
+        if !(0.0..=1.0).contains(&self.quantize_swing) {
+            return Err(TransformError::new(
+                "quantize",
+                "swing",
+                format!("{} is outside 0.0-1.0", self.quantize_swing),
+            ));
+        }
+
+        if !(0.0..=1.0).contains(&self.quantize_humanize) {
+            return Err(TransformError::new(
+                "quantize",
+                "humanize",
+                format!("{} is outside 0.0-1.0", self.quantize_humanize),
+            ));
+        }
+
+        if self.quantize_grid == 0 && (self.quantize_swing != 0.0 || self.quantize_humanize != 0.0)
+        {
+            return Err(TransformError::new(
+                "quantize",
+                "grid",
+                "swing and humanize require a quantize grid".to_string(),
+            ));
+        }
+
+        if !(self.humanize_timing.is_finite() && self.humanize_timing >= 0.0) {
+            return Err(TransformError::new(
+                "humanize",
+                "timing",
+                format!("{} is not 0 or more beats", self.humanize_timing),
+            ));
+        }
+
+        if !(0.0..=1.0).contains(&self.humanize_velocity) {
+            return Err(TransformError::new(
+                "humanize",
+                "velocity",
+                format!("{} is outside 0.0-1.0", self.humanize_velocity),
+            ));
+        }
+
+        if let (Some(start), Some(end)) = (self.trim_start, self.trim_end)
+            && start >= end
+        {
+            return Err(TransformError::new(
+                "trim",
+                "end",
+                format!("{} is not after the start {}", end, start),
+            ));
+        }
+
+        if let Some(&grid) = self
+            .quantize_multi
+            .iter()
+            .find(|&&g| g == 0 || g > MAX_QUANTIZE_GRID)
+        {
+            return Err(TransformError::new(
+                "quantize",
+                "grids",
+                format!("{} is outside 1-{}", grid, MAX_QUANTIZE_GRID),
+            ));
+        }
+
+        if self.quantize_grid > 0 && !self.quantize_multi.is_empty() {
+            return Err(TransformError::new(
+                "quantize",
+                "grids",
+                "a single grid and multiple grids cannot be combined".to_string(),
+            ));
+        }
+
+        if let Some((_, 0)) = self.fold_octaves {
+            return Err(TransformError::new(
+                "fold",
+                "span",
+                "must be at least one octave".to_string(),
+            ));
+        }
+
+        if let Err(error) = BeatTime::try_from_beats(self.offset_amount.abs() as f64) {
+            return Err(TransformError::new("offset", "amount", error.to_string()));
+        }
+
+        if self.flatten && self.extract_directives {
+            return Err(TransformError::new(
+                "flatten",
+                "directives",
+                "flattened output cannot have directives extracted again".to_string(),
+            ));
+        }
+
+        if let Some((_, loop_len)) = self.rotate
+            && loop_len == BeatTime::zero()
+        {
+            return Err(TransformError::new(
+                "rotate",
+                "loop length",
+                "must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.merge_notes && self.split_notes {
+            return Err(TransformError::new(
+                "split",
+                "notes",
+                "merging and splitting notes cannot be combined".to_string(),
+            ));
+        }
+
+        if let Some(ramp) = &self.tempo_ramp {
+            if ramp.end <= ramp.start {
+                return Err(TransformError::new(
+                    "tempo_ramp",
+                    "end",
+                    format!("{} is not after the start {}", ramp.end, ramp.start),
+                ));
+            }
+
+            if let Some(bpm) = [ramp.from_bpm, ramp.to_bpm]
+                .into_iter()
+                .find(|bpm| !bpm.is_finite() || *bpm <= 0.0)
+            {
+                return Err(TransformError::new(
+                    "tempo_ramp",
+                    "bpm",
+                    format!("{} is not a positive tempo", bpm),
+                ));
+            }
+        }
+
+        if let Some(channel) = self
+            .include_channels
+            .intersection(&self.exclude_channels)
+            .min()
+        {
+            return Err(TransformError::new(
+                "exclude",
+                "channels",
+                format!("channel {} is both included and excluded", channel),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs the transforms. A stage that fails on the records, like an octave shift moving a
+/// note out of range, leaves them as they are; the checked variants report it.
 pub fn apply_transforms(
     records: &[MtxtRecordLine],
     transforms: &TransformDescriptor,
 ) -> Vec<MtxtRecordLine> {
-    let mut current_records = records.to_vec();
+    run_stages(records.to_vec(), transforms, None, &mut None)
+}
+
+/// Same as `apply_transforms`, taking the records by value. Each stage passes them on
+/// to the next, so records are only copied where a stage creates new ones.
+pub fn apply_transforms_owned(
+    records: Vec<MtxtRecordLine>,
+    transforms: &TransformDescriptor,
+) -> Vec<MtxtRecordLine> {
+    run_stages(records, transforms, None, &mut None)
+}
 
+/// Validates the descriptor before running any stage, and fails if a stage fails on
+/// the records.
+pub fn apply_transforms_checked(
+    records: &[MtxtRecordLine],
+    transforms: &TransformDescriptor,
+) -> Result<Vec<MtxtRecordLine>, TransformError> {
+    run_stages_checked(records.to_vec(), transforms, None)
+}
+
+/// Same as `apply_transforms_checked`, taking the records by value like
+/// `apply_transforms_owned`.
+pub fn apply_transforms_owned_checked(
+    records: Vec<MtxtRecordLine>,
+    transforms: &TransformDescriptor,
+) -> Result<Vec<MtxtRecordLine>, TransformError> {
+    run_stages_checked(records, transforms, None)
+}
+
+/// Same as `apply_transforms_checked`, appending a report for each executed stage.
+pub fn apply_transforms_checked_with_report(
+    records: &[MtxtRecordLine],
+    transforms: &TransformDescriptor,
+    report: &mut Vec<StageReport>,
+) -> Result<Vec<MtxtRecordLine>, TransformError> {
+    run_stages_checked(records.to_vec(), transforms, Some(report))
+}
+
+fn run_stages_checked(
+    records: Vec<MtxtRecordLine>,
+    transforms: &TransformDescriptor,
+    report: Option<&mut Vec<StageReport>>,
+) -> Result<Vec<MtxtRecordLine>, TransformError> {
+    transforms.validate()?;
+    let mut error = None;
+    let records = run_stages(records, transforms, report, &mut error);
+    match error {
+        Some(error) => Err(error),
+        None => Ok(records),
+    }
+}
+
+fn run_stage(
+    records: Vec<MtxtRecordLine>,
+    stage: &'static str,
+    report: &mut Option<&mut Vec<StageReport>>,
+    transform: impl FnOnce(Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine>,
+) -> Vec<MtxtRecordLine> {
+    let started = Instant::now();
+    let records_in = records.len();
+    let records = transform(records);
+
+    if let Some(report) = report {
+        report.push(StageReport {
+            stage,
+            records_in,
+            records_out: records.len(),
+            elapsed: started.elapsed(),
+        });
+    }
+
+    records
+}
+
+/// Same as `run_stage` for stages that change the records in place.
+fn run_stage_in_place(
+    records: Vec<MtxtRecordLine>,
+    stage: &'static str,
+    report: &mut Option<&mut Vec<StageReport>>,
+    transform: impl FnOnce(&mut Vec<MtxtRecordLine>),
+) -> Vec<MtxtRecordLine> {
+    run_stage(records, stage, report, |mut records| {
+        transform(&mut records);
+        records
+    })
+}
+
+// `error` keeps the first stage that failed, that stage leaves the records as they are
+fn run_stages(
+    mut records: Vec<MtxtRecordLine>,
+    transforms: &TransformDescriptor,
+    mut report: Option<&mut Vec<StageReport>>,
+    error: &mut Option<TransformError>,
+) -> Vec<MtxtRecordLine> {
     // order is important here
 
     if transforms.apply_directives {
-        current_records = apply::transform(&current_records);
+        records = run_stage(records, "apply", &mut report, apply::transform_owned);
+    }
+
+    // before flatten gives every note the default duration
+    if transforms.infer_duration {
+        records = run_stage_in_place(records, "infer_duration", &mut report, |r| {
+            infer_duration::transform_in_place(r)
+        });
+    }
+
+    if transforms.flatten {
+        records = run_stage(records, "flatten", &mut report, flatten::transform_owned);
     }
 
     if !transforms.include_channels.is_empty() {
-        current_records = include::transform(&current_records, &transforms.include_channels);
+        records = run_stage_in_place(records, "include", &mut report, |r| {
+            include::transform_in_place(r, &transforms.include_channels)
+        });
     }
 
     if !transforms.exclude_channels.is_empty() {
-        current_records = exclude::transform(&current_records, &transforms.exclude_channels);
+        records = run_stage_in_place(records, "exclude", &mut report, |r| {
+            exclude::transform_in_place(r, &transforms.exclude_channels)
+        });
+    }
+
+    if !transforms.remap_channels.is_empty() {
+        records = run_stage_in_place(records, "channel_remap", &mut report, |r| {
+            channel_remap::transform_in_place(r, &transforms.remap_channels)
+        });
+    }
+
+    if !transforms.include_controllers.is_empty() {
+        records = run_stage(records, "include_cc", &mut report, |r| {
+            controller_filter::include_owned(r, &transforms.include_controllers)
+        });
+    }
+
+    if !transforms.exclude_controllers.is_empty() {
+        records = run_stage(records, "exclude_cc", &mut report, |r| {
+            controller_filter::exclude_owned(r, &transforms.exclude_controllers)
+        });
+    }
+
+    if let Some(kinds) = &transforms.strip_meta {
+        records = run_stage(records, "strip_meta", &mut report, |r| {
+            strip_meta::transform_owned(r, kinds)
+        });
     }
 
     if transforms.transpose_amount != 0 {
-        current_records = transpose::transform(&current_records, transforms.transpose_amount);
+        records = run_stage_in_place(records, "transpose", &mut report, |r| {
+            transpose::transform_in_place(r, transforms.transpose_amount)
+        });
+    }
+
+    if let Some(interval) = transforms.transpose_diatonic {
+        records = run_stage_in_place(records, "transpose_diatonic", &mut report, |r| {
+            transpose::transform_diatonic_in_place(r, interval)
+        });
+    }
+
+    if let Some((key_root, scale, steps)) = transforms.transpose_steps {
+        records = run_stage_in_place(records, "transpose_steps", &mut report, |r| {
+            transpose_diatonic::transform_in_place(r, key_root, scale, steps)
+        });
+    }
+
+    if transforms.transpose_octaves != 0 {
+        records = run_stage_in_place(records, "octave", &mut report, |r| {
+            if let Err(e) = octave::shift_in_place(r, transforms.transpose_octaves) {
+                error.get_or_insert(TransformError::new("octave", "octaves", e.to_string()));
+            }
+        });
+    }
+
+    if let Some((low, span_octaves)) = &transforms.fold_octaves {
+        records = run_stage_in_place(records, "fold", &mut report, |r| {
+            if let Err(e) = octave::fold_in_place(r, low.clone(), *span_octaves) {
+                error.get_or_insert(TransformError::new("fold", "range", e.to_string()));
+            }
+        });
     }
 
     if transforms.offset_amount != 0.0 {
-        current_records = offset::transform(&current_records, transforms.offset_amount);
+        records = run_stage_in_place(records, "offset", &mut report, |r| {
+            offset::transform_in_place(r, transforms.offset_amount)
+        });
+    }
+
+    if let Some((lead, policy)) = transforms.cc_lead {
+        records = run_stage_in_place(records, "cc_lead", &mut report, |r| {
+            cc_lead::transform_in_place(r, lead, policy)
+        });
+    }
+
+    if let Some((amount, loop_len)) = transforms.rotate {
+        records = run_stage(records, "rotate", &mut report, |r| {
+            rotate::transform_owned(r, amount, loop_len)
+        });
+    }
+
+    if transforms.compress_tempo {
+        records = run_stage(records, "tempo_compress", &mut report, |r| {
+            tempo_compress::transform_owned(r, tempo_compress::DEFAULT_TOLERANCE)
+        });
+    }
+
+    if let Some(ramp) = &transforms.tempo_ramp {
+        records = run_stage(records, "tempo_ramp", &mut report, |r| {
+            tempo_ramp::transform_owned(
+                r,
+                ramp.start,
+                ramp.end,
+                ramp.from_bpm,
+                ramp.to_bpm,
+                ramp.curve,
+            )
+        });
+    }
+
+    if transforms.frame_sysex {
+        records = run_stage(records, "sysex", &mut report, sysex::transform_owned);
     }
 
     if transforms.merge_notes {
-        current_records = merge::transform(&current_records);
+        records = run_stage(records, "merge", &mut report, |r| {
+            merge::transform_owned(r, merge::MergePolicy::Lifo)
+        });
+    }
+
+    if transforms.split_notes {
+        records = run_stage(records, "split", &mut report, split::transform_owned);
     }
 
     if transforms.quantize_grid > 0 {
-        current_records = quantize::transform(
-            &current_records,
-            transforms.quantize_grid,
-            transforms.quantize_swing,
-            transforms.quantize_humanize,
+        records = run_stage_in_place(records, "quantize", &mut report, |r| {
+            quantize::transform_in_place(
+                r,
+                transforms.quantize_grid,
+                transforms.quantize_swing,
+                transforms.quantize_humanize,
+                transforms.humanize_distribution,
+                transforms.seed,
+                transforms.quantize_targets,
+            )
+        });
+    }
+
+    if !transforms.quantize_multi.is_empty() {
+        records = run_stage_in_place(records, "quantize", &mut report, |r| {
+            quantize::transform_multi_in_place(
+                r,
+                &transforms.quantize_multi,
+                transforms.quantize_targets,
+            )
+        });
+    }
+
+    if transforms.humanize_timing > 0.0 || transforms.humanize_velocity > 0.0 {
+        records = run_stage_in_place(records, "humanize", &mut report, |r| {
+            humanize::transform_in_place(
+                r,
+                transforms.humanize_timing,
+                transforms.humanize_velocity,
+                transforms.humanize_distribution,
+                transforms.seed,
+            )
+        });
+    }
+
+    if transforms.quantize_duration > 0 {
+        records = run_stage_in_place(records, "quantize_duration", &mut report, |r| {
+            quantize::transform_durations_in_place(r, transforms.quantize_duration)
+        });
+    }
+
+    if transforms.trim_start.is_some() || transforms.trim_end.is_some() {
+        records = run_stage(records, "trim", &mut report, |r| {
+            trim::transform_owned(
+                r,
+                transforms.trim_start,
+                transforms.trim_end,
+                transforms.trim_options,
+            )
+        });
+    }
+
+    if let Some(gap_tolerance) = transforms.merge_ties {
+        records = run_stage(records, "tie", &mut report, |r| {
+            tie::transform_owned(r, gap_tolerance)
+        });
+    }
+
+    if !transforms.voice_allocate.is_empty() {
+        records = run_stage(records, "voice_allocate", &mut report, |r| {
+            voice_allocate::transform_owned(r, &transforms.voice_allocate)
+        });
+    }
+
+    if transforms.sort_canonical {
+        records = run_stage(
+            records,
+            "sort",
+            &mut report,
+            sort::transform_canonical_owned,
         );
+    } else if transforms.sort_by_time {
+        records = run_stage(records, "sort", &mut report, sort::transform_owned);
     }
 
-    if transforms.sort_by_time {
-        current_records = sort::transform(&current_records);
+    if transforms.shuffle_simultaneous {
+        let seed = transforms.seed.unwrap_or_else(rand::random);
+        records = run_stage(records, "shuffle", &mut report, |r| {
+            shuffle_simultaneous::transform_owned(r, seed)
+        });
     }
 
     if transforms.group_channels {
-        current_records = group::transform(&current_records);
+        records = run_stage(records, "group", &mut report, group::transform_owned);
     }
 
     if transforms.extract_directives {
-        current_records = extract::transform(&current_records);
+        records = run_stage(records, "extract", &mut report, extract::transform_owned);
     }
 
-    current_records
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_mtxt;
+
+    fn descriptor() -> TransformDescriptor {
+        TransformDescriptor {
+            apply_directives: false,
+            extract_directives: false,
+            flatten: false,
+            infer_duration: false,
+            sort_by_time: false,
+            sort_canonical: false,
+            merge_notes: false,
+            split_notes: false,
+            merge_ties: None,
+            voice_allocate: Vec::new(),
+            quantize_grid: 0,
+            quantize_swing: 0.0,
+            quantize_humanize: 0.0,
+            humanize_distribution: HumanizeDistribution::Uniform,
+            seed: None,
+            quantize_multi: Vec::new(),
+            quantize_targets: quantize::QuantizeTargets::ALL,
+            quantize_duration: 0,
+            humanize_timing: 0.0,
+            humanize_velocity: 0.0,
+            trim_start: None,
+            trim_end: None,
+            trim_options: trim::TrimOptions::default(),
+            transpose_amount: 0,
+            transpose_diatonic: None,
+            transpose_steps: None,
+            transpose_octaves: 0,
+            fold_octaves: None,
+            offset_amount: 0.0,
+            cc_lead: None,
+            rotate: None,
+            compress_tempo: false,
+            tempo_ramp: None,
+            include_channels: HashSet::new(),
+            exclude_channels: HashSet::new(),
+            remap_channels: HashMap::new(),
+            include_controllers: HashSet::new(),
+            exclude_controllers: HashSet::new(),
+            strip_meta: None,
+            shuffle_simultaneous: false,
+            group_channels: false,
+            frame_sysex: false,
+        }
+    }
+
+    #[test]
+    fn test_checked_rejects_out_of_range_values() {
+        let file = parse_mtxt("mtxt 1.0\n1.0 note C4\n").unwrap();
+        let transforms = TransformDescriptor {
+            quantize_grid: 4,
+            quantize_swing: 1.5,
+            ..descriptor()
+        };
+
+        let err = apply_transforms_checked(&file.records, &transforms).unwrap_err();
+        assert_eq!(err.stage, "quantize");
+        assert_eq!(err.parameter, "swing");
+        assert_eq!(
+            err.to_string(),
+            "quantize stage: invalid swing: 1.5 is outside 0.0-1.0"
+        );
+
+        let transforms = TransformDescriptor {
+            offset_amount: -1e12,
+            ..descriptor()
+        };
+        let err = apply_transforms_checked(&file.records, &transforms).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "offset stage: invalid amount: 999999995904 is not a valid time in beats"
+        );
+
+        let transforms = TransformDescriptor {
+            transpose_octaves: 200,
+            ..descriptor()
+        };
+        let err = apply_transforms_checked(&file.records, &transforms).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "octave stage: invalid octaves: C4 moves to octave 204, outside -128-127"
+        );
+        assert_eq!(apply_transforms(&file.records, &transforms), file.records);
+    }
+
+    #[test]
+    fn test_checked_reports_stage_counts() {
+        let file = parse_mtxt("mtxt 1.0\n1.0 on C4\n2.0 off C4\n").unwrap();
+        let transforms = TransformDescriptor {
+            merge_notes: true,
+            sort_by_time: true,
+            ..descriptor()
+        };
+
+        let mut report = Vec::new();
+        let records =
+            apply_transforms_checked_with_report(&file.records, &transforms, &mut report).unwrap();
+        assert_eq!(records.len(), 2);
+
+        let counts: Vec<_> = report
+            .iter()
+            .map(|s| (s.stage, s.records_in, s.records_out))
+            .collect();
+        assert_eq!(counts, vec![("merge", 3, 2), ("sort", 2, 2)]);
+    }
+
+    fn per_record_input() -> Vec<MtxtRecordLine> {
+        // enough records to be split up with the `parallel` feature
+        let mut content = String::from("mtxt 1.0\nalias chord C4,E4,G4\n");
+        for idx in 0..5000 {
+            let time = idx as f64 * 0.13;
+            content.push_str(&format!("{:.2} note chord ch={}\n", time, idx % 3));
+            content.push_str(&format!("{:.2} cc D4 volume 0.5 ch={}\n", time, idx % 3));
+        }
+        parse_mtxt(&content).unwrap().records
+    }
+
+    fn per_record_transforms() -> TransformDescriptor {
+        TransformDescriptor {
+            transpose_amount: 5,
+            transpose_octaves: -1,
+            offset_amount: -1.5,
+            exclude_channels: HashSet::from([2]),
+            quantize_grid: 8,
+            quantize_humanize: 0.5,
+            seed: Some(3),
+            ..descriptor()
+        }
+    }
+
+    #[test]
+    fn test_in_place_stages_match_transforms() {
+        let records = per_record_input();
+        let output = apply_transforms(&records, &per_record_transforms());
+
+        let expected = exclude::transform(&records, &HashSet::from([2]));
+        let expected = transpose::transform(&expected, 5);
+        let expected = octave::shift(&expected, -1).unwrap();
+        let expected = offset::transform(&expected, -1.5);
+        let expected = quantize::transform(
+            &expected,
+            8,
+            0.0,
+            0.5,
+            HumanizeDistribution::Uniform,
+            Some(3),
+            quantize::QuantizeTargets::ALL,
+        );
+        assert_eq!(output, expected);
+        assert!(output.len() < records.len());
+    }
+
+    #[test]
+    fn test_owned_stages_match_transforms() {
+        let records = parse_mtxt(
+            "mtxt 1.0\nalias chord C4,E4\nmeta global title Song\n0.0 tempo 120\n\
+             ch=2\nvel=0.7\n0.0 note chord dur=1.0 // first\n0.5 cc volume 0.5\n\
+             0.5 cc pitch 0.1\n1.0 meta lyric la\n1.0 on G4\n2.0 off G4\n\
+             2.0 sysex 43 10\n3.0 note D4\n3.0 note F4\n",
+        )
+        .unwrap()
+        .records;
+
+        let transforms = TransformDescriptor {
+            flatten: true,
+            exclude_controllers: HashSet::from(["pitch".to_string()]),
+            strip_meta: Some(HashSet::from(["lyric".to_string()])),
+            rotate: Some((BeatTime::from_beats(0.5), BeatTime::from_beats(4.0))),
+            tempo_ramp: Some(tempo_ramp::TempoRamp {
+                start: BeatTime::zero(),
+                end: BeatTime::from_beats(2.0),
+                from_bpm: 120.0,
+                to_bpm: 90.0,
+                curve: Default::default(),
+            }),
+            frame_sysex: true,
+            merge_notes: true,
+            sort_by_time: true,
+            shuffle_simultaneous: true,
+            seed: Some(7),
+            extract_directives: true,
+            ..descriptor()
+        };
+        let ramp = transforms.tempo_ramp.clone().unwrap();
+
+        let expected = flatten::transform(&records);
+        let expected = controller_filter::exclude(&expected, &transforms.exclude_controllers);
+        let expected = strip_meta::transform(&expected, transforms.strip_meta.as_ref().unwrap());
+        let expected = rotate::transform(
+            &expected,
+            BeatTime::from_beats(0.5),
+            BeatTime::from_beats(4.0),
+        );
+        let expected = tempo_ramp::transform(
+            &expected,
+            ramp.start,
+            ramp.end,
+            ramp.from_bpm,
+            ramp.to_bpm,
+            ramp.curve,
+        );
+        let expected = sysex::transform(&expected);
+        let expected = merge::transform(&expected, merge::MergePolicy::Lifo);
+        let expected = sort::transform(&expected);
+        let expected = shuffle_simultaneous::transform(&expected, 7);
+        let expected = extract::transform(&expected);
+
+        assert_eq!(apply_transforms(&records, &transforms), expected);
+        assert_eq!(
+            apply_transforms_owned(records.clone(), &transforms),
+            expected
+        );
+
+        let transforms = TransformDescriptor {
+            apply_directives: true,
+            split_notes: true,
+            group_channels: true,
+            ..descriptor()
+        };
+        let expected = group::transform(&split::transform(&apply::transform(&records)));
+        assert_eq!(apply_transforms_owned(records, &transforms), expected);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let records = per_record_input();
+        let run = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| apply_transforms(&records, &per_record_transforms()))
+        };
+
+        let sequential = run(1);
+        assert_eq!(run(4), sequential);
+        assert_eq!(run(4), run(4));
+    }
 }
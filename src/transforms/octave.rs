@@ -0,0 +1,252 @@
+use crate::transforms::for_each_record;
+use crate::types::note::{Note, NoteTarget};
+use crate::types::record::{AliasDefinition, MtxtRecord, MtxtRecordLine};
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn map_target(
+    target: &NoteTarget,
+    map_note: &impl Fn(&Note) -> Note,
+    alias_map: &HashMap<usize, Arc<AliasDefinition>>,
+) -> NoteTarget {
+    match target {
+        NoteTarget::Note(n) => NoteTarget::Note(map_note(n)),
+        NoteTarget::AliasKey(k) => NoteTarget::AliasKey(k.clone()),
+        NoteTarget::Alias(alias) => match alias_map.get(&(Arc::as_ptr(alias) as usize)) {
+            Some(new_alias) => NoteTarget::Alias(new_alias.clone()),
+            None => NoteTarget::Alias(alias.clone()),
+        },
+    }
+}
+
+/// Replaces every note, including the notes of alias definitions, with `map_note(note)`.
+/// Events referring to an alias definition get the mapped definition.
+pub(crate) fn map_notes(
+    records: &mut [MtxtRecordLine],
+    map_note: impl Fn(&Note) -> Note + Send + Sync,
+) {
+    // mapped copy of each alias definition, by the address of the original. Every
+    // original outlives the pass below, so no address is reused for another definition.
+    let alias_map: HashMap<usize, Arc<AliasDefinition>> = records
+        .iter()
+        .filter_map(|line| match &line.record {
+            MtxtRecord::AliasDef { value } => Some((
+                Arc::as_ptr(value) as usize,
+                Arc::new(AliasDefinition {
+                    name: value.name.clone(),
+                    notes: value.notes.iter().map(&map_note).collect(),
+                }),
+            )),
+            _ => None,
+        })
+        .collect();
+
+    for_each_record(records, |line| match &mut line.record {
+        MtxtRecord::AliasDef { value } => {
+            if let Some(new_def) = alias_map.get(&(Arc::as_ptr(value) as usize)) {
+                *value = new_def.clone();
+            }
+        }
+        MtxtRecord::Note { note, .. }
+        | MtxtRecord::NoteOn { note, .. }
+        | MtxtRecord::NoteOff { note, .. } => {
+            *note = map_target(note, &map_note, &alias_map);
+        }
+        MtxtRecord::ControlChange {
+            note: Some(note), ..
+        } => {
+            *note = map_target(note, &map_note, &alias_map);
+        }
+        _ => {}
+    });
+}
+
+/// Sets the octave of every note to `octave(note)`, keeping the pitch class spelling and
+/// cents. Fails without changing anything when a note would leave the octave range of
+/// `Note` (-128-127).
+fn map_octaves(
+    records: &mut [MtxtRecordLine],
+    octave: impl Fn(&Note) -> i64 + Send + Sync,
+) -> Result<()> {
+    for line in records.iter() {
+        let notes = match &line.record {
+            MtxtRecord::AliasDef { value } => value.notes.as_slice(),
+            MtxtRecord::Note {
+                note: NoteTarget::Note(note),
+                ..
+            }
+            | MtxtRecord::NoteOn {
+                note: NoteTarget::Note(note),
+                ..
+            }
+            | MtxtRecord::NoteOff {
+                note: NoteTarget::Note(note),
+                ..
+            }
+            | MtxtRecord::ControlChange {
+                note: Some(NoteTarget::Note(note)),
+                ..
+            } => std::slice::from_ref(note),
+            _ => &[],
+        };
+        for note in notes {
+            let new_octave = octave(note);
+            if i8::try_from(new_octave).is_err() {
+                bail!("{} moves to octave {}, outside -128-127", note, new_octave);
+            }
+        }
+    }
+
+    map_notes(records, |note| Note {
+        pitch_class: note.pitch_class,
+        octave: i8::try_from(octave(note)).expect("octave checked above"),
+        cents: note.cents,
+    });
+    Ok(())
+}
+
+/// Moves every note by whole octaves, keeping the pitch class spelling and cents.
+/// Fails when a note would leave the octave range, see `Note`.
+pub fn shift(records: &[MtxtRecordLine], octaves: i32) -> Result<Vec<MtxtRecordLine>> {
+    let mut records = records.to_vec();
+    shift_in_place(&mut records, octaves)?;
+    Ok(records)
+}
+
+/// Same as `shift`, changing the records in place. Nothing changes when it fails.
+pub(crate) fn shift_in_place(records: &mut [MtxtRecordLine], octaves: i32) -> Result<()> {
+    if octaves == 0 {
+        return Ok(());
+    }
+
+    map_octaves(records, |note| note.octave as i64 + octaves as i64)
+}
+
+/// Wraps every note into the range of `span_octaves` octaves starting at `low`.
+/// The range is half-open: a note exactly `span_octaves` above `low` moves down one octave.
+/// Only the octave changes, pitch class spelling and cents are preserved. Fails when a
+/// note would leave the octave range, see `Note`.
+pub fn fold(
+    records: &[MtxtRecordLine],
+    low: Note,
+    span_octaves: u32,
+) -> Result<Vec<MtxtRecordLine>> {
+    let mut records = records.to_vec();
+    fold_in_place(&mut records, low, span_octaves)?;
+    Ok(records)
+}
+
+/// Same as `fold`, changing the records in place. Nothing changes when it fails.
+pub(crate) fn fold_in_place(
+    records: &mut [MtxtRecordLine],
+    low: Note,
+    span_octaves: u32,
+) -> Result<()> {
+    let low_semitone = low.semitone();
+    let span = 12 * span_octaves.max(1) as i32;
+
+    map_octaves(records, |note| {
+        let offset = note.semitone() - low_semitone;
+        let octave_shift = if offset < 0 {
+            offset.div_euclid(12)
+        } else if offset >= span {
+            (offset - span).div_euclid(12) + 1
+        } else {
+            0
+        };
+
+        note.octave as i64 - octave_shift as i64
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_mtxt;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_shift() {
+        let input = r#"
+mtxt 1.0
+alias Dbmaj Db4,F4,Ab4
+1.0 note C#4+10
+2.0 note Dbmaj
+"#;
+        let expected = r#"
+mtxt 1.0
+alias Dbmaj Db2,F2,Ab2
+1.0 note C#2+10
+2.0 note Dbmaj
+"#;
+        assert_eq_records(input, |r| shift(r, -2).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_shift_out_of_range() {
+        let mut records = parse_mtxt("mtxt 1.0\n1.0 note C4\n2.0 note G9\n")
+            .unwrap()
+            .records;
+        let original = records.clone();
+
+        let err = shift_in_place(&mut records, 120).unwrap_err();
+        assert_eq!(err.to_string(), "G9 moves to octave 129, outside -128-127");
+        assert_eq!(records, original);
+        assert!(shift(&records, i32::MAX).is_err());
+    }
+
+    #[test]
+    fn test_fold_two_octave_scale() {
+        let input = r#"
+mtxt 1.0
+0.0 note C3
+1.0 note E3
+2.0 note G3
+3.0 note B3-20
+4.0 note C4
+5.0 note Eb4
+6.0 note G4
+7.0 note B4
+8.0 note C5
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 note C4
+1.0 note E4
+2.0 note G4
+3.0 note B4-20
+4.0 note C4
+5.0 note Eb4
+6.0 note G4
+7.0 note B4
+8.0 note C4
+"#;
+        assert_eq_records(
+            input,
+            |r| fold(r, "C4".parse().unwrap(), 1).unwrap(),
+            expected,
+        );
+    }
+
+    #[test]
+    fn test_fold_boundary() {
+        let input = r#"
+mtxt 1.0
+0.0 on A3
+1.0 off A3
+2.0 cc A5 volume 0.5
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 on A3
+1.0 off A3
+2.0 cc A4 volume 0.5
+"#;
+        assert_eq_records(
+            input,
+            |r| fold(r, "A3".parse().unwrap(), 2).unwrap(),
+            expected,
+        );
+    }
+}
@@ -1,34 +1,55 @@
 use crate::BeatTime;
+use crate::transforms::for_each_record;
 use crate::types::record::MtxtRecordLine;
 
 pub fn transform(records: &[MtxtRecordLine], offset: f32) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_in_place(&mut records, offset);
+    records
+}
+
+/// Same as `transform`, moving the records in place.
+pub(crate) fn transform_in_place(records: &mut Vec<MtxtRecordLine>, offset: f32) {
     if offset == 0.0 {
-        return records.to_vec();
+        return;
     }
 
-    let abs_offset = offset.abs();
-    let beat = abs_offset.floor() as u32;
-    let frac = abs_offset.fract();
-    let offset_time = BeatTime::from_parts(beat, frac);
-    let is_negative = offset < 0.0;
+    // saturates, `TransformDescriptor::validate` rejects offsets out of range
+    let offset_time = BeatTime::from_beats(offset.abs() as f64);
+
+    transform_by_in_place(records, offset_time, offset < 0.0);
+}
 
+/// Offsets by an exact beat time. With `is_negative`, events before `offset_time` are dropped.
+pub fn transform_by(
+    records: &[MtxtRecordLine],
+    offset_time: BeatTime,
+    is_negative: bool,
+) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_by_in_place(&mut records, offset_time, is_negative);
     records
-        .iter()
-        .filter_map(|line| {
-            let mut new_line = line.clone();
-            if let Some(time) = new_line.record.time() {
-                if is_negative {
-                    if time < offset_time {
-                        return None;
-                    }
-                    new_line.record.set_time(time - offset_time);
-                } else {
-                    new_line.record.set_time(time + offset_time);
-                }
+}
+
+/// Same as `transform_by`, moving the records in place.
+pub(crate) fn transform_by_in_place(
+    records: &mut Vec<MtxtRecordLine>,
+    offset_time: BeatTime,
+    is_negative: bool,
+) {
+    if is_negative {
+        records.retain(|line| line.record.time().is_none_or(|time| time >= offset_time));
+    }
+
+    for_each_record(records, |line| {
+        if let Some(time) = line.record.time() {
+            if is_negative {
+                line.record.set_time(time - offset_time);
+            } else {
+                line.record.set_time(time + offset_time);
             }
-            Some(new_line)
-        })
-        .collect()
+        }
+    });
 }
 
 #[cfg(test)]
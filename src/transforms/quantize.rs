@@ -1,47 +1,209 @@
+use crate::transforms::for_each_record;
+use crate::types::beat_time::BeatTime;
+use crate::types::duration::NoteDuration;
+use crate::types::humanize::HumanizeDistribution;
 use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::ops::BitOr;
 
+/// Kinds of events moved by quantize, combine them with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantizeTargets(u8);
+
+impl QuantizeTargets {
+    /// Notes and note on / off events.
+    pub const NOTES: Self = Self(1);
+    /// Controller changes.
+    pub const CC: Self = Self(1 << 1);
+    /// Everything else with a time: tempo, time signatures, bars, voices, tuning, resets,
+    /// sysex and meta events.
+    pub const META: Self = Self(1 << 2);
+    pub const ALL: Self = Self(0b111);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn includes(self, record: &MtxtRecord) -> bool {
+        match record {
+            MtxtRecord::Note { .. } | MtxtRecord::NoteOn { .. } | MtxtRecord::NoteOff { .. } => {
+                self.contains(Self::NOTES)
+            }
+            MtxtRecord::ControlChange { .. } => self.contains(Self::CC),
+            _ => self.contains(Self::META),
+        }
+    }
+}
+
+impl Default for QuantizeTargets {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl BitOr for QuantizeTargets {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Humanize offsets are random, the same `seed` always gives the same timings.
 pub fn transform(
     records: &[MtxtRecordLine],
     grid: u32,
     swing: f32,
     humanize: f32,
+    distribution: HumanizeDistribution,
+    seed: Option<u64>,
+    targets: QuantizeTargets,
 ) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_in_place(
+        &mut records,
+        grid,
+        swing,
+        humanize,
+        distribution,
+        seed,
+        targets,
+    );
+    records
+}
+
+/// Same as `transform`, moving the records in place. Humanize draws its offsets in
+/// record order so a seed gives the same timings, without it records are independent.
+pub(crate) fn transform_in_place(
+    records: &mut [MtxtRecordLine],
+    grid: u32,
+    swing: f32,
+    humanize: f32,
+    distribution: HumanizeDistribution,
+    seed: Option<u64>,
+    targets: QuantizeTargets,
+) {
     if grid == 0 {
-        return records.to_vec();
+        return;
+    }
+
+    if humanize > 0.0 {
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        for line in records.iter_mut() {
+            map_time(line, targets, |time| {
+                time.quantize_with_rng(grid, swing, humanize, distribution, &mut rng)
+            });
+        }
+    } else {
+        for_each_record(records, |line| {
+            map_time(line, targets, |time| {
+                time.quantize(grid, swing, 0.0, distribution)
+            })
+        });
+    }
+}
+
+/// Snaps each event to the nearest line of any of the given grids, each in
+/// subdivisions per beat, see `BeatTime::quantize_multi`.
+pub fn transform_multi(
+    records: &[MtxtRecordLine],
+    grids: &[u32],
+    targets: QuantizeTargets,
+) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_multi_in_place(&mut records, grids, targets);
+    records
+}
+
+/// Same as `transform_multi`, moving the records in place.
+pub(crate) fn transform_multi_in_place(
+    records: &mut [MtxtRecordLine],
+    grids: &[u32],
+    targets: QuantizeTargets,
+) {
+    if grids.iter().all(|&g| g == 0) {
+        return;
     }
 
+    for_each_record(records, |line| {
+        map_time(line, targets, |time| time.quantize_multi(grids))
+    });
+}
+
+/// Rounds note lengths written in beats, on notes, `on ... len=` and `dur=` directives, to the
+/// nearest `1/grid` beat. Lengths never go below one grid step, so short notes don't
+/// vanish. Lengths in clock time (`250ms`) depend on the tempo and stay as they are.
+pub fn transform_durations(records: &[MtxtRecordLine], grid: u32) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_durations_in_place(&mut records, grid);
     records
-        .iter()
-        .map(|line| {
-            let mut new_line = line.clone();
-            match &mut new_line.record {
-                MtxtRecord::Note { time, .. }
-                | MtxtRecord::NoteOn { time, .. }
-                | MtxtRecord::NoteOff { time, .. }
-                | MtxtRecord::ControlChange { time, .. }
-                | MtxtRecord::Voice { time, .. }
-                | MtxtRecord::Tempo { time, .. }
-                | MtxtRecord::TimeSignature { time, .. }
-                | MtxtRecord::Tuning { time, .. }
-                | MtxtRecord::Reset { time, .. }
-                | MtxtRecord::SysEx { time, .. } => {
-                    *time = time.quantize(grid, swing, humanize);
-                }
-                MtxtRecord::Meta { time, .. } => {
-                    if let Some(t) = time {
-                        *t = t.quantize(grid, swing, humanize);
-                    }
-                }
-                _ => {}
+}
+
+/// Same as `transform_durations`, changing the records in place.
+pub(crate) fn transform_durations_in_place(records: &mut [MtxtRecordLine], grid: u32) {
+    if grid == 0 {
+        return;
+    }
+
+    let step = BeatTime::from_beats(1.0 / grid as f64);
+    for_each_record(records, |line| {
+        let duration = match &mut line.record {
+            MtxtRecord::Note {
+                duration: Some(duration),
+                ..
+            }
+            | MtxtRecord::NoteOn {
+                length: Some(duration),
+                ..
             }
-            new_line
-        })
-        .collect()
+            | MtxtRecord::DurationDirective { duration } => duration,
+            _ => return,
+        };
+        if let NoteDuration::Beats(beats) = duration {
+            *beats = beats
+                .quantize(grid, 0.0, 0.0, HumanizeDistribution::Uniform)
+                .max(step);
+        }
+    });
+}
+
+fn map_time(
+    line: &mut MtxtRecordLine,
+    targets: QuantizeTargets,
+    f: impl FnOnce(&BeatTime) -> BeatTime,
+) {
+    if !targets.includes(&line.record) {
+        return;
+    }
+    match &mut line.record {
+        MtxtRecord::Note { time, .. }
+        | MtxtRecord::NoteOn { time, .. }
+        | MtxtRecord::NoteOff { time, .. }
+        | MtxtRecord::ControlChange { time, .. }
+        | MtxtRecord::Voice { time, .. }
+        | MtxtRecord::Tempo { time, .. }
+        | MtxtRecord::TimeSignature { time, .. }
+        | MtxtRecord::Bar { time, .. }
+        | MtxtRecord::Tuning { time, .. }
+        | MtxtRecord::Reset { time, .. }
+        | MtxtRecord::SysEx { time, .. } => {
+            *time = f(time);
+        }
+        MtxtRecord::Meta { time: Some(t), .. } => {
+            *t = f(t);
+        }
+        _ => {}
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parse_mtxt;
     use crate::util::assert_eq_records;
 
     #[test]
@@ -58,6 +220,129 @@ mtxt 1.0
 2.0 note E4
 4.0 note G4
 "#;
-        assert_eq_records(input, |r| transform(r, 4, 0.0, 0.0), expected);
+        assert_eq_records(
+            input,
+            |r| {
+                transform(
+                    r,
+                    4,
+                    0.0,
+                    0.0,
+                    HumanizeDistribution::Uniform,
+                    None,
+                    QuantizeTargets::ALL,
+                )
+            },
+            expected,
+        );
+    }
+
+    #[test]
+    fn test_quantize_durations() {
+        let input = r#"
+mtxt 1.0
+dur=0.3
+1.0 note C4 dur=0.47
+1.1 note E4 dur=0.05
+1.2 note G4 dur=250ms
+1.3 note B4
+1.4 on D4 len=0.6
+"#;
+        let expected = r#"
+mtxt 1.0
+dur=0.25
+1.0 note C4 dur=0.5
+1.1 note E4 dur=0.25
+1.2 note G4 dur=250ms
+1.3 note B4
+1.4 on D4 len=0.5
+"#;
+        assert_eq_records(input, |r| transform_durations(r, 4), expected);
+    }
+
+    #[test]
+    fn test_quantize_multi() {
+        let file = parse_mtxt(
+            r#"
+mtxt 1.0
+1.01 note C4
+1.35 note E4
+2.7 note G4
+"#,
+        )
+        .unwrap();
+
+        // triplet positions are not exactly representable, so compare formatted times
+        let times: Vec<String> = transform_multi(&file.records, &[16, 12], QuantizeTargets::ALL)
+            .iter()
+            .filter_map(|line| match &line.record {
+                MtxtRecord::Note { time, .. } => Some(time.to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(times, vec!["1.0", "1.33333", "2.6875"]);
+    }
+
+    #[test]
+    fn test_quantize_notes_only() {
+        let input = r#"
+mtxt 1.0
+1.01 note C4
+1.13 cc volume 0.5
+1.38 cc volume 0.7
+1.49 tempo 100
+2.02 on E4
+3.99 off E4
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 note C4
+1.13 cc volume 0.5
+1.38 cc volume 0.7
+1.49 tempo 100
+2.0 on E4
+4.0 off E4
+"#;
+        assert_eq_records(
+            input,
+            |r| {
+                transform(
+                    r,
+                    4,
+                    0.0,
+                    0.0,
+                    HumanizeDistribution::Uniform,
+                    None,
+                    QuantizeTargets::NOTES,
+                )
+            },
+            expected,
+        );
+
+        let targets = QuantizeTargets::NOTES | QuantizeTargets::CC;
+        assert!(targets.contains(QuantizeTargets::CC));
+        assert!(!targets.contains(QuantizeTargets::META));
+        assert!(QuantizeTargets::default().contains(targets));
+    }
+
+    #[test]
+    fn test_humanize_seed() {
+        let file =
+            parse_mtxt("mtxt 1.0\n1.0 note C4\n2.0 note E4\n3.0 note G4\n4.0 note C5\n").unwrap();
+        let humanized = |seed| {
+            transform(
+                &file.records,
+                4,
+                0.0,
+                1.0,
+                HumanizeDistribution::Uniform,
+                Some(seed),
+                QuantizeTargets::ALL,
+            )
+        };
+
+        assert_eq!(humanized(7), humanized(7));
+        assert_ne!(humanized(7), humanized(8));
+        assert_ne!(humanized(7), file.records);
     }
 }
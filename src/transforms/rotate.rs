@@ -0,0 +1,59 @@
+use crate::BeatTime;
+use crate::types::record::MtxtRecordLine;
+
+/// Shifts timed events by `amount` within a loop of `loop_len` beats, wrapping
+/// events pushed past the end back to the start. An event landing exactly on
+/// the boundary wraps to 0. Note durations are kept, so a note near the end may
+/// still ring past the loop. Records keep their order, so directives still apply.
+pub fn transform(
+    records: &[MtxtRecordLine],
+    amount: BeatTime,
+    loop_len: BeatTime,
+) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec(), amount, loop_len)
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(
+    mut records: Vec<MtxtRecordLine>,
+    amount: BeatTime,
+    loop_len: BeatTime,
+) -> Vec<MtxtRecordLine> {
+    for line in &mut records {
+        if let Some(time) = line.record.time() {
+            line.record
+                .set_time((time % loop_len + amount % loop_len) % loop_len);
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_rotate_wraps_around() {
+        let input = r#"
+mtxt 1.0
+ch=1
+0.0 note C4
+1.0 note E4
+2.5 note G4 dur=0.5
+3.0 note C5 dur=2
+"#;
+        let expected = r#"
+mtxt 1.0
+ch=1
+1.0 note C4
+2.0 note E4
+3.5 note G4 dur=0.5
+0.0 note C5 dur=2
+"#;
+
+        const AMOUNT: BeatTime = BeatTime::from_whole_beats(1);
+        const LOOP_LEN: BeatTime = BeatTime::from_whole_beats(4);
+        assert_eq_records(input, |r| transform(r, AMOUNT, LOOP_LEN), expected);
+    }
+}
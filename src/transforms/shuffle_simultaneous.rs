@@ -0,0 +1,98 @@
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+/// Randomly reorders adjacent notes that share the same time, the same `seed` always
+/// gives the same order. Only `note` events trade places: voice, cc, tempo, reset,
+/// note on / off and other events keep their positions, and directives and other
+/// untimed lines stay in place with events never moving across them.
+pub fn transform(records: &[MtxtRecordLine], seed: u64) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec(), seed)
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(records: Vec<MtxtRecordLine>, seed: u64) -> Vec<MtxtRecordLine> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut order = Vec::with_capacity(records.len());
+
+    let mut start = 0;
+    while start < records.len() {
+        let Some(time) = records[start].record.time() else {
+            order.push(start);
+            start += 1;
+            continue;
+        };
+        let mut end = start + 1;
+        while end < records.len() && records[end].record.time() == Some(time) {
+            end += 1;
+        }
+
+        let is_note = |idx: &usize| matches!(records[*idx].record, MtxtRecord::Note { .. });
+        let mut notes: Vec<usize> = (start..end).filter(is_note).collect();
+        notes.shuffle(&mut rng);
+
+        // the notes go back into the slots of notes, in their new order
+        let mut notes = notes.into_iter();
+        for idx in start..end {
+            let idx = if is_note(&idx) {
+                notes.next().unwrap()
+            } else {
+                idx
+            };
+            order.push(idx);
+        }
+
+        start = end;
+    }
+
+    let mut records: Vec<Option<MtxtRecordLine>> = records.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .map(|idx| records[idx].take().unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::MtxtFile;
+    use crate::parse_mtxt;
+
+    fn lines(records: &[MtxtRecordLine]) -> Vec<String> {
+        MtxtFile::from_records(records.to_vec())
+            .to_string()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_shuffle_keeps_records() {
+        let file = parse_mtxt(
+            "mtxt 1.0\n0.0 note C4\n0.0 note E4\n0.0 note G4\n0.0 cc volume 0.5\n\
+             ch=2\n1.0 note C5\n1.0 note E5\n2.0 off C4\n2.0 on C4\n",
+        )
+        .unwrap();
+        let original = lines(&file.records);
+
+        let mut changed = false;
+        for seed in 0..20 {
+            let shuffled = lines(&transform(&file.records, seed));
+            assert_eq!(shuffled, lines(&transform(&file.records, seed)));
+
+            let mut sorted = shuffled.clone();
+            sorted.sort();
+            let mut expected = original.clone();
+            expected.sort();
+            assert_eq!(sorted, expected);
+
+            // the cc and the directive keep their places, off stays before on
+            assert_eq!(shuffled[4], "0.0 cc volume 0.5");
+            assert_eq!(shuffled[5], "ch=2");
+            assert_eq!(shuffled[8..], original[8..]);
+            changed |= shuffled != original;
+        }
+        assert!(changed);
+    }
+}
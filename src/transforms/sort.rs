@@ -1,44 +1,156 @@
-use crate::types::record::MtxtRecordLine;
+use crate::types::note::NoteTarget;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
 use std::cmp::Ordering;
 
 pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec())
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(records: Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine> {
+    sort_within_barriers(records, |(_, a), (_, b)| {
+        let ta = a.record.time().unwrap();
+        let tb = b.record.time().unwrap();
+        ta.partial_cmp(&tb).unwrap_or(Ordering::Equal)
+    })
+}
+
+/// Sorts by time like `transform`, then orders events at the same time by kind
+/// (bars, meta, tempo and time signatures first, then tuning, voices, controllers,
+/// sysex, note offs and notes), channel, pitch and, for controllers, controller name and
+/// value, so the output doesn't depend on the input order of simultaneous events.
+pub fn transform_canonical(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
+    transform_canonical_owned(records.to_vec())
+}
+
+/// Same as `transform_canonical`, taking the records by value so nothing is copied.
+pub fn transform_canonical_owned(records: Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine> {
+    sort_within_barriers(records, |(ch_a, a), (ch_b, b)| {
+        let (a, b) = (&a.record, &b.record);
+        a.time()
+            .cmp(&b.time())
+            .then_with(|| kind_priority(a).cmp(&kind_priority(b)))
+            .then_with(|| record_channel(a, *ch_a).cmp(&record_channel(b, *ch_b)))
+            .then_with(|| match (note_target(a), note_target(b)) {
+                (Some(a), Some(b)) => cmp_targets(a, b),
+                // channel wide cc before per-note cc
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            })
+            .then_with(|| cmp_controllers(a, b))
+    })
+}
+
+// Stable sort of the timed records between untimed ones (directives, comments), which
+// stay where they are. `compare` gets each record with the channel of its section.
+fn sort_within_barriers(
+    records: Vec<MtxtRecordLine>,
+    compare: impl Fn(&(u16, MtxtRecordLine), &(u16, MtxtRecordLine)) -> Ordering,
+) -> Vec<MtxtRecordLine> {
     let mut new_records = Vec::with_capacity(records.len());
-    let mut buffer: Vec<MtxtRecordLine> = Vec::new();
+    let mut buffer: Vec<(u16, MtxtRecordLine)> = Vec::new();
+    let mut current_channel = 0;
 
     for line in records {
         if line.record.time().is_some() {
-            buffer.push(line.clone());
+            buffer.push((current_channel, line));
         } else {
             // Barrier encountered: sort and flush buffer
-            if !buffer.is_empty() {
-                buffer.sort_by(|a, b| {
-                    let ta = a.record.time().unwrap();
-                    let tb = b.record.time().unwrap();
-                    ta.partial_cmp(&tb).unwrap_or(Ordering::Equal)
-                });
-                new_records.append(&mut buffer);
+            buffer.sort_by(&compare);
+            new_records.extend(buffer.drain(..).map(|(_, line)| line));
+            if let MtxtRecord::ChannelDirective { channel } = line.record {
+                current_channel = channel;
             }
             // Push the barrier record
-            new_records.push(line.clone());
+            new_records.push(line);
         }
     }
 
     // Flush remaining buffer
-    if !buffer.is_empty() {
-        buffer.sort_by(|a, b| {
-            let ta = a.record.time().unwrap();
-            let tb = b.record.time().unwrap();
-            ta.partial_cmp(&tb).unwrap_or(Ordering::Equal)
-        });
-        new_records.append(&mut buffer);
-    }
+    buffer.sort_by(&compare);
+    new_records.extend(buffer.into_iter().map(|(_, line)| line));
 
     new_records
 }
 
+fn kind_priority(record: &MtxtRecord) -> u8 {
+    match record {
+        MtxtRecord::Bar { .. } => 0,
+        MtxtRecord::Meta { .. } => 1,
+        MtxtRecord::Tempo { .. } => 2,
+        MtxtRecord::TimeSignature { .. } => 3,
+        MtxtRecord::Tuning { .. } | MtxtRecord::Reset { .. } => 4,
+        MtxtRecord::Voice { .. } => 5,
+        MtxtRecord::ControlChange { .. } => 6,
+        MtxtRecord::SysEx { .. } => 7,
+        MtxtRecord::NoteOff { .. } => 8,
+        _ => 9,
+    }
+}
+
+fn record_channel(record: &MtxtRecord, current: u16) -> u16 {
+    match record {
+        MtxtRecord::Note { channel, .. }
+        | MtxtRecord::NoteOn { channel, .. }
+        | MtxtRecord::NoteOff { channel, .. }
+        | MtxtRecord::ControlChange { channel, .. }
+        | MtxtRecord::Voice { channel, .. }
+        | MtxtRecord::Meta { channel, .. }
+        | MtxtRecord::Tuning { channel, .. } => channel.unwrap_or(current),
+        _ => current,
+    }
+}
+
+fn note_target(record: &MtxtRecord) -> Option<&NoteTarget> {
+    match record {
+        MtxtRecord::Note { note, .. }
+        | MtxtRecord::NoteOn { note, .. }
+        | MtxtRecord::NoteOff { note, .. } => Some(note),
+        MtxtRecord::ControlChange { note, .. } => note.as_ref(),
+        _ => None,
+    }
+}
+
+// notes by pitch, then aliases by name
+fn cmp_targets(a: &NoteTarget, b: &NoteTarget) -> Ordering {
+    let alias_name = |target: &NoteTarget| match target {
+        NoteTarget::Note(_) => String::new(),
+        NoteTarget::AliasKey(name) => name.clone(),
+        NoteTarget::Alias(def) => def.name.clone(),
+    };
+    match (a, b) {
+        (NoteTarget::Note(a), NoteTarget::Note(b)) => a.cmp_pitch(b),
+        (NoteTarget::Note(_), _) => Ordering::Less,
+        (_, NoteTarget::Note(_)) => Ordering::Greater,
+        _ => alias_name(a).cmp(&alias_name(b)),
+    }
+}
+
+// cc events by controller name, then value
+fn cmp_controllers(a: &MtxtRecord, b: &MtxtRecord) -> Ordering {
+    match (a, b) {
+        (
+            MtxtRecord::ControlChange {
+                controller: controller_a,
+                value: value_a,
+                ..
+            },
+            MtxtRecord::ControlChange {
+                controller: controller_b,
+                value: value_b,
+                ..
+            },
+        ) => controller_a
+            .cmp(controller_b)
+            .then_with(|| value_a.total_cmp(value_b)),
+        _ => Ordering::Equal,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::file::MtxtFile;
+    use crate::parse_mtxt;
     use crate::util::assert_eq_records;
 
     #[test]
@@ -72,4 +184,61 @@ ch=2
 
         assert_eq_records(input, transform, expected);
     }
+
+    #[test]
+    fn test_sort_canonical() {
+        let input = r#"
+mtxt 1.0
+ch=1
+1.0 note G4
+1.0 note C4 ch=2
+1.0 note E4
+1.0 cc volume 0.5
+1.0 off D4
+1.0 tempo 100
+0.5 note A4
+1.0 meta text intro
+1.0 voice piano
+"#;
+        let expected = r#"
+mtxt 1.0
+ch=1
+0.5 note A4
+1.0 meta text intro
+1.0 tempo 100
+1.0 voice piano
+1.0 cc volume 0.5
+1.0 off D4
+1.0 note E4
+1.0 note G4
+1.0 note C4 ch=2
+"#;
+        assert_eq_records(input, transform_canonical, expected);
+
+        // simultaneous events in any input order come out the same
+        let records = parse_mtxt(input).unwrap().records;
+        let mut reversed = records.clone();
+        reversed[3..].reverse();
+        assert_eq!(
+            MtxtFile::from_records(transform_canonical(&reversed)).to_string(),
+            MtxtFile::from_records(transform_canonical(&records)).to_string()
+        );
+    }
+
+    #[test]
+    fn test_sort_canonical_controllers() {
+        let expected = r#"
+mtxt 1.0
+1.0 cc pan 0.2
+1.0 cc volume 0.3
+1.0 cc volume 0.5
+1.0 cc C4 pressure 0.1
+"#;
+        for input in [
+            "mtxt 1.0\n1.0 cc volume 0.5\n1.0 cc C4 pressure 0.1\n1.0 cc pan 0.2\n1.0 cc volume 0.3\n",
+            "mtxt 1.0\n1.0 cc volume 0.3\n1.0 cc pan 0.2\n1.0 cc volume 0.5\n1.0 cc C4 pressure 0.1\n",
+        ] {
+            assert_eq_records(&format!("\n{}", input), transform_canonical, expected);
+        }
+    }
 }
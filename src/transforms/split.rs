@@ -0,0 +1,138 @@
+use crate::process::{DEFAULT_BPM, DEFAULT_DURATION};
+use crate::types::beat_time::BeatTime;
+use crate::types::duration::NoteDuration;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+
+/// Moves pending note offs due at or before `time` to the output, in time order.
+fn flush_offs(
+    new_records: &mut Vec<MtxtRecordLine>,
+    pending_offs: &mut Vec<MtxtRecordLine>,
+    time: Option<BeatTime>,
+) {
+    pending_offs.sort_by_key(|line| line.record.time());
+    let due = match time {
+        Some(time) => pending_offs
+            .iter()
+            .take_while(|line| line.record.time().is_some_and(|t| t <= time))
+            .count(),
+        None => pending_offs.len(),
+    };
+    new_records.extend(pending_offs.drain(..due));
+}
+
+/// Expands `note` shorthand into explicit `on` / `off` pairs, the inverse of `merge`.
+/// Notes without a duration use the current `dur=` directive (1 beat by default), clock time
+/// durations end at the tempo of the last tempo event.
+/// Offs are placed in time order but never moved past a directive, so they keep the
+/// channel and off velocity context of their note.
+pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec())
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(records: Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine> {
+    let mut new_records = Vec::with_capacity(records.len());
+    let mut pending_offs: Vec<MtxtRecordLine> = Vec::new();
+    let mut current_duration = NoteDuration::from(DEFAULT_DURATION);
+    let mut bpm = DEFAULT_BPM;
+
+    for line in records {
+        // empty and comment lines don't change the context, offs can move past them
+        if !matches!(
+            line.record,
+            MtxtRecord::EmptyLine | MtxtRecord::Comment { .. } | MtxtRecord::BlockComment { .. }
+        ) {
+            flush_offs(&mut new_records, &mut pending_offs, line.record.time());
+        }
+
+        match line.record {
+            MtxtRecord::Note {
+                time,
+                note,
+                duration,
+                velocity,
+                off_velocity,
+                channel,
+            } => {
+                new_records.push(MtxtRecordLine {
+                    record: MtxtRecord::NoteOn {
+                        time,
+                        note: note.clone(),
+                        velocity,
+                        channel,
+                        length: None,
+                    },
+                    comment: line.comment,
+                });
+                pending_offs.push(MtxtRecordLine::new(MtxtRecord::NoteOff {
+                    time: time + duration.unwrap_or(current_duration).to_beats(bpm as f64),
+                    note,
+                    off_velocity,
+                    channel,
+                }));
+            }
+            _ => {
+                match line.record {
+                    MtxtRecord::DurationDirective { duration } => current_duration = duration,
+                    MtxtRecord::Tempo { bpm: tempo, .. } => bpm = tempo,
+                    _ => {}
+                }
+                new_records.push(line);
+            }
+        }
+    }
+
+    flush_offs(&mut new_records, &mut pending_offs, None);
+
+    new_records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_split_notes() {
+        let input = r#"
+mtxt 1.0
+0.0 note C4 dur=1
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 on C4
+1.0 off C4
+"#;
+        assert_eq_records(input, transform, expected);
+    }
+
+    #[test]
+    fn test_split_notes_interleaved() {
+        let input = r#"
+mtxt 1.0
+ch=1
+dur=2
+1.0 note C4 vel=0.5 offvel=0.8
+1.5 note E4 dur=0.5 ch=2
+ch=3
+4.0 note G4
+
+// end
+"#;
+        let expected = r#"
+mtxt 1.0
+ch=1
+dur=2
+1.0 on C4 vel=0.5
+1.5 on E4 ch=2
+2.0 off E4 ch=2
+3.0 off C4 offvel=0.8
+ch=3
+4.0 on G4
+
+// end
+6.0 off G4
+"#;
+        assert_eq_records(input, transform, expected);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use std::collections::HashSet;
+
+/// Removes `meta` and `meta global` events whose type is in `kinds`, or every textual meta
+/// if `kinds` is empty. `meta global pickup` shifts bar numbering rather than carrying text,
+/// so it is only removed when listed explicitly.
+pub fn transform(records: &[MtxtRecordLine], kinds: &HashSet<String>) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec(), kinds)
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(
+    mut records: Vec<MtxtRecordLine>,
+    kinds: &HashSet<String>,
+) -> Vec<MtxtRecordLine> {
+    let strip = |meta_type: &String| {
+        if kinds.is_empty() {
+            meta_type != "pickup"
+        } else {
+            kinds.contains(meta_type)
+        }
+    };
+
+    records.retain(|line| match &line.record {
+        MtxtRecord::Meta { meta_type, .. } | MtxtRecord::GlobalMeta { meta_type, .. } => {
+            !strip(meta_type)
+        }
+        _ => true,
+    });
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    const INPUT: &str = r#"
+mtxt 1.0
+meta global title Song
+meta global pickup 1
+0.0 meta lyric Hel
+0.0 note C4
+1.0 meta marker Verse
+1.0 meta lyric lo
+1.0 note D4
+"#;
+
+    #[test]
+    fn test_strip_lyrics() {
+        let expected = r#"
+mtxt 1.0
+meta global title Song
+meta global pickup 1
+0.0 note C4
+1.0 meta marker Verse
+1.0 note D4
+"#;
+
+        assert_eq_records(
+            INPUT,
+            |r| transform(r, &HashSet::from(["lyric".to_string()])),
+            expected,
+        );
+    }
+
+    #[test]
+    fn test_strip_all() {
+        let expected = r#"
+mtxt 1.0
+meta global pickup 1
+0.0 note C4
+1.0 note D4
+"#;
+
+        assert_eq_records(INPUT, |r| transform(r, &HashSet::new()), expected);
+    }
+}
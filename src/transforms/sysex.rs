@@ -0,0 +1,46 @@
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+
+/// Wraps SysEx data lacking the F0 start or F7 end byte into a complete framed message.
+pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec())
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(mut records: Vec<MtxtRecordLine>) -> Vec<MtxtRecordLine> {
+    for line in &mut records {
+        if let MtxtRecord::SysEx { data, .. } = &mut line.record {
+            if data.first() != Some(&0xF0) {
+                data.insert(0, 0xF0);
+            }
+            if data.len() < 2 || data.last() != Some(&0xF7) {
+                data.push(0xF7);
+            }
+        }
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_frame_sysex() {
+        let input = r#"
+mtxt 1.0
+1.0 sysex 7e 7f 09 01
+2.0 sysex 43 10 f7
+3.0 sysex f0 41 f7
+4.0 sysex
+"#;
+        let expected = r#"
+mtxt 1.0
+1.0 sysex f0 7e 7f 09 01 f7
+2.0 sysex f0 43 10 f7
+3.0 sysex f0 41 f7
+4.0 sysex f0 f7
+"#;
+        assert_eq_records(input, transform, expected);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::Easing;
+use crate::types::beat_time::BeatTime;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+
+/// Largest distance in BPM of a tempo event from the straight line of its ramp
+/// accepted by `transform`.
+pub const DEFAULT_TOLERANCE: f32 = 0.5;
+
+/// Collapses runs of three or more consecutive tempo events that rise or fall steadily
+/// in a straight line, like the many small steps of a ramp exported by a DAW, into the
+/// first event and a linear transition to the last, stepping as many times as the events
+/// did. See `transform_with` for the tolerance.
+/// Records are expected to be sorted by time, tempo events that already have a
+/// transition are kept as they are.
+pub fn transform(records: &[MtxtRecordLine]) -> Vec<MtxtRecordLine> {
+    transform_with(records, DEFAULT_TOLERANCE)
+}
+
+/// Same as `transform`, events inside a ramp may be up to `tolerance` BPM off the
+/// straight line between its first and last event.
+pub fn transform_with(records: &[MtxtRecordLine], tolerance: f32) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec(), tolerance)
+}
+
+/// Same as `transform_with`, taking the records by value so nothing is copied.
+pub fn transform_owned(mut records: Vec<MtxtRecordLine>, tolerance: f32) -> Vec<MtxtRecordLine> {
+    // index, time, bpm and whether the event has a transition of its own
+    let tempos: Vec<(usize, BeatTime, f32, bool)> = records
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, line)| match &line.record {
+            MtxtRecord::Tempo {
+                time,
+                bpm,
+                transition_time,
+                ..
+            } => Some((idx, *time, *bpm, transition_time.is_some())),
+            _ => None,
+        })
+        .collect();
+
+    let mut removed = vec![false; records.len()];
+    let mut start = 0;
+    while start < tempos.len() {
+        let mut end = start + 1;
+        while end < tempos.len() && is_ramp(&tempos[start..=end], tolerance) {
+            end += 1;
+        }
+        let end = end - 1;
+        if end < start + 2 {
+            start += 1;
+            continue;
+        }
+
+        for (idx, ..) in &tempos[start + 1..end] {
+            removed[*idx] = true;
+        }
+        let (idx, time, ..) = tempos[end];
+        // explicit curve and steps, so `transition_curve=` and `transition_steps=`
+        // directives before the ramp don't change it
+        if let MtxtRecord::Tempo {
+            transition_curve,
+            transition_time,
+            transition_steps,
+            ..
+        } = &mut records[idx].record
+        {
+            *transition_curve = Some(Easing::Linear);
+            *transition_time = Some(time - tempos[start].1);
+            *transition_steps = Some((end - start) as u32);
+        }
+        start = end;
+    }
+
+    let mut removed = removed.into_iter();
+    records.retain(|_| !removed.next().unwrap_or(false));
+    records
+}
+
+/// Whether the tempos after the first rise or fall steadily and stay within `tolerance`
+/// of the line from the first to the last one.
+fn is_ramp(tempos: &[(usize, BeatTime, f32, bool)], tolerance: f32) -> bool {
+    let (_, start_time, start_bpm, _) = tempos[0];
+    let (_, end_time, end_bpm, _) = tempos[tempos.len() - 1];
+    if end_time <= start_time || end_bpm == start_bpm {
+        return false;
+    }
+    let rising = end_bpm > start_bpm;
+    let length = (end_time - start_time).as_beats();
+
+    tempos.windows(2).all(|pair| {
+        let (_, previous_time, previous_bpm, _) = pair[0];
+        let (_, time, bpm, has_transition) = pair[1];
+        let progress = ((time - start_time).as_beats() / length) as f32;
+        let expected = start_bpm + (end_bpm - start_bpm) * progress;
+        !has_transition
+            && time > previous_time
+            && (bpm > previous_bpm) == rising
+            && bpm != previous_bpm
+            && (bpm - expected).abs() <= tolerance
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_tempo_compress_linear_ramp() {
+        let input = r#"
+mtxt 1.0
+0.0 tempo 120
+0.0 note C4
+1.0 tempo 125
+2.0 tempo 130
+2.0 note E4
+3.0 tempo 135
+4.0 tempo 140
+6.0 tempo 100
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 tempo 120
+0.0 note C4
+2.0 note E4
+4.0 tempo 140 transition_curve=linear transition_time=4.0 transition_steps=4
+6.0 tempo 100
+"#;
+        assert_eq_records(input, transform, expected);
+    }
+
+    #[test]
+    fn test_tempo_compress_tolerance() {
+        // 100.3 is close enough to the line, the turn at 3.0 starts a new run
+        let input = r#"
+mtxt 1.0
+0.0 tempo 90
+1.0 tempo 100.3
+2.0 tempo 110
+3.0 tempo 100
+4.0 tempo 100
+5.0 tempo 110 transition_time=1.0
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 tempo 90
+2.0 tempo 110 transition_curve=linear transition_time=2.0 transition_steps=2
+3.0 tempo 100
+4.0 tempo 100
+5.0 tempo 110 transition_time=1.0
+"#;
+        assert_eq_records(input, transform, expected);
+
+        // with a smaller tolerance 100.3 is off the line and nothing is collapsed
+        assert_eq_records(input, |r| transform_with(r, 0.1), input);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::types::beat_time::BeatTime;
+use crate::types::easing::Easing;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+
+/// A gradual tempo change (ritardando / accelerando) between two beat times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoRamp {
+    pub start: BeatTime,
+    pub end: BeatTime,
+    pub from_bpm: f32,
+    pub to_bpm: f32,
+    pub curve: Easing,
+}
+
+fn tempo(time: BeatTime, bpm: f32, transition: Option<(BeatTime, Easing)>) -> MtxtRecordLine {
+    MtxtRecordLine::new(MtxtRecord::Tempo {
+        time,
+        bpm,
+        transition_curve: transition
+            .map(|(_, curve)| curve)
+            .filter(|curve| *curve != Easing::default()),
+        transition_time: transition.map(|(duration, _)| duration),
+        transition_interval: None,
+        transition_steps: None,
+    })
+}
+
+/// Replaces all tempo events between `start` and `end` (inclusive) with `from_bpm` at `start`
+/// and a tempo event at `end` transitioning to `to_bpm` over `end - start`.
+/// Records are expected to be sorted by time, the new events are inserted before other
+/// events at the same time.
+pub fn transform(
+    records: &[MtxtRecordLine],
+    start: BeatTime,
+    end: BeatTime,
+    from_bpm: f32,
+    to_bpm: f32,
+    curve: Easing,
+) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec(), start, end, from_bpm, to_bpm, curve)
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(
+    records: Vec<MtxtRecordLine>,
+    start: BeatTime,
+    end: BeatTime,
+    from_bpm: f32,
+    to_bpm: f32,
+    curve: Easing,
+) -> Vec<MtxtRecordLine> {
+    if end <= start {
+        return records;
+    }
+
+    replace_tempos(
+        records,
+        start,
+        end,
+        vec![
+            tempo(start, from_bpm, None),
+            tempo(end, to_bpm, Some((end - start, curve))),
+        ],
+    )
+}
+
+/// Like `transform`, but writes the ramp as `steps` explicit tempo events spaced evenly from
+/// `start`, with bpm interpolated linearly, followed by `to_bpm` at `end`. The result
+/// has no transitions and every step can be edited by hand.
+pub fn insert_ramp(
+    records: &[MtxtRecordLine],
+    start: BeatTime,
+    end: BeatTime,
+    from_bpm: f32,
+    to_bpm: f32,
+    steps: u32,
+) -> Vec<MtxtRecordLine> {
+    if end <= start || steps == 0 {
+        return records.to_vec();
+    }
+
+    let length = (end - start).as_beats();
+    let mut tempos: Vec<MtxtRecordLine> = (0..steps)
+        .map(|step| {
+            let progress = step as f64 / steps as f64;
+            let time = start + BeatTime::from_beats(length * progress);
+            let bpm = from_bpm + (to_bpm - from_bpm) * progress as f32;
+            tempo(time, bpm, None)
+        })
+        .collect();
+    tempos.push(tempo(end, to_bpm, None));
+
+    replace_tempos(records.to_vec(), start, end, tempos)
+}
+
+fn replace_tempos(
+    mut output: Vec<MtxtRecordLine>,
+    start: BeatTime,
+    end: BeatTime,
+    tempos: Vec<MtxtRecordLine>,
+) -> Vec<MtxtRecordLine> {
+    output.retain(|line| {
+        !matches!(line.record, MtxtRecord::Tempo { time, .. } if time >= start && time <= end)
+    });
+
+    for line in tempos {
+        let time = line.record.time().unwrap_or(start);
+        let idx = output
+            .iter()
+            .position(|other| other.record.time().is_some_and(|t| t >= time))
+            .unwrap_or(output.len());
+        output.insert(idx, line);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_tempo_ramp() {
+        let input = r#"
+mtxt 1.0
+0.0 tempo 120
+2.0 note C4
+4.0 note E4
+6.0 tempo 100
+8.0 note G4
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 tempo 120
+2.0 note C4
+4.0 tempo 120
+4.0 note E4
+8.0 tempo 80 transition_time=4.0
+8.0 note G4
+"#;
+        assert_eq_records(
+            input,
+            |r| {
+                transform(
+                    r,
+                    BeatTime::from_parts(4, 0.0),
+                    BeatTime::from_parts(8, 0.0),
+                    120.0,
+                    80.0,
+                    Easing::default(),
+                )
+            },
+            expected,
+        );
+    }
+
+    #[test]
+    fn test_tempo_ramp_with_curve() {
+        let input = r#"
+mtxt 1.0
+1.0 note C4
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 tempo 90
+1.0 note C4
+2.0 tempo 140 transition_curve=easein transition_time=2.0
+"#;
+        assert_eq_records(
+            input,
+            |r| {
+                transform(
+                    r,
+                    BeatTime::zero(),
+                    BeatTime::from_parts(2, 0.0),
+                    90.0,
+                    140.0,
+                    Easing::EaseIn,
+                )
+            },
+            expected,
+        );
+    }
+
+    #[test]
+    fn test_insert_ramp() {
+        let input = r#"
+mtxt 1.0
+0.0 tempo 120
+2.0 note C4
+5.0 tempo 100
+8.0 note G4
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 tempo 120
+2.0 note C4
+4.0 tempo 120
+5.0 tempo 110
+6.0 tempo 100
+7.0 tempo 90
+8.0 tempo 80
+8.0 note G4
+"#;
+        assert_eq_records(
+            input,
+            |r| {
+                insert_ramp(
+                    r,
+                    BeatTime::from_parts(4, 0.0),
+                    BeatTime::from_parts(8, 0.0),
+                    120.0,
+                    80.0,
+                    4,
+                )
+            },
+            expected,
+        );
+
+        let output = insert_ramp(
+            &crate::parse_mtxt(input).unwrap().records,
+            BeatTime::from_parts(4, 0.0),
+            BeatTime::from_parts(8, 0.0),
+            120.0,
+            80.0,
+            4,
+        );
+        let bpms: Vec<f32> = output
+            .iter()
+            .filter_map(|line| match line.record {
+                MtxtRecord::Tempo { time, bpm, .. } if time >= BeatTime::from_parts(4, 0.0) => {
+                    Some(bpm)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(bpms.len(), 5);
+        assert!(bpms.windows(2).all(|pair| pair[1] < pair[0]));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::process::{DEFAULT_BPM, DEFAULT_DURATION};
+use crate::transforms::merge::{NoteKey, get_key};
+use crate::types::beat_time::BeatTime;
+use crate::types::duration::NoteDuration;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use std::collections::HashMap;
+
+/// Joins notes of the same pitch and channel that follow each other into one longer note,
+/// as if they were tied. A note starting at most `gap_tolerance` after the previous one
+/// ends, or while it still sounds, extends it to whichever of the two ends later.
+/// The first note keeps its position, velocity and comment.
+///
+/// Only `note` events are joined, run `merge` first for note on / off pairs.
+pub fn transform(records: &[MtxtRecordLine], gap_tolerance: BeatTime) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec(), gap_tolerance)
+}
+
+/// Same as `transform`, taking the records by value so nothing is copied.
+pub fn transform_owned(
+    records: Vec<MtxtRecordLine>,
+    gap_tolerance: BeatTime,
+) -> Vec<MtxtRecordLine> {
+    let mut new_records: Vec<MtxtRecordLine> = Vec::with_capacity(records.len());
+    // (effective channel, note) -> index in new_records of the last note and its end
+    let mut last_notes: HashMap<(u16, NoteKey), (usize, BeatTime)> = HashMap::new();
+    let mut current_channel = 0;
+    let mut current_duration = NoteDuration::from(DEFAULT_DURATION);
+    let mut bpm = DEFAULT_BPM;
+
+    for line in records {
+        match &line.record {
+            MtxtRecord::ChannelDirective { channel } => current_channel = *channel,
+            MtxtRecord::DurationDirective { duration } => current_duration = *duration,
+            MtxtRecord::Tempo { bpm: tempo, .. } => bpm = *tempo,
+            MtxtRecord::Note {
+                time,
+                note,
+                duration,
+                channel,
+                ..
+            } => {
+                let key = (channel.unwrap_or(current_channel), get_key(note));
+                let end = *time + duration.unwrap_or(current_duration).to_beats(bpm as f64);
+
+                if let Some((idx, last_end)) = last_notes.get_mut(&key)
+                    && *time <= *last_end + gap_tolerance
+                    && let MtxtRecord::Note {
+                        time: start,
+                        duration: tied_duration,
+                        ..
+                    } = &mut new_records[*idx].record
+                    && *time >= *start
+                {
+                    *last_end = (*last_end).max(end);
+                    *tied_duration = Some((*last_end - *start).into());
+                    continue;
+                }
+                last_notes.insert(key, (new_records.len(), end));
+            }
+            _ => {}
+        }
+        new_records.push(line);
+    }
+
+    new_records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_tie_quarter_notes() {
+        let input = r#"
+mtxt 1.0
+ch=1
+0.0 note C4 dur=1.0 vel=0.8
+1.0 note C4 dur=1.0 vel=0.5
+1.0 note E4 dur=1.0
+"#;
+        let expected = r#"
+mtxt 1.0
+ch=1
+0.0 note C4 dur=2.0 vel=0.8
+1.0 note E4 dur=1.0
+"#;
+        assert_eq_records(input, |r| transform(r, BeatTime::zero()), expected);
+    }
+
+    #[test]
+    fn test_tie_overlap_and_gap() {
+        // the overlapping note ends first, the tie keeps the longer end; the next note
+        // starts inside the tolerance, another channel or a larger gap is not tied
+        let input = r#"
+mtxt 1.0
+dur=0.5
+0.0 note C4 dur=2.0
+1.0 note C4
+2.25 note C4
+2.25 note C4 ch=2
+4.0 note C4
+"#;
+        let expected = r#"
+mtxt 1.0
+dur=0.5
+0.0 note C4 dur=2.75
+2.25 note C4 ch=2
+4.0 note C4
+"#;
+        assert_eq_records(
+            input,
+            |r| transform(r, BeatTime::from_beats(0.25)),
+            expected,
+        );
+    }
+}
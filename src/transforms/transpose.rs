@@ -1,121 +1,73 @@
-use crate::types::note::{Note, NoteTarget};
-use crate::types::record::{AliasDefinition, MtxtRecord, MtxtRecordLine};
-use std::collections::HashMap;
-use std::rc::Rc;
+use crate::transforms::octave::map_notes;
+use crate::types::interval::Interval;
+use crate::types::key::Key;
+use crate::types::note::Note;
+use crate::types::pitch::PitchClass;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
 
-fn transpose_target(
-    target: &NoteTarget,
-    amount: i32,
-    map: &HashMap<usize, Rc<AliasDefinition>>,
-) -> NoteTarget {
-    match target {
-        NoteTarget::Note(n) => NoteTarget::Note(n.transpose(amount)),
-        NoteTarget::AliasKey(k) => NoteTarget::AliasKey(k.clone()),
-        NoteTarget::Alias(rc) => {
-            let ptr = Rc::as_ptr(rc) as usize;
-            if let Some(new_rc) = map.get(&ptr) {
-                NoteTarget::Alias(new_rc.clone())
-            } else {
-                // If not found, it means the alias def was not in the file or not yet seen.
-                // We return the original.
-                NoteTarget::Alias(rc.clone())
-            }
-        }
-    }
+pub fn transform(records: &[MtxtRecordLine], amount: i32) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_in_place(&mut records, amount);
+    records
 }
 
-pub fn transform(records: &[MtxtRecordLine], amount: i32) -> Vec<MtxtRecordLine> {
-    if amount == 0 {
-        return records.to_vec();
+/// Same as `transform`, changing the records in place.
+pub(crate) fn transform_in_place(records: &mut [MtxtRecordLine], amount: i32) {
+    if amount != 0 {
+        map_notes(records, |note| note.transpose(amount));
     }
+}
 
-    let mut new_records = Vec::with_capacity(records.len());
-    let mut alias_map: HashMap<usize, Rc<AliasDefinition>> = HashMap::new();
+/// Transposes by `interval`, spelling notes by the interval and the key of the file's
+/// `meta global key` (C major without one), see `Note::transpose_diatonic`. The key
+/// meta is transposed as well.
+pub fn transform_diatonic(records: &[MtxtRecordLine], interval: Interval) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_diatonic_in_place(&mut records, interval);
+    records
+}
 
-    for line in records {
-        let record = &line.record;
-        let new_record = match record {
-            MtxtRecord::AliasDef { value } => {
-                let new_notes: Vec<Note> =
-                    value.notes.iter().map(|n| n.transpose(amount)).collect();
-                let new_def = Rc::new(AliasDefinition {
-                    name: value.name.clone(),
-                    notes: new_notes,
-                });
-                alias_map.insert(Rc::as_ptr(value) as usize, new_def.clone());
-                MtxtRecord::AliasDef { value: new_def }
+/// Same as `transform_diatonic`, changing the records in place.
+pub(crate) fn transform_diatonic_in_place(records: &mut [MtxtRecordLine], interval: Interval) {
+    let key = records
+        .iter()
+        .find_map(|line| match &line.record {
+            MtxtRecord::GlobalMeta { meta_type, value } if meta_type == "key" => {
+                value.parse::<Key>().ok()
             }
-            MtxtRecord::Note {
-                time,
-                note,
-                duration,
-                velocity,
-                off_velocity,
-                channel,
-            } => MtxtRecord::Note {
-                time: *time,
-                note: transpose_target(note, amount, &alias_map),
-                duration: *duration,
-                velocity: *velocity,
-                off_velocity: *off_velocity,
-                channel: *channel,
-            },
-            MtxtRecord::NoteOn {
-                time,
-                note,
-                velocity,
-                channel,
-            } => MtxtRecord::NoteOn {
-                time: *time,
-                note: transpose_target(note, amount, &alias_map),
-                velocity: *velocity,
-                channel: *channel,
-            },
-            MtxtRecord::NoteOff {
-                time,
-                note,
-                off_velocity,
-                channel,
-            } => MtxtRecord::NoteOff {
-                time: *time,
-                note: transpose_target(note, amount, &alias_map),
-                off_velocity: *off_velocity,
-                channel: *channel,
-            },
-            MtxtRecord::ControlChange {
-                time,
-                note,
-                controller,
-                value,
-                channel,
-                transition_curve,
-                transition_time,
-                transition_interval,
-            } => MtxtRecord::ControlChange {
-                time: *time,
-                note: note
-                    .as_ref()
-                    .map(|n| transpose_target(n, amount, &alias_map)),
-                controller: controller.clone(),
-                value: *value,
-                channel: *channel,
-                transition_curve: *transition_curve,
-                transition_time: *transition_time,
-                transition_interval: *transition_interval,
-            },
-            _ => record.clone(),
-        };
-        new_records.push(MtxtRecordLine {
-            record: new_record,
-            comment: line.comment.clone(),
+            _ => None,
+        })
+        .unwrap_or(Key {
+            tonic: PitchClass::C,
+            minor: false,
         });
+
+    map_notes(records, |note| note.transpose_diatonic(interval, key));
+
+    let tonic = Note {
+        pitch_class: key.tonic,
+        octave: 4,
+        cents: 0.0,
+    };
+    let transposed = Key {
+        tonic: tonic.transpose_diatonic(interval, key).pitch_class,
+        minor: key.minor,
+    };
+    for line in records {
+        if let MtxtRecord::GlobalMeta { meta_type, value } = &mut line.record
+            && meta_type == "key"
+            && value.parse::<Key>().is_ok()
+        {
+            *value = transposed.to_string();
+        }
     }
-    new_records
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::file::MtxtFile;
+    use crate::parse_mtxt;
     use crate::util::assert_eq_records;
 
     #[test]
@@ -137,4 +89,31 @@ alias Cmaj B2,Eb3,F#3
 
         assert_eq_records(input, |records| transform(records, -13), expected);
     }
+
+    #[test]
+    fn test_transpose_diatonic() {
+        let input = r#"
+mtxt 1.0
+meta global key Eb major
+alias Ebmaj Eb4,G4,Bb4
+1.0 note Eb4
+2.0 note Ebmaj
+3.0 note Bb4
+"#;
+        // a minor third up from Eb major is Gb major, chromatically Eb4 would become F#4
+        let records = parse_mtxt(input.trim_start()).unwrap().records;
+        let diatonic = transform_diatonic(&records, "m3".parse().unwrap());
+        assert_eq!(
+            MtxtFile::from_records(diatonic).to_string(),
+            "mtxt 1.0\nmeta global key Gb major\nalias Ebmaj Gb4,Bb4,Db5\n\
+             1.0 note Gb4\n2.0 note Ebmaj\n3.0 note Db5\n"
+        );
+
+        let chromatic = transform(&records, 3);
+        assert_eq!(
+            MtxtFile::from_records(chromatic).to_string(),
+            "mtxt 1.0\nmeta global key Eb major\nalias Ebmaj F#4,Bb4,C#5\n\
+             1.0 note F#4\n2.0 note Ebmaj\n3.0 note C#5\n"
+        );
+    }
 }
@@ -0,0 +1,150 @@
+use crate::transforms::octave::map_notes;
+use crate::types::note::Note;
+use crate::types::pitch::PitchClass;
+use crate::types::record::MtxtRecordLine;
+use crate::types::scale::Scale;
+
+/// Moves every note `steps` degrees along the scale of `key_root` and `scale`, so the
+/// melody stays in key: up two steps in C major, C goes to E and D to F. A note
+/// outside the scale keeps its distance above the scale degree below it, C#4 goes
+/// to F4 like C4 goes to E4. Notes are spelled with the letter of their degree where
+/// a single accidental allows it.
+pub fn transform(
+    records: &[MtxtRecordLine],
+    key_root: PitchClass,
+    scale: Scale,
+    steps: i32,
+) -> Vec<MtxtRecordLine> {
+    let mut records = records.to_vec();
+    transform_in_place(&mut records, key_root, scale, steps);
+    records
+}
+
+/// Same as `transform`, changing the records in place.
+pub(crate) fn transform_in_place(
+    records: &mut [MtxtRecordLine],
+    key_root: PitchClass,
+    scale: Scale,
+    steps: i32,
+) {
+    if steps != 0 {
+        map_notes(records, |note| {
+            transpose_steps(note, key_root, scale, steps)
+        });
+    }
+}
+
+fn transpose_steps(note: &Note, key_root: PitchClass, scale: Scale, steps: i32) -> Note {
+    const LETTERS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+    let intervals = scale.intervals();
+    let semitone = note.semitone();
+    let offset = (semitone - key_root.to_semitone() as i32).rem_euclid(12);
+    // last degree at or below the note, the root (0) at the least
+    let degree = intervals.iter().rposition(|i| *i <= offset).unwrap_or(0);
+    let chromatic = offset - intervals[degree];
+
+    let target = degree as i32 + steps;
+    let moved =
+        target.div_euclid(7) * 12 + intervals[target.rem_euclid(7) as usize] - intervals[degree];
+    let transposed = note.transpose(moved);
+
+    let root_name = key_root.to_string();
+    let root_letter = root_name.chars().next().unwrap_or('C');
+    let root_index = LETTERS.iter().position(|l| *l == root_letter).unwrap_or(0) as i32;
+    let letter = LETTERS[(root_index + target).rem_euclid(7) as usize];
+    let spelled = if chromatic == 0 {
+        transposed
+            .enharmonic_equivalents()
+            .into_iter()
+            .find(|note| note.staff_position().0 == letter)
+    } else {
+        None
+    };
+    spelled.unwrap_or(transposed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_mtxt;
+    use crate::types::note::NoteTarget;
+    use crate::types::record::MtxtRecord;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_transpose_diatonic_steps() {
+        let input = r#"
+mtxt 1.0
+0.0 note C4
+1.0 note D4
+2.0 note E4
+3.0 note F4
+4.0 note G4
+5.0 note A4
+6.0 note B4
+7.0 note C5
+8.0 note C#4
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 note E4
+1.0 note F4
+2.0 note G4
+3.0 note A4
+4.0 note B4
+5.0 note C5
+6.0 note D5
+7.0 note E5
+8.0 note F4
+"#;
+        assert_eq_records(
+            input,
+            |r| transform(r, PitchClass::C, Scale::Major, 2),
+            expected,
+        );
+
+        let output = transform(
+            &parse_mtxt(input).unwrap().records,
+            PitchClass::C,
+            Scale::Major,
+            2,
+        );
+        let major = Scale::Major.intervals();
+        for line in &output {
+            if let MtxtRecord::Note { note, .. } = &line.record {
+                let NoteTarget::Note(note) = note else {
+                    panic!("expected a note");
+                };
+                assert!(major.contains(&note.semitone().rem_euclid(12)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_transpose_diatonic_steps_spelling() {
+        // down a step from C in D minor is Bb, written as a flat like the key
+        let input = r#"
+mtxt 1.0
+0.0 note C5
+1.0 note D4
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 note Bb4
+1.0 note C4
+"#;
+        assert_eq_records(
+            input,
+            |r| transform(r, PitchClass::D, Scale::Minor, -1),
+            expected,
+        );
+        // notes compare by pitch, A#4 would pass the check above
+        let output = transform(
+            &parse_mtxt(input).unwrap().records,
+            PitchClass::D,
+            Scale::Minor,
+            -1,
+        );
+        assert_eq!(output[2].to_string(), "0.0 note Bb4");
+    }
+}
@@ -0,0 +1,486 @@
+use crate::BeatTime;
+use crate::process::DEFAULT_DURATION;
+use crate::transforms::merge::{NoteKey, get_key};
+use crate::types::duration::NoteDuration;
+use crate::types::note::NoteTarget;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+
+/// Options of `transform_with`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TrimOptions {
+    /// Keep event times as they are instead of moving `start` to 0.
+    pub keep_origin: bool,
+    /// Notes started before `start` and still sounding there begin at `start` with the
+    /// rest of their duration, instead of being dropped.
+    pub keep_sounding: bool,
+}
+
+// effective channel and note
+type Key = (u16, NoteKey);
+
+// what an event before `start` sets up, the last one of each moves to `start`
+#[derive(PartialEq)]
+enum StateKey {
+    // effective channel
+    Voice(u16),
+    // effective channel and controller
+    Cc(u16, String),
+    // channel as written, `tuning` without one applies to all channels
+    Tuning(Option<u16>, String),
+}
+
+/// Keeps the events from `start` up to `end` and moves `start` to 0, e.g. to take a
+/// section out of a piece or cut a loop at its last bar. Directives and events
+/// without a time stay, and the last tempo and time signature before `start` move
+/// to it, as do the last voice of each channel, the last value of each cc on each
+/// channel and the tunings in effect. Notes are cut at `end` so nothing sounds past it: longer notes are
+/// shortened, including those taking their duration from a `dur=` directive, and an
+/// `on` still sounding at `end` gets an `off` there in place of its later one.
+/// Durations in seconds are kept, since their length in beats depends on the tempo.
+pub fn transform(
+    records: &[MtxtRecordLine],
+    start: Option<BeatTime>,
+    end: Option<BeatTime>,
+) -> Vec<MtxtRecordLine> {
+    transform_with(records, start, end, TrimOptions::default())
+}
+
+/// Same as `transform`, with `options`.
+pub fn transform_with(
+    records: &[MtxtRecordLine],
+    start: Option<BeatTime>,
+    end: Option<BeatTime>,
+    options: TrimOptions,
+) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec(), start, end, options)
+}
+
+/// Same as `transform_with`, taking the records by value so nothing is copied.
+pub fn transform_owned(
+    records: Vec<MtxtRecordLine>,
+    start: Option<BeatTime>,
+    end: Option<BeatTime>,
+    options: TrimOptions,
+) -> Vec<MtxtRecordLine> {
+    let start = start.unwrap_or(BeatTime::zero());
+    let outside = |time: BeatTime| time < start || end.is_some_and(|end| time >= end);
+
+    let mut new_records = Vec::with_capacity(records.len());
+    // note ons without their note off yet, in order: before `start`, in the window,
+    // and those before `start` left out, whose note offs go too
+    let mut before: Vec<(Key, MtxtRecordLine)> = Vec::new();
+    let mut sounding: Vec<(Key, NoteTarget)> = Vec::new();
+    let mut dropped: Vec<Key> = Vec::new();
+    let mut current_channel: u16 = 0;
+    let mut current_duration = NoteDuration::Beats(DEFAULT_DURATION);
+    let mut last_tempo = None;
+    let mut last_time_signature = None;
+    let mut last_state: Vec<(StateKey, MtxtRecordLine)> = Vec::new();
+    // where the events moved to `start` and the added note offs at `end` go: before
+    // the first event at or after `start` and after `end`, with the channel in effect
+    let mut at_start = None;
+    let mut at_end = None;
+
+    for mut line in records {
+        let time = line.record.time();
+        if at_start.is_none() && time.is_some_and(|time| time >= start) {
+            let carried = reach_start(
+                &mut before,
+                &mut sounding,
+                &mut dropped,
+                options.keep_sounding,
+            );
+            at_start = Some((new_records.len(), current_channel, carried));
+        }
+        if at_end.is_none() && time.is_some_and(|time| end.is_some_and(|end| time > end)) {
+            at_end = Some((new_records.len(), current_channel));
+        }
+
+        if time.is_some_and(|time| time < start) {
+            if matches!(line.record, MtxtRecord::Tempo { .. }) {
+                last_tempo = Some(line);
+                continue;
+            }
+            if matches!(line.record, MtxtRecord::TimeSignature { .. }) {
+                last_time_signature = Some(line);
+                continue;
+            }
+            if let Some(key) = state_key(&line.record, current_channel) {
+                remove_last(&mut last_state, |(k, _)| *k == key);
+                last_state.push((key, line));
+                continue;
+            }
+        }
+
+        match &mut line.record {
+            MtxtRecord::ChannelDirective { channel } => current_channel = *channel,
+            MtxtRecord::DurationDirective { duration } => current_duration = *duration,
+            MtxtRecord::Note { time, duration, .. } => match duration.unwrap_or(current_duration) {
+                NoteDuration::Beats(length) => {
+                    let Some(clipped) = clip(*time, length, start, end, options.keep_sounding)
+                    else {
+                        continue;
+                    };
+                    if clipped != (*time, length) {
+                        *time = clipped.0;
+                        *duration = Some(NoteDuration::Beats(clipped.1));
+                    }
+                }
+                NoteDuration::Millis(_) if outside(*time) => continue,
+                NoteDuration::Millis(_) => {}
+            },
+            MtxtRecord::NoteOn {
+                time,
+                length: Some(length),
+                ..
+            } => match length {
+                NoteDuration::Beats(length) => {
+                    let Some(clipped) = clip(*time, *length, start, end, options.keep_sounding)
+                    else {
+                        continue;
+                    };
+                    (*time, *length) = clipped;
+                }
+                NoteDuration::Millis(_) if outside(*time) => continue,
+                NoteDuration::Millis(_) => {}
+            },
+            MtxtRecord::NoteOn {
+                time,
+                note,
+                channel,
+                length: None,
+                ..
+            } => {
+                let key = (channel.unwrap_or(current_channel), get_key(note));
+                if *time < start {
+                    before.push((key, line.clone()));
+                    continue;
+                }
+                if outside(*time) {
+                    continue;
+                }
+                sounding.push((key, note.clone()));
+            }
+            MtxtRecord::NoteOff {
+                time,
+                note,
+                channel,
+                ..
+            } => {
+                let key = (channel.unwrap_or(current_channel), get_key(note));
+                if *time < start {
+                    remove_last(&mut before, |(k, _)| *k == key);
+                    continue;
+                }
+                if end.is_some_and(|end| *time > end)
+                    || !remove_last(&mut sounding, |(k, _)| *k == key)
+                        && remove_last(&mut dropped, |k| *k == key)
+                {
+                    continue;
+                }
+            }
+            _ if time.is_some_and(outside) => continue,
+            _ => {}
+        }
+        new_records.push(line);
+    }
+
+    let (start_index, start_channel, carried) = match at_start {
+        Some(at_start) => at_start,
+        None => {
+            let carried = reach_start(
+                &mut before,
+                &mut sounding,
+                &mut dropped,
+                options.keep_sounding,
+            );
+            (new_records.len(), current_channel, carried)
+        }
+    };
+
+    // the note offs go after `start`, add them first so its index holds
+    if let Some(end) = end {
+        let (index, channel_there) = at_end.unwrap_or((new_records.len(), current_channel));
+        let offs = sounding.into_iter().map(|((channel, _), note)| {
+            MtxtRecordLine::new(MtxtRecord::NoteOff {
+                time: end,
+                note,
+                off_velocity: None,
+                channel: (channel != channel_there).then_some(channel),
+            })
+        });
+        new_records.splice(index..index, offs);
+    }
+
+    let mut moved: Vec<MtxtRecordLine> = [last_tempo, last_time_signature]
+        .into_iter()
+        .flatten()
+        .collect();
+    moved.extend(last_state.into_iter().map(|(key, mut line)| {
+        if let StateKey::Voice(channel) | StateKey::Cc(channel, _) = key
+            && let MtxtRecord::Voice { channel: ch, .. }
+            | MtxtRecord::ControlChange { channel: ch, .. } = &mut line.record
+        {
+            *ch = (channel != start_channel).then_some(channel);
+        }
+        line
+    }));
+    if options.keep_sounding {
+        moved.extend(carried.into_iter().map(|((channel, _), mut line)| {
+            if let MtxtRecord::NoteOn { channel: ch, .. } = &mut line.record {
+                *ch = (channel != start_channel).then_some(channel);
+            }
+            line
+        }));
+    }
+    for line in &mut moved {
+        line.record.set_time(start);
+        // the value it reached before `start`, without the ramp towards it
+        if let MtxtRecord::Tempo {
+            transition_curve,
+            transition_time,
+            transition_interval,
+            transition_steps,
+            ..
+        }
+        | MtxtRecord::ControlChange {
+            transition_curve,
+            transition_time,
+            transition_interval,
+            transition_steps,
+            ..
+        } = &mut line.record
+        {
+            *transition_curve = None;
+            *transition_time = None;
+            *transition_interval = None;
+            *transition_steps = None;
+        }
+    }
+    new_records.splice(start_index..start_index, moved);
+
+    if !options.keep_origin && start > BeatTime::zero() {
+        for line in &mut new_records {
+            if let Some(time) = line.record.time() {
+                line.record.set_time(time - start);
+            }
+        }
+    }
+    new_records
+}
+
+fn state_key(record: &MtxtRecord, current_channel: u16) -> Option<StateKey> {
+    match record {
+        MtxtRecord::Voice { channel, .. } => {
+            Some(StateKey::Voice(channel.unwrap_or(current_channel)))
+        }
+        MtxtRecord::ControlChange {
+            note: None,
+            controller,
+            channel,
+            ..
+        } => Some(StateKey::Cc(
+            channel.unwrap_or(current_channel),
+            controller.clone(),
+        )),
+        MtxtRecord::Tuning {
+            target, channel, ..
+        } => Some(StateKey::Tuning(*channel, target.clone())),
+        _ => None,
+    }
+}
+
+/// Start and length of a note of `length` beats at `time` within the window, `None`
+/// when none of it is left.
+fn clip(
+    time: BeatTime,
+    length: BeatTime,
+    start: BeatTime,
+    end: Option<BeatTime>,
+    keep_sounding: bool,
+) -> Option<(BeatTime, BeatTime)> {
+    let mut note_start = time;
+    let mut note_end = time + length;
+    if time < start {
+        if !keep_sounding || note_end <= start {
+            return None;
+        }
+        note_start = start;
+    }
+    if let Some(end) = end {
+        if note_start >= end {
+            return None;
+        }
+        note_end = note_end.min(end);
+    }
+    Some((note_start, note_end - note_start))
+}
+
+/// The note ons before `start` still sounding there, which join `sounding` when they
+/// are kept and `dropped` otherwise.
+fn reach_start(
+    before: &mut Vec<(Key, MtxtRecordLine)>,
+    sounding: &mut Vec<(Key, NoteTarget)>,
+    dropped: &mut Vec<Key>,
+    keep_sounding: bool,
+) -> Vec<(Key, MtxtRecordLine)> {
+    let carried = std::mem::take(before);
+    for (key, line) in &carried {
+        if !keep_sounding {
+            dropped.push(key.clone());
+        } else if let MtxtRecord::NoteOn { note, .. } = &line.record {
+            sounding.push((key.clone(), note.clone()));
+        }
+    }
+    carried
+}
+
+fn remove_last<T>(items: &mut Vec<T>, found: impl Fn(&T) -> bool) -> bool {
+    match items.iter().rposition(found) {
+        Some(index) => {
+            items.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_trim_at_end() {
+        let input = r#"
+mtxt 1.0
+ch=1
+0.0 note C4 dur=2
+2.0 note E4 dur=4
+dur=3
+3.0 note G4
+3.0 on C5
+ch=2
+3.5 on E5
+4.0 note A4
+5.0 off E5
+ch=1
+6.0 off C5
+"#;
+        let expected = r#"
+mtxt 1.0
+ch=1
+0.0 note C4 dur=2
+2.0 note E4 dur=2
+dur=3
+3.0 note G4 dur=1
+3.0 on C5
+ch=2
+3.5 on E5
+4.0 off C5 ch=1
+4.0 off E5
+ch=1
+"#;
+
+        const END: BeatTime = BeatTime::from_whole_beats(4);
+        assert_eq_records(input, |r| transform(r, None, Some(END)), expected);
+    }
+
+    const INPUT: &str = r#"
+mtxt 1.0
+0.0 tempo 100
+ch=1
+0.0 note C4 dur=3
+1.0 on E4
+1.5 off E4
+1.5 on G4
+2.0 note A4
+3.0 cc volume 0.5
+3.5 off G4
+5.0 note B4
+"#;
+
+    const START: BeatTime = BeatTime::from_whole_beats(2);
+    const END: BeatTime = BeatTime::from_whole_beats(4);
+
+    #[test]
+    fn test_trim_window() {
+        let expected = r#"
+mtxt 1.0
+ch=1
+0.0 tempo 100
+0.0 note A4
+1.0 cc volume 0.5
+"#;
+        assert_eq_records(INPUT, |r| transform(r, Some(START), Some(END)), expected);
+
+        const KEEP_ORIGIN: TrimOptions = TrimOptions {
+            keep_origin: true,
+            keep_sounding: false,
+        };
+        let expected = r#"
+mtxt 1.0
+ch=1
+2.0 tempo 100
+2.0 note A4
+3.0 cc volume 0.5
+"#;
+        assert_eq_records(
+            INPUT,
+            |r| transform_with(r, Some(START), Some(END), KEEP_ORIGIN),
+            expected,
+        );
+    }
+
+    #[test]
+    fn test_trim_keep_sounding() {
+        const KEEP_SOUNDING: TrimOptions = TrimOptions {
+            keep_origin: false,
+            keep_sounding: true,
+        };
+        let expected = r#"
+mtxt 1.0
+ch=1
+0.0 note C4 dur=1
+0.0 tempo 100
+0.0 on G4
+0.0 note A4
+1.0 cc volume 0.5
+1.5 off G4
+"#;
+        assert_eq_records(
+            INPUT,
+            |r| transform_with(r, Some(START), Some(END), KEEP_SOUNDING),
+            expected,
+        );
+    }
+
+    #[test]
+    fn test_trim_carries_state() {
+        let input = r#"
+mtxt 1.0
+ch=1
+0.0 voice piano
+0.0 cc volume 0.3
+0.5 cc volume 0.6 transition_time=0.5
+0.5 voice ch=2 strings
+1.0 tuning C +10
+1.0 tuning ch=2 E -5
+1.0 tuning C +20
+1.5 cc pan -0.5 ch=2
+2.0 note C4
+"#;
+        let expected = r#"
+mtxt 1.0
+ch=1
+0.0 voice piano
+0.0 cc volume 0.6
+0.0 voice ch=2 strings
+0.0 tuning ch=2 E -5
+0.0 tuning C +20
+0.0 cc pan -0.5 ch=2
+0.0 note C4
+"#;
+        assert_eq_records(input, |r| transform(r, Some(START), None), expected);
+    }
+}
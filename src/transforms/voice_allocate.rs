@@ -0,0 +1,258 @@
+use crate::transforms::merge::{NoteKey, get_key};
+use crate::transforms::{flatten, sort};
+use crate::types::beat_time::BeatTime;
+use crate::types::duration::NoteDuration;
+use crate::types::record::{MtxtRecord, MtxtRecordLine};
+use std::collections::{HashMap, VecDeque};
+
+/// Spreads notes across a pool of `channels` so that no two overlapping notes share a
+/// channel, e.g. for monophonic synths. Each note gets the first channel of the pool
+/// that is free at its start. When every channel is busy, the one whose note ends
+/// soonest is reused.
+///
+/// The records are flattened and sorted by time first. Note on / off pairs are
+/// allocated like notes. Voice, cc and channel tuning events are copied to every pool
+/// channel at their time, so each channel sounds like the original one; with notes from
+/// several channels, the state of all of them lands on every pool channel and the last
+/// event at a time wins. A per-note cc follows the last note of its pitch instead. Other
+/// events keep their channel. An empty pool leaves the records as they are.
+pub fn transform(records: &[MtxtRecordLine], channels: &[u16]) -> Vec<MtxtRecordLine> {
+    transform_owned(records.to_vec(), channels)
+}
+
+/// Same as `transform`, taking the records by value so only aliased notes are copied.
+pub fn transform_owned(records: Vec<MtxtRecordLine>, channels: &[u16]) -> Vec<MtxtRecordLine> {
+    if channels.is_empty() {
+        return records;
+    }
+
+    let records = sort::transform_owned(flatten::transform_owned(records));
+    let mut new_records = Vec::with_capacity(records.len());
+    // end of the last note of each pool channel, `None` while a note on is held
+    let mut ends: Vec<Option<BeatTime>> = vec![Some(BeatTime::zero()); channels.len()];
+    // allocation number of the note owning each pool channel, so the off of a held note
+    // whose channel was taken over doesn't end the note that took it
+    let mut owners: Vec<usize> = vec![0; channels.len()];
+    let mut allocations = 0;
+    // (original channel, note) -> pool indices and allocation numbers of the held note ons
+    let mut held: HashMap<(u16, NoteKey), VecDeque<(usize, usize)>> = HashMap::new();
+    // (original channel, note) -> pool index of the last note started
+    let mut last_voice: HashMap<(u16, NoteKey), usize> = HashMap::new();
+
+    for mut line in records {
+        let copy_to_pool = match &line.record {
+            MtxtRecord::Voice {
+                channel: Some(_), ..
+            }
+            | MtxtRecord::Tuning {
+                channel: Some(_), ..
+            }
+            | MtxtRecord::ControlChange {
+                note: None,
+                channel: Some(_),
+                ..
+            } => true,
+            MtxtRecord::ControlChange {
+                note: Some(note),
+                channel: Some(channel),
+                ..
+            } => !last_voice.contains_key(&(*channel, get_key(note))),
+            _ => false,
+        };
+        if copy_to_pool {
+            for (i, &pool_channel) in channels.iter().enumerate() {
+                let mut record = line.record.clone();
+                if let MtxtRecord::Voice { channel, .. }
+                | MtxtRecord::Tuning { channel, .. }
+                | MtxtRecord::ControlChange { channel, .. } = &mut record
+                {
+                    *channel = Some(pool_channel);
+                }
+                new_records.push(MtxtRecordLine {
+                    record,
+                    comment: if i == 0 { line.comment.clone() } else { None },
+                });
+            }
+            continue;
+        }
+
+        match &mut line.record {
+            MtxtRecord::Note {
+                time,
+                note,
+                duration: Some(NoteDuration::Beats(duration)),
+                channel: Some(channel),
+                ..
+            }
+            | MtxtRecord::NoteOn {
+                time,
+                note,
+                length: Some(NoteDuration::Beats(duration)),
+                channel: Some(channel),
+                ..
+            } => {
+                let voice = allocate(&ends, *time);
+                ends[voice] = Some(*time + *duration);
+                allocations += 1;
+                owners[voice] = allocations;
+                last_voice.insert((*channel, get_key(note)), voice);
+                *channel = channels[voice];
+            }
+            MtxtRecord::NoteOn {
+                time,
+                note,
+                channel: Some(channel),
+                length: None,
+                ..
+            } => {
+                let voice = allocate(&ends, *time);
+                ends[voice] = None;
+                allocations += 1;
+                owners[voice] = allocations;
+                held.entry((*channel, get_key(note)))
+                    .or_default()
+                    .push_back((voice, allocations));
+                last_voice.insert((*channel, get_key(note)), voice);
+                *channel = channels[voice];
+            }
+            MtxtRecord::NoteOff {
+                time,
+                note,
+                channel: Some(channel),
+                ..
+            } => {
+                if let Some((voice, allocation)) = held
+                    .get_mut(&(*channel, get_key(note)))
+                    .and_then(|voices| voices.pop_front())
+                {
+                    if owners[voice] == allocation {
+                        ends[voice] = Some(*time);
+                    }
+                    *channel = channels[voice];
+                }
+            }
+            MtxtRecord::ControlChange {
+                note: Some(note),
+                channel: Some(channel),
+                ..
+            } => {
+                *channel = channels[last_voice[&(*channel, get_key(note))]];
+            }
+            _ => {}
+        }
+        new_records.push(line);
+    }
+
+    new_records
+}
+
+/// First pool index free at `time`, or the one ending soonest. Held notes end last.
+fn allocate(ends: &[Option<BeatTime>], time: BeatTime) -> usize {
+    if let Some(voice) = ends
+        .iter()
+        .position(|end| end.is_some_and(|end| end <= time))
+    {
+        return voice;
+    }
+    (0..ends.len())
+        .min_by_key(|&voice| (ends[voice].is_none(), ends[voice]))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::assert_eq_records;
+
+    #[test]
+    fn test_voice_allocate_overlapping() {
+        let input = r#"
+mtxt 1.0
+0.0 note C4 dur=2.0
+0.5 note E4 dur=2.0
+1.0 note G4 dur=2.0
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 note C4 dur=2.0 vel=0.5 offvel=0.0 ch=1
+0.5 note E4 dur=2.0 vel=0.5 offvel=0.0 ch=2
+1.0 note G4 dur=2.0 vel=0.5 offvel=0.0 ch=3
+"#;
+        assert_eq_records(input, |r| transform(r, &[1, 2, 3, 4]), expected);
+    }
+
+    #[test]
+    fn test_voice_allocate_reuse() {
+        // C4 has ended when A4 starts, D5 steals the channel of E4 which ends first,
+        // G4 steals the channel of A4 as D5 is still held
+        let input = r#"
+mtxt 1.0
+0.0 note C4 dur=1.0
+0.5 note E4 dur=1.0
+1.0 note A4 dur=4.0
+1.2 note G4 dur=1.0
+1.0 on D5
+2.0 off D5
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 note C4 dur=1.0 vel=0.5 offvel=0.0 ch=1
+0.5 note E4 dur=1.0 vel=0.5 offvel=0.0 ch=2
+1.0 note A4 dur=4.0 vel=0.5 offvel=0.0 ch=1
+1.0 on D5 vel=0.5 ch=2
+1.2 note G4 dur=1.0 vel=0.5 offvel=0.0 ch=1
+2.0 off D5 offvel=0.0 ch=2
+"#;
+        assert_eq_records(input, |r| transform(r, &[1, 2]), expected);
+    }
+
+    #[test]
+    fn test_voice_allocate_channel_state() {
+        let input = r#"
+mtxt 1.0
+0.0 voice strings
+0.0 cc volume 0.8
+0.0 note C4 dur=2.0
+0.5 note E4 dur=2.0
+1.0 cc E4 pressure 0.3
+1.5 cc volume 0.6 // fade
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 voice ch=1 strings
+0.0 voice ch=2 strings
+0.0 cc volume 0.8 ch=1
+0.0 cc volume 0.8 ch=2
+0.0 note C4 dur=2.0 vel=0.5 offvel=0.0 ch=1
+0.5 note E4 dur=2.0 vel=0.5 offvel=0.0 ch=2
+1.0 cc E4 pressure 0.3 ch=2
+1.5 cc volume 0.6 ch=1 // fade
+1.5 cc volume 0.6 ch=2
+"#;
+        assert_eq_records(input, |r| transform(r, &[1, 2]), expected);
+    }
+
+    #[test]
+    fn test_voice_allocate_off_of_replaced_note() {
+        // E4 takes over the channel of the held C4, whose off must not free it for F4
+        let input = r#"
+mtxt 1.0
+0.0 on C4
+0.0 on D4
+1.0 note E4 dur=4.0
+2.0 off C4
+2.0 off D4
+3.0 note F4 dur=1.0
+"#;
+        let expected = r#"
+mtxt 1.0
+0.0 on C4 vel=0.5 ch=1
+0.0 on D4 vel=0.5 ch=2
+1.0 note E4 dur=4.0 vel=0.5 offvel=0.0 ch=1
+2.0 off C4 offvel=0.0 ch=1
+2.0 off D4 offvel=0.0 ch=2
+3.0 note F4 dur=1.0 vel=0.5 offvel=0.0 ch=2
+"#;
+        assert_eq_records(input, |r| transform(r, &[1, 2]), expected);
+    }
+}
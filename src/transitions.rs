@@ -1,16 +1,44 @@
 use crate::BeatTime;
-use crate::process::IntermediateRecord;
+use crate::Easing;
+use crate::process::{IntermediateRecord, semitones_to_pitch_bend};
 use crate::types::output_record::MtxtOutputRecord;
 use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::iter::Peekable;
+use std::vec;
+
+/// With `steps` > 0 the eased progress is snapped down to `steps` levels (sample-and-hold),
+/// the final position always reaches the end value.
+fn apply_transition_curve(v0: f32, v1: f32, pos: f32, easing: Easing, steps: u32) -> f32 {
+    let mut progress = easing.apply(pos);
+    if steps > 0 && pos < 1.0 {
+        progress = (progress * steps as f32).floor() / steps as f32;
+    }
+    v0 + (v1 - v0) * progress
+}
+
+/// The value as a MIDI step (7-bit CC, 14-bit pitch bend over `pitch_bend_range`), used
+/// to drop ramp points that would produce the same MIDI message as the previously emitted
+/// one. Tempo ramps are compared by their MIDI tempo the same way.
+fn midi_step(controller: &str, value: f32, pitch_bend_range: f32) -> i32 {
+    match controller {
+        "pitch" => semitones_to_pitch_bend(value, pitch_bend_range) as i32,
+        "pan" | "balance" => ((value.clamp(-1.0, 1.0) + 1.0) / 2.0 * 127.0) as i32,
+        _ => (value.clamp(0.0, 1.0) * 127.0) as i32,
+    }
+}
 
-/// - curve > 0: ease-in (starts slow, ends fast)
-/// - curve < 0: ease-out (starts fast, ends slow)
-/// - curve = 0: linear interpolation
-fn apply_transition_curve(v0: f32, v1: f32, pos: f32, curve: f32) -> f32 {
-    v0 + (v1 - v0)
-        * (pos + curve.max(0.0) * (pos.powi(4) - pos)
-            - (-curve).max(0.0) * ((1.0 - (1.0 - pos).powi(4)) - pos))
+/// `midi_step` of a ramp point of `record` with `value`, `None` for parameters that are
+/// never dropped.
+fn record_step(record: &MtxtOutputRecord, value: f32, pitch_bend_range: f32) -> Option<i32> {
+    match record {
+        MtxtOutputRecord::ControlChange { controller, .. } => {
+            Some(midi_step(controller, value, pitch_bend_range))
+        }
+        // microseconds per quarter note, as MIDI writes a tempo
+        MtxtOutputRecord::Tempo { .. } => Some((60_000_000.0 / value) as i32),
+        _ => None,
+    }
 }
 
 #[derive(Clone)]
@@ -19,23 +47,27 @@ struct ActiveTransition {
     end_value: f32,
     next_pos: f32,
     next_micros: u64,
+    last_step: Option<i32>,
     original_record: IntermediateRecord,
 }
 
 pub struct TransitionProcessor {
     active_transitions: Vec<ActiveTransition>,
-    records: Vec<IntermediateRecord>,
-    next_record_idx: usize,
+    records: Peekable<vec::IntoIter<IntermediateRecord>>,
     current_micros: u64,
     current_beat_time: BeatTime,
     current_bpm: f32,
     last_values: HashMap<String, f32>,
+    // reused for the `last_values` key of every emitted value
+    param_key: String,
     next_beat_to_emit: BeatTime,
+    pitch_bend_range: f32,
 }
 
 impl TransitionProcessor {
-    pub fn new(records: &[IntermediateRecord]) -> Self {
-        let mut sorted_records = records.to_vec();
+    /// `pitch_bend_range` is the range in semitones `pitch` values are sent with.
+    pub fn new(records: Vec<IntermediateRecord>, pitch_bend_range: f32) -> Self {
+        let mut sorted_records = records;
         sorted_records.sort_by(|a, b| match a.start_beat_time.cmp(&b.start_beat_time) {
             Ordering::Equal => a.transition_time.cmp(&b.transition_time),
             other => other,
@@ -43,49 +75,56 @@ impl TransitionProcessor {
 
         Self {
             active_transitions: Vec::new(),
-            records: sorted_records,
-            next_record_idx: 0,
+            records: sorted_records.into_iter().peekable(),
 
             current_micros: 0,
             current_bpm: 120.0,
             current_beat_time: BeatTime::zero(),
             last_values: HashMap::new(),
+            param_key: String::new(),
             next_beat_to_emit: BeatTime::zero(),
+            pitch_bend_range,
         }
     }
 
     fn consume_record(&mut self, next_record_micros: u64) -> Option<MtxtOutputRecord> {
-        let record = self.records.get(self.next_record_idx).unwrap().clone();
+        let record = self.records.next().unwrap();
 
+        // a new value for a parameter aborts its in-progress ramp at this point
         self.active_transitions
             .retain(|t| !t.original_record.record.is_same_parameter(&record.record));
 
-        if record.start_beat_time != record.end_beat_time {
+        // a per-note ramp without a previous per-note value starts from the channel value,
+        // the first value of a parameter has nothing to ramp from and is set right away
+        let start_value = if record.start_beat_time != record.end_beat_time {
             let key = record.record.get_param_key().unwrap();
+            self.last_values.get(&key).copied().or_else(|| {
+                let channel_key = record.record.get_channel_param_key()?;
+                self.last_values.get(&channel_key).copied()
+            })
+        } else {
+            None
+        };
 
-            let start_value = *self
-                .last_values
-                .get(&key)
-                .unwrap_or_else(|| panic!("Error getting key {}", key));
-
+        if let Some(start_value) = start_value {
             let next_pos = (record.transition_interval * 1000.0)
                 / (record.transition_time.as_micros(self.current_bpm as f64) as f32);
 
             let next_micros =
                 next_record_micros + (record.transition_interval * 1000.0).round() as u64;
 
+            self.current_micros = next_record_micros;
+            self.current_beat_time = record.start_beat_time;
+
             let transition = ActiveTransition {
                 start_value,
                 end_value: record.record.get_parameter_value().unwrap(),
                 next_pos,
                 next_micros,
-                original_record: record.clone(),
+                last_step: None,
+                original_record: record,
             };
             self.active_transitions.push(transition);
-            self.current_micros = next_record_micros;
-
-            self.current_beat_time = record.start_beat_time;
-            self.next_record_idx += 1;
 
             return self.process();
         }
@@ -93,13 +132,13 @@ impl TransitionProcessor {
         self.current_micros = next_record_micros;
 
         self.current_beat_time = record.start_beat_time;
-        self.next_record_idx += 1;
 
-        let mut res = record.record.clone();
+        let mut res = record.record;
         res.set_time(self.current_micros);
         Some(res)
     }
 
+    /// Returns `None` when the value is skipped as a MIDI duplicate of the previous one.
     fn consume_transition(&mut self, transition_idx: usize) -> Option<MtxtOutputRecord> {
         let transition = &mut self.active_transitions[transition_idx];
         let mut res = transition.original_record.record.clone();
@@ -115,12 +154,21 @@ impl TransitionProcessor {
             transition.end_value,
             transition.next_pos.min(1.0),
             transition.original_record.transition_curve,
+            transition.original_record.transition_steps,
         );
 
         res.set_parameter_value(new_value);
 
+        let mut is_duplicate = false;
+        if let Some(step) = record_step(&res, new_value, self.pitch_bend_range) {
+            is_duplicate = transition.last_step == Some(step);
+            transition.last_step = Some(step);
+        }
+
         if transition.next_pos >= 1.0 {
             self.active_transitions.remove(transition_idx);
+            // the final endpoint is always emitted
+            return Some(res);
         } else {
             let bpm = if matches!(
                 transition.original_record.record,
@@ -157,7 +205,7 @@ impl TransitionProcessor {
             transition.next_micros = self.current_micros + remaining_beats.as_micros(bpm as f64);
         }
 
-        Some(res)
+        if is_duplicate { None } else { Some(res) }
     }
 
     fn consume_beat(&mut self, next_beat_micros: u64) -> Option<MtxtOutputRecord> {
@@ -174,74 +222,80 @@ impl TransitionProcessor {
     }
 
     fn process(&mut self) -> Option<MtxtOutputRecord> {
-        let (transition_idx, next_transition_micros) = match self
-            .active_transitions
-            .iter()
-            .enumerate()
-            .min_by_key(|(_, a)| a.next_micros)
-        {
-            Some((idx, t)) => (idx, t.next_micros),
-            None => (usize::MAX, u64::MAX),
-        };
+        loop {
+            let (transition_idx, next_transition_micros) = match self
+                .active_transitions
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, a)| a.next_micros)
+            {
+                Some((idx, t)) => (idx, t.next_micros),
+                None => (usize::MAX, u64::MAX),
+            };
 
-        let next_record = self.records.get(self.next_record_idx);
-        let next_record_micros = if let Some(record) = next_record {
-            let next_beat_time = record.start_beat_time;
-            let remaining_beats = next_beat_time - self.current_beat_time;
-            let micros_to_next = remaining_beats.as_micros(self.current_bpm as f64);
-            self.current_micros + micros_to_next
-        } else {
-            u64::MAX
-        };
+            let next_record = self.records.peek();
+            let next_record_micros = if let Some(record) = next_record {
+                let next_beat_time = record.start_beat_time;
+                let remaining_beats = next_beat_time - self.current_beat_time;
+                let micros_to_next = remaining_beats.as_micros(self.current_bpm as f64);
+                self.current_micros + micros_to_next
+            } else {
+                u64::MAX
+            };
 
-        if next_record_micros == u64::MAX && next_transition_micros == u64::MAX {
-            return None;
-        }
+            if next_record_micros == u64::MAX && next_transition_micros == u64::MAX {
+                return None;
+            }
 
-        // Calculate next beat time
-        let next_beat_time = self.next_beat_to_emit;
+            // Calculate next beat time
+            let next_beat_time = self.next_beat_to_emit;
 
-        let next_beat_micros = if next_beat_time >= self.current_beat_time {
-            let diff = next_beat_time - self.current_beat_time;
-            self.current_micros + diff.as_micros(self.current_bpm as f64)
-        } else {
-            self.current_micros
-        };
+            let next_beat_micros = if next_beat_time >= self.current_beat_time {
+                let diff = next_beat_time - self.current_beat_time;
+                self.current_micros + diff.as_micros(self.current_bpm as f64)
+            } else {
+                self.current_micros
+            };
 
-        // Determine which event is next
-        let mut min_micros = next_transition_micros;
-        let mut do_record = false;
+            // Determine which event is next
+            let mut min_micros = next_transition_micros;
+            let mut do_record = false;
 
-        if next_record_micros <= min_micros {
-            min_micros = next_record_micros;
-            do_record = true;
-        }
+            if next_record_micros <= min_micros {
+                min_micros = next_record_micros;
+                do_record = true;
+            }
 
-        if next_beat_micros <= min_micros {
-            return self.consume_beat(next_beat_micros);
-        }
+            if next_beat_micros <= min_micros {
+                return self.consume_beat(next_beat_micros);
+            }
 
-        if do_record {
-            return self.consume_record(next_record_micros);
-        }
+            if do_record {
+                return self.consume_record(next_record_micros);
+            }
 
-        self.consume_transition(transition_idx)
+            if let Some(res) = self.consume_transition(transition_idx) {
+                return Some(res);
+            }
+        }
     }
 
     fn process_item(&mut self) -> Option<MtxtOutputRecord> {
         let res = self.process();
         if let Some(record) = res.as_ref() {
-            match record {
-                MtxtOutputRecord::Tempo { bpm, .. } => {
-                    self.current_bpm = *bpm;
-                    let key = record.get_param_key().unwrap();
-                    self.last_values.insert(key, *bpm);
-                }
-                MtxtOutputRecord::ControlChange { value, .. } => {
-                    let key = record.get_param_key().unwrap();
-                    self.last_values.insert(key, *value);
+            if let MtxtOutputRecord::Tempo { bpm, .. } = record {
+                self.current_bpm = *bpm;
+            }
+            if let Some(value) = record.get_parameter_value()
+                && record.write_param_key(&mut self.param_key)
+            {
+                // only a parameter seen for the first time allocates its key
+                match self.last_values.get_mut(&self.param_key) {
+                    Some(last_value) => *last_value = value,
+                    None => {
+                        self.last_values.insert(self.param_key.clone(), value);
+                    }
                 }
-                _ => {}
             }
         }
         res
@@ -255,3 +309,189 @@ impl TransitionProcessor {
         output
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::parse_mtxt;
+    use crate::types::output_record::MtxtOutputRecord;
+
+    fn cc_values(content: &str, name: &str) -> Vec<(u64, f32)> {
+        parse_mtxt(content)
+            .unwrap()
+            .get_output_records()
+            .iter()
+            .filter_map(|record| match record {
+                MtxtOutputRecord::ControlChange {
+                    time,
+                    controller,
+                    value,
+                    ..
+                } if controller == name => Some((*time, *value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_stepped_transition() {
+        let values = cc_values(
+            r#"
+mtxt 1.0
+0.0 tempo 60
+0.0 cc volume 0.0
+1.0 cc volume 1.0 transition_time=1.0 transition_interval=100 transition_steps=4
+"#,
+            "volume",
+        );
+
+        // held levels collapse into a single event per step
+        let levels: Vec<(u64, f32)> = values.into_iter().collect();
+        assert_eq!(
+            levels,
+            vec![
+                (0, 0.0),
+                (100_000, 0.0),
+                (300_000, 0.25),
+                (500_000, 0.5),
+                (800_000, 0.75),
+                (1_000_000, 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_global_transition_time() {
+        let inline = cc_values(
+            "mtxt 1.0\n0.0 tempo 60\n0.0 cc volume 0.0\n\
+             2.0 cc volume 1.0 transition_time=2 transition_steps=4\n",
+            "volume",
+        );
+        let global = cc_values(
+            "mtxt 1.0\n0.0 tempo 60\ntransition_time=2\ntransition_steps=4\n\
+             0.0 cc volume 0.0\n2.0 cc volume 1.0\n",
+            "volume",
+        );
+
+        assert!(inline.len() > 2, "{:?}", inline);
+        assert_eq!(global, inline);
+    }
+
+    #[test]
+    fn test_first_value_after_global_transition_time() {
+        // nothing to ramp from, so the first value is set when its ramp would start
+        let values = cc_values("mtxt 1.0\ntransition_time=2\n4.0 cc volume 0.5\n", "volume");
+        assert_eq!(values, vec![(1_000_000, 0.5)]);
+
+        let tempos: Vec<(u64, f32)> = parse_mtxt("mtxt 1.0\ntransition_time=2\n4.0 tempo 100\n")
+            .unwrap()
+            .get_output_records()
+            .iter()
+            .filter_map(|record| match record {
+                MtxtOutputRecord::Tempo { time, bpm } => Some((*time, *bpm)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(tempos, vec![(1_000_000, 100.0)]);
+    }
+
+    #[test]
+    fn test_slow_ramp_skips_duplicate_midi_steps() {
+        let values = cc_values(
+            r#"
+mtxt 1.0
+0.0 tempo 60
+0.0 cc volume 0.0
+10.0 cc volume 0.1 transition_time=10.0 transition_interval=10
+"#,
+            "volume",
+        );
+
+        // 1000 interpolation points, but only 13 distinct 7-bit values between 0.0 and 0.1,
+        // plus the initial value and the ramp's final endpoint
+        assert!(values.len() <= 13 + 2, "{} values emitted", values.len());
+        assert_eq!(values.last(), Some(&(10_000_000, 0.1)));
+        for pair in values[1..values.len() - 1].windows(2) {
+            assert_ne!(
+                (pair[0].1 * 127.0) as i32,
+                (pair[1].1 * 127.0) as i32,
+                "duplicate step at {}",
+                pair[1].0
+            );
+        }
+    }
+
+    #[test]
+    fn test_explicit_value_aborts_ramp() {
+        let content = r#"
+mtxt 1.0
+0.0 tempo 60
+0.0 cc volume 0.0
+0.0 cc expression 0.0
+2.0 cc volume 0.2
+4.0 cc volume 1.0 transition_time=4.0 transition_interval=250
+4.0 cc expression 1.0 transition_time=4.0 transition_interval=250
+"#;
+
+        // the volume ramp stops at the explicit value, nothing is emitted after it
+        let volume = cc_values(content, "volume");
+        assert_eq!(volume.last(), Some(&(2_000_000, 0.2)));
+        assert!(volume.iter().all(|(time, _)| *time <= 2_000_000));
+        assert!(volume.iter().all(|(_, value)| *value < 0.5));
+
+        // other parameters keep ramping
+        let expression = cc_values(content, "expression");
+        assert_eq!(expression.last(), Some(&(4_000_000, 1.0)));
+    }
+
+    #[test]
+    fn test_per_note_ramps_are_independent() {
+        let records = parse_mtxt(
+            r#"
+mtxt 1.0
+0.0 tempo 60
+0.0 cc cutoff 0.5
+0.0 cc C4 cutoff 0.0
+2.0 cc C4 cutoff 1.0 transition_time=2.0 transition_interval=500
+2.0 cc E4 cutoff 0.0 transition_time=2.0 transition_interval=500
+"#,
+        )
+        .unwrap()
+        .get_output_records();
+
+        let note_values = |target: &str| -> Vec<(u64, f32)> {
+            records
+                .iter()
+                .filter_map(|record| match record {
+                    MtxtOutputRecord::ControlChange {
+                        time,
+                        note: Some(note),
+                        value,
+                        ..
+                    } if note.to_string() == target => Some((*time, *value)),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            note_values("C4"),
+            vec![
+                (0, 0.0),
+                (500_000, 0.25),
+                (1_000_000, 0.5),
+                (1_500_000, 0.75),
+                (2_000_000, 1.0)
+            ]
+        );
+        // E4 has no value of its own yet and starts from the channel value
+        assert_eq!(
+            note_values("E4"),
+            vec![
+                (500_000, 0.375),
+                (1_000_000, 0.25),
+                (1_500_000, 0.125),
+                (2_000_000, 0.0)
+            ]
+        );
+    }
+}
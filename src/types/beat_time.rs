@@ -1,7 +1,9 @@
+use crate::types::humanize::HumanizeDistribution;
 use anyhow::Result;
 use anyhow::anyhow;
+use rand::Rng;
 use std::fmt;
-use std::ops::{Add, Sub};
+use std::ops::{Add, Rem, Sub};
 use std::str::FromStr;
 
 /// Beat-based time notation using fixed-point units
@@ -18,10 +20,19 @@ impl BeatTime {
     /// Bitmask to extract the sub-unit part from the total units.
     const FRAC_BEAT_MASK: u64 = Self::FRAC_BEAT_COUNT - 1;
 
+    /// Latest representable time, just before beat 2^32.
+    pub const MAX: Self = Self { repr: u64::MAX };
+
     pub const fn zero() -> Self {
         Self { repr: 0 }
     }
 
+    pub const fn from_whole_beats(beats: u32) -> Self {
+        Self {
+            repr: (beats as u64) << Self::FRAC_BEAT_BITS,
+        }
+    }
+
     const fn from_units(units: u64) -> Self {
         Self { repr: units }
     }
@@ -37,10 +48,31 @@ impl BeatTime {
         }
     }
 
+    /// Splits a beat value like 2.75 into whole beats and a fraction, with the same
+    /// precision as `from_parts`. The inverse of `as_beats`. Fails for negative values,
+    /// NaN and values past `u32::MAX` beats.
+    pub fn try_from_beats(beats: f64) -> Result<Self> {
+        if !(0.0..u32::MAX as f64).contains(&beats) {
+            return Err(anyhow!("{} is not a valid time in beats", beats));
+        }
+        Ok(Self::from_parts(beats.floor() as u32, beats.fract() as f32))
+    }
+
+    /// Like `try_from_beats`, saturating instead: negative values and NaN give zero,
+    /// values that are too large `BeatTime::MAX`. Check user input with `try_from_beats`.
+    pub fn from_beats(beats: f64) -> Self {
+        Self::try_from_beats(beats).unwrap_or(if beats > 0.0 { Self::MAX } else { Self::zero() })
+    }
+
     pub fn as_f64(&self) -> f64 {
         self.repr_beat() as f64 + self.repr_frac_f32() as f64
     }
 
+    /// Same as `as_f64`, the inverse of `from_beats`.
+    pub fn as_beats(&self) -> f64 {
+        self.as_f64()
+    }
+
     pub fn as_micros(&self, bpm: f64) -> u64 {
         let micros_per_beat = 60_000_000.0 / bpm;
         (self.as_f64() * micros_per_beat).round() as u64
@@ -48,10 +80,7 @@ impl BeatTime {
 
     pub fn from_micros(micros: u64, bpm: f64) -> Self {
         let micros_per_beat = 60_000_000.0 / bpm;
-        let beat = micros as f64 / micros_per_beat;
-        let beat_int = beat.floor() as u32;
-        let frac = beat.fract() as f32;
-        Self::from_parts(beat_int, frac)
+        Self::from_beats(micros as f64 / micros_per_beat)
     }
 
     fn repr_beat(&self) -> u64 {
@@ -70,7 +99,75 @@ impl BeatTime {
         (self.repr_frac() as f64 / Self::FRAC_BEAT_COUNT as f64) as f32
     }
 
-    pub fn quantize(&self, grid: u32, swing: f32, humanize: f32) -> Self {
+    /// Renders the time as a fraction with a denominator of at most `max_denom`,
+    /// `1/3` or `2+2/3`, when one is within the rounding of the decimal display
+    /// (0.33333 is `1/3`). `None` for whole beats and times no fraction matches.
+    ///
+    /// This is for display only and lossy: the fraction read back is the exact value,
+    /// not the time it was written from.
+    pub fn to_fraction_string(&self, max_denom: u32) -> Option<String> {
+        // half of the last decimal place `Display` writes
+        const TOLERANCE: f64 = 0.5e-5;
+
+        let frac = self.repr_frac() as f64 / Self::FRAC_BEAT_COUNT as f64;
+        let (numerator, denominator) = (2..=max_denom).find_map(|denominator| {
+            let numerator = (frac * denominator as f64).round();
+            let close = (frac - numerator / denominator as f64).abs() < TOLERANCE;
+            (close && numerator > 0.0 && numerator < denominator as f64)
+                .then_some((numerator as u64, denominator))
+        })?;
+
+        Some(match self.repr_beat() {
+            0 => format!("{}/{}", numerator, denominator),
+            beat => format!("{}+{}/{}", beat, numerator, denominator),
+        })
+    }
+
+    /// Parses `n/d` or `w+n/d` as written by `to_fraction_string`.
+    fn parse_fraction(s: &str) -> Result<Self> {
+        let invalid = || anyhow!("Invalid time: {}", s);
+        let parse_number = |part: &str| -> Result<u64> {
+            if part.is_empty() || !part.chars().all(|c| c.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            part.parse().map_err(|_e| invalid())
+        };
+
+        let (beat, fraction) = s.split_once('+').unwrap_or(("0", s));
+        let (numerator, denominator) = fraction.split_once('/').ok_or_else(invalid)?;
+        let beat = u32::try_from(parse_number(beat)?).map_err(|_e| invalid())?;
+        let numerator = parse_number(numerator)?;
+        let denominator = parse_number(denominator)?;
+        if denominator == 0 || numerator >= denominator {
+            return Err(invalid());
+        }
+
+        let units = (numerator as f64 * Self::FRAC_BEAT_COUNT as f64 / denominator as f64).round();
+        Ok(Self::from_units(
+            ((beat as u64) << Self::FRAC_BEAT_BITS) + units as u64,
+        ))
+    }
+
+    pub fn quantize(
+        &self,
+        grid: u32,
+        swing: f32,
+        humanize: f32,
+        distribution: HumanizeDistribution,
+    ) -> Self {
+        self.quantize_with_rng(grid, swing, humanize, distribution, &mut rand::thread_rng())
+    }
+
+    /// Same as `quantize`, drawing the humanize offsets from `rng`. Use a seeded rng
+    /// for reproducible results.
+    pub fn quantize_with_rng(
+        &self,
+        grid: u32,
+        swing: f32,
+        humanize: f32,
+        distribution: HumanizeDistribution,
+        rng: &mut impl Rng,
+    ) -> Self {
         if grid == 0 {
             return *self;
         }
@@ -101,12 +198,36 @@ impl BeatTime {
             // is a quarter of the sub-grid size, scaled by the humanize factor.
             let sub_grid_size = grid_size / 2.0;
             let humanize_amount = sub_grid_size * 0.25 * humanize as f64;
-            let humanize_offset = (rand::random::<f64>() - 0.5) * 2.0 * humanize_amount;
-            quantized_units += humanize_offset;
+            quantized_units += distribution.sample(rng, humanize_amount);
         }
 
         Self::from_units(quantized_units.round() as u64)
     }
+
+    /// Snaps to the nearest grid line across all `grids`, each in subdivisions per beat
+    /// (e.g. `[4, 3]` for 16ths and 8th-triplets in quarter note beats). When two grids
+    /// are equally close, the finer (larger) grid wins.
+    pub fn quantize_multi(&self, grids: &[u32]) -> Self {
+        let mut best: Option<(u64, u32, u64)> = None;
+
+        for &grid in grids.iter().filter(|&&g| g > 0) {
+            let grid_size = Self::FRAC_BEAT_COUNT as f64 / grid as f64;
+            let units = ((self.repr as f64 / grid_size).round() * grid_size).round() as u64;
+            let distance = units.abs_diff(self.repr);
+
+            let better = match best {
+                None => true,
+                Some((best_distance, best_grid, _)) => {
+                    distance < best_distance || (distance == best_distance && grid > best_grid)
+                }
+            };
+            if better {
+                best = Some((distance, grid, units));
+            }
+        }
+
+        best.map_or(*self, |(_, _, units)| Self::from_units(units))
+    }
 }
 
 impl fmt::Display for BeatTime {
@@ -136,7 +257,7 @@ impl Add for BeatTime {
     type Output = Self;
 
     fn add(self, other: Self) -> Self::Output {
-        Self::from_units(self.repr + other.repr)
+        Self::from_units(self.repr.saturating_add(other.repr))
     }
 }
 
@@ -148,11 +269,22 @@ impl Sub for BeatTime {
     }
 }
 
+impl Rem for BeatTime {
+    type Output = Self;
+
+    fn rem(self, other: Self) -> Self::Output {
+        Self::from_units(self.repr % other.repr)
+    }
+}
+
 impl FromStr for BeatTime {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self> {
         let s = s.trim();
+        if s.contains('/') {
+            return Self::parse_fraction(s);
+        }
 
         let mut parts = s.splitn(2, '.');
         let beat: u32 = parts
@@ -179,6 +311,33 @@ impl FromStr for BeatTime {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_from_beats() {
+        for beats in [0.0, 2.75, 3.999999] {
+            let time = BeatTime::from_beats(beats);
+            assert!((time.as_beats() - beats).abs() < 1e-6, "{}", beats);
+        }
+
+        assert_eq!(BeatTime::from_beats(3.0), BeatTime::from_whole_beats(3));
+        assert_eq!(BeatTime::from_beats(2.75).to_string(), "2.75");
+        // fractions that round to a whole beat carry into the next one
+        assert_eq!(
+            BeatTime::from_beats(3.999999999),
+            BeatTime::from_whole_beats(4)
+        );
+
+        for beats in [-1.0, f64::NAN, f64::INFINITY, 1e20] {
+            assert_eq!(
+                BeatTime::try_from_beats(beats).unwrap_err().to_string(),
+                format!("{} is not a valid time in beats", beats)
+            );
+        }
+        assert_eq!(BeatTime::from_beats(-1.0), BeatTime::zero());
+        assert_eq!(BeatTime::from_beats(f64::NAN), BeatTime::zero());
+        assert_eq!(BeatTime::from_beats(1e20), BeatTime::MAX);
+        assert_eq!(BeatTime::MAX + BeatTime::from_whole_beats(1), BeatTime::MAX);
+    }
+
     #[test]
     fn test_parsing() {
         let time: BeatTime = "4.123".parse().unwrap();
@@ -215,6 +374,49 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fractions() {
+        let third: BeatTime = "1/3".parse().unwrap();
+        assert_eq!(third.to_string(), "0.33333");
+        assert_eq!(third.to_fraction_string(16).as_deref(), Some("1/3"));
+        // the decimal display reads back close to, but not exactly at, a third
+        let decimal: BeatTime = "0.33333".parse().unwrap();
+        assert_ne!(decimal, third);
+        assert_eq!(decimal.to_fraction_string(16).as_deref(), Some("1/3"));
+
+        let time: BeatTime = "2+2/3".parse().unwrap();
+        assert_eq!(time.to_string(), "2.66667");
+        assert_eq!(time.to_fraction_string(16).as_deref(), Some("2+2/3"));
+        assert_eq!(
+            "0.16667"
+                .parse::<BeatTime>()
+                .unwrap()
+                .to_fraction_string(16),
+            Some("1/6".to_string())
+        );
+        assert_eq!(
+            "1.5".parse::<BeatTime>().unwrap().to_fraction_string(16),
+            Some("1+1/2".to_string())
+        );
+
+        // whole beats, denominators above the limit and no match fall back to decimals
+        assert_eq!(BeatTime::from_whole_beats(3).to_fraction_string(16), None);
+        assert_eq!(
+            "1/24".parse::<BeatTime>().unwrap().to_fraction_string(16),
+            None
+        );
+        assert_eq!(
+            "0.123".parse::<BeatTime>().unwrap().to_fraction_string(16),
+            None
+        );
+
+        for invalid in [
+            "1/0", "3/2", "/3", "1/", "+1/3", "1+/3", "a/3", "1/3/4", "0.5/2",
+        ] {
+            assert!(invalid.parse::<BeatTime>().is_err(), "{}", invalid);
+        }
+    }
+
     #[test]
     fn test_parse_error() {
         assert!("".parse::<BeatTime>().is_err());
@@ -248,30 +450,59 @@ mod tests {
     #[test]
     fn test_quantize() {
         let time: BeatTime = "0.12".parse().unwrap();
-        let quantized = time.quantize(4, 0.0, 0.0);
+        let quantized = time.quantize(4, 0.0, 0.0, HumanizeDistribution::Uniform);
         assert_eq!(quantized.to_string(), "0.0"); // Quantized to the nearest 1/4 beat
 
         let time: BeatTime = "0.13".parse().unwrap();
-        let quantized = time.quantize(4, 0.0, 0.0);
+        let quantized = time.quantize(4, 0.0, 0.0, HumanizeDistribution::Uniform);
         assert_eq!(quantized.to_string(), "0.25"); // Quantized to the nearest 1/4 beat
 
         let time: BeatTime = "0.49".parse().unwrap();
-        let quantized = time.quantize(4, 0.0, 0.0);
+        let quantized = time.quantize(4, 0.0, 0.0, HumanizeDistribution::Uniform);
         assert_eq!(quantized.to_string(), "0.5");
 
         let time: BeatTime = "0.51".parse().unwrap();
-        let quantized = time.quantize(4, 0.0, 0.0);
+        let quantized = time.quantize(4, 0.0, 0.0, HumanizeDistribution::Uniform);
         assert_eq!(quantized.to_string(), "0.5");
 
         // Test with swing
         let time: BeatTime = "0.25".parse().unwrap(); // 0.25 is index 1 on grid=4 (0.25 spacing)
-        let quantized = time.quantize(4, 1.0, 0.0);
+        let quantized = time.quantize(4, 1.0, 0.0, HumanizeDistribution::Uniform);
         // 0.25 + (0.25/6) = 0.25 + 0.041666... = 0.29167
         assert_eq!(quantized.to_string(), "0.29167");
 
         // Test with humanize
         let time: BeatTime = "0.25".parse().unwrap();
-        let quantized = time.quantize(4, 0.0, 0.5);
+        let quantized = time.quantize(4, 0.0, 0.5, HumanizeDistribution::Uniform);
         assert!(quantized.to_string() != "0.25");
+
+        // gaussian offsets stay within the humanize window
+        let window = BeatTime::from_parts(0, 0.25 / 2.0 * 0.25 * 0.5);
+        for _ in 0..100 {
+            let quantized = time.quantize(4, 0.0, 0.5, HumanizeDistribution::Gaussian);
+            assert!(quantized >= time - window && quantized <= time + window);
+        }
+    }
+
+    #[test]
+    fn test_quantize_multi() {
+        // closer to the 8th-triplet at 0.33333 than to the 16th at 0.375
+        let time: BeatTime = "0.35".parse().unwrap();
+        assert_eq!(time.quantize_multi(&[16, 12]).to_string(), "0.33333");
+        assert_eq!(
+            time.quantize(16, 0.0, 0.0, HumanizeDistribution::Uniform)
+                .to_string(),
+            "0.375"
+        );
+
+        let time: BeatTime = "1.26".parse().unwrap();
+        assert_eq!(time.quantize_multi(&[16, 12]).to_string(), "1.25");
+
+        // equidistant from 0.0 (grid 1) and 0.5 (grid 2): the finer grid wins
+        let time: BeatTime = "0.25".parse().unwrap();
+        assert_eq!(time.quantize_multi(&[1, 2]).to_string(), "0.5");
+        assert_eq!(time.quantize_multi(&[2, 1]).to_string(), "0.5");
+
+        assert_eq!(time.quantize_multi(&[]).to_string(), "0.25");
     }
 }
@@ -0,0 +1,104 @@
+use crate::types::beat_time::BeatTime;
+use anyhow::{Result, bail};
+use std::fmt;
+use std::str::FromStr;
+
+/// Length of a note as written after `dur=`: beats, or clock time (`250ms`, `0.5s`).
+/// Clock time depends on the tempo and is turned into beats when the file is processed,
+/// at the tempo of the last tempo event before the note.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteDuration {
+    Beats(BeatTime),
+    /// Milliseconds, `0.5s` is stored as 500.
+    Millis(f64),
+}
+
+impl NoteDuration {
+    /// The duration in beats at `bpm`.
+    pub fn to_beats(&self, bpm: f64) -> BeatTime {
+        match self {
+            NoteDuration::Beats(beats) => *beats,
+            NoteDuration::Millis(millis) => {
+                BeatTime::from_micros((millis * 1000.0).round() as u64, bpm)
+            }
+        }
+    }
+
+    /// The duration in beats if it doesn't depend on the tempo.
+    pub fn as_beats(&self) -> Option<BeatTime> {
+        match self {
+            NoteDuration::Beats(beats) => Some(*beats),
+            NoteDuration::Millis(_) => None,
+        }
+    }
+}
+
+impl From<BeatTime> for NoteDuration {
+    fn from(beats: BeatTime) -> Self {
+        NoteDuration::Beats(beats)
+    }
+}
+
+impl fmt::Display for NoteDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoteDuration::Beats(beats) => write!(f, "{}", beats),
+            NoteDuration::Millis(millis) => write!(f, "{}ms", millis),
+        }
+    }
+}
+
+impl FromStr for NoteDuration {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (value, scale) = if let Some(value) = s.strip_suffix("ms") {
+            (value, 1.0)
+        } else if let Some(value) = s.strip_suffix('s') {
+            (value, 1000.0)
+        } else {
+            return Ok(NoteDuration::Beats(s.parse()?));
+        };
+
+        let millis = match value.parse::<f64>() {
+            Ok(value) if value.is_finite() && value >= 0.0 => value * scale,
+            _ => bail!("Invalid duration: {}", s),
+        };
+        // whole microseconds, so 0.1s is 100ms and not 100.00000000000001ms
+        Ok(NoteDuration::Millis((millis * 1000.0).round() / 1000.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(
+            "1.5".parse::<NoteDuration>().unwrap(),
+            NoteDuration::Beats(BeatTime::from_beats(1.5))
+        );
+        assert_eq!(
+            "250ms".parse::<NoteDuration>().unwrap(),
+            NoteDuration::Millis(250.0)
+        );
+        assert_eq!(
+            "0.1s".parse::<NoteDuration>().unwrap(),
+            NoteDuration::Millis(100.0)
+        );
+        assert_eq!("0.5s".parse::<NoteDuration>().unwrap().to_string(), "500ms");
+        assert!("-1ms".parse::<NoteDuration>().is_err());
+        assert!("ms".parse::<NoteDuration>().is_err());
+        assert!("1.5x".parse::<NoteDuration>().is_err());
+    }
+
+    #[test]
+    fn test_clock_duration_to_beats() {
+        let half_second = NoteDuration::Millis(500.0);
+        assert_eq!(half_second.to_beats(120.0), BeatTime::from_beats(1.0));
+        assert_eq!(half_second.to_beats(60.0), BeatTime::from_beats(0.5));
+        assert_eq!(half_second.as_beats(), None);
+    }
+}
@@ -0,0 +1,140 @@
+use crate::util::format_float32;
+use anyhow::{Result, anyhow};
+use std::fmt;
+use std::str::FromStr;
+
+/// Shape of a CC or tempo transition, set with `transition_curve=`.
+/// Numeric curves are kept for backward compatibility, names select a fixed easing function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Easing {
+    /// - curve > 0: ease-in (starts slow, ends fast)
+    /// - curve < 0: ease-out (starts fast, ends slow)
+    /// - curve = 0: linear interpolation
+    Curve(f32),
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    Exponential,
+    SCurve,
+}
+
+impl Easing {
+    /// Maps the transition progress `pos` (0.0-1.0) to the eased progress.
+    pub fn apply(&self, pos: f32) -> f32 {
+        match self {
+            Easing::Curve(curve) => {
+                pos + curve.max(0.0) * (pos.powi(4) - pos)
+                    - (-curve).max(0.0) * ((1.0 - (1.0 - pos).powi(4)) - pos)
+            }
+            Easing::Linear => pos,
+            Easing::EaseIn => pos.powi(3),
+            Easing::EaseOut => 1.0 - (1.0 - pos).powi(3),
+            Easing::EaseInOut => {
+                if pos < 0.5 {
+                    4.0 * pos.powi(3)
+                } else {
+                    1.0 - (-2.0 * pos + 2.0).powi(3) / 2.0
+                }
+            }
+            Easing::Exponential => {
+                if pos <= 0.0 {
+                    0.0
+                } else {
+                    2.0f32.powf(10.0 * pos - 10.0)
+                }
+            }
+            Easing::SCurve => pos * pos * (3.0 - 2.0 * pos),
+        }
+    }
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Easing::Curve(0.0)
+    }
+}
+
+impl fmt::Display for Easing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Easing::Curve(curve) => write!(f, "{}", format_float32(*curve)),
+            Easing::Linear => write!(f, "linear"),
+            Easing::EaseIn => write!(f, "easein"),
+            Easing::EaseOut => write!(f, "easeout"),
+            Easing::EaseInOut => write!(f, "easeinout"),
+            Easing::Exponential => write!(f, "exponential"),
+            Easing::SCurve => write!(f, "scurve"),
+        }
+    }
+}
+
+impl FromStr for Easing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Ok(curve) = s.parse::<f32>() {
+            return Ok(Easing::Curve(curve));
+        }
+
+        // "ease-in", "ease_in" and "EaseIn" are all accepted
+        let name: String = s
+            .chars()
+            .filter(|c| *c != '-' && *c != '_')
+            .collect::<String>()
+            .to_lowercase();
+
+        match name.as_str() {
+            "linear" => Ok(Easing::Linear),
+            "easein" => Ok(Easing::EaseIn),
+            "easeout" => Ok(Easing::EaseOut),
+            "easeinout" => Ok(Easing::EaseInOut),
+            "exponential" | "exp" => Ok(Easing::Exponential),
+            "scurve" => Ok(Easing::SCurve),
+            _ => Err(anyhow!("Invalid easing: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_eased(easing: &str, expected: [f32; 3]) {
+        let easing: Easing = easing.parse().unwrap();
+        let values = [0.25, 0.5, 0.75].map(|pos| easing.apply(pos));
+        for (value, expected) in values.iter().zip(expected.iter()) {
+            assert!(
+                (value - expected).abs() < 1e-5,
+                "{}: {:?} != {:?}",
+                easing,
+                values,
+                expected
+            );
+        }
+        assert_eq!(easing.apply(0.0), 0.0);
+        assert!((easing.apply(1.0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_easing_values() {
+        assert_eased("linear", [0.25, 0.5, 0.75]);
+        assert_eased("0", [0.25, 0.5, 0.75]);
+        assert_eased("1", [0.00390625, 0.0625, 0.31640625]);
+        assert_eased("easein", [0.015625, 0.125, 0.421875]);
+        assert_eased("easeout", [0.578125, 0.875, 0.984375]);
+        assert_eased("easeinout", [0.0625, 0.5, 0.9375]);
+        assert_eased("exponential", [0.0055243, 0.03125, 0.1767767]);
+        assert_eased("scurve", [0.15625, 0.5, 0.84375]);
+    }
+
+    #[test]
+    fn test_easing_parsing() {
+        assert_eq!("ease-in-out".parse::<Easing>().unwrap(), Easing::EaseInOut);
+        assert_eq!("Ease_Out".parse::<Easing>().unwrap(), Easing::EaseOut);
+        assert_eq!("0.5".parse::<Easing>().unwrap(), Easing::Curve(0.5));
+        assert_eq!(Easing::Curve(-0.5).to_string(), "-0.5");
+        assert_eq!(Easing::SCurve.to_string(), "scurve");
+        assert!("bounce".parse::<Easing>().is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::{Result, anyhow};
+use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+
+/// Distribution of the random offsets applied by humanize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HumanizeDistribution {
+    /// Any offset within the window is equally likely.
+    #[default]
+    Uniform,
+    /// Offsets cluster around the grid position, with the window at two standard deviations.
+    /// Samples outside the window are clamped to its edges.
+    Gaussian,
+}
+
+impl HumanizeDistribution {
+    /// Returns a random offset in `-amount..=amount`.
+    pub fn sample(&self, rng: &mut impl Rng, amount: f64) -> f64 {
+        match self {
+            HumanizeDistribution::Uniform => (rng.r#gen::<f64>() - 0.5) * 2.0 * amount,
+            HumanizeDistribution::Gaussian => {
+                // Box-Muller transform, 1.0 - gen() keeps the logarithm finite
+                let u1 = 1.0 - rng.r#gen::<f64>();
+                let u2 = rng.r#gen::<f64>();
+                let standard = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                (standard * amount / 2.0).clamp(-amount, amount)
+            }
+        }
+    }
+}
+
+impl fmt::Display for HumanizeDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HumanizeDistribution::Uniform => write!(f, "uniform"),
+            HumanizeDistribution::Gaussian => write!(f, "gaussian"),
+        }
+    }
+}
+
+impl FromStr for HumanizeDistribution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "uniform" => Ok(HumanizeDistribution::Uniform),
+            "gaussian" | "normal" => Ok(HumanizeDistribution::Gaussian),
+            _ => Err(anyhow!("Invalid humanize distribution: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn spread(distribution: HumanizeDistribution) -> (f64, f64) {
+        let mut rng = StdRng::seed_from_u64(42);
+        let samples: Vec<f64> = (0..10_000)
+            .map(|_| distribution.sample(&mut rng, 1.0))
+            .collect();
+
+        let max = samples.iter().fold(0.0f64, |max, s| max.max(s.abs()));
+        let std_dev = (samples.iter().map(|s| s * s).sum::<f64>() / samples.len() as f64).sqrt();
+        (max, std_dev)
+    }
+
+    #[test]
+    fn test_distribution_spread() {
+        let (uniform_max, uniform_std_dev) = spread(HumanizeDistribution::Uniform);
+        let (gaussian_max, gaussian_std_dev) = spread(HumanizeDistribution::Gaussian);
+
+        // both stay within the window
+        assert!(uniform_max <= 1.0);
+        assert!(gaussian_max <= 1.0);
+
+        // uniform: 1/sqrt(3) ~ 0.577, gaussian: ~0.5 before clamping
+        assert!(
+            (uniform_std_dev - 0.577).abs() < 0.02,
+            "{}",
+            uniform_std_dev
+        );
+        assert!(
+            (gaussian_std_dev - 0.49).abs() < 0.02,
+            "{}",
+            gaussian_std_dev
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "Gaussian".parse::<HumanizeDistribution>().unwrap(),
+            HumanizeDistribution::Gaussian
+        );
+        assert_eq!(HumanizeDistribution::default().to_string(), "uniform");
+        assert!("poisson".parse::<HumanizeDistribution>().is_err());
+    }
+}
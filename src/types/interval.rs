@@ -0,0 +1,114 @@
+use anyhow::{Result, anyhow, bail};
+use std::fmt;
+use std::str::FromStr;
+
+/// Semitones above the tonic of each scale degree of a major scale.
+const MAJOR_SCALE: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// Unisons, fourths and fifths are perfect, the other degrees major or minor.
+fn is_perfect(degree: i32) -> bool {
+    matches!(degree, 0 | 3 | 4)
+}
+
+/// Musical interval written as quality and number: `P5`, `M3`, `m3`, `A4`, `d5`, `M9`.
+/// A leading `-` goes down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    /// Letter names to move, e.g. 2 for a third.
+    pub steps: i32,
+    pub semitones: i32,
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.steps < 0 || (self.steps == 0 && self.semitones < 0) {
+            -1
+        } else {
+            1
+        };
+        let (steps, semitones) = (self.steps * sign, self.semitones * sign);
+        let degree = steps % 7;
+        let offset = semitones - MAJOR_SCALE[degree as usize] - steps / 7 * 12;
+        let quality = match (is_perfect(degree), offset) {
+            (true, 0) => "P".to_string(),
+            (false, 0) => "M".to_string(),
+            (false, -1) => "m".to_string(),
+            (true, offset) if offset < 0 => "d".repeat(-offset as usize),
+            (false, offset) if offset < 0 => "d".repeat((-offset - 1) as usize),
+            (_, offset) => "A".repeat(offset as usize),
+        };
+        let sign = if sign < 0 { "-" } else { "" };
+        write!(f, "{}{}{}", sign, quality, steps + 1)
+    }
+}
+
+impl FromStr for Interval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (sign, name) = match s.trim().strip_prefix('-') {
+            Some(name) => (-1, name),
+            None => (1, s.trim()),
+        };
+        let split = name
+            .find(|c: char| c.is_ascii_digit())
+            .ok_or_else(|| anyhow!("Invalid interval: {}", s))?;
+        let (quality, number) = name.split_at(split);
+        let number: i32 = number
+            .parse()
+            .ok()
+            .filter(|number| *number >= 1)
+            .ok_or_else(|| anyhow!("Invalid interval: {}", s))?;
+
+        let steps = number - 1;
+        let degree = steps % 7;
+        let base = MAJOR_SCALE[degree as usize] + steps / 7 * 12;
+        let count = quality.len() as i32;
+        let offset = match quality.chars().next() {
+            Some('P') if count == 1 && is_perfect(degree) => 0,
+            Some('M') if count == 1 && !is_perfect(degree) => 0,
+            Some('m') if count == 1 && !is_perfect(degree) => -1,
+            Some('A') if quality.chars().all(|c| c == 'A') => count,
+            Some('d') if quality.chars().all(|c| c == 'd') => {
+                if is_perfect(degree) {
+                    -count
+                } else {
+                    -count - 1
+                }
+            }
+            _ => bail!("Invalid interval: {}", s),
+        };
+
+        Ok(Interval {
+            steps: steps * sign,
+            semitones: (base + offset) * sign,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval_parsing() {
+        let interval = |s: &str| {
+            let interval: Interval = s.parse().unwrap();
+            assert_eq!(interval.to_string(), s);
+            (interval.steps, interval.semitones)
+        };
+        assert_eq!(interval("P1"), (0, 0));
+        assert_eq!(interval("m3"), (2, 3));
+        assert_eq!(interval("M3"), (2, 4));
+        assert_eq!(interval("A4"), (3, 6));
+        assert_eq!(interval("d5"), (4, 6));
+        assert_eq!(interval("d7"), (6, 9));
+        assert_eq!(interval("P8"), (7, 12));
+        assert_eq!(interval("M9"), (8, 14));
+        assert_eq!(interval("-m2"), (-1, -1));
+
+        for invalid in ["", "3", "m", "P3", "M5", "m0", "x2", "Ad4"] {
+            assert!(invalid.parse::<Interval>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use crate::types::pitch::PitchClass;
+use anyhow::{Result, bail};
+use std::fmt;
+use std::str::FromStr;
+
+/// Key of a `meta global key` value, e.g. `Eb major` or `C# minor` (the mode defaults
+/// to major).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub tonic: PitchClass,
+    pub minor: bool,
+}
+
+impl Key {
+    /// Sharps (positive) or flats (negative) of the key signature, "Eb minor" is -6.
+    /// Keys like D# major go past 7.
+    pub fn fifths(&self) -> i8 {
+        let name = self.tonic.to_string();
+        let letter = letter_fifths(name.chars().next().unwrap_or('C'));
+        let accidental = match &name[1..] {
+            "#" => 7,
+            "b" => -7,
+            _ => 0,
+        };
+        letter + accidental - if self.minor { 3 } else { 0 }
+    }
+
+    /// Accidental (-1, 0 or 1) the key signature puts on a letter.
+    pub fn accidental(&self, letter: char) -> i8 {
+        signature_accidental(self.fifths(), letter)
+    }
+}
+
+/// Position of a natural on the circle of fifths, relative to C.
+fn letter_fifths(letter: char) -> i8 {
+    match letter {
+        'F' => -1,
+        'G' => 1,
+        'D' => 2,
+        'A' => 3,
+        'E' => 4,
+        'B' => 5,
+        _ => 0,
+    }
+}
+
+/// Accidental a key signature of `fifths` sharps or flats puts on a letter.
+pub(crate) fn signature_accidental(fifths: i8, letter: char) -> i8 {
+    const SHARPS: [char; 7] = ['F', 'C', 'G', 'D', 'A', 'E', 'B'];
+    const FLATS: [char; 7] = ['B', 'E', 'A', 'D', 'G', 'C', 'F'];
+    let position = |order: &[char]| order.iter().position(|l| *l == letter).unwrap_or(7) as i8;
+    if fifths > 0 && position(&SHARPS) < fifths {
+        1
+    } else if fifths < 0 && position(&FLATS) < -fifths {
+        -1
+    } else {
+        0
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.minor { "minor" } else { "major" };
+        write!(f, "{} {}", self.tonic, mode)
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (tonic, mode) = s.trim().split_once(' ').unwrap_or((s.trim(), "major"));
+        let minor = match mode.trim() {
+            "major" => false,
+            "minor" => true,
+            _ => bail!("Invalid key: {}", s),
+        };
+        let Ok(tonic) = tonic.parse::<PitchClass>() else {
+            bail!("Invalid key: {}", s);
+        };
+        Ok(Key { tonic, minor })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_fifths() {
+        let fifths = |s: &str| s.parse::<Key>().unwrap().fifths();
+        assert_eq!(fifths("C"), 0);
+        assert_eq!(fifths("Eb major"), -3);
+        assert_eq!(fifths("Eb minor"), -6);
+        assert_eq!(fifths("A minor"), 0);
+        assert_eq!(fifths("C# major"), 7);
+        assert_eq!(fifths("D# major"), 9);
+
+        let key: Key = "Eb".parse().unwrap();
+        assert_eq!(key.to_string(), "Eb major");
+        assert_eq!(key.accidental('A'), -1);
+        assert_eq!(key.accidental('D'), 0);
+        assert!("Eb dorian".parse::<Key>().is_err());
+    }
+}
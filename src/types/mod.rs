@@ -1,7 +1,13 @@
 pub mod beat_time;
+pub mod duration;
+pub mod easing;
+pub mod humanize;
+pub mod interval;
+pub mod key;
 pub mod note;
 pub mod output_record;
 pub mod pitch;
 pub mod record;
+pub mod scale;
 pub mod time_signature;
 pub mod version;
@@ -1,10 +1,53 @@
 use crate::PitchClass;
+use crate::types::interval::Interval;
+use crate::types::key::Key;
 use crate::types::record::AliasDefinition;
 use anyhow::{Result, bail};
+use std::cmp::Ordering;
 use std::fmt;
-use std::rc::Rc;
 use std::str::FromStr;
+use std::sync::Arc;
 
+/// Octave numbering of note names. Notes store the scientific octave, so MIDI numbers,
+/// `semitone` and transforms don't depend on it: it only applies when parsing
+/// (`MtxtParser::with_octave_convention`) and writing (`FormatOptions::octave_convention`)
+/// notes. `Note`'s `Display` and `FromStr` always use `Scientific`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OctaveConvention {
+    /// Middle C (MIDI 60) is C4.
+    #[default]
+    Scientific,
+    /// Middle C is C3, as on Yamaha instruments and in some DAWs.
+    Yamaha,
+}
+
+impl OctaveConvention {
+    // written octave minus the scientific octave
+    fn offset(self) -> i8 {
+        match self {
+            OctaveConvention::Scientific => 0,
+            OctaveConvention::Yamaha => -1,
+        }
+    }
+}
+
+impl FromStr for OctaveConvention {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "scientific" => Ok(OctaveConvention::Scientific),
+            "yamaha" => Ok(OctaveConvention::Yamaha),
+            _ => bail!(
+                "Unknown octave convention \"{}\", expected scientific or yamaha",
+                s
+            ),
+        }
+    }
+}
+
+/// Notes are equal when they are spelled the same, use `same_pitch` and `cmp_pitch` to
+/// compare pitches, where enharmonic spellings (`C#4`, `Db4`) are equal.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Note {
     pub pitch_class: PitchClass,
@@ -27,6 +70,27 @@ impl Note {
         })
     }
 
+    /// Absolute semitone number, C4 = 60 like MIDI but not limited to 0-127.
+    /// The octave number is kept as written, so B#3 is 48 (C3), not C4.
+    pub fn semitone(&self) -> i32 {
+        (self.octave as i32 + 1) * 12 + self.pitch_class.to_semitone() as i32
+    }
+
+    /// Whether both notes sound the same pitch, e.g. `C#4` and `Db4`.
+    pub fn same_pitch(&self, other: &Note) -> bool {
+        self.cmp_pitch(other) == Ordering::Equal
+    }
+
+    /// Orders notes by pitch: by `semitone`, then by cents within a semitone.
+    pub fn cmp_pitch(&self, other: &Note) -> Ordering {
+        // cents are never NaN for notes built by `new` or parsed
+        self.semitone().cmp(&other.semitone()).then(
+            self.cents
+                .partial_cmp(&other.cents)
+                .unwrap_or(Ordering::Equal),
+        )
+    }
+
     /// Convert to MIDI note number (C4 = 60)
     pub fn to_midi_note(&self) -> u8 {
         let base = (self.octave + 1) * 12 + self.pitch_class.to_semitone() as i8;
@@ -34,29 +98,12 @@ impl Note {
     }
 
     pub fn transpose(&self, semitones: i32) -> Note {
-        let current_semitone = self.pitch_class.to_semitone() as i32; // 0-11
-        let current_abs = (self.octave as i32 + 1) * 12 + current_semitone;
-        let new_abs = current_abs + semitones;
+        let new_abs = self.semitone() + semitones;
 
         let new_octave = new_abs.div_euclid(12) - 1;
         let new_semitone_idx = new_abs.rem_euclid(12);
 
-        let new_pitch_class = match new_semitone_idx {
-            0 => PitchClass::C,
-            1 => PitchClass::CSharp,
-            2 => PitchClass::D,
-            3 => PitchClass::DSharp,
-            4 => PitchClass::E,
-            5 => PitchClass::F,
-            6 => PitchClass::FSharp,
-            7 => PitchClass::G,
-            8 => PitchClass::GSharp,
-            9 => PitchClass::A,
-            10 => PitchClass::ASharp,
-            11 => PitchClass::B,
-            _ => unreachable!(),
-        }
-        .to_canonical();
+        let new_pitch_class = PitchClass::from_semitone(new_semitone_idx as u8);
 
         Note {
             pitch_class: new_pitch_class,
@@ -64,15 +111,86 @@ impl Note {
             cents: self.cents,
         }
     }
+
+    /// Every spelling of the same pitch with the same cents, this note included.
+    /// Octave numbers stay as they are, see `semitone`.
+    pub fn enharmonic_equivalents(&self) -> Vec<Note> {
+        self.pitch_class
+            .enharmonics()
+            .map(|pitch_class| Note {
+                pitch_class,
+                octave: self.octave,
+                cents: self.cents,
+            })
+            .collect()
+    }
+
+    /// Transposes by `interval`, spelled by its letter distance: up a minor third from
+    /// Eb is Gb, not F# as `transpose` gives. Where that would need a double accidental,
+    /// the spelling of `key`'s signature is used, or else its kind of accidental.
+    pub fn transpose_diatonic(&self, interval: Interval, key: Key) -> Note {
+        const LETTERS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+        let (letter, _, _) = self.staff_position();
+        let index = LETTERS.iter().position(|l| *l == letter).unwrap_or(0) as i32;
+        let target_letter = LETTERS[(index + interval.steps).rem_euclid(7) as usize];
+
+        let chromatic = self.transpose(interval.semitones);
+        let candidates = chromatic.enharmonic_equivalents();
+        let spelled = |check: &dyn Fn(char, i8) -> bool| {
+            candidates.iter().find(|note| {
+                let (letter, accidental, _) = note.staff_position();
+                check(letter, accidental)
+            })
+        };
+        spelled(&|letter, _| letter == target_letter)
+            .or_else(|| spelled(&|letter, accidental| accidental == key.accidental(letter)))
+            .or_else(|| spelled(&|_, accidental| accidental == key.fifths().signum()))
+            .cloned()
+            .unwrap_or(chromatic)
+    }
+
+    /// Letter, accidental (-1, 0 or 1) and octave as notation writes them. The octave
+    /// belongs to the letter, so B#3 (which is C3 here, see `semitone`) is B# in octave 2.
+    pub(crate) fn staff_position(&self) -> (char, i8, i32) {
+        let name = self.pitch_class.to_string();
+        let letter = name.chars().next().unwrap_or('C');
+        let accidental = match &name[1..] {
+            "#" => 1,
+            "b" => -1,
+            _ => 0,
+        };
+        let octave = match self.pitch_class {
+            PitchClass::BSharp => self.octave as i32 - 1,
+            PitchClass::Cb => self.octave as i32 + 1,
+            _ => self.octave as i32,
+        };
+        (letter, accidental, octave)
+    }
+
+    /// Parses a note name written in `convention`, see `OctaveConvention`.
+    pub fn parse_with(s: &str, convention: OctaveConvention) -> Result<Self> {
+        let mut note = parse_note(s)?;
+        note.octave = note
+            .octave
+            .checked_sub(convention.offset())
+            .ok_or_else(|| anyhow::anyhow!("Octave out of range: {}", s))?;
+        Ok(note)
+    }
+
+    /// Writes the note name in `convention`, e.g. middle C is `C3` in `Yamaha`.
+    pub fn to_string_with(&self, convention: OctaveConvention) -> String {
+        let octave = self.octave as i32 + convention.offset() as i32;
+        let mut name = format!("{}{}", self.pitch_class, octave);
+        if self.cents != 0.0 {
+            name += &format!("{:+}", self.cents);
+        }
+        name
+    }
 }
 
 impl fmt::Display for Note {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}{}", self.pitch_class, self.octave)?;
-        if self.cents != 0.0 {
-            write!(f, "{:+}", self.cents)?;
-        }
-        Ok(())
+        write!(f, "{}", self.to_string_with(OctaveConvention::Scientific))
     }
 }
 
@@ -80,53 +198,77 @@ impl FromStr for Note {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        // Parse note like "C4", "D#5", "Bb3+50", "F4-25"
-        let mut chars = s.chars().peekable();
-
-        // Parse pitch class
-        let pitch_char = chars
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("Empty note string"))?;
-        let mut pitch_str = pitch_char.to_string();
-
-        // Check for accidental
-        if let Some(&next_char) = chars.peek()
-            && (next_char == '#' || next_char == 'b' || next_char == 'B') {
-                pitch_str.push(chars.next().unwrap());
-            }
-
-        let pitch_class: PitchClass = pitch_str.parse()?;
-
-        // Parse octave
-        let remaining: String = chars.collect();
-
-        // Find where octave ends and cents begin
-        let mut octave_end = remaining.len();
-        for (i, ch) in remaining.chars().enumerate() {
-            if ch == '+' || ch == '-' {
-                octave_end = i;
-                break;
-            }
-        }
+        Note::parse_with(s, OctaveConvention::Scientific)
+    }
+}
 
-        let octave: i8 = remaining[..octave_end].parse()?;
+// Parses a note like "C4", "D#5", "Bb3+50", "F4-25" or "C-1", keeping the octave as written
+fn parse_note(s: &str) -> Result<Note> {
+    let mut chars = s.chars().peekable();
 
-        // Parse cents if present
-        let cents = if octave_end < remaining.len() {
-            remaining[octave_end..].parse()?
-        } else {
-            0.0f32
-        };
+    // Parse pitch class
+    let pitch_char = chars
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Empty note string"))?;
+    let mut pitch_str = pitch_char.to_string();
+
+    // Check for accidental
+    if let Some(&next_char) = chars.peek()
+        && (next_char == '#' || next_char == 'b' || next_char == 'B')
+    {
+        pitch_str.push(chars.next().unwrap());
+    }
 
-        Self::new(pitch_class, octave, cents)
+    let pitch_class: PitchClass = pitch_str.parse()?;
+
+    // Parse octave
+    let remaining: String = chars.collect();
+
+    // Find where octave ends and cents begin, a leading '-' is a negative octave
+    let mut octave_end = remaining.len();
+    for (i, ch) in remaining.chars().enumerate().skip(1) {
+        if ch == '+' || ch == '-' {
+            octave_end = i;
+            break;
+        }
     }
+
+    let octave: i8 = remaining[..octave_end].parse()?;
+
+    // Parse cents if present
+    let cents = if octave_end < remaining.len() {
+        remaining[octave_end..].parse()?
+    } else {
+        0.0f32
+    };
+
+    Note::new(pitch_class, octave, cents)
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum NoteTarget {
     Note(Note),
     AliasKey(String),
-    Alias(Rc<AliasDefinition>),
+    Alias(Arc<AliasDefinition>),
+}
+
+impl NoteTarget {
+    /// Parses a note name written in `convention`, anything else is taken as an alias.
+    pub fn parse_with(s: &str, convention: OctaveConvention) -> Result<Self> {
+        if let Ok(note) = Note::parse_with(s, convention) {
+            return Ok(NoteTarget::Note(note));
+        }
+
+        Ok(NoteTarget::AliasKey(s.to_string()))
+    }
+
+    /// Writes a note name in `convention`, aliases by their name.
+    pub fn to_string_with(&self, convention: OctaveConvention) -> String {
+        match self {
+            NoteTarget::Note(note) => note.to_string_with(convention),
+            _ => self.to_string(),
+        }
+    }
 }
 
 impl fmt::Display for NoteTarget {
@@ -143,12 +285,7 @@ impl FromStr for NoteTarget {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        // try to parse as note
-        if let Ok(note) = s.parse::<Note>() {
-            return Ok(NoteTarget::Note(note));
-        }
-
-        Ok(NoteTarget::AliasKey(s.to_string()))
+        NoteTarget::parse_with(s, OctaveConvention::Scientific)
     }
 }
 
@@ -176,6 +313,32 @@ mod tests {
         assert_eq!("Db0".parse::<Note>().unwrap().pitch_class, PitchClass::Db);
     }
 
+    #[test]
+    fn diatonic_transpose() {
+        let transpose = |note: &str, interval: &str, key: &str| {
+            let note: Note = note.parse().unwrap();
+            let diatonic = note.transpose_diatonic(interval.parse().unwrap(), key.parse().unwrap());
+            let chromatic = note.transpose(diatonic.semitone() - note.semitone());
+            (diatonic.to_string(), chromatic.to_string())
+        };
+        assert_eq!(transpose("Eb4", "m3", "Eb"), ("Gb4".into(), "F#4".into()));
+        assert_eq!(transpose("E4", "A4", "C"), ("A#4".into(), "Bb4".into()));
+        assert_eq!(transpose("A4", "-m3", "C"), ("F#4".into(), "F#4".into()));
+        assert_eq!(transpose("Bb3", "M2", "F"), ("C4".into(), "C4".into()));
+        // F## has no spelling here, G is natural in E major
+        assert_eq!(transpose("D#4", "M3", "E"), ("G4".into(), "G4".into()));
+        // Eb major has no G or F accidental, so the flat spelling wins
+        assert_eq!(transpose("C4", "d5", "Eb"), ("Gb4".into(), "F#4".into()));
+
+        let note: Note = "C#4+10".parse().unwrap();
+        let spellings: Vec<String> = note
+            .enharmonic_equivalents()
+            .iter()
+            .map(|note| note.to_string())
+            .collect();
+        assert_eq!(spellings, vec!["C#4+10", "Db4+10"]);
+    }
+
     #[test]
     fn cents() {
         assert_eq!("D4+50.5".parse::<Note>().unwrap().cents, 50.5);
@@ -184,4 +347,85 @@ mod tests {
         assert_eq!("D4-0".parse::<Note>().unwrap().cents, 0.0);
         assert_eq!("D4+0".parse::<Note>().unwrap().cents, 0.0);
     }
+
+    #[test]
+    fn cents_round_trip() {
+        assert_eq!("C4+50".parse::<Note>().unwrap().to_string(), "C4+50");
+        for name in ["C4-50", "Bb3+12.5", "F#-1-0.25", "G9+99.99"] {
+            assert_eq!(name.parse::<Note>().unwrap().to_string(), name);
+        }
+        // no cents is written without a sign
+        assert_eq!("D4+0".parse::<Note>().unwrap().to_string(), "D4");
+        assert_eq!("D4-0".parse::<Note>().unwrap().to_string(), "D4");
+
+        let line: crate::MtxtRecordLine = "1.0 note C4+50 dur=2".parse().unwrap();
+        assert_eq!(line.to_string(), "1.0 note C4+50 dur=2.0");
+    }
+
+    #[test]
+    fn ordering() {
+        let mut notes: Vec<Note> = ["G4", "C4+25", "B3", "C5", "Db4", "C4", "A0"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        notes.sort_by(Note::cmp_pitch);
+
+        let sorted: Vec<String> = notes.iter().map(|n| n.to_string()).collect();
+        assert_eq!(sorted, vec!["A0", "B3", "C4", "C4+25", "Db4", "G4", "C5"]);
+        assert_eq!(notes.last().unwrap().semitone(), 72);
+        assert_eq!(notes[0].semitone(), 21);
+
+        // enharmonic spellings are the same pitch, but not equal notes
+        let c_sharp: Note = "C#4".parse().unwrap();
+        let d_flat: Note = "Db4".parse().unwrap();
+        assert_ne!(c_sharp, d_flat);
+        assert!(c_sharp.same_pitch(&d_flat));
+        assert_eq!(c_sharp.cmp_pitch(&d_flat), Ordering::Equal);
+        assert!(!c_sharp.same_pitch(&"C#4+1".parse().unwrap()));
+        assert!(c_sharp.cmp_pitch(&"C#4+1".parse().unwrap()).is_lt());
+    }
+
+    #[test]
+    fn octave_conventions() {
+        let middle_c = Note::parse_with("C4", OctaveConvention::Scientific).unwrap();
+        assert_eq!(middle_c.to_midi_note(), 60);
+        assert_eq!(middle_c.to_string_with(OctaveConvention::Yamaha), "C3");
+
+        let yamaha_c = Note::parse_with("C3", OctaveConvention::Yamaha).unwrap();
+        assert_eq!(yamaha_c.to_midi_note(), 60);
+        assert_eq!(yamaha_c, middle_c);
+        assert_eq!(yamaha_c.to_string_with(OctaveConvention::Scientific), "C4");
+
+        // the lowest MIDI octave is negative in both conventions
+        let lowest = Note::parse_with("C-2+25", OctaveConvention::Yamaha).unwrap();
+        assert_eq!((lowest.to_midi_note(), lowest.cents), (0, 25.0));
+        assert_eq!(
+            lowest.to_string_with(OctaveConvention::Scientific),
+            "C-1+25"
+        );
+        assert_eq!("C-1".parse::<Note>().unwrap().to_midi_note(), 0);
+
+        assert_eq!(
+            "yamaha".parse::<OctaveConvention>().unwrap(),
+            OctaveConvention::Yamaha
+        );
+        assert!("roland".parse::<OctaveConvention>().is_err());
+    }
+
+    #[test]
+    fn octave_conventions_midi_numbers() {
+        // the same pitch written in each convention
+        for (scientific, yamaha, midi) in [
+            ("A4", "A3", 69),
+            ("Bb4+10", "Bb3+10", 70),
+            ("F#2", "F#1", 42),
+            ("G9", "G8", 127),
+        ] {
+            let scientific = Note::parse_with(scientific, OctaveConvention::Scientific).unwrap();
+            let yamaha = Note::parse_with(yamaha, OctaveConvention::Yamaha).unwrap();
+            assert_eq!(scientific.to_midi_note(), midi);
+            assert_eq!(yamaha.to_midi_note(), midi);
+            assert_eq!(scientific, yamaha);
+        }
+    }
 }
@@ -2,6 +2,10 @@ use crate::Note;
 use crate::TimeSignature;
 use crate::types::record::VoiceList;
 use std::fmt;
+use std::fmt::Write;
+
+/// Header row naming the columns of `MtxtOutputRecord::to_tsv_row`.
+pub const TSV_HEADER: &str = "time_us\tevent\tchannel\tnote\tname\tvalue";
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum MtxtOutputRecord {
@@ -40,6 +44,7 @@ pub enum MtxtOutputRecord {
     Reset {
         time: u64,
         target: String,
+        channel: u16,
     },
     GlobalMeta {
         time: u64,
@@ -95,6 +100,30 @@ impl MtxtOutputRecord {
         };
     }
 
+    pub fn channel(&self) -> Option<u16> {
+        match self {
+            MtxtOutputRecord::NoteOn { channel, .. }
+            | MtxtOutputRecord::NoteOff { channel, .. }
+            | MtxtOutputRecord::ControlChange { channel, .. }
+            | MtxtOutputRecord::Voice { channel, .. }
+            | MtxtOutputRecord::Reset { channel, .. }
+            | MtxtOutputRecord::ChannelMeta { channel, .. } => Some(*channel),
+            _ => None,
+        }
+    }
+
+    pub fn channel_mut(&mut self) -> Option<&mut u16> {
+        match self {
+            MtxtOutputRecord::NoteOn { channel, .. }
+            | MtxtOutputRecord::NoteOff { channel, .. }
+            | MtxtOutputRecord::ControlChange { channel, .. }
+            | MtxtOutputRecord::Voice { channel, .. }
+            | MtxtOutputRecord::Reset { channel, .. }
+            | MtxtOutputRecord::ChannelMeta { channel, .. } => Some(channel),
+            _ => None,
+        }
+    }
+
     // used for transitions
     pub fn get_parameter_value(&self) -> Option<f32> {
         match self {
@@ -113,7 +142,36 @@ impl MtxtOutputRecord {
         }
     }
 
+    /// Per-note CC is keyed separately for each note.
     pub fn get_param_key(&self) -> Option<String> {
+        let mut key = String::new();
+        self.write_param_key(&mut key).then_some(key)
+    }
+
+    /// Replaces the contents of `key` with `get_param_key`, so one buffer can be reused
+    /// for every record. Returns false if the record has no parameter.
+    pub(crate) fn write_param_key(&self, key: &mut String) -> bool {
+        key.clear();
+        match self {
+            MtxtOutputRecord::ControlChange {
+                channel,
+                controller,
+                note,
+                ..
+            } => {
+                let _ = write!(key, "cc:{}:{}", channel, controller);
+                if let Some(note) = note {
+                    let _ = write!(key, ":{}", note);
+                }
+            }
+            MtxtOutputRecord::Tempo { .. } => key.push_str("tempo"),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Same as `get_param_key`, ignoring the note of per-note CC.
+    pub fn get_channel_param_key(&self) -> Option<String> {
         match self {
             MtxtOutputRecord::ControlChange {
                 channel,
@@ -125,6 +183,63 @@ impl MtxtOutputRecord {
         }
     }
 
+    /// Tab-separated row for scripts, in the columns of `TSV_HEADER`: the time in
+    /// microseconds, event kind, channel, note, name (controller, target or meta type)
+    /// and value. Columns that don't apply are empty. Tabs, newlines and backslashes in
+    /// text are escaped as `\t`, `\n` and `\\`.
+    pub fn to_tsv_row(&self) -> String {
+        let (kind, note, name, value) = match self {
+            MtxtOutputRecord::NoteOn { note, velocity, .. } => {
+                ("NoteOn", Some(note), "", velocity.to_string())
+            }
+            MtxtOutputRecord::NoteOff {
+                note, off_velocity, ..
+            } => ("NoteOff", Some(note), "", off_velocity.to_string()),
+            MtxtOutputRecord::ControlChange {
+                note,
+                controller,
+                value,
+                ..
+            } => ("CC", note.as_ref(), controller.as_str(), value.to_string()),
+            MtxtOutputRecord::Voice { voices, .. } => ("Voice", None, "", voices.to_string()),
+            MtxtOutputRecord::Tempo { bpm, .. } => ("Tempo", None, "", bpm.to_string()),
+            MtxtOutputRecord::TimeSignature { signature, .. } => {
+                ("TimeSignature", None, "", signature.to_string())
+            }
+            MtxtOutputRecord::Reset { target, .. } => {
+                ("Reset", None, target.as_str(), String::new())
+            }
+            MtxtOutputRecord::GlobalMeta {
+                meta_type, value, ..
+            }
+            | MtxtOutputRecord::ChannelMeta {
+                meta_type, value, ..
+            } => ("Meta", None, meta_type.as_str(), value.clone()),
+            MtxtOutputRecord::Beat { beat, .. } => ("Beat", None, "", beat.to_string()),
+            MtxtOutputRecord::SysEx { data, .. } => {
+                let hex = data.iter().map(|byte| format!("{:02X}", byte)).collect();
+                ("SysEx", None, "", hex)
+            }
+        };
+
+        let escape = |text: &str| {
+            text.replace('\\', "\\\\")
+                .replace('\t', "\\t")
+                .replace('\n', "\\n")
+        };
+        let channel = self.channel().map(|ch| ch.to_string()).unwrap_or_default();
+        let note = note.map(|note| note.to_string()).unwrap_or_default();
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.time(),
+            kind,
+            channel,
+            note,
+            escape(name),
+            escape(&value)
+        )
+    }
+
     // used for aborting transitions
     pub fn is_same_parameter(&self, other: &MtxtOutputRecord) -> bool {
         match (self, other) {
@@ -228,9 +343,11 @@ impl fmt::Display for MtxtOutputRecord {
                 write!(f, "{} TimeSignature {}", format_time(*time), signature)
             }
 
-            MtxtOutputRecord::Reset { time, target } => {
-                write!(f, "{} Reset {}", format_time(*time), target)
-            }
+            MtxtOutputRecord::Reset {
+                time,
+                target,
+                channel,
+            } => write!(f, "{} Reset {} ch={}", format_time(*time), target, channel),
             MtxtOutputRecord::GlobalMeta {
                 time,
                 meta_type,
@@ -264,3 +381,29 @@ impl fmt::Display for MtxtOutputRecord {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tsv_row() {
+        let note_on = MtxtOutputRecord::NoteOn {
+            time: 1_500_000,
+            note: "C#4".parse().unwrap(),
+            velocity: 0.75,
+            channel: 2,
+        };
+        let row = note_on.to_tsv_row();
+        let columns: Vec<&str> = row.split('\t').collect();
+        assert_eq!(columns, ["1500000", "NoteOn", "2", "C#4", "", "0.75"]);
+        assert_eq!(columns.len(), TSV_HEADER.split('\t').count());
+
+        let meta = MtxtOutputRecord::GlobalMeta {
+            time: 0,
+            meta_type: "text".to_string(),
+            value: "a\tb".to_string(),
+        };
+        assert_eq!(meta.to_tsv_row(), "0\tMeta\t\t\ttext\ta\\tb");
+    }
+}
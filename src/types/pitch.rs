@@ -27,7 +27,76 @@ pub enum PitchClass {
     BSharp,
 }
 
+const ALL_SPELLINGS: [PitchClass; 21] = [
+    PitchClass::Cb,
+    PitchClass::C,
+    PitchClass::CSharp,
+    PitchClass::Db,
+    PitchClass::D,
+    PitchClass::DSharp,
+    PitchClass::Eb,
+    PitchClass::E,
+    PitchClass::ESharp,
+    PitchClass::Fb,
+    PitchClass::F,
+    PitchClass::FSharp,
+    PitchClass::Gb,
+    PitchClass::G,
+    PitchClass::GSharp,
+    PitchClass::Ab,
+    PitchClass::A,
+    PitchClass::ASharp,
+    PitchClass::Bb,
+    PitchClass::B,
+    PitchClass::BSharp,
+];
+
 impl PitchClass {
+    /// The twelve pitch classes in canonical spelling, ascending from C.
+    pub fn all() -> impl Iterator<Item = PitchClass> {
+        (0..12).map(Self::from_semitone)
+    }
+
+    /// Canonical pitch class for a semitone, wrapping around the octave.
+    pub fn from_semitone(semitone: u8) -> Self {
+        match semitone % 12 {
+            0 => PitchClass::C,
+            1 => PitchClass::CSharp,
+            2 => PitchClass::D,
+            3 => PitchClass::Eb,
+            4 => PitchClass::E,
+            5 => PitchClass::F,
+            6 => PitchClass::FSharp,
+            7 => PitchClass::G,
+            8 => PitchClass::Ab,
+            9 => PitchClass::A,
+            10 => PitchClass::Bb,
+            _ => PitchClass::B,
+        }
+    }
+
+    /// Upward distance in semitones to `other` (0-11), e.g. B to C is 1.
+    pub fn interval_to(self, other: PitchClass) -> u8 {
+        (other.to_semitone() + 12 - self.to_semitone()) % 12
+    }
+
+    /// Shifts by `semitones` (either direction), returning the canonical spelling.
+    pub fn transpose(self, semitones: i32) -> Self {
+        Self::from_semitone((self.to_semitone() as i32 + semitones).rem_euclid(12) as u8)
+    }
+
+    /// Whether both spell the same pitch, e.g. C# and Db. `==` compares the spelling.
+    pub fn is_enharmonic(self, other: PitchClass) -> bool {
+        self.to_semitone() == other.to_semitone()
+    }
+
+    /// Every spelling of the same pitch, this one included, e.g. C# and Db for Db.
+    pub fn enharmonics(self) -> impl Iterator<Item = PitchClass> {
+        ALL_SPELLINGS
+            .into_iter()
+            .filter(move |other| other.is_enharmonic(self))
+    }
+
     pub fn to_semitone(self) -> u8 {
         match self {
             PitchClass::BSharp | PitchClass::C => 0,
@@ -145,4 +214,41 @@ mod tests {
         assert_eq!("F#".parse::<PitchClass>().unwrap(), PitchClass::FSharp);
         assert_eq!("f#".parse::<PitchClass>().unwrap(), PitchClass::FSharp);
     }
+
+    #[test]
+    fn test_all_pitch_classes() {
+        let all: Vec<String> = PitchClass::all().map(|pc| pc.to_string()).collect();
+        assert_eq!(
+            all,
+            vec![
+                "C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B"
+            ]
+        );
+        assert!(
+            PitchClass::all()
+                .enumerate()
+                .all(|(i, pc)| pc.to_semitone() == i as u8)
+        );
+    }
+
+    #[test]
+    fn test_intervals() {
+        assert_eq!(PitchClass::B.interval_to(PitchClass::C), 1);
+        assert_eq!(PitchClass::C.interval_to(PitchClass::B), 11);
+        assert_eq!(PitchClass::A.interval_to(PitchClass::Db), 4);
+        assert_eq!(PitchClass::CSharp.interval_to(PitchClass::Db), 0);
+
+        assert_eq!(PitchClass::B.transpose(1), PitchClass::C);
+        assert_eq!(PitchClass::C.transpose(-1), PitchClass::B);
+        assert_eq!(PitchClass::Db.transpose(25), PitchClass::D);
+
+        assert!(PitchClass::CSharp.is_enharmonic(PitchClass::Db));
+        assert!(PitchClass::BSharp.is_enharmonic(PitchClass::C));
+        assert!(!PitchClass::CSharp.is_enharmonic(PitchClass::D));
+        assert_eq!(
+            PitchClass::C.enharmonics().collect::<Vec<_>>(),
+            vec![PitchClass::C, PitchClass::BSharp]
+        );
+        assert_eq!(PitchClass::D.enharmonics().count(), 1);
+    }
 }
@@ -1,11 +1,17 @@
 use crate::BeatTime;
+use crate::Easing;
 use crate::Note;
 use crate::TimeSignature;
 use crate::Version;
-use crate::types::note::NoteTarget;
-use crate::util::format_float32;
+use crate::record_parser::find_inline_comment_index;
+use crate::types::duration::NoteDuration;
+use crate::types::note::{NoteTarget, OctaveConvention};
+use crate::util::{DEFAULT_FLOAT_PRECISION, format_float32_with_precision};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
-use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct AliasDefinition {
@@ -13,6 +19,44 @@ pub struct AliasDefinition {
     pub notes: Vec<Note>,
 }
 
+/// Alias definitions seen so far, by name.
+#[derive(Debug, Default)]
+pub(crate) struct AliasTable {
+    aliases: HashMap<String, Arc<AliasDefinition>>,
+}
+
+impl AliasTable {
+    /// Adds a definition, replacing an earlier one with the same name. Returns the
+    /// earlier definition when it had different notes, identical redefinitions are fine.
+    pub fn define(&mut self, value: &Arc<AliasDefinition>) -> Option<Arc<AliasDefinition>> {
+        self.aliases
+            .insert(value.name.clone(), value.clone())
+            .filter(|previous| previous.notes != value.notes)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<AliasDefinition>> {
+        self.aliases.get(name)
+    }
+}
+
+/// Channel written as `ch=*`, the event goes out on every channel. A `ch=*` line
+/// works like any other channel directive: events without their own `ch=` that
+/// follow it go to every channel, until the next channel directive.
+pub const ALL_CHANNELS: u16 = u16::MAX;
+
+/// Displays a channel number, or `*` for [`ALL_CHANNELS`].
+pub(crate) struct ChannelLabel(pub u16);
+
+impl fmt::Display for ChannelLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == ALL_CHANNELS {
+            write!(f, "*")
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct VoiceList {
     pub voices: Vec<String>,
@@ -60,7 +104,7 @@ pub enum MtxtRecord {
     },
 
     DurationDirective {
-        duration: BeatTime,
+        duration: NoteDuration,
     },
     ChannelDirective {
         channel: u16,
@@ -72,20 +116,26 @@ pub enum MtxtRecord {
         off_velocity: f32,
     },
     TransitionCurveDirective {
-        curve: f32,
+        curve: Easing,
+    },
+    TransitionTimeDirective {
+        duration: BeatTime,
     },
     TransitionIntervalDirective {
         interval: f32,
     },
+    TransitionStepsDirective {
+        steps: u32,
+    },
 
     AliasDef {
-        value: Rc<AliasDefinition>,
+        value: Arc<AliasDefinition>,
     },
 
     Note {
         time: BeatTime,
         note: NoteTarget,
-        duration: Option<BeatTime>,
+        duration: Option<NoteDuration>,
         velocity: Option<f32>,
         off_velocity: Option<f32>,
         channel: Option<u16>, // channel might be defined by ChannelDirective
@@ -95,6 +145,9 @@ pub enum MtxtRecord {
         note: NoteTarget,
         velocity: Option<f32>,
         channel: Option<u16>, // channel might be defined by ChannelDirective
+        /// `len=`: the note off is generated this long after the note on when the file
+        /// is processed, while the text keeps the `on` event.
+        length: Option<NoteDuration>,
     },
     NoteOff {
         time: BeatTime,
@@ -109,32 +162,43 @@ pub enum MtxtRecord {
         controller: String,
         value: f32,
         channel: Option<u16>, // if None, affect all channels
-        transition_curve: Option<f32>,
+        transition_curve: Option<Easing>,
         transition_time: Option<BeatTime>,
         transition_interval: Option<f32>,
+        transition_steps: Option<u32>,
     },
     Voice {
         time: BeatTime,
         voices: VoiceList,
         channel: Option<u16>, // channel might be defined by ChannelDirective
+        bank: Option<u16>,
     },
 
     Tempo {
         time: BeatTime,
         bpm: f32,
-        transition_curve: Option<f32>,
+        transition_curve: Option<Easing>,
         transition_time: Option<BeatTime>,
         transition_interval: Option<f32>,
+        transition_steps: Option<u32>,
     },
     TimeSignature {
         time: BeatTime,
         signature: TimeSignature,
     },
+    /// `bar 5`: bar number marker, written to MIDI as a `Bar 5` marker.
+    Bar {
+        time: BeatTime,
+        number: u32,
+    },
 
+    /// `tuning C +10`, or `tuning ch=2 C +10` for a single channel. The unscoped form
+    /// applies to every channel without its own tuning of that pitch class.
     Tuning {
         time: BeatTime,
         target: String,
         cents: f32,
+        channel: Option<u16>,
     },
     Reset {
         time: BeatTime,
@@ -148,6 +212,14 @@ pub enum MtxtRecord {
 
     // Formatting events for passthrough conversion
     EmptyLine,
+    /// Full-line `// text` comment.
+    Comment {
+        text: String,
+    },
+    /// `/* ... */` comment, `text` is everything between the outer markers and may span lines.
+    BlockComment {
+        text: String,
+    },
 }
 
 /// A line in an MTXT file, containing a record and an optional inline comment.
@@ -174,40 +246,236 @@ impl MtxtRecordLine {
     }
 }
 
+/// Writes the line as it appears in a file, with the event time and inline comment.
+impl fmt::Display for MtxtRecordLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(time) = self.record.time() {
+            write!(f, "{} ", time)?;
+        }
+        write!(f, "{}", self.record)?;
+        if let Some(comment) = &self.comment {
+            if self.record != MtxtRecord::EmptyLine {
+                write!(f, " ")?;
+            }
+            write!(f, "// {}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a single line with `parse_mtxt_line`, relative times are taken from 0.
+///
+/// ```
+/// use mtxt::{BeatTime, MtxtRecordLine};
+///
+/// let line: MtxtRecordLine = "1.0 note C4 dur=1 // melody".parse().unwrap();
+/// assert_eq!(line.record.time(), Some(BeatTime::from_beats(1.0)));
+/// assert_eq!(line.comment.as_deref(), Some("melody"));
+///
+/// assert_eq!(line.to_string(), "1.0 note C4 dur=1.0 // melody");
+/// assert_eq!(line.to_string().parse::<MtxtRecordLine>().unwrap(), line);
+///
+/// assert!("1.0 note".parse::<MtxtRecordLine>().is_err());
+/// ```
+impl FromStr for MtxtRecordLine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        crate::record_parser::parse_mtxt_line(s)
+    }
+}
+
+/// Parses a single line like `MtxtRecordLine`, dropping the inline comment. Writing a
+/// record leaves out its time, so records without one (directives, `meta global`, ...)
+/// round-trip on their own and timed events through `MtxtRecordLine`.
+///
+/// ```
+/// use mtxt::MtxtRecord;
+///
+/// let record: MtxtRecord = "vel=0.8".parse().unwrap();
+/// assert_eq!(record, MtxtRecord::VelocityDirective { velocity: 0.8 });
+/// assert_eq!(record.to_string().parse::<MtxtRecord>().unwrap(), record);
+///
+/// let record: MtxtRecord = "0.5 cc volume 0.25".parse().unwrap();
+/// assert_eq!(record.to_string(), "cc volume 0.25");
+/// ```
+impl FromStr for MtxtRecord {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        s.parse::<MtxtRecordLine>().map(|line| line.record)
+    }
+}
+
+/// Wraps a meta value in double quotes when it would otherwise be read back differently:
+/// it contains a comment marker or is itself quoted.
+fn quote_meta_value(value: &str) -> Cow<'_, str> {
+    let is_quoted = value.len() >= 2 && value.starts_with('"') && value.ends_with('"');
+    if !is_quoted && find_inline_comment_index(value).is_none() {
+        return Cow::Borrowed(value);
+    }
+
+    let mut output = String::with_capacity(value.len() + 2);
+    output.push('"');
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => {
+                output.push(c);
+                output.extend(chars.next());
+            }
+            _ => output.push(c),
+        }
+    }
+    output.push('"');
+    Cow::Owned(output)
+}
+
+/// How `vel=` and `offvel=` values are written. Records always store 0.0-1.0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VelocityScale {
+    /// Floats from 0.0 to 1.0 (`vel=0.8`).
+    #[default]
+    Unit,
+    /// MIDI style integers from 0 to 127 (`vel=100`), stored as `value / 127`.
+    Midi127,
+}
+
+/// How times and durations are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// Decimal beats (`1.33333`).
+    #[default]
+    Decimal,
+    /// Fractions up to the given denominator where one matches (`1+1/3`), decimal
+    /// beats otherwise. Display only: times close to a fraction are written as it.
+    Fraction(u32),
+}
+
+impl TimeFormat {
+    /// Largest denominator of `fraction`, enough for triplets down to 1/24.
+    pub const DEFAULT_MAX_DENOMINATOR: u32 = 32;
+
+    pub fn format(&self, time: BeatTime) -> String {
+        match self {
+            TimeFormat::Decimal => time.to_string(),
+            TimeFormat::Fraction(max_denom) => time
+                .to_fraction_string(*max_denom)
+                .unwrap_or_else(|| time.to_string()),
+        }
+    }
+}
+
+impl FromStr for TimeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "decimal" => Ok(TimeFormat::Decimal),
+            "fraction" => Ok(TimeFormat::Fraction(Self::DEFAULT_MAX_DENOMINATOR)),
+            _ => Err(anyhow::anyhow!("Invalid time format: {}", s)),
+        }
+    }
+}
+
+/// Options applied when writing records as text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatContext {
+    pub float_precision: usize,
+    pub velocity_scale: VelocityScale,
+    pub time_format: TimeFormat,
+    pub octave_convention: OctaveConvention,
+}
+
+impl Default for FormatContext {
+    fn default() -> Self {
+        Self {
+            float_precision: DEFAULT_FLOAT_PRECISION,
+            velocity_scale: VelocityScale::Unit,
+            time_format: TimeFormat::Decimal,
+            octave_convention: OctaveConvention::Scientific,
+        }
+    }
+}
+
 impl fmt::Display for MtxtRecord {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &FormatContext::default())
+    }
+}
+
+/// Display adapter returned by `MtxtRecord::display_with`.
+pub struct MtxtRecordDisplay<'a> {
+    record: &'a MtxtRecord,
+    context: &'a FormatContext,
+}
+
+impl fmt::Display for MtxtRecordDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.record.fmt_with(f, self.context)
+    }
+}
+
+impl MtxtRecord {
+    pub fn display_with<'a>(&'a self, context: &'a FormatContext) -> MtxtRecordDisplay<'a> {
+        MtxtRecordDisplay {
+            record: self,
+            context,
+        }
+    }
+
+    pub fn fmt_with(&self, f: &mut fmt::Formatter<'_>, ctx: &FormatContext) -> fmt::Result {
+        let format_float32 = |value: f32| format_float32_with_precision(value, ctx.float_precision);
+        let format_velocity = |value: f32| match ctx.velocity_scale {
+            VelocityScale::Unit => format_float32(value),
+            VelocityScale::Midi127 => ((value * 127.0).round() as u8).to_string(),
+        };
+        let format_time = |time: BeatTime| ctx.time_format.format(time);
+        let format_duration = |duration: NoteDuration| match duration {
+            NoteDuration::Beats(beats) => format_time(beats),
+            NoteDuration::Millis(_) => duration.to_string(),
+        };
+        let format_note = |note: &NoteTarget| note.to_string_with(ctx.octave_convention);
+
         match self {
             MtxtRecord::Header { version } => {
                 write!(f, "mtxt {}", version)
             }
             MtxtRecord::GlobalMeta { meta_type, value } => {
-                write!(f, "meta global {} {}", meta_type, value)
+                write!(f, "meta global {} {}", meta_type, quote_meta_value(value))
             }
             MtxtRecord::ChannelDirective { channel } => {
-                write!(f, "ch={}", channel)
+                write!(f, "ch={}", ChannelLabel(*channel))
             }
             MtxtRecord::VelocityDirective { velocity } => {
-                write!(f, "vel={}", format_float32(*velocity))
+                write!(f, "vel={}", format_velocity(*velocity))
             }
             MtxtRecord::OffVelocityDirective { off_velocity } => {
-                write!(f, "offvel={}", format_float32(*off_velocity))
+                write!(f, "offvel={}", format_velocity(*off_velocity))
             }
             MtxtRecord::DurationDirective { duration } => {
-                write!(f, "dur={}", *duration)
+                write!(f, "dur={}", format_duration(*duration))
             }
             MtxtRecord::TransitionCurveDirective { curve } => {
-                write!(f, "transition_curve={}", format_float32(*curve))
+                write!(f, "transition_curve={}", curve)
+            }
+            MtxtRecord::TransitionTimeDirective { duration } => {
+                write!(f, "transition_time={}", format_time(*duration))
             }
             MtxtRecord::TransitionIntervalDirective { interval } => {
                 write!(f, "transition_interval={}", format_float32(*interval))
             }
+            MtxtRecord::TransitionStepsDirective { steps } => {
+                write!(f, "transition_steps={}", steps)
+            }
             MtxtRecord::AliasDef { value } => {
                 write!(f, "alias {} ", value.name)?;
                 for (i, note) in value.notes.iter().enumerate() {
                     if i > 0 {
                         write!(f, ",")?;
                     }
-                    write!(f, "{}", note)?;
+                    write!(f, "{}", note.to_string_with(ctx.octave_convention))?;
                 }
                 Ok(())
             }
@@ -219,18 +487,18 @@ impl fmt::Display for MtxtRecord {
                 off_velocity,
                 channel,
             } => {
-                write!(f, "note {}", note)?;
+                write!(f, "note {}", format_note(note))?;
                 if let Some(duration) = duration {
-                    write!(f, " dur={}", *duration)?;
+                    write!(f, " dur={}", format_duration(*duration))?;
                 }
                 if let Some(vel) = velocity {
-                    write!(f, " vel={}", format_float32(*vel))?;
+                    write!(f, " vel={}", format_velocity(*vel))?;
                 }
                 if let Some(off_vel) = off_velocity {
-                    write!(f, " offvel={}", format_float32(*off_vel))?;
+                    write!(f, " offvel={}", format_velocity(*off_vel))?;
                 }
                 if let Some(ch) = channel {
-                    write!(f, " ch={}", ch)?;
+                    write!(f, " ch={}", ChannelLabel(*ch))?;
                 }
                 Ok(())
             }
@@ -239,13 +507,17 @@ impl fmt::Display for MtxtRecord {
                 note,
                 velocity,
                 channel,
+                length,
             } => {
-                write!(f, "on {}", note)?;
+                write!(f, "on {}", format_note(note))?;
+                if let Some(length) = length {
+                    write!(f, " len={}", length)?;
+                }
                 if let Some(vel) = velocity {
-                    write!(f, " vel={}", format_float32(*vel))?;
+                    write!(f, " vel={}", format_velocity(*vel))?;
                 }
                 if let Some(ch) = channel {
-                    write!(f, " ch={}", ch)?;
+                    write!(f, " ch={}", ChannelLabel(*ch))?;
                 }
                 Ok(())
             }
@@ -255,12 +527,12 @@ impl fmt::Display for MtxtRecord {
                 off_velocity,
                 channel,
             } => {
-                write!(f, "off {}", note)?;
+                write!(f, "off {}", format_note(note))?;
                 if let Some(off_vel) = off_velocity {
-                    write!(f, " offvel={}", format_float32(*off_vel))?;
+                    write!(f, " offvel={}", format_velocity(*off_vel))?;
                 }
                 if let Some(ch) = channel {
-                    write!(f, " ch={}", ch)?;
+                    write!(f, " ch={}", ChannelLabel(*ch))?;
                 }
                 Ok(())
             }
@@ -273,36 +545,44 @@ impl fmt::Display for MtxtRecord {
                 transition_curve,
                 transition_time,
                 transition_interval,
+                transition_steps,
             } => {
                 write!(f, "cc")?;
                 if let Some(n) = note {
-                    write!(f, " {}", n)?;
+                    write!(f, " {}", format_note(n))?;
                 }
 
                 write!(f, " {} {}", controller, format_float32(*value))?;
 
                 if let Some(ch) = channel {
-                    write!(f, " ch={}", ch)?;
+                    write!(f, " ch={}", ChannelLabel(*ch))?;
                 }
                 if let Some(curve) = transition_curve {
-                    write!(f, " transition_curve={}", format_float32(*curve))?;
+                    write!(f, " transition_curve={}", curve)?;
                 }
                 if let Some(time) = transition_time {
-                    write!(f, " transition_time={}", *time)?;
+                    write!(f, " transition_time={}", format_time(*time))?;
                 }
                 if let Some(interval) = transition_interval {
                     write!(f, " transition_interval={}", format_float32(*interval))?;
                 }
+                if let Some(steps) = transition_steps {
+                    write!(f, " transition_steps={}", steps)?;
+                }
                 Ok(())
             }
             MtxtRecord::Voice {
                 time: _,
                 voices,
                 channel,
+                bank,
             } => {
                 write!(f, "voice")?;
                 if let Some(ch) = channel {
-                    write!(f, " ch={}", ch)?;
+                    write!(f, " ch={}", ChannelLabel(*ch))?;
+                }
+                if let Some(bank) = bank {
+                    write!(f, " bank={}", bank)?;
                 }
 
                 write!(f, " {}", voices)?;
@@ -314,32 +594,44 @@ impl fmt::Display for MtxtRecord {
                 transition_curve,
                 transition_time,
                 transition_interval,
+                transition_steps,
             } => {
                 write!(f, "tempo {}", format_float32(*bpm))?;
                 if let Some(curve) = transition_curve {
-                    write!(f, " transition_curve={}", format_float32(*curve))?;
+                    write!(f, " transition_curve={}", curve)?;
                 }
                 if let Some(time) = transition_time {
-                    write!(f, " transition_time={}", *time)?;
+                    write!(f, " transition_time={}", format_time(*time))?;
                 }
                 if let Some(interval) = transition_interval {
                     write!(f, " transition_interval={}", format_float32(*interval))?;
                 }
+                if let Some(steps) = transition_steps {
+                    write!(f, " transition_steps={}", steps)?;
+                }
                 Ok(())
             }
             MtxtRecord::TimeSignature { time: _, signature } => {
                 write!(f, "timesig {}", signature)
             }
+            MtxtRecord::Bar { time: _, number } => {
+                write!(f, "bar {}", number)
+            }
             MtxtRecord::Tuning {
                 time: _,
                 target,
                 cents,
+                channel,
             } => {
+                write!(f, "tuning ")?;
+                if let Some(ch) = channel {
+                    write!(f, "ch={} ", ChannelLabel(*ch))?;
+                }
                 let s = format_float32(*cents);
                 if *cents >= 0.0 && !s.starts_with('+') {
-                    write!(f, "tuning {} +{}", target, s)
+                    write!(f, "{} +{}", target, s)
                 } else {
-                    write!(f, "tuning {} {}", target, s)
+                    write!(f, "{} {}", target, s)
                 }
             }
             MtxtRecord::Reset { time: _, target } => {
@@ -353,9 +645,9 @@ impl fmt::Display for MtxtRecord {
             } => {
                 write!(f, "meta")?;
                 if let Some(ch) = channel {
-                    write!(f, " ch={}", ch)?;
+                    write!(f, " ch={}", ChannelLabel(*ch))?;
                 }
-                write!(f, " {} {}", meta_type, value)
+                write!(f, " {} {}", meta_type, quote_meta_value(value))
             }
             MtxtRecord::SysEx { time: _, data } => {
                 write!(f, "sysex")?;
@@ -367,6 +659,12 @@ impl fmt::Display for MtxtRecord {
             MtxtRecord::EmptyLine => {
                 write!(f, "")
             }
+            MtxtRecord::Comment { text } => {
+                write!(f, "// {}", text)
+            }
+            MtxtRecord::BlockComment { text } => {
+                write!(f, "/*{}*/", text)
+            }
         }
     }
 }
@@ -380,6 +678,7 @@ impl MtxtRecord {
             | MtxtRecord::ControlChange { time, .. }
             | MtxtRecord::Tempo { time, .. }
             | MtxtRecord::TimeSignature { time, .. }
+            | MtxtRecord::Bar { time, .. }
             | MtxtRecord::Voice { time, .. }
             | MtxtRecord::Tuning { time, .. }
             | MtxtRecord::Reset { time, .. }
@@ -397,6 +696,7 @@ impl MtxtRecord {
             | MtxtRecord::ControlChange { time, .. }
             | MtxtRecord::Tempo { time, .. }
             | MtxtRecord::TimeSignature { time, .. }
+            | MtxtRecord::Bar { time, .. }
             | MtxtRecord::Voice { time, .. }
             | MtxtRecord::Tuning { time, .. }
             | MtxtRecord::Reset { time, .. }
@@ -0,0 +1,86 @@
+use anyhow::{Result, bail};
+use std::fmt;
+use std::str::FromStr;
+
+/// Seven note scale, counted in degrees by the diatonic step transpose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scale {
+    Major,
+    /// Natural minor.
+    Minor,
+    HarmonicMinor,
+    /// Ascending melodic minor.
+    MelodicMinor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+}
+
+impl Scale {
+    /// Semitones above the root of each degree.
+    pub fn intervals(&self) -> [i32; 7] {
+        match self {
+            Scale::Major => [0, 2, 4, 5, 7, 9, 11],
+            Scale::Minor => [0, 2, 3, 5, 7, 8, 10],
+            Scale::HarmonicMinor => [0, 2, 3, 5, 7, 8, 11],
+            Scale::MelodicMinor => [0, 2, 3, 5, 7, 9, 11],
+            Scale::Dorian => [0, 2, 3, 5, 7, 9, 10],
+            Scale::Phrygian => [0, 1, 3, 5, 7, 8, 10],
+            Scale::Lydian => [0, 2, 4, 6, 7, 9, 11],
+            Scale::Mixolydian => [0, 2, 4, 5, 7, 9, 10],
+            Scale::Locrian => [0, 1, 3, 5, 6, 8, 10],
+        }
+    }
+}
+
+impl fmt::Display for Scale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Scale::Major => "major",
+            Scale::Minor => "minor",
+            Scale::HarmonicMinor => "harmonic-minor",
+            Scale::MelodicMinor => "melodic-minor",
+            Scale::Dorian => "dorian",
+            Scale::Phrygian => "phrygian",
+            Scale::Lydian => "lydian",
+            Scale::Mixolydian => "mixolydian",
+            Scale::Locrian => "locrian",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Scale {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "major" | "ionian" => Ok(Scale::Major),
+            "minor" | "aeolian" => Ok(Scale::Minor),
+            "harmonic-minor" => Ok(Scale::HarmonicMinor),
+            "melodic-minor" => Ok(Scale::MelodicMinor),
+            "dorian" => Ok(Scale::Dorian),
+            "phrygian" => Ok(Scale::Phrygian),
+            "lydian" => Ok(Scale::Lydian),
+            "mixolydian" => Ok(Scale::Mixolydian),
+            "locrian" => Ok(Scale::Locrian),
+            _ => bail!("Unknown scale: {}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_round_trip() {
+        for name in ["major", "minor", "harmonic-minor", "dorian", "locrian"] {
+            assert_eq!(name.parse::<Scale>().unwrap().to_string(), name);
+        }
+        assert_eq!("Aeolian".parse::<Scale>().unwrap(), Scale::Minor);
+        assert!("blues".parse::<Scale>().is_err());
+    }
+}
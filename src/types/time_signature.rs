@@ -1,3 +1,4 @@
+use crate::types::beat_time::BeatTime;
 use anyhow::{Result, bail};
 use std::fmt;
 use std::str::FromStr;
@@ -8,6 +9,14 @@ pub struct TimeSignature {
     pub denominator: u8,
 }
 
+impl TimeSignature {
+    /// Length of one bar in (quarter note) beats, e.g. 3.5 for 7/8.
+    pub fn bar_length(&self) -> BeatTime {
+        let beats = self.numerator as f64 * 4.0 / self.denominator as f64;
+        BeatTime::from_beats(beats)
+    }
+}
+
 impl fmt::Display for TimeSignature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}/{}", self.numerator, self.denominator)
@@ -43,4 +52,12 @@ mod tests {
         assert_eq!(ts.numerator, 4);
         assert_eq!(ts.denominator, 4);
     }
+
+    #[test]
+    fn test_bar_length() {
+        let bar_length = |s: &str| s.parse::<TimeSignature>().unwrap().bar_length().to_string();
+        assert_eq!(bar_length("4/4"), "4.0");
+        assert_eq!(bar_length("6/8"), "3.0");
+        assert_eq!(bar_length("7/8"), "3.5");
+    }
 }
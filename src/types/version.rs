@@ -2,6 +2,16 @@ use anyhow::{Result, bail};
 use std::fmt;
 use std::str::FromStr;
 
+/// How well this version of the library understands a file version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compat {
+    Supported,
+    /// A newer minor version: known records parse as usual, unknown event types are
+    /// skipped with a warning.
+    PartiallySupported,
+    Unsupported,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Version {
     pub major: u16,
@@ -13,8 +23,19 @@ impl Version {
         Version { major: 1, minor: 0 }
     }
 
+    pub fn compatibility(&self) -> Compat {
+        let latest = Self::latest();
+        if self.major != latest.major {
+            Compat::Unsupported
+        } else if self.minor > latest.minor {
+            Compat::PartiallySupported
+        } else {
+            Compat::Supported
+        }
+    }
+
     pub fn fail_if_not_supported(&self) -> Result<()> {
-        if self.major != 1 {
+        if self.compatibility() == Compat::Unsupported {
             bail!(
                 "Version {} is not supported. Only version 1 is supported",
                 self
@@ -60,4 +81,13 @@ mod tests {
         assert_eq!(version.major, 25);
         assert_eq!(version.minor, 63);
     }
+
+    #[test]
+    fn test_compatibility() {
+        let compat = |version: &str| version.parse::<Version>().unwrap().compatibility();
+        assert_eq!(compat("1.0"), Compat::Supported);
+        assert_eq!(compat("1.1"), Compat::PartiallySupported);
+        assert_eq!(compat("2.0"), Compat::Unsupported);
+        assert_eq!(compat("0.9"), Compat::Unsupported);
+    }
 }
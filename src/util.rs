@@ -2,8 +2,21 @@ use crate::MtxtFile;
 use crate::parse_mtxt;
 use crate::types::record::MtxtRecordLine;
 
+/// Number of decimal places used when formatting floats, unless configured otherwise.
+pub const DEFAULT_FLOAT_PRECISION: usize = 5;
+
 pub fn format_float32(value: f32) -> String {
-    let trimmed_zeros = format!("{:.5}", value).trim_end_matches('0').to_string();
+    format_float32_with_precision(value, DEFAULT_FLOAT_PRECISION)
+}
+
+/// Formats with at most `precision` decimal places, trimming trailing zeros but keeping one.
+pub fn format_float32_with_precision(value: f32, precision: usize) -> String {
+    let formatted = format!("{:.*}", precision, value);
+    if !formatted.contains('.') {
+        return formatted + ".0";
+    }
+
+    let trimmed_zeros = formatted.trim_end_matches('0').to_string();
 
     if trimmed_zeros.ends_with('.') {
         trimmed_zeros + "0"
@@ -51,5 +64,9 @@ mod tests {
         assert_eq!(format_float32(-0.0), "-0.0");
         assert_eq!(format_float32(0.0023), "0.0023");
         assert_eq!(format_float32(123456789123.456), "123456790528.0");
+
+        assert_eq!(format_float32_with_precision(1.234567, 2), "1.23");
+        assert_eq!(format_float32_with_precision(1.999, 2), "2.0");
+        assert_eq!(format_float32_with_precision(10.6, 0), "11.0");
     }
 }
@@ -0,0 +1,52 @@
+#![cfg(feature = "midi")]
+
+use mtxt::midi::convert_mtxt_to_midi;
+use mtxt::parse_mtxt;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// this file holds a single test, so no other test allocates while it counts
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn test_export_allocations() {
+    let events = 100_000;
+    let mut content = String::from("mtxt 1.0\n");
+    for idx in 0..events / 2 {
+        let time = idx as f64 / 100.0;
+        let _ = writeln!(content, "{} cc volume {}", time, (idx % 100) as f32 / 100.0);
+        let _ = writeln!(content, "{} note C4 dur=0.005", time);
+    }
+    let file = parse_mtxt(&content).unwrap();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let midi_bytes = convert_mtxt_to_midi(&file).unwrap();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    assert!(!midi_bytes.is_empty());
+
+    // the controller name of each cc is copied once, everything else only allocates
+    // per parameter or per buffer (was about 4 per event)
+    assert!(
+        allocations < events,
+        "{} allocations for {} events",
+        allocations,
+        events
+    );
+}